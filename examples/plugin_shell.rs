@@ -0,0 +1,61 @@
+//! A shell with an extra `greet` builtin, as a plugin crate would build to
+//! test its builtins through `ShellHarness::binary`
+//!
+//! ```sh
+//! cargo run --example plugin_shell -- -c 'greet world'
+//! ```
+
+use codecrafters_shell::builtin::BuiltinCommand;
+use codecrafters_shell::cli::{Cli, USAGE};
+use codecrafters_shell::error::ShellError;
+use codecrafters_shell::shell::Shell;
+use std::io::Write;
+
+/// `greet NAME...`: print a greeting for the names given
+struct GreetCommand;
+
+impl BuiltinCommand for GreetCommand {
+    fn name(&self) -> &'static str {
+        "greet"
+    }
+
+    fn execute(
+        &self,
+        args: &[String],
+        _shell: &mut Shell,
+        stdout: &mut dyn Write,
+        _stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError> {
+        writeln!(stdout, "hello, {}", args.join(" "))?;
+        Ok(0)
+    }
+}
+
+fn main() {
+    let cli = match Cli::parse(std::env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(msg) => {
+            eprintln!("plugin_shell: {}\n{}", msg, USAGE);
+            std::process::exit(2);
+        }
+    };
+    let status = match Shell::with_options(cli.shell_options()) {
+        Ok(mut shell) => {
+            shell.register_builtin(Box::new(GreetCommand));
+            let result = match (&cli.command, &cli.script) {
+                (Some(cmd), _) => shell.run_command_string(cmd),
+                (None, Some(script)) => shell.run_script(script),
+                (None, None) => shell.run(),
+            };
+            result.unwrap_or_else(|e| {
+                eprintln!("plugin_shell: {}", e);
+                1
+            })
+        }
+        Err(e) => {
+            eprintln!("Failed to initialize shell: {:?}", e);
+            1
+        }
+    };
+    std::process::exit(status);
+}
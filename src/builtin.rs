@@ -1,26 +1,56 @@
-use crate::error::ShellError;
+use crate::command::{is_name, CommandParts, Word, RESERVED_WORDS};
+use crate::completion::{is_executable, CompletionAction, CompletionSpec};
+use crate::error::{describe_io_error, ShellError};
+use crate::expand;
+use crate::history;
+use crate::jobs::{JobState, JobTable};
+use crate::limits;
+use crate::printf;
+use crate::prompt;
+use crate::shell::{LoopControl, Shell, Value};
+use crate::signals;
 use std::collections::HashMap;
 use std::env;
-use std::path::{Path, PathBuf};
+use std::ffi::CString;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 /// Trait for implementing built-in shell commands
 ///
 /// Each built-in command implements this trait to provide its name
-/// and execution logic. Commands receive arguments and the current
-/// working directory, and return output as a String.
+/// and execution logic. Commands receive arguments and the running
+/// shell, write their output and diagnostics as raw bytes to the given
+/// writers, and return their exit status.
 pub trait BuiltinCommand {
     /// Return the command name (e.g., "cd", "echo")
     fn name(&self) -> &'static str;
 
-    /// Execute the command with given arguments
+    /// Execute the command with given arguments, returning its exit status
+    ///
+    /// An `Err` is written to the command's stderr by the shell and sets
+    /// `$?` to the error's exit status.
     ///
     /// # Arguments
     /// * `args` - Command arguments (not including the command name itself)
-    /// * `working_dir` - Current working directory
-    ///
-    /// # Returns
-    /// Command output as a String, or an error
-    fn execute(&self, args: &[String], working_dir: &Path) -> Result<String, ShellError>;
+    /// * `shell` - The shell the command runs in, for state such as the working directory
+    /// * `stdout` - Where the command writes its output (terminal, file or buffer)
+    /// * `stderr` - Where the command writes diagnostics
+    fn execute(
+        &self,
+        args: &[String],
+        shell: &mut Shell,
+        stdout: &mut dyn Write,
+        stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError>;
+
+    /// What the command's arguments complete to at the prompt, if not file
+    /// names
+    fn completion(&self) -> Option<CompletionSpec> {
+        None
+    }
 }
 
 /// Registry that holds all built-in commands
@@ -44,11 +74,45 @@ impl BuiltinRegistry {
         };
         // Register all built-in commands
         registry.register(Box::new(CdCommand));
+        registry.register(Box::new(PushdCommand));
+        registry.register(Box::new(PopdCommand));
+        registry.register(Box::new(DirsCommand));
         registry.register(Box::new(EchoCommand));
+        registry.register(Box::new(PrintfCommand));
         registry.register(Box::new(PwdCommand));
         registry.register(Box::new(ExitCommand));
         registry.register(Box::new(TypeCommand));
+        registry.register(Box::new(WhichCommand));
         registry.register(Box::new(HistoryCommand));
+        registry.register(Box::new(BenchCommand));
+        registry.register(Box::new(PathCommand));
+        registry.register(Box::new(RehashCommand));
+        registry.register(Box::new(HashCommand));
+        registry.register(Box::new(JobsCommand));
+        registry.register(Box::new(FgCommand));
+        registry.register(Box::new(BgCommand));
+        registry.register(Box::new(WaitCommand));
+        registry.register(Box::new(DisownCommand));
+        registry.register(Box::new(KillCommand));
+        registry.register(Box::new(UlimitCommand));
+        registry.register(Box::new(ReadCommand));
+        registry.register(Box::new(ExportCommand));
+        registry.register(Box::new(UnsetCommand));
+        registry.register(Box::new(LocalCommand));
+        registry.register(Box::new(ReadonlyCommand));
+        registry.register(Box::new(SetCommand));
+        registry.register(Box::new(ShiftCommand));
+        registry.register(Box::new(AliasCommand));
+        registry.register(Box::new(UnaliasCommand));
+        registry.register(Box::new(ReturnCommand));
+        registry.register(Box::new(SourceCommand { dot: false }));
+        registry.register(Box::new(SourceCommand { dot: true }));
+        registry.register(Box::new(BreakCommand));
+        registry.register(Box::new(ContinueCommand));
+        registry.register(Box::new(TestCommand { bracket: false }));
+        registry.register(Box::new(TestCommand { bracket: true }));
+        registry.register(Box::new(CompleteCommand));
+        registry.register(Box::new(CompgenCommand));
         registry
     }
 
@@ -60,6 +124,14 @@ impl BuiltinRegistry {
             .collect()
     }
 
+    /// The completion specs the built-in commands provide, by name
+    pub fn completion_specs(&self) -> HashMap<String, CompletionSpec> {
+        self.commands
+            .iter()
+            .filter_map(|(name, cmd)| Some((name.clone(), cmd.completion()?)))
+            .collect()
+    }
+
     /// Register a new built-in command
     pub fn register(&mut self, command: Box<dyn BuiltinCommand>) {
         self.commands.insert(command.name().to_string(), command);
@@ -76,39 +148,314 @@ impl BuiltinRegistry {
     }
 }
 
-/// Change directory command
+/// Change the working directory
+///
+/// Usage: `cd [-L | -P] [DIR | -]`. Without DIR, changes to `$HOME`; `cd -`
+/// changes to `$OLDPWD`, the directory before the last change, and prints
+/// it. A relative DIR not starting with `.` or `..` is looked for in each
+/// directory of `$CDPATH` first, and printed if found there. `-P` resolves
+/// symbolic links in the new directory; `-L`, the default, keeps them.
 struct CdCommand;
 
+/// Split the leading `-L` and `-P` options of `cd` or `pwd` from the other
+/// arguments, returning whether the last one asked for the physical path
+fn physical_option<'a>(
+    builtin: &str,
+    args: &'a [String],
+) -> Result<(bool, &'a [String]), ShellError> {
+    let mut physical = false;
+    let mut rest = args;
+    while let Some((arg, after)) = rest.split_first() {
+        if arg == "--" {
+            return Ok((physical, after));
+        }
+        let Some(flags) = arg.strip_prefix('-').filter(|flags| !flags.is_empty()) else {
+            break;
+        };
+        for flag in flags.chars() {
+            match flag {
+                'L' => physical = false,
+                'P' => physical = true,
+                _ => return Err(usage_error(builtin, format!("-{}: invalid option", flag))),
+            }
+        }
+        rest = after;
+    }
+    Ok((physical, rest))
+}
+
+/// Where `dir` is under a directory in `$CDPATH`, with whether that entry
+/// named a directory (rather than being empty, for the working directory)
+fn cdpath_lookup(shell: &Shell, dir: &str) -> Option<(PathBuf, bool)> {
+    let first = Path::new(dir).components().next()?;
+    if !matches!(first, Component::Normal(_)) {
+        return None;
+    }
+    let cdpath = shell.variable("CDPATH")?;
+    cdpath.split(':').find_map(|entry| {
+        let base = if entry.is_empty() { "." } else { entry };
+        let candidate = Path::new(base).join(dir);
+        candidate.is_dir().then_some((candidate, !entry.is_empty()))
+    })
+}
+
 impl BuiltinCommand for CdCommand {
     fn name(&self) -> &'static str {
         "cd"
     }
 
-    fn execute(&self, args: &[String], _working_dir: &Path) -> Result<String, ShellError> {
-        // Determine target directory: HOME if no args, otherwise the specified path
-        // Handles ~ and ~/ expansion
-        let target_dir = match args.first() {
-            Some(dir) if dir == "~" => {
-                env::var("HOME").map_err(|_| ShellError::EnvVarNotFound("HOME".to_string()))?
-            }
-            Some(dir) if dir.starts_with("~/") => {
-                let home =
-                    env::var("HOME").map_err(|_| ShellError::EnvVarNotFound("HOME".to_string()))?;
-                format!("{}{}", home, &dir[1..])
-            }
-            Some(dir) => dir.clone(),
+    fn completion(&self) -> Option<CompletionSpec> {
+        Some(CompletionSpec::new(CompletionAction::Directories))
+    }
+
+    fn execute(
+        &self,
+        args: &[String],
+        shell: &mut Shell,
+        stdout: &mut dyn Write,
+        _stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError> {
+        let (physical, args) = physical_option("cd", args)?;
+        // Determine target directory: HOME if no args, otherwise the specified
+        // path (with any `~` already expanded)
+        let target_dir = match args.first().map(String::as_str) {
+            Some("-") => shell
+                .variable("OLDPWD")
+                .ok_or_else(|| ShellError::BuiltinError {
+                    name: "cd".to_string(),
+                    status: 1,
+                    message: "OLDPWD not set".to_string(),
+                })?,
+            Some(dir) => dir.to_string(),
             None => env::var("HOME").map_err(|_| ShellError::EnvVarNotFound("HOME".to_string()))?,
         };
+        let (path, found_in_cdpath) = cdpath_lookup(shell, &target_dir)
+            .unwrap_or_else(|| (PathBuf::from(&target_dir), false));
 
         // Attempt to change directory
-        if env::set_current_dir(&target_dir).is_err() {
-            return Ok(format!("cd: {}: No such file or directory", target_dir));
+        shell
+            .change_dir(&path, physical)
+            .map_err(|e| ShellError::CdError(target_dir, describe_io_error(&e)))?;
+        if found_in_cdpath || args.first().is_some_and(|arg| arg == "-") {
+            writeln!(stdout, "{}", shell.current_dir().display())?;
+        }
+        Ok(0)
+    }
+}
+
+/// The directory stack as `dirs` shows it: the working directory, then
+/// the directories saved by `pushd`, most recent first
+fn directory_stack(shell: &Shell) -> Vec<PathBuf> {
+    let mut dirs = vec![shell.current_dir().to_path_buf()];
+    dirs.extend_from_slice(shell.dir_stack());
+    dirs
+}
+
+/// Which entry of a directory stack of `len` entries `+N` (counting from
+/// the left of `dirs`, from 0) or `-N` (from the right) names, or `None`
+/// if `arg` isn't either
+fn stack_index(builtin: &str, arg: &str, len: usize) -> Option<Result<usize, ShellError>> {
+    let (from_right, digits) = match arg.split_at_checked(1)? {
+        ("+", digits) => (false, digits),
+        ("-", digits) => (true, digits),
+        _ => return None,
+    };
+    let n: usize = digits.parse().ok()?;
+    let index = if from_right {
+        len.checked_sub(n + 1)
+    } else {
+        Some(n).filter(|&n| n < len)
+    };
+    Some(index.ok_or_else(|| ShellError::BuiltinError {
+        name: builtin.to_string(),
+        status: 1,
+        message: format!("{}: directory stack index out of range", arg),
+    }))
+}
+
+/// Make the first of `dirs` the working directory and the rest the saved
+/// directories, printing the stack as `dirs` does
+fn set_directory_stack(
+    builtin: &str,
+    shell: &mut Shell,
+    mut dirs: Vec<PathBuf>,
+    stdout: &mut dyn Write,
+) -> Result<i32, ShellError> {
+    let dir = dirs.remove(0);
+    if dir != shell.current_dir() {
+        shell
+            .change_dir(&dir, false)
+            .map_err(|e| ShellError::BuiltinError {
+                name: builtin.to_string(),
+                status: 1,
+                message: format!("{}: {}", dir.display(), describe_io_error(&e)),
+            })?;
+    }
+    shell.set_dir_stack(dirs);
+    let home = shell.variable("HOME");
+    let listed: Vec<String> = directory_stack(shell)
+        .iter()
+        .map(|dir| prompt::abbreviate_home(dir, home.as_deref()))
+        .collect();
+    writeln!(stdout, "{}", listed.join(" "))?;
+    Ok(0)
+}
+
+/// Save the working directory and change to another
+///
+/// Usage: `pushd [DIR | +N | -N]`. With DIR, the working directory is
+/// saved on the directory stack and DIR becomes the working directory.
+/// `+N` and `-N` rotate the stack so its Nth entry, counting from 0 from
+/// the left or right of what `dirs` lists, is on top and becomes the
+/// working directory; with no argument the top two entries swap.
+struct PushdCommand;
+
+impl BuiltinCommand for PushdCommand {
+    fn name(&self) -> &'static str {
+        "pushd"
+    }
+
+    fn completion(&self) -> Option<CompletionSpec> {
+        Some(CompletionSpec::new(CompletionAction::Directories))
+    }
+
+    fn execute(
+        &self,
+        args: &[String],
+        shell: &mut Shell,
+        stdout: &mut dyn Write,
+        _stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError> {
+        let mut dirs = directory_stack(shell);
+        match args.first() {
+            None if dirs.len() < 2 => {
+                return Err(ShellError::BuiltinError {
+                    name: "pushd".to_string(),
+                    status: 1,
+                    message: "no other directory".to_string(),
+                })
+            }
+            None => dirs.swap(0, 1),
+            Some(arg) => match stack_index("pushd", arg, dirs.len()) {
+                Some(index) => dirs.rotate_left(index?),
+                None => dirs.insert(0, PathBuf::from(arg)),
+            },
+        }
+        set_directory_stack("pushd", shell, dirs, stdout)
+    }
+}
+
+/// Return to the directory saved last
+///
+/// Usage: `popd [+N | -N]`. The top of the directory stack is removed and
+/// the next entry becomes the working directory; `+N` or `-N` remove the
+/// Nth entry of what `dirs` lists instead, counting from 0 from the left or
+/// right.
+struct PopdCommand;
+
+impl BuiltinCommand for PopdCommand {
+    fn name(&self) -> &'static str {
+        "popd"
+    }
+
+    fn execute(
+        &self,
+        args: &[String],
+        shell: &mut Shell,
+        stdout: &mut dyn Write,
+        _stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError> {
+        let mut dirs = directory_stack(shell);
+        if dirs.len() < 2 {
+            return Err(ShellError::BuiltinError {
+                name: "popd".to_string(),
+                status: 1,
+                message: "directory stack empty".to_string(),
+            });
+        }
+        let index = match args.first() {
+            None => 0,
+            Some(arg) => stack_index("popd", arg, dirs.len()).unwrap_or_else(|| {
+                Err(usage_error("popd", format!("{}: invalid argument", arg)))
+            })?,
+        };
+        dirs.remove(index);
+        set_directory_stack("popd", shell, dirs, stdout)
+    }
+}
+
+/// Show the directory stack
+///
+/// Usage: `dirs [-clpv] [+N | -N]` lists the working directory and then
+/// the directories saved by `pushd`, with `~` for the home directory unless
+/// `-l` is given. `-p` puts each on its own line and `-v` numbers them as
+/// well; `+N` and `-N` show only the Nth. `-c` empties the stack.
+struct DirsCommand;
+
+impl BuiltinCommand for DirsCommand {
+    fn name(&self) -> &'static str {
+        "dirs"
+    }
+
+    fn execute(
+        &self,
+        args: &[String],
+        shell: &mut Shell,
+        stdout: &mut dyn Write,
+        _stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError> {
+        let dirs = directory_stack(shell);
+        let mut options = Vec::new();
+        let mut only = None;
+        for arg in args {
+            if let Some(index) = stack_index("dirs", arg, dirs.len()) {
+                only = Some(index?);
+                continue;
+            }
+            let flags = arg.strip_prefix('-').filter(|flags| !flags.is_empty());
+            let flags =
+                flags.ok_or_else(|| usage_error("dirs", format!("{}: invalid argument", arg)))?;
+            for flag in flags.chars() {
+                if !"clpv".contains(flag) {
+                    return Err(usage_error("dirs", format!("-{}: invalid option", flag)));
+                }
+                options.push(flag);
+            }
         }
-        Ok(String::new())
+        if options.contains(&'c') {
+            shell.set_dir_stack(Vec::new());
+            return Ok(0);
+        }
+
+        let home = shell.variable("HOME").filter(|_| !options.contains(&'l'));
+        let listed: Vec<(usize, String)> = dirs
+            .iter()
+            .map(|dir| prompt::abbreviate_home(dir, home.as_deref()))
+            .enumerate()
+            .filter(|&(index, _)| only.is_none_or(|only| only == index))
+            .collect();
+        if options.contains(&'v') {
+            for (index, dir) in listed {
+                writeln!(stdout, "{:2}  {}", index, dir)?;
+            }
+        } else if options.contains(&'p') {
+            for (_, dir) in listed {
+                writeln!(stdout, "{}", dir)?;
+            }
+        } else {
+            let dirs: Vec<String> = listed.into_iter().map(|(_, dir)| dir).collect();
+            writeln!(stdout, "{}", dirs.join(" "))?;
+        }
+        Ok(0)
     }
 }
 
 /// Print arguments to stdout
+///
+/// Usage: `echo [-neE] [ARGUMENT...]`. `-n` leaves off the newline, and
+/// `-e` replaces backslash escapes such as `\n`, `\t`, `\xHH` and `\0NNN`
+/// (`\c` stops the output there), which `-E` turns off again. Arguments
+/// that only look like options are printed.
 struct EchoCommand;
 
 impl BuiltinCommand for EchoCommand {
@@ -116,12 +463,89 @@ impl BuiltinCommand for EchoCommand {
         "echo"
     }
 
-    fn execute(&self, args: &[String], _working_dir: &Path) -> Result<String, ShellError> {
-        Ok(args.join(" "))
+    fn execute(
+        &self,
+        args: &[String],
+        _shell: &mut Shell,
+        stdout: &mut dyn Write,
+        _stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError> {
+        let (mut newline, mut escapes) = (true, false);
+        let mut words = args;
+        while let Some((arg, rest)) = words.split_first() {
+            let Some(flags) = arg.strip_prefix('-') else {
+                break;
+            };
+            if flags.is_empty() || !flags.chars().all(|flag| "neE".contains(flag)) {
+                break;
+            }
+            for flag in flags.chars() {
+                match flag {
+                    'n' => newline = false,
+                    'e' => escapes = true,
+                    _ => escapes = false,
+                }
+            }
+            words = rest;
+        }
+
+        let text = words.join(" ");
+        if !escapes {
+            stdout.write_all(text.as_bytes())?;
+        } else {
+            let (bytes, stop) = printf::unescape(&text, printf::EscapeStyle::Echo);
+            stdout.write_all(&bytes)?;
+            newline &= !stop;
+        }
+        if newline {
+            stdout.write_all(b"\n")?;
+        }
+        Ok(0)
+    }
+}
+
+/// Print arguments as a format says
+///
+/// Usage: `printf FORMAT [ARGUMENT...]`; see [`printf::printf`] for the
+/// conversions. The status is 1 if an argument wasn't the number its
+/// conversion needed.
+struct PrintfCommand;
+
+impl BuiltinCommand for PrintfCommand {
+    fn name(&self) -> &'static str {
+        "printf"
+    }
+
+    fn execute(
+        &self,
+        args: &[String],
+        _shell: &mut Shell,
+        stdout: &mut dyn Write,
+        stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError> {
+        let args = match args.first().map(String::as_str) {
+            Some("--") => &args[1..],
+            _ => args,
+        };
+        let Some((format, args)) = args.split_first() else {
+            return Err(usage_error(
+                "printf",
+                "usage: printf FORMAT [ARGUMENT...]".to_string(),
+            ));
+        };
+        let formatted = printf::printf(format, args);
+        stdout.write_all(&formatted.output)?;
+        for error in &formatted.errors {
+            writeln!(stderr, "printf: {}", error)?;
+        }
+        Ok(if formatted.errors.is_empty() { 0 } else { 1 })
     }
 }
 
-/// Print working directory command
+/// Print the working directory
+///
+/// Usage: `pwd [-L | -P]`. `-P` prints it with symbolic links resolved;
+/// `-L`, the default, as `cd` reached it.
 struct PwdCommand;
 
 impl BuiltinCommand for PwdCommand {
@@ -129,8 +553,20 @@ impl BuiltinCommand for PwdCommand {
         "pwd"
     }
 
-    fn execute(&self, _args: &[String], working_dir: &Path) -> Result<String, ShellError> {
-        Ok(working_dir.display().to_string())
+    fn execute(
+        &self,
+        args: &[String],
+        shell: &mut Shell,
+        stdout: &mut dyn Write,
+        _stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError> {
+        let (physical, _) = physical_option("pwd", args)?;
+        if physical {
+            writeln!(stdout, "{}", env::current_dir()?.display())?;
+        } else {
+            writeln!(stdout, "{}", shell.current_dir().display())?;
+        }
+        Ok(0)
     }
 }
 
@@ -142,17 +578,125 @@ impl BuiltinCommand for ExitCommand {
         "exit"
     }
 
-    fn execute(&self, args: &[String], _working_dir: &Path) -> Result<String, ShellError> {
-        // Parse exit code from first argument, default to 0
-        let status = args
-            .first()
-            .and_then(|s| s.parse::<i32>().ok())
-            .unwrap_or(0);
-        std::process::exit(status);
+    fn execute(
+        &self,
+        args: &[String],
+        shell: &mut Shell,
+        _stdout: &mut dyn Write,
+        stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError> {
+        // Exit with the given code, or the last command's status if none is given
+        let status = match args.first() {
+            Some(arg) => match arg.parse::<i32>() {
+                Ok(code) => code & 0xff,
+                Err(_) => {
+                    writeln!(stderr, "exit: {}: numeric argument required", arg)?;
+                    2
+                }
+            },
+            None => shell.last_status(),
+        };
+        shell.request_exit(status);
+        Ok(status)
+    }
+}
+
+/// Something a command name runs, as the shell looks it up
+enum Resolution {
+    /// A reserved word of the shell's grammar
+    Keyword,
+    /// An alias, with its value
+    Alias(String),
+    Function,
+    Builtin,
+    /// An executable found in PATH
+    File(PathBuf),
+}
+
+/// Everything `name` could run, in the order the shell tries them: reserved
+/// word, alias, function, builtin, then each executable of that name in
+/// PATH, starting with where the command hash says it is
+fn resolve_command(shell: &Shell, name: &str) -> Vec<Resolution> {
+    let mut found = Vec::new();
+    if RESERVED_WORDS.contains(&name) {
+        found.push(Resolution::Keyword);
+    }
+    if let Some(value) = shell.aliases().get(name) {
+        found.push(Resolution::Alias(value.clone()));
+    }
+    if shell.is_function(name) {
+        found.push(Resolution::Function);
+    }
+    if shell.is_builtin(name) {
+        found.push(Resolution::Builtin);
+    }
+    if name.contains('/') {
+        if is_executable(Path::new(name)) {
+            found.push(Resolution::File(PathBuf::from(name)));
+        }
+        return found;
+    }
+    let dirs = env::var_os("PATH")
+        .map(|paths| env::split_paths(&paths).collect::<Vec<_>>())
+        .unwrap_or_default();
+    let hashed = shell
+        .command_hash()
+        .read()
+        .unwrap()
+        .get(name)
+        .map(Path::to_path_buf);
+    if let Some(path) = hashed.filter(|path| is_executable(path)) {
+        found.push(Resolution::File(path));
+    }
+    let mut seen = Vec::new();
+    for dir in dirs {
+        let path = dir.join(name);
+        let listed = found
+            .iter()
+            .any(|r| matches!(r, Resolution::File(p) if *p == path));
+        if !seen.contains(&dir) && !listed && is_executable(&path) {
+            found.push(Resolution::File(path));
+        }
+        seen.push(dir);
+    }
+    found
+}
+
+/// Split the single-letter options in `allowed` off the arguments of
+/// `type`, `which` and `hash`, returning the ones given and the names after
+/// them
+fn lookup_options<'a>(
+    builtin: &str,
+    args: &'a [String],
+    allowed: &str,
+) -> Result<(Vec<char>, &'a [String]), ShellError> {
+    let mut given = Vec::new();
+    let mut names = args;
+    while let Some((arg, rest)) = names.split_first() {
+        let Some(flags) = arg.strip_prefix('-').filter(|flags| !flags.is_empty()) else {
+            break;
+        };
+        names = rest;
+        if flags == "-" {
+            break;
+        }
+        for flag in flags.chars() {
+            if !allowed.contains(flag) {
+                return Err(usage_error(builtin, format!("-{}: invalid option", flag)));
+            }
+            given.push(flag);
+        }
     }
+    Ok((given, names))
 }
 
-/// Determine the type of a command (builtin or executable path)
+/// Describe what a command name runs
+///
+/// Usage: `type [-atp] NAME...` says whether each NAME is a reserved word,
+/// an alias, a function, a builtin or a file in PATH, whichever runs; with
+/// `-a`, all of them in the order they're looked up. `-t` prints just the
+/// kind (`keyword`, `alias`, `function`, `builtin` or `file`) and `-p` just
+/// the path of a file, with nothing for the other kinds. The status is 1 if any NAME isn't found.
 struct TypeCommand;
 
 impl BuiltinCommand for TypeCommand {
@@ -160,45 +704,2433 @@ impl BuiltinCommand for TypeCommand {
         "type"
     }
 
-    fn execute(&self, args: &[String], _working_dir: &Path) -> Result<String, ShellError> {
-        if let Some(cmd) = args.first() {
-            // Check if it's a built-in command
-            if BUILTIN_COMMANDS.contains(&cmd.as_str()) {
-                return Ok(format!("{} is a shell builtin", cmd));
+    fn completion(&self) -> Option<CompletionSpec> {
+        Some(CompletionSpec::new(CompletionAction::Commands))
+    }
+
+    fn execute(
+        &self,
+        args: &[String],
+        shell: &mut Shell,
+        stdout: &mut dyn Write,
+        stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError> {
+        let (options, names) = lookup_options("type", args, "atp")?;
+        let all = options.contains(&'a');
+        let mut status = 0;
+        for cmd in names {
+            let found = resolve_command(shell, cmd);
+            if found.is_empty() {
+                // `-t` and `-p` only say what they find
+                if !options.contains(&'t') && !options.contains(&'p') {
+                    writeln!(stderr, "{}: not found", cmd)?;
+                }
+                status = 1;
+            }
+            for resolution in found.iter().take(if all { found.len() } else { 1 }) {
+                if options.contains(&'t') {
+                    let kind = match resolution {
+                        Resolution::Keyword => "keyword",
+                        Resolution::Alias(_) => "alias",
+                        Resolution::Function => "function",
+                        Resolution::Builtin => "builtin",
+                        Resolution::File(_) => "file",
+                    };
+                    writeln!(stdout, "{}", kind)?;
+                    continue;
+                }
+                match resolution {
+                    Resolution::File(path) if options.contains(&'p') => {
+                        writeln!(stdout, "{}", path.display())?
+                    }
+                    _ if options.contains(&'p') => {}
+                    Resolution::Keyword => writeln!(stdout, "{} is a shell keyword", cmd)?,
+                    Resolution::Alias(value) => {
+                        writeln!(stdout, "{} is aliased to `{}'", cmd, value)?
+                    }
+                    Resolution::Function => writeln!(stdout, "{} is a function", cmd)?,
+                    Resolution::Builtin => writeln!(stdout, "{} is a shell builtin", cmd)?,
+                    Resolution::File(path)
+                        if shell.command_hash().read().unwrap().get(cmd) == Some(path) =>
+                    {
+                        writeln!(stdout, "{} is hashed ({})", cmd, path.display())?
+                    }
+                    Resolution::File(path) => writeln!(stdout, "{} is {}", cmd, path.display())?,
+                }
+            }
+        }
+        Ok(status)
+    }
+}
+
+/// Show what a command name runs, seeing the shell's own commands
+///
+/// Usage: `which [-a] NAME...` prints the reserved word, alias, function or
+/// builtin each NAME is, or the path of the file in PATH it runs; with `-a`, all of them
+/// in the order they're looked up. The status is 1 if any NAME isn't found.
+struct WhichCommand;
+
+impl BuiltinCommand for WhichCommand {
+    fn name(&self) -> &'static str {
+        "which"
+    }
+
+    fn completion(&self) -> Option<CompletionSpec> {
+        Some(CompletionSpec::new(CompletionAction::Commands))
+    }
+
+    fn execute(
+        &self,
+        args: &[String],
+        shell: &mut Shell,
+        stdout: &mut dyn Write,
+        stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError> {
+        let (options, names) = lookup_options("which", args, "a")?;
+        let all = options.contains(&'a');
+        let mut status = 0;
+        for cmd in names {
+            let found = resolve_command(shell, cmd);
+            if found.is_empty() {
+                writeln!(stderr, "{} not found", cmd)?;
+                status = 1;
             }
-            // Check if it's an executable in PATH
-            if let Some(path) = find_executable(cmd) {
-                return Ok(format!("{} is {}", cmd, path.display()));
+            for resolution in found.iter().take(if all { found.len() } else { 1 }) {
+                match resolution {
+                    Resolution::Keyword => writeln!(stdout, "{}: shell reserved word", cmd)?,
+                    Resolution::Alias(value) => writeln!(stdout, "{}: aliased to {}", cmd, value)?,
+                    Resolution::Function => writeln!(stdout, "{}: shell function", cmd)?,
+                    Resolution::Builtin => writeln!(stdout, "{}: shell builtin", cmd)?,
+                    Resolution::File(path) => writeln!(stdout, "{}", path.display())?,
+                }
             }
-            return Ok(format!("{}: not found", cmd));
         }
-        Ok(String::new())
+        Ok(status)
     }
 }
 
-/// Display command history (currently not implemented)
+/// List, search or edit the commands entered at the prompt
+///
+/// Usage: `history [-t] [-s PATTERN] [N]` prints the history (or its last
+/// `N` entries), numbered from the oldest. `-s` only lists entries
+/// containing `PATTERN`, and `-t` shows when each was entered, formatted by
+/// `$HISTTIMEFORMAT` (`%F %T` if it's unset; setting it also turns `-t` on).
+/// `history -d N` deletes entry `N` (a negative `N` counts back from the
+/// newest) and `history -c` clears them all; like bash, neither changes
+/// what's already in the history file. Only interactive shells keep a
+/// history.
 struct HistoryCommand;
 
+/// How `history -t` shows times when `HISTTIMEFORMAT` is unset
+const DEFAULT_HISTORY_TIME_FORMAT: &str = "%F %T  ";
+
 impl BuiltinCommand for HistoryCommand {
     fn name(&self) -> &'static str {
         "history"
     }
 
-    fn execute(&self, _args: &[String], _working_dir: &Path) -> Result<String, ShellError> {
-        // History is managed by rustyline, not implemented here
-        Ok(String::new())
+    fn execute(
+        &self,
+        args: &[String],
+        shell: &mut Shell,
+        stdout: &mut dyn Write,
+        _stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError> {
+        let mut time_format = shell.variable("HISTTIMEFORMAT");
+        let mut pattern = None;
+        let mut count = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-c" => {
+                    shell.clear_history();
+                    return Ok(0);
+                }
+                "-d" => {
+                    let position = option_argument("history", arg, args.next())?;
+                    return delete_history_entry(shell, position);
+                }
+                "-s" => pattern = Some(option_argument("history", arg, args.next())?),
+                "-t" => {
+                    time_format.get_or_insert_with(|| DEFAULT_HISTORY_TIME_FORMAT.to_string());
+                }
+                _ if arg.starts_with('-') && arg.len() > 1 => {
+                    return Err(usage_error("history", format!("{}: invalid option", arg)));
+                }
+                _ if count.is_some() => {
+                    return Err(usage_error("history", "too many arguments".to_string()));
+                }
+                _ => {
+                    count = Some(arg.parse::<usize>().map_err(|_| {
+                        usage_error("history", format!("{}: numeric argument required", arg))
+                    })?)
+                }
+            }
+        }
+
+        let entries = shell.history();
+        let skip = count.map_or(0, |count| entries.len().saturating_sub(count));
+        let listed = entries.iter().enumerate().skip(skip).filter(|(_, entry)| {
+            pattern.is_none_or(|pattern| entry.command.contains(pattern.as_str()))
+        });
+        for (index, entry) in listed {
+            let time = match (&time_format, entry.time) {
+                (Some(format), Some(time)) => history::format_time(time, format),
+                _ => String::new(),
+            };
+            writeln!(stdout, "{:5}  {}{}", index + 1, time, entry.command)?;
+        }
+        Ok(0)
     }
 }
 
-/// Search for an executable in PATH
-fn find_executable(cmd: &str) -> Option<PathBuf> {
-    env::var_os("PATH").and_then(|paths| {
-        env::split_paths(&paths).find_map(|dir| {
-            let full_path = dir.join(cmd);
-            full_path.exists().then_some(full_path)
-        })
-    })
+/// `history -d`: delete the entry at `position`, 1 for the oldest or -1 for
+/// the newest
+fn delete_history_entry(shell: &mut Shell, position: &str) -> Result<i32, ShellError> {
+    let len = shell.history().len() as i64;
+    let index = match position.parse::<i64>() {
+        Ok(number) if number < 0 => len + number,
+        Ok(number) => number - 1,
+        Err(_) => {
+            return Err(usage_error(
+                "history",
+                format!("{}: numeric argument required", position),
+            ))
+        }
+    };
+    if index < 0 || !shell.delete_history_entry(index as usize) {
+        return Err(ShellError::BuiltinError {
+            name: "history".to_string(),
+            status: 1,
+            message: format!("{}: history position out of range", position),
+        });
+    }
+    Ok(0)
+}
+
+/// Run a command repeatedly and report wall-clock timing statistics
+///
+/// Usage: `bench [-n RUNS] command [args...]`. Output of builtins run under
+/// `bench` is discarded so only the timing summary is printed. A run that
+/// fails stops the benchmark, which exits with that run's status and no
+/// summary.
+struct BenchCommand;
+
+/// Number of runs when `-n` is not given
+const DEFAULT_BENCH_RUNS: usize = 10;
+
+impl BuiltinCommand for BenchCommand {
+    fn name(&self) -> &'static str {
+        "bench"
+    }
+
+    fn execute(
+        &self,
+        args: &[String],
+        shell: &mut Shell,
+        stdout: &mut dyn Write,
+        _stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError> {
+        let (runs, command) = match args {
+            [flag, count, rest @ ..] if flag == "-n" => {
+                let runs = count
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or_else(|| usage_error("bench", format!("{}: invalid run count", count)))?;
+                (runs, rest)
+            }
+            _ => (DEFAULT_BENCH_RUNS, args),
+        };
+
+        if command.is_empty() {
+            return Err(usage_error(
+                "bench",
+                "usage: bench [-n RUNS] command [args...]".to_string(),
+            ));
+        }
+
+        let mut samples = Vec::with_capacity(runs);
+        for _ in 0..runs {
+            let cmd = CommandParts {
+                words: command.iter().map(Word::quoted).collect(),
+                ..Default::default()
+            };
+            let start = Instant::now();
+            shell.execute_command(cmd)?;
+            samples.push(start.elapsed());
+            if shell.last_status() != 0 {
+                return Err(ShellError::BuiltinError {
+                    name: "bench".to_string(),
+                    status: shell.last_status(),
+                    message: format!(
+                        "`{}` failed with status {} on run {} of {}",
+                        command.join(" "),
+                        shell.last_status(),
+                        samples.len(),
+                        runs
+                    ),
+                });
+            }
+        }
+
+        writeln!(
+            stdout,
+            "{}",
+            format_bench_report(&command.join(" "), &samples)
+        )?;
+        Ok(0)
+    }
+}
+
+/// Summarize timing samples as min/mean/max/stddev
+fn format_bench_report(command: &str, samples: &[Duration]) -> String {
+    let secs: Vec<f64> = samples.iter().map(Duration::as_secs_f64).collect();
+    let n = secs.len() as f64;
+    let mean = secs.iter().sum::<f64>() / n;
+    let min = secs.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = secs.iter().copied().fold(0.0, f64::max);
+    let variance = secs.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / n;
+
+    format!(
+        "bench: {} runs of `{}`\n  min     {}\n  mean    {}\n  max     {}\n  stddev  {}",
+        secs.len(),
+        command,
+        format_seconds(min),
+        format_seconds(mean),
+        format_seconds(max),
+        format_seconds(variance.sqrt()),
+    )
+}
+
+/// Format a duration in seconds using the most readable unit
+fn format_seconds(secs: f64) -> String {
+    if secs >= 1.0 {
+        format!("{:.3} s", secs)
+    } else if secs >= 1e-3 {
+        format!("{:.3} ms", secs * 1e3)
+    } else {
+        format!("{:.1} µs", secs * 1e6)
+    }
+}
+
+/// Inspect the PATH variable
+///
+/// Usage: `path` lists PATH entries one per line; `path doctor [--fix]`
+/// reports missing entries, non-directories, duplicates and executables
+/// shadowed by an earlier entry, and with `--fix` prints an `export` line
+/// for a cleaned-up PATH.
+struct PathCommand;
+
+impl BuiltinCommand for PathCommand {
+    fn name(&self) -> &'static str {
+        "path"
+    }
+
+    fn execute(
+        &self,
+        args: &[String],
+        _shell: &mut Shell,
+        stdout: &mut dyn Write,
+        _stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError> {
+        let entries: Vec<PathBuf> = env::var_os("PATH")
+            .map(|paths| env::split_paths(&paths).collect())
+            .unwrap_or_default();
+
+        match args.first().map(String::as_str) {
+            None => {
+                for dir in &entries {
+                    writeln!(stdout, "{}", dir.display())?;
+                }
+            }
+            Some("doctor") => match args.get(1).map(String::as_str) {
+                None => writeln!(stdout, "{}", diagnose_path(&entries, false))?,
+                Some("--fix") => writeln!(stdout, "{}", diagnose_path(&entries, true))?,
+                Some(other) => {
+                    return Err(usage_error(
+                        "path",
+                        format!("doctor: {}: invalid option", other),
+                    ))
+                }
+            },
+            Some(other) => {
+                return Err(usage_error(
+                    "path",
+                    format!(
+                        "{}: unknown subcommand (usage: path [doctor [--fix]])",
+                        other
+                    ),
+                ))
+            }
+        }
+        Ok(0)
+    }
+}
+
+/// Produce the `path doctor` report for the given PATH entries
+fn diagnose_path(entries: &[PathBuf], fix: bool) -> String {
+    let mut problems = Vec::new();
+    let mut kept: Vec<&PathBuf> = Vec::new();
+    // Canonical directory -> 1-based index of the first entry resolving to it
+    let mut seen: HashMap<PathBuf, usize> = HashMap::new();
+    // Executable name -> first directory providing it
+    let mut providers: HashMap<String, &PathBuf> = HashMap::new();
+
+    for (i, dir) in entries.iter().enumerate() {
+        let position = i + 1;
+        if dir.as_os_str().is_empty() {
+            problems.push(format!(
+                "empty:      entry {} (searches the current directory)",
+                position
+            ));
+            continue;
+        }
+        let Ok(metadata) = std::fs::metadata(dir) else {
+            problems.push(format!(
+                "missing:    {} (entry {})",
+                dir.display(),
+                position
+            ));
+            continue;
+        };
+        if !metadata.is_dir() {
+            problems.push(format!(
+                "not a dir:  {} (entry {})",
+                dir.display(),
+                position
+            ));
+            continue;
+        }
+
+        let canonical = dir.canonicalize().unwrap_or_else(|_| dir.clone());
+        if let Some(first) = seen.get(&canonical) {
+            problems.push(format!(
+                "duplicate:  {} (entry {}, same directory as entry {})",
+                dir.display(),
+                position,
+                first
+            ));
+            continue;
+        }
+        seen.insert(canonical, position);
+        kept.push(dir);
+
+        let Ok(read_dir) = std::fs::read_dir(dir) else {
+            continue;
+        };
+        let mut names: Vec<(String, PathBuf)> = read_dir
+            .filter_map(Result::ok)
+            .filter(|entry| is_executable(&entry.path()))
+            .filter_map(|entry| Some((entry.file_name().into_string().ok()?, entry.path())))
+            .collect();
+        names.sort();
+        for (name, full_path) in names {
+            match providers.get(&name) {
+                Some(earlier) => problems.push(format!(
+                    "shadowed:   {} shadows {}",
+                    earlier.join(&name).display(),
+                    full_path.display()
+                )),
+                None => {
+                    providers.insert(name, dir);
+                }
+            }
+        }
+    }
+
+    let mut report = if problems.is_empty() {
+        format!("PATH has {} entries, no problems found", entries.len())
+    } else {
+        format!(
+            "PATH has {} entries, {} problem(s) found:\n{}",
+            entries.len(),
+            problems.len(),
+            problems.join("\n")
+        )
+    };
+
+    if fix {
+        let cleaned: Vec<String> = kept.iter().map(|dir| dir.display().to_string()).collect();
+        report.push_str(&format!("\nexport PATH='{}'", cleaned.join(":")));
+    }
+    report
+}
+
+/// Rebuild the command caches immediately
+///
+/// Useful right after installing a tool, instead of waiting for the caches
+/// to notice the change on their own. Also empties the command hash, as
+/// `hash -r` does.
+struct RehashCommand;
+
+impl BuiltinCommand for RehashCommand {
+    fn name(&self) -> &'static str {
+        "rehash"
+    }
+
+    fn execute(
+        &self,
+        _args: &[String],
+        shell: &mut Shell,
+        _stdout: &mut dyn Write,
+        _stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError> {
+        shell.rehash();
+        Ok(0)
+    }
+}
+
+/// Show or change where commands run from PATH were found
+///
+/// Usage: `hash [-r] [-d | -t] [NAME...]`. With no NAME, lists each command
+/// in the hash with how many times it has run from there. `-r` empties the
+/// hash, `-d` forgets each NAME and `-t` prints where each one was found;
+/// otherwise each NAME is searched for in PATH and remembered.
+struct HashCommand;
+
+impl BuiltinCommand for HashCommand {
+    fn name(&self) -> &'static str {
+        "hash"
+    }
+
+    fn completion(&self) -> Option<CompletionSpec> {
+        Some(CompletionSpec::new(CompletionAction::Commands))
+    }
+
+    fn execute(
+        &self,
+        args: &[String],
+        shell: &mut Shell,
+        stdout: &mut dyn Write,
+        stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError> {
+        let (options, names) = lookup_options("hash", args, "rdt")?;
+        let hashes = Arc::clone(shell.command_hash());
+        let mut hash = hashes.write().unwrap();
+        if options.contains(&'r') {
+            hash.clear();
+        }
+        if names.is_empty() {
+            if options.contains(&'d') || options.contains(&'t') {
+                return Err(usage_error("hash", "a name is needed".to_string()));
+            }
+            if !options.contains(&'r') {
+                let entries = hash.entries();
+                if entries.is_empty() {
+                    writeln!(stdout, "hash: hash table empty")?;
+                } else {
+                    writeln!(stdout, "hits\tcommand")?;
+                    for (_, entry) in entries {
+                        writeln!(stdout, "{:4}\t{}", entry.hits, entry.path.display())?;
+                    }
+                }
+            }
+            return Ok(0);
+        }
+
+        let mut status = 0;
+        for name in names {
+            let found = if options.contains(&'d') {
+                hash.remove(name)
+            } else if options.contains(&'t') {
+                let path = hash.get(name);
+                match (path, names.len()) {
+                    (Some(path), 1) => writeln!(stdout, "{}", path.display())?,
+                    (Some(path), _) => writeln!(stdout, "{}\t{}", name, path.display())?,
+                    (None, _) => {}
+                }
+                path.is_some()
+            } else if shell.is_builtin(name) || shell.is_function(name) {
+                true
+            } else {
+                hash.remember(name).is_some()
+            };
+            if !found {
+                writeln!(stderr, "hash: {}: not found", name)?;
+                status = 1;
+            }
+        }
+        Ok(status)
+    }
+}
+
+/// List background jobs
+///
+/// Usage: `jobs [-o]`. With `-o`, also print (and clear) the output each job
+/// has produced since it was last shown.
+struct JobsCommand;
+
+impl BuiltinCommand for JobsCommand {
+    fn name(&self) -> &'static str {
+        "jobs"
+    }
+
+    fn execute(
+        &self,
+        args: &[String],
+        shell: &mut Shell,
+        stdout: &mut dyn Write,
+        _stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError> {
+        let show_output = match args.first().map(String::as_str) {
+            None => false,
+            Some("-o") => true,
+            Some(other) => return Err(usage_error("jobs", format!("{}: invalid option", other))),
+        };
+
+        let mut jobs = shell.jobs().write().unwrap();
+        jobs.poll();
+        for job in jobs.jobs() {
+            writeln!(
+                stdout,
+                "[{}]  {}  {}  {}",
+                job.id, job.pid, job.state, job.command
+            )?;
+            if show_output {
+                // Job output is passed through byte for byte, whatever its encoding
+                let output = job.output.take();
+                stdout.write_all(&output)?;
+                if !output.is_empty() && !output.ends_with(b"\n") {
+                    writeln!(stdout)?;
+                }
+            }
+        }
+        Ok(0)
+    }
+}
+
+/// Error for a builtin called with arguments it doesn't accept (status 2)
+fn usage_error(builtin: &str, message: String) -> ShellError {
+    ShellError::BuiltinError {
+        name: builtin.to_string(),
+        status: 2,
+        message,
+    }
+}
+
+/// The argument given after `option`, or a usage error if it's missing
+fn option_argument<'a>(
+    builtin: &str,
+    option: &str,
+    argument: Option<&'a String>,
+) -> Result<&'a String, ShellError> {
+    argument.ok_or_else(|| usage_error(builtin, format!("{}: option requires an argument", option)))
+}
+
+/// Error for a job spec that doesn't name a job
+fn no_such_job(builtin: &str, spec: Option<&String>) -> ShellError {
+    let spec = spec.map_or("current", String::as_str);
+    ShellError::JobError(format!("{}: {}: no such job", builtin, spec))
+}
+
+/// Resume a job in the foreground and wait for it to finish or stop
+struct FgCommand;
+
+impl BuiltinCommand for FgCommand {
+    fn name(&self) -> &'static str {
+        "fg"
+    }
+
+    fn completion(&self) -> Option<CompletionSpec> {
+        Some(CompletionSpec::new(CompletionAction::Jobs))
+    }
+
+    fn execute(
+        &self,
+        args: &[String],
+        shell: &mut Shell,
+        stdout: &mut dyn Write,
+        _stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError> {
+        let job_control = shell.is_interactive();
+        let mut jobs = shell.jobs().write().unwrap();
+        let job = jobs
+            .resolve(args.first().map(String::as_str))
+            .and_then(|id| jobs.get(id))
+            .ok_or_else(|| no_such_job("fg", args.first()))?;
+        let (id, command) = (job.id, job.command.clone());
+
+        writeln!(stdout, "{}", command)?;
+        if job_control {
+            signals::give_terminal_to(job.pgid);
+        }
+        let resumed = match job.state {
+            JobState::Done(_) => Ok(()),
+            _ => job.signal(libc::SIGCONT),
+        };
+        let state = resumed.as_ref().ok().and_then(|()| jobs.wait(id));
+        if job_control {
+            signals::reclaim_terminal();
+        }
+        resumed?;
+        match state {
+            Some(JobState::Stopped) => {
+                writeln!(stdout, "\n[{}]+  Stopped  {}", id, command)?;
+                Ok(128 + libc::SIGTSTP)
+            }
+            _ => {
+                let job = jobs.remove(id);
+                if let Some(job) = &job {
+                    job.output.drain();
+                    stdout.write_all(&job.output.take())?;
+                }
+                Ok(match job.map(|job| job.state) {
+                    Some(JobState::Done(status)) => status,
+                    _ => 0,
+                })
+            }
+        }
+    }
 }
 
-/// List of all built-in command names
-const BUILTIN_COMMANDS: &[&str] = &["cd", "echo", "pwd", "exit", "type", "history"];
+/// Remove jobs from the job table
+///
+/// Usage: `disown [-h] [-ar] [%JOB...]`. Disowned jobs keep running but are
+/// no longer listed, waited for or sent SIGHUP when the shell exits with
+/// `huponexit` set. `-h` keeps them in the table and only spares them that
+/// SIGHUP. Without job specs, disowns the current job, or with `-a` every
+/// job and with `-r` every running one.
+struct DisownCommand;
+
+impl BuiltinCommand for DisownCommand {
+    fn name(&self) -> &'static str {
+        "disown"
+    }
+
+    fn completion(&self) -> Option<CompletionSpec> {
+        Some(CompletionSpec::new(CompletionAction::Jobs))
+    }
+
+    fn execute(
+        &self,
+        args: &[String],
+        shell: &mut Shell,
+        _stdout: &mut dyn Write,
+        _stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError> {
+        let (mut keep, mut all, mut running) = (false, false, false);
+        let mut specs = args;
+        while let Some(flags) = specs.first().and_then(|arg| arg.strip_prefix('-')) {
+            for flag in flags.chars() {
+                match flag {
+                    'h' => keep = true,
+                    'a' => all = true,
+                    'r' => running = true,
+                    _ => return Err(usage_error("disown", format!("-{}: invalid option", flag))),
+                }
+            }
+            specs = &specs[1..];
+        }
+
+        let mut jobs = shell.jobs().write().unwrap();
+        let ids: Vec<usize> = if specs.is_empty() && (all || running) {
+            jobs.jobs()
+                .iter()
+                .filter(|job| !running || job.state == JobState::Running)
+                .map(|job| job.id)
+                .collect()
+        } else if specs.is_empty() {
+            vec![jobs
+                .resolve(None)
+                .ok_or_else(|| no_such_job("disown", None))?]
+        } else {
+            specs
+                .iter()
+                .map(|spec| {
+                    jobs.resolve(Some(spec))
+                        .ok_or_else(|| no_such_job("disown", Some(spec)))
+                })
+                .collect::<Result<_, _>>()?
+        };
+        for id in ids {
+            if keep {
+                if let Some(job) = jobs.get_mut(id) {
+                    job.no_hangup = true;
+                }
+            } else {
+                jobs.disown(id);
+            }
+        }
+        Ok(0)
+    }
+}
+
+/// Wait for background jobs to finish
+///
+/// Usage: `wait [%JOB | PID...]`. Without arguments, waits for every job
+/// and returns 0. Otherwise returns the status of the last job or process
+/// waited for, or 127 if it isn't one the shell started. Output the jobs
+/// collected is passed on once they finish.
+struct WaitCommand;
+
+impl BuiltinCommand for WaitCommand {
+    fn name(&self) -> &'static str {
+        "wait"
+    }
+
+    fn completion(&self) -> Option<CompletionSpec> {
+        Some(CompletionSpec::new(CompletionAction::Jobs))
+    }
+
+    fn execute(
+        &self,
+        args: &[String],
+        shell: &mut Shell,
+        stdout: &mut dyn Write,
+        stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError> {
+        let mut jobs = shell.jobs().write().unwrap();
+        if args.is_empty() {
+            let ids: Vec<usize> = jobs.jobs().iter().map(|job| job.id).collect();
+            for id in ids {
+                wait_for_job(&mut jobs, id, None, stdout)?;
+            }
+            return Ok(0);
+        }
+
+        let mut status = 0;
+        for arg in args {
+            if arg.starts_with('%') {
+                status = match jobs.resolve(Some(arg)) {
+                    Some(id) => wait_for_job(&mut jobs, id, None, stdout)?,
+                    None => {
+                        writeln!(stderr, "wait: {}: no such job", arg)?;
+                        127
+                    }
+                };
+                continue;
+            }
+            let Ok(pid) = arg.parse::<u32>() else {
+                return Err(ShellError::BuiltinError {
+                    name: "wait".to_string(),
+                    status: 2,
+                    message: format!("`{}': not a pid or valid job spec", arg),
+                });
+            };
+            let process = jobs.jobs().iter().find_map(|job| {
+                let index = job.pids.iter().position(|&process| process == pid)?;
+                Some((job.id, index))
+            });
+            status = match process {
+                Some((id, index)) => wait_for_job(&mut jobs, id, Some(index), stdout)?,
+                None => match jobs.take_reaped(pid) {
+                    Some(status) => status,
+                    None => {
+                        writeln!(stderr, "wait: pid {} is not a child of this shell", pid)?;
+                        127
+                    }
+                },
+            };
+        }
+        Ok(status)
+    }
+}
+
+/// Wait for job `id` to finish or stop, writing out the output it collected,
+/// and return the status of its process at `index` (by default its last)
+fn wait_for_job(
+    jobs: &mut JobTable,
+    id: usize,
+    index: Option<usize>,
+    stdout: &mut dyn Write,
+) -> Result<i32, ShellError> {
+    if jobs.wait(id) == Some(JobState::Stopped) {
+        return Ok(128 + libc::SIGTSTP);
+    }
+    let Some(job) = jobs.remove(id) else {
+        return Ok(0);
+    };
+    job.output.drain();
+    stdout.write_all(&job.output.take())?;
+    let statuses = job.statuses();
+    let index = index.unwrap_or(statuses.len().saturating_sub(1));
+    Ok(statuses.get(index).copied().unwrap_or(0))
+}
+
+/// Resume a stopped job in the background
+struct BgCommand;
+
+impl BuiltinCommand for BgCommand {
+    fn name(&self) -> &'static str {
+        "bg"
+    }
+
+    fn completion(&self) -> Option<CompletionSpec> {
+        Some(CompletionSpec::new(CompletionAction::Jobs))
+    }
+
+    fn execute(
+        &self,
+        args: &[String],
+        shell: &mut Shell,
+        stdout: &mut dyn Write,
+        stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError> {
+        let mut jobs = shell.jobs().write().unwrap();
+        let job = jobs
+            .resolve(args.first().map(String::as_str))
+            .and_then(|id| jobs.get_mut(id))
+            .ok_or_else(|| no_such_job("bg", args.first()))?;
+
+        match job.state {
+            JobState::Running => {
+                writeln!(stderr, "bg: job {} already in background", job.id)?;
+                return Ok(0);
+            }
+            JobState::Done(_) => {
+                return Err(ShellError::JobError(format!(
+                    "bg: job {} has terminated",
+                    job.id
+                )))
+            }
+            JobState::Stopped => job.signal(libc::SIGCONT)?,
+        }
+        job.state = JobState::Running;
+        writeln!(stdout, "[{}]  {} &", job.id, job.command)?;
+        Ok(0)
+    }
+}
+
+/// Show or change resource limits of the shell and the commands it runs
+///
+/// Usage: `ulimit [-HS] [-a | -cdefilmnqrRstuvx...] [LIMIT]`. Shows the
+/// limit each option selects (the file size, `-f`, without one), all of
+/// them with `-a`. LIMIT is a number of the limit's units, `unlimited`, or
+/// `soft` or `hard` for the current soft or hard limit; it sets both limits
+/// unless `-S` or `-H` picks one. The soft limit is shown unless `-H` is given.
+struct UlimitCommand;
+
+impl BuiltinCommand for UlimitCommand {
+    fn name(&self) -> &'static str {
+        "ulimit"
+    }
+
+    fn execute(
+        &self,
+        args: &[String],
+        _shell: &mut Shell,
+        stdout: &mut dyn Write,
+        _stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError> {
+        let (mut soft, mut hard, mut all) = (false, false, false);
+        let mut selected = Vec::new();
+        let mut value = None;
+        for arg in args {
+            match arg.strip_prefix('-').filter(|flags| !flags.is_empty()) {
+                Some(flags) if value.is_none() => {
+                    for flag in flags.chars() {
+                        match flag {
+                            'S' => soft = true,
+                            'H' => hard = true,
+                            'a' => all = true,
+                            _ => selected.push(limits::find(flag).ok_or_else(|| {
+                                usage_error("ulimit", format!("-{}: invalid option", flag))
+                            })?),
+                        }
+                    }
+                }
+                _ if value.is_none() => value = Some(arg),
+                _ => return Err(usage_error("ulimit", "too many arguments".to_string())),
+            }
+        }
+        if all {
+            selected = limits::LIMITS.iter().collect();
+        } else if selected.is_empty() {
+            selected.extend(limits::find('f'));
+        }
+        let limit_error =
+            |limit: &limits::Limit, action: &str, e: io::Error| ShellError::BuiltinError {
+                name: "ulimit".to_string(),
+                status: 1,
+                message: format!(
+                    "{}: cannot {} limit: {}",
+                    limit.description,
+                    action,
+                    describe_io_error(&e)
+                ),
+            };
+
+        let Some(value) = value.filter(|_| !all) else {
+            let long = selected.len() > 1;
+            for limit in selected {
+                let (current_soft, current_hard) =
+                    limit.get().map_err(|e| limit_error(limit, "get", e))?;
+                let current = if hard && !soft {
+                    current_hard
+                } else {
+                    current_soft
+                };
+                let shown = current.map_or("unlimited".to_string(), |units| units.to_string());
+                if long {
+                    let unit = match limit.unit {
+                        Some(unit) => format!("({}, -{}) ", unit, limit.option),
+                        None => format!("(-{}) ", limit.option),
+                    };
+                    writeln!(stdout, "{:<20} {:>20}{}", limit.description, unit, shown)?;
+                } else {
+                    writeln!(stdout, "{}", shown)?;
+                }
+            }
+            return Ok(0);
+        };
+
+        for limit in selected {
+            let (current_soft, current_hard) =
+                limit.get().map_err(|e| limit_error(limit, "get", e))?;
+            let units = match value.as_str() {
+                "unlimited" => None,
+                "soft" => current_soft,
+                "hard" => current_hard,
+                number => Some(number.parse().map_err(|_| ShellError::BuiltinError {
+                    name: "ulimit".to_string(),
+                    status: 1,
+                    message: format!("{}: invalid number", number),
+                })?),
+            };
+            // Without -S or -H, both limits change
+            let (set_soft, set_hard) = if soft || hard {
+                (soft, hard)
+            } else {
+                (true, true)
+            };
+            limit
+                .set(units, set_soft, set_hard)
+                .map_err(|e| limit_error(limit, "modify", e))?;
+        }
+        Ok(0)
+    }
+}
+
+/// Send a signal to jobs or processes
+///
+/// Usage: `kill [-s SIGNAL | -n NUMBER | -SIGNAL] %JOB|PID...` sends SIGTERM,
+/// or the signal given by name or number, to each job's process group or
+/// to each process. A stopped job sent SIGTERM or SIGHUP is also continued,
+/// so it can act on the signal. `kill -l` lists the signal names, and
+/// `kill -l SIGNAL...` translates between names, numbers and exit statuses.
+struct KillCommand;
+
+impl KillCommand {
+    /// Print every signal name, or translate each of `specs` between a name
+    /// and a number; a number above 128 is taken as an exit status
+    fn list(specs: &[String], stdout: &mut dyn Write) -> Result<i32, ShellError> {
+        if specs.is_empty() {
+            let names: Vec<&str> = signals::SIGNAL_NAMES
+                .iter()
+                .map(|&(name, _)| name)
+                .collect();
+            writeln!(stdout, "{}", names.join(" "))?;
+            return Ok(0);
+        }
+        for spec in specs {
+            let translated = match spec.parse::<libc::c_int>() {
+                Ok(number) => {
+                    let signal = if number > 128 { number - 128 } else { number };
+                    signals::signal_name(signal).map(str::to_string)
+                }
+                Err(_) => signals::signal_number(spec).map(|number| number.to_string()),
+            };
+            let translated = translated.ok_or_else(|| invalid_signal(spec))?;
+            writeln!(stdout, "{}", translated)?;
+        }
+        Ok(0)
+    }
+}
+
+/// Error for a signal name or number `kill` doesn't know
+fn invalid_signal(spec: &str) -> ShellError {
+    ShellError::BuiltinError {
+        name: "kill".to_string(),
+        status: 1,
+        message: format!("{}: invalid signal specification", spec),
+    }
+}
+
+impl BuiltinCommand for KillCommand {
+    fn name(&self) -> &'static str {
+        "kill"
+    }
+
+    fn completion(&self) -> Option<CompletionSpec> {
+        Some(CompletionSpec::new(CompletionAction::Jobs))
+    }
+
+    fn execute(
+        &self,
+        args: &[String],
+        shell: &mut Shell,
+        stdout: &mut dyn Write,
+        stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError> {
+        let mut signal = libc::SIGTERM;
+        let mut targets = args;
+        match args.first().map(String::as_str) {
+            Some("-l" | "-L") => return Self::list(&args[1..], stdout),
+            Some(option @ ("-s" | "-n")) => {
+                let spec = option_argument("kill", option, args.get(1))?;
+                signal = signals::signal_number(spec).ok_or_else(|| invalid_signal(spec))?;
+                targets = &args[2..];
+            }
+            Some("--") => targets = &args[1..],
+            Some(option) if option.len() > 1 && option.starts_with('-') => {
+                let spec = &option[1..];
+                signal = signals::signal_number(spec).ok_or_else(|| invalid_signal(spec))?;
+                targets = &args[1..];
+            }
+            _ => {}
+        }
+        if targets.first().map(String::as_str) == Some("--") {
+            targets = &targets[1..];
+        }
+        if targets.is_empty() {
+            return Err(usage_error(
+                "kill",
+                "usage: kill [-s SIGNAL | -n NUMBER | -SIGNAL] %JOB|PID... or kill -l [SIGNAL]"
+                    .to_string(),
+            ));
+        }
+
+        let jobs = shell.jobs().read().unwrap();
+        let mut status = 0;
+        for target in targets {
+            let sent = if target.starts_with('%') {
+                let Some(job) = jobs.resolve(Some(target)).and_then(|id| jobs.get(id)) else {
+                    writeln!(stderr, "kill: {}: no such job", target)?;
+                    status = 1;
+                    continue;
+                };
+                let resume = job.state == JobState::Stopped
+                    && [libc::SIGTERM, libc::SIGHUP].contains(&signal);
+                job.signal(signal).and_then(|()| {
+                    if resume {
+                        job.signal(libc::SIGCONT)
+                    } else {
+                        Ok(())
+                    }
+                })
+            } else if let Ok(pid) = target.parse::<libc::pid_t>() {
+                // SAFETY: kill has no memory-safety preconditions
+                if unsafe { libc::kill(pid, signal) } == 0 {
+                    Ok(())
+                } else {
+                    Err(io::Error::last_os_error())
+                }
+            } else {
+                writeln!(
+                    stderr,
+                    "kill: {}: arguments must be process or job IDs",
+                    target
+                )?;
+                status = 1;
+                continue;
+            };
+            if let Err(err) = sent {
+                writeln!(stderr, "kill: ({}) - {}", target, describe_io_error(&err))?;
+                status = 1;
+            }
+        }
+        Ok(status)
+    }
+}
+
+/// Read a line from stdin into variables
+///
+/// Usage: `read [-rs] [-n COUNT] [-p PROMPT] [NAME...]` splits the line on
+/// the characters of `$IFS` (blanks by default) and assigns the fields to
+/// the NAMEs in turn, the last one taking the rest of the line; with no
+/// NAME, the whole line goes into `REPLY`. A backslash keeps the next
+/// character from splitting the line, or joins the line to the next one,
+/// unless `-r` is given. `-n` stops after COUNT characters, `-p` shows
+/// PROMPT on stderr when stdin is a terminal, and `-s` doesn't echo what's
+/// typed. The status is 1 if the input ended first.
+struct ReadCommand;
+
+/// Characters read by `read`, with which ones a backslash escaped
+#[derive(Default)]
+struct ReadInput {
+    bytes: Vec<u8>,
+    escaped: Vec<bool>,
+    /// Whether the line ended, or the count was reached, before the input did
+    complete: bool,
+}
+
+impl ReadInput {
+    /// Read a line from the shell's stdin, or `count` characters of it
+    ///
+    /// Bytes are read one at a time, so nothing after the line is taken
+    /// from a pipe or file that the next command reads.
+    fn read(count: Option<usize>, raw: bool) -> io::Result<Self> {
+        let terminal = io::stdin().is_terminal();
+        let mut input = ReadInput::default();
+        let mut chars = 0;
+        while count.is_none_or(|count| chars < count) {
+            let Some(mut byte) = read_byte(terminal)? else {
+                return Ok(input);
+            };
+            let mut escaped = false;
+            if byte == b'\\' && !raw {
+                match read_byte(terminal)? {
+                    None => return Ok(input),
+                    Some(b'\n') => continue,
+                    Some(next) => (byte, escaped) = (next, true),
+                }
+            } else if byte == b'\n' {
+                break;
+            }
+            input.bytes.push(byte);
+            input.escaped.push(escaped);
+            // The rest of a character started at the count is read with it
+            if byte & 0xC0 != 0x80 {
+                chars += 1;
+                let length = byte.leading_ones().max(1) as usize;
+                for _ in 1..length.min(4) {
+                    let Some(next) = read_byte(terminal)? else {
+                        return Ok(input);
+                    };
+                    input.bytes.push(next);
+                    input.escaped.push(escaped);
+                }
+            }
+        }
+        input.complete = true;
+        Ok(input)
+    }
+
+    /// The text read, without the backslashes that escaped it
+    fn text(&self) -> String {
+        String::from_utf8_lossy(&self.bytes).into_owned()
+    }
+
+    /// Split the text into at most `count` fields on the characters of
+    /// `ifs` that weren't escaped, the last field taking the rest
+    ///
+    /// Runs of blanks in `ifs` count as one separator and are trimmed from
+    /// both ends; any other character in `ifs` ends a field by itself.
+    fn fields(&self, ifs: &str, count: usize) -> Vec<String> {
+        let separator =
+            |index: usize| !self.escaped[index] && ifs.as_bytes().contains(&self.bytes[index]);
+        let blank = |index: usize| separator(index) && self.bytes[index].is_ascii_whitespace();
+        let text = |range: std::ops::Range<usize>| {
+            String::from_utf8_lossy(&self.bytes[range]).into_owned()
+        };
+
+        let mut end = self.bytes.len();
+        while end > 0 && blank(end - 1) {
+            end -= 1;
+        }
+        let mut start = 0;
+        while start < end && blank(start) {
+            start += 1;
+        }
+        let mut fields = Vec::new();
+        while start < end {
+            if fields.len() + 1 == count {
+                fields.push(text(start..end));
+                break;
+            }
+            let mut stop = start;
+            while stop < end && !separator(stop) {
+                stop += 1;
+            }
+            fields.push(text(start..stop));
+            start = stop;
+            while start < end && blank(start) {
+                start += 1;
+            }
+            if start < end && separator(start) {
+                start += 1;
+                while start < end && blank(start) {
+                    start += 1;
+                }
+            }
+        }
+        fields
+    }
+}
+
+/// One byte of the shell's stdin, or `None` at end of input
+///
+/// Reading from a `terminal` gives up if Ctrl+C is pressed.
+fn read_byte(terminal: bool) -> io::Result<Option<u8>> {
+    let mut byte = 0u8;
+    loop {
+        if terminal {
+            let mut poll = libc::pollfd {
+                fd: libc::STDIN_FILENO,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            // SAFETY: poll is given one valid pollfd; unlike read, it isn't
+            // restarted after the shell's SIGINT handler runs
+            if unsafe { libc::poll(&mut poll, 1, -1) } == -1 && signals::interrupted() {
+                return Err(io::ErrorKind::Interrupted.into());
+            }
+        }
+        // SAFETY: reads at most one byte into `byte`
+        match unsafe { libc::read(libc::STDIN_FILENO, (&mut byte as *mut u8).cast(), 1) } {
+            1 => return Ok(Some(byte)),
+            0 => return Ok(None),
+            _ => {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            }
+        }
+    }
+}
+
+impl BuiltinCommand for ReadCommand {
+    fn name(&self) -> &'static str {
+        "read"
+    }
+
+    fn completion(&self) -> Option<CompletionSpec> {
+        Some(CompletionSpec::new(CompletionAction::Variables))
+    }
+
+    fn execute(
+        &self,
+        args: &[String],
+        shell: &mut Shell,
+        _stdout: &mut dyn Write,
+        stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError> {
+        let (mut raw, mut silent, mut count, mut prompt) = (false, false, None, None);
+        let mut names = args;
+        while let Some((arg, rest)) = names.split_first() {
+            let Some(flags) = arg.strip_prefix('-').filter(|flags| !flags.is_empty()) else {
+                break;
+            };
+            names = rest;
+            if flags == "-" {
+                break;
+            }
+            for (index, flag) in flags.char_indices() {
+                match flag {
+                    'r' => raw = true,
+                    's' => silent = true,
+                    'n' | 'p' => {
+                        // The value is the rest of this argument or the next one
+                        let attached = &flags[index + 1..];
+                        let value = if attached.is_empty() {
+                            let option = format!("-{}", flag);
+                            let value = option_argument("read", &option, names.first())?;
+                            names = &names[1..];
+                            value.as_str()
+                        } else {
+                            attached
+                        };
+                        if flag == 'p' {
+                            prompt = Some(value.to_string());
+                        } else {
+                            let number = value.parse().map_err(|_| {
+                                usage_error("read", format!("{}: invalid number", value))
+                            })?;
+                            count = Some(number);
+                        }
+                        break;
+                    }
+                    _ => return Err(usage_error("read", format!("-{}: invalid option", flag))),
+                }
+            }
+        }
+        if let Some(name) = names.iter().find(|name| !is_name(name)) {
+            return Err(ShellError::BuiltinError {
+                name: "read".to_string(),
+                status: 1,
+                message: format!("`{}': not a valid identifier", name),
+            });
+        }
+
+        if let Some(prompt) = prompt.filter(|_| io::stdin().is_terminal()) {
+            write!(stderr, "{}", prompt)?;
+            stderr.flush()?;
+        }
+        let input = {
+            let _mode = (silent || count.is_some())
+                .then(|| signals::TerminalMode::set(!silent, count.is_none()))
+                .flatten();
+            match ReadInput::read(count, raw) {
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {
+                    return Ok(128 + libc::SIGINT)
+                }
+                result => result?,
+            }
+        };
+
+        if names.is_empty() {
+            shell.set_variable("REPLY", &input.text())?;
+        } else {
+            let ifs = shell.variable("IFS").unwrap_or_else(|| " \t\n".to_string());
+            let mut fields = input.fields(&ifs, names.len()).into_iter();
+            for name in names {
+                shell.set_variable(name, &fields.next().unwrap_or_default())?;
+            }
+        }
+        Ok(if input.complete { 0 } else { 1 })
+    }
+}
+
+/// Export variables to the environment of commands the shell runs
+///
+/// Usage: `export NAME[=value]...`. Without arguments, lists every exported
+/// variable in a form that can be read back by the shell.
+struct ExportCommand;
+
+impl BuiltinCommand for ExportCommand {
+    fn name(&self) -> &'static str {
+        "export"
+    }
+
+    fn completion(&self) -> Option<CompletionSpec> {
+        Some(CompletionSpec::new(CompletionAction::Variables))
+    }
+
+    fn execute(
+        &self,
+        args: &[String],
+        shell: &mut Shell,
+        stdout: &mut dyn Write,
+        _stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError> {
+        if args.is_empty() {
+            let mut vars: Vec<(String, String)> = env::vars().collect();
+            vars.sort();
+            for (name, value) in vars {
+                writeln!(stdout, "export {}={}", name, Word::quoted(value))?;
+            }
+            return Ok(0);
+        }
+
+        let mut failure = None;
+        for arg in args {
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (arg.as_str(), None),
+            };
+            if !is_name(name) {
+                failure.get_or_insert(format!("`{}': not a valid identifier", arg));
+            } else if let Err(err) = shell.export_variable(name, value) {
+                failure.get_or_insert(err.to_string());
+            }
+        }
+        match failure {
+            Some(message) => Err(ShellError::BuiltinError {
+                name: "export".to_string(),
+                status: 1,
+                message,
+            }),
+            None => Ok(0),
+        }
+    }
+}
+
+/// Set shell options and positional parameters, or list variables
+///
+/// Usage: `set [-eux] [+eux] [-o NAME] [+o NAME] [--] [ARG...]`. `-` turns
+/// an option on and `+` off, by letter or with `-o` by name (`errexit`,
+/// `nounset`, `xtrace` or `pipefail`); `-o` or `+o` alone lists the options.
+/// Arguments after the options, or after `--`, replace the positional
+/// parameters. Without arguments, lists every variable.
+struct SetCommand;
+
+impl BuiltinCommand for SetCommand {
+    fn name(&self) -> &'static str {
+        "set"
+    }
+
+    fn execute(
+        &self,
+        args: &[String],
+        shell: &mut Shell,
+        stdout: &mut dyn Write,
+        _stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError> {
+        if args.is_empty() {
+            for (name, value) in shell.variables() {
+                writeln!(stdout, "{}={}", name, value)?;
+            }
+            return Ok(0);
+        }
+
+        let mut args = args.iter();
+        while let Some(arg) = args.as_slice().first() {
+            let on = match arg.chars().next() {
+                _ if arg == "--" => {
+                    args.next();
+                    shell.set_positional(args.as_slice().to_vec());
+                    return Ok(0);
+                }
+                Some('-') => true,
+                Some('+') => false,
+                _ => break,
+            };
+            args.next();
+            for letter in arg[1..].chars() {
+                // `o` takes the option's name from the next argument, as
+                // in `set -euo pipefail`
+                let name = match letter {
+                    'o' => match args.next() {
+                        Some(name) => name.clone(),
+                        None => {
+                            list_options(shell, on, stdout)?;
+                            continue;
+                        }
+                    },
+                    _ => letter.to_string(),
+                };
+                let Some(flag) = shell.flags_mut().get_mut(&name) else {
+                    let message = match letter {
+                        'o' => format!("{}: invalid option name", name),
+                        _ => format!("{}{}: invalid option", &arg[..1], letter),
+                    };
+                    return Err(usage_error("set", message));
+                };
+                *flag = on;
+            }
+        }
+        if !args.as_slice().is_empty() {
+            shell.set_positional(args.as_slice().to_vec());
+        }
+        Ok(0)
+    }
+}
+
+/// List the shell options for `set -o`, or as `set` commands that would
+/// restore them for `set +o`
+fn list_options(shell: &Shell, table: bool, stdout: &mut dyn Write) -> io::Result<()> {
+    for (name, _, on) in shell.flags().list() {
+        if table {
+            writeln!(stdout, "{:<15}\t{}", name, if on { "on" } else { "off" })?;
+        } else {
+            writeln!(stdout, "set {}o {}", if on { '-' } else { '+' }, name)?;
+        }
+    }
+    Ok(())
+}
+
+/// Shift the positional parameters left
+///
+/// Usage: `shift [n]`. `$n+1` becomes `$1`, and so on; `n` defaults to 1.
+/// Shifting more parameters than there are changes nothing, with status 1.
+struct ShiftCommand;
+
+impl BuiltinCommand for ShiftCommand {
+    fn name(&self) -> &'static str {
+        "shift"
+    }
+
+    fn execute(
+        &self,
+        args: &[String],
+        shell: &mut Shell,
+        _stdout: &mut dyn Write,
+        _stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError> {
+        let error = |message: String| ShellError::BuiltinError {
+            name: "shift".to_string(),
+            status: 1,
+            message,
+        };
+        let count = match args {
+            [] => 1,
+            [arg] => {
+                let count = arg
+                    .parse::<i64>()
+                    .map_err(|_| error(format!("{}: numeric argument required", arg)))?;
+                usize::try_from(count)
+                    .map_err(|_| error(format!("{}: shift count out of range", arg)))?
+            }
+            _ => return Err(error("too many arguments".to_string())),
+        };
+        let positional = shell.positional();
+        if count > positional.len() {
+            return Ok(1);
+        }
+        shell.set_positional(positional[count..].to_vec());
+        Ok(0)
+    }
+}
+
+/// Remove variables or functions
+///
+/// Usage: `unset [-f | -v] NAME...`. `-v` removes variables, whether
+/// shell-local or exported, and `-f` functions; without either, a name that
+/// isn't a variable is removed as a function. `NAME[index]` removes one
+/// element of an array. Names that aren't set are passed over.
+struct UnsetCommand;
+
+impl BuiltinCommand for UnsetCommand {
+    fn name(&self) -> &'static str {
+        "unset"
+    }
+
+    fn completion(&self) -> Option<CompletionSpec> {
+        Some(CompletionSpec::new(CompletionAction::Variables))
+    }
+
+    fn execute(
+        &self,
+        args: &[String],
+        shell: &mut Shell,
+        _stdout: &mut dyn Write,
+        _stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError> {
+        let (functions, variables, names) = match args.first().map(String::as_str) {
+            Some("-f") => (true, false, &args[1..]),
+            Some("-v") => (false, true, &args[1..]),
+            Some("--") => (true, true, &args[1..]),
+            Some(flag) if flag.starts_with('-') && flag.len() > 1 => {
+                return Err(usage_error("unset", format!("{}: invalid option", flag)));
+            }
+            _ => (true, true, args),
+        };
+
+        let mut failure = None;
+        for name in names {
+            let element = (name.strip_suffix(']'))
+                .and_then(|name| name.split_once('['))
+                .filter(|(array, _)| variables && is_name(array));
+            if let Some((array, index)) = element {
+                let unset = expand::expand_index(shell, index)
+                    .and_then(|index| shell.unset_element(array, index));
+                match unset {
+                    Ok(_) => {}
+                    Err(ShellError::ReadonlyVariable(_)) => {
+                        failure
+                            .get_or_insert(format!("{}: cannot unset: readonly variable", array));
+                    }
+                    Err(e) => {
+                        failure.get_or_insert(e.to_string());
+                    }
+                }
+                continue;
+            }
+            if variables && !is_name(name) {
+                failure.get_or_insert(format!("`{}': not a valid identifier", name));
+                continue;
+            }
+            let removed = match variables.then(|| shell.unset_variable(name)) {
+                Some(Ok(removed)) => removed,
+                Some(Err(_)) => {
+                    failure.get_or_insert(format!("{}: cannot unset: readonly variable", name));
+                    continue;
+                }
+                None => false,
+            };
+            if functions && !removed {
+                shell.remove_function(name);
+            }
+        }
+        match failure {
+            Some(message) => Err(ShellError::BuiltinError {
+                name: "unset".to_string(),
+                status: 1,
+                message,
+            }),
+            None => Ok(0),
+        }
+    }
+}
+
+/// Declare variables local to the running function
+///
+/// Usage: `local [-ar] [--] [NAME[=value]...]`. Each variable shadows the
+/// one of the same name until the function returns, starting out unset
+/// unless given a value; `-a` makes it an array, empty or with the value as
+/// element 0, and `-r` makes it readonly. `NAME=(a b c)` gives it an array
+/// value, with or without `-a`. Without names, lists the function's local
+/// variables.
+struct LocalCommand;
+
+impl BuiltinCommand for LocalCommand {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    fn completion(&self) -> Option<CompletionSpec> {
+        Some(CompletionSpec::new(CompletionAction::Variables))
+    }
+
+    fn execute(
+        &self,
+        args: &[String],
+        shell: &mut Shell,
+        stdout: &mut dyn Write,
+        _stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError> {
+        let mut readonly = false;
+        let mut array = false;
+        let mut names = args;
+        while let Some(flag) = names.first() {
+            if flag == "--" {
+                names = &names[1..];
+                break;
+            }
+            let Some(letters) = flag.strip_prefix('-').filter(|rest| !rest.is_empty()) else {
+                break;
+            };
+            for letter in letters.chars() {
+                match letter {
+                    'r' => readonly = true,
+                    'a' => array = true,
+                    _ => {
+                        return Err(usage_error("local", format!("-{}: invalid option", letter)));
+                    }
+                }
+            }
+            names = &names[1..];
+        }
+
+        if names.is_empty() {
+            let Some(locals) = shell.locals() else {
+                return Err(ShellError::BuiltinError {
+                    name: "local".to_string(),
+                    status: 1,
+                    message: "can only be used in a function".to_string(),
+                });
+            };
+            for (name, value) in locals {
+                match value {
+                    Some(value) => writeln!(stdout, "{}={}", name, value)?,
+                    None => writeln!(stdout, "{}", name)?,
+                }
+            }
+            return Ok(0);
+        }
+
+        let mut failure = None;
+        for arg in names {
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (arg.as_str(), None),
+            };
+            if !is_name(name) {
+                failure.get_or_insert(format!("`{}': not a valid identifier", arg));
+                continue;
+            }
+            let elements = match value {
+                Some("") => shell.take_array_arg(name),
+                _ => None,
+            };
+            let value = match (value, array) {
+                _ if elements.is_some() => Some(Value::Array(
+                    elements.into_iter().flatten().enumerate().collect(),
+                )),
+                (Some(value), false) => Some(Value::Scalar(value.to_string())),
+                (value, true) => Some(Value::Array(
+                    value.map(str::to_string).into_iter().enumerate().collect(),
+                )),
+                (None, false) => None,
+            };
+            match shell.declare_local(name, value, readonly) {
+                Ok(()) => {}
+                Err(ShellError::ReadonlyVariable(name)) => {
+                    failure.get_or_insert(format!("{}: readonly variable", name));
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        match failure {
+            Some(message) => Err(ShellError::BuiltinError {
+                name: "local".to_string(),
+                status: 1,
+                message,
+            }),
+            None => Ok(0),
+        }
+    }
+}
+
+/// Mark variables readonly
+///
+/// Usage: `readonly [-p] [--] [NAME[=value]...]`. A readonly variable can't
+/// be assigned or unset for the rest of the session. Without names, or
+/// with `-p`, lists the readonly variables.
+struct ReadonlyCommand;
+
+impl BuiltinCommand for ReadonlyCommand {
+    fn name(&self) -> &'static str {
+        "readonly"
+    }
+
+    fn completion(&self) -> Option<CompletionSpec> {
+        Some(CompletionSpec::new(CompletionAction::Variables))
+    }
+
+    fn execute(
+        &self,
+        args: &[String],
+        shell: &mut Shell,
+        stdout: &mut dyn Write,
+        _stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError> {
+        let names = match args.first().map(String::as_str) {
+            Some("-p" | "--") => &args[1..],
+            Some(flag) if flag.starts_with('-') && flag.len() > 1 => {
+                return Err(usage_error("readonly", format!("{}: invalid option", flag)));
+            }
+            _ => args,
+        };
+
+        if names.is_empty() {
+            for (name, value) in shell.readonly_variables() {
+                match value {
+                    Some(value) => writeln!(stdout, "readonly {}={}", name, value)?,
+                    None => writeln!(stdout, "readonly {}", name)?,
+                }
+            }
+            return Ok(0);
+        }
+
+        let mut failure = None;
+        for arg in names {
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (arg.as_str(), None),
+            };
+            if !is_name(name) {
+                failure.get_or_insert(format!("`{}': not a valid identifier", arg));
+            } else if let Err(err) = shell.make_readonly(name, value) {
+                failure.get_or_insert(err.to_string());
+            }
+        }
+        match failure {
+            Some(message) => Err(ShellError::BuiltinError {
+                name: "readonly".to_string(),
+                status: 1,
+                message,
+            }),
+            None => Ok(0),
+        }
+    }
+}
+
+/// Define or list aliases
+///
+/// Usage: `alias [name[=value]...]`. `name=value` defines an alias, a bare
+/// `name` prints its definition, and no arguments lists every alias.
+struct AliasCommand;
+
+impl BuiltinCommand for AliasCommand {
+    fn name(&self) -> &'static str {
+        "alias"
+    }
+
+    fn execute(
+        &self,
+        args: &[String],
+        shell: &mut Shell,
+        stdout: &mut dyn Write,
+        _stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError> {
+        if args.is_empty() {
+            let mut aliases: Vec<_> = shell.aliases().iter().collect();
+            aliases.sort();
+            for (name, value) in aliases {
+                writeln!(stdout, "alias {}={}", name, Word::quoted(value.as_str()))?;
+            }
+            return Ok(0);
+        }
+
+        let mut missing = None;
+        for arg in args {
+            match arg.split_once('=') {
+                Some((name, value)) => shell.set_alias(name, value),
+                None => match shell.aliases().get(arg) {
+                    Some(value) => {
+                        writeln!(stdout, "alias {}={}", arg, Word::quoted(value.as_str()))?
+                    }
+                    None => {
+                        missing.get_or_insert(arg);
+                    }
+                },
+            }
+        }
+        match missing {
+            Some(name) => Err(ShellError::BuiltinError {
+                name: "alias".to_string(),
+                status: 1,
+                message: format!("{}: not found", name),
+            }),
+            None => Ok(0),
+        }
+    }
+}
+
+/// Remove aliases
+///
+/// Usage: `unalias [-a] name...`. `-a` removes every alias.
+struct UnaliasCommand;
+
+impl BuiltinCommand for UnaliasCommand {
+    fn name(&self) -> &'static str {
+        "unalias"
+    }
+
+    fn execute(
+        &self,
+        args: &[String],
+        shell: &mut Shell,
+        _stdout: &mut dyn Write,
+        _stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError> {
+        if args.first().map(String::as_str) == Some("-a") {
+            let names: Vec<String> = shell.aliases().keys().cloned().collect();
+            for name in names {
+                shell.remove_alias(&name);
+            }
+            return Ok(0);
+        }
+
+        let mut missing = None;
+        for name in args {
+            if !shell.remove_alias(name) {
+                missing.get_or_insert(name);
+            }
+        }
+        match missing {
+            Some(name) => Err(ShellError::BuiltinError {
+                name: "unalias".to_string(),
+                status: 1,
+                message: format!("{}: not found", name),
+            }),
+            None => Ok(0),
+        }
+    }
+}
+
+/// Parse the options `complete` and `compgen` share into a spec, returning
+/// it with the arguments after the options and which of the `extra` flags
+/// the caller handles itself were given
+///
+/// The options are `-c`, `-d`, `-f`, `-j` and `-v` for actions, `-W WORDS`
+/// for a word list split on blanks, and `-F FUNCTION` for a function
+/// printing candidates.
+fn parse_spec<'a>(
+    builtin: &str,
+    args: &'a [String],
+    extra: &str,
+) -> Result<(CompletionSpec, &'a [String], Vec<char>), ShellError> {
+    let mut spec = CompletionSpec::default();
+    let mut given = Vec::new();
+    let mut rest = args;
+    while let Some((arg, after)) = rest.split_first() {
+        let Some(flags) = arg.strip_prefix('-').filter(|flags| !flags.is_empty()) else {
+            break;
+        };
+        rest = after;
+        if flags == "-" {
+            break;
+        }
+        for (index, flag) in flags.char_indices() {
+            match (flag, CompletionAction::from_flag(flag)) {
+                ('W' | 'F', _) => {
+                    // The value is the rest of this argument or the next one
+                    let attached = &flags[index + 1..];
+                    let value = if attached.is_empty() {
+                        let (value, after) = rest.split_first().ok_or_else(|| {
+                            usage_error(builtin, format!("-{}: option requires an argument", flag))
+                        })?;
+                        rest = after;
+                        value.as_str()
+                    } else {
+                        attached
+                    };
+                    if flag == 'W' {
+                        spec.words = value.split_whitespace().map(str::to_string).collect();
+                    } else {
+                        spec.function = Some(value.to_string());
+                    }
+                    break;
+                }
+                (_, Some(action)) if !spec.actions.contains(&action) => spec.actions.push(action),
+                (_, Some(_)) => {}
+                (_, None) if extra.contains(flag) => given.push(flag),
+                (_, None) => {
+                    return Err(usage_error(builtin, format!("-{}: invalid option", flag)))
+                }
+            }
+        }
+    }
+    Ok((spec, rest, given))
+}
+
+/// Set what commands' arguments complete to at the prompt
+///
+/// Usage: `complete [-cdfjv] [-W WORDS] [-F FUNCTION] NAME...` makes the
+/// arguments of each NAME complete to command names (`-c`), directories
+/// (`-d`), files (`-f`), job specs and process IDs (`-j`), variable names
+/// (`-v`), the words in WORDS, or the lines FUNCTION prints, merging the
+/// candidates of several. FUNCTION is called with the command, the word
+/// being completed and the word before it, and `COMP_LINE` and
+/// `COMP_POINT` set. `complete -r [NAME...]` removes the specs (every one
+/// without a NAME), and `complete [-p] [NAME...]` prints them as `complete`
+/// commands.
+struct CompleteCommand;
+
+impl BuiltinCommand for CompleteCommand {
+    fn name(&self) -> &'static str {
+        "complete"
+    }
+
+    fn completion(&self) -> Option<CompletionSpec> {
+        Some(CompletionSpec::new(CompletionAction::Commands))
+    }
+
+    fn execute(
+        &self,
+        args: &[String],
+        shell: &mut Shell,
+        stdout: &mut dyn Write,
+        stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError> {
+        let (spec, names, given) = parse_spec("complete", args, "pr")?;
+        let (print, remove) = (given.contains(&'p'), given.contains(&'r'));
+
+        if !remove && !print && spec != CompletionSpec::default() {
+            if names.is_empty() {
+                return Err(usage_error(
+                    "complete",
+                    "a command name is required".to_string(),
+                ));
+            }
+            for name in names {
+                shell.set_completion(name, spec.clone());
+            }
+            return Ok(0);
+        }
+        if remove && names.is_empty() {
+            shell.clear_completions();
+            return Ok(0);
+        }
+        if names.is_empty() {
+            for (name, spec) in shell.completions() {
+                writeln!(stdout, "complete {} {}", spec, name)?;
+            }
+            return Ok(0);
+        }
+
+        let mut status = 0;
+        for name in names {
+            let found = if remove {
+                shell.remove_completion(name)
+            } else {
+                match shell.completion(name) {
+                    Some(spec) => {
+                        writeln!(stdout, "complete {} {}", spec, name)?;
+                        true
+                    }
+                    None => false,
+                }
+            };
+            if !found {
+                writeln!(stderr, "complete: {}: no completion specification", name)?;
+                status = 1;
+            }
+        }
+        Ok(status)
+    }
+}
+
+/// Print the candidates a completion spec gives for a word
+///
+/// Usage: `compgen [-cdfjv] [-W WORDS] [--] [WORD]` lists, one per line,
+/// what `complete` with the same options would offer for WORD (everything,
+/// without one). The status is 1 if there's nothing to list.
+struct CompgenCommand;
+
+impl BuiltinCommand for CompgenCommand {
+    fn name(&self) -> &'static str {
+        "compgen"
+    }
+
+    fn execute(
+        &self,
+        args: &[String],
+        shell: &mut Shell,
+        stdout: &mut dyn Write,
+        _stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError> {
+        let (spec, words, _) = parse_spec("compgen", args, "")?;
+        if spec.function.is_some() {
+            return Err(usage_error(
+                "compgen",
+                "-F: not supported; call the function instead".to_string(),
+            ));
+        }
+        let word = match words {
+            [] => "",
+            [word] => word,
+            _ => return Err(usage_error("compgen", "too many arguments".to_string())),
+        };
+        let matches = shell.completion_matches(word, &spec);
+        for candidate in &matches {
+            // Directories are listed without the `/` completion adds
+            let name = candidate.strip_suffix('/').filter(|name| !name.is_empty());
+            writeln!(stdout, "{}", name.unwrap_or(candidate))?;
+        }
+        Ok(if matches.is_empty() { 1 } else { 0 })
+    }
+}
+
+/// Leave the running shell function
+///
+/// Usage: `return [n]`. The function's status is `n`, or the status of the
+/// last command it ran.
+struct ReturnCommand;
+
+impl BuiltinCommand for ReturnCommand {
+    fn name(&self) -> &'static str {
+        "return"
+    }
+
+    fn execute(
+        &self,
+        args: &[String],
+        shell: &mut Shell,
+        _stdout: &mut dyn Write,
+        _stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError> {
+        if !shell.can_return() {
+            return Err(ShellError::BuiltinError {
+                name: "return".to_string(),
+                status: 1,
+                message: "can only `return' from a function or sourced script".to_string(),
+            });
+        }
+        let status = match args.first() {
+            Some(arg) => arg.parse::<i32>().map(|code| code & 0xff).map_err(|_| {
+                ShellError::BuiltinError {
+                    name: "return".to_string(),
+                    status: 2,
+                    message: format!("{}: numeric argument required", arg),
+                }
+            })?,
+            None => shell.last_status(),
+        };
+        shell.request_return(status);
+        Ok(0)
+    }
+}
+
+/// Run a file's commands in the current shell
+///
+/// Usage: `source file [args...]` or `. file [args...]`. A name without a `/`
+/// is looked up in PATH, then in the current directory. The status is that of
+/// the last command in the file.
+struct SourceCommand {
+    /// Registered as `.`
+    dot: bool,
+}
+
+impl BuiltinCommand for SourceCommand {
+    fn name(&self) -> &'static str {
+        if self.dot {
+            "."
+        } else {
+            "source"
+        }
+    }
+
+    fn execute(
+        &self,
+        args: &[String],
+        shell: &mut Shell,
+        _stdout: &mut dyn Write,
+        _stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError> {
+        let Some((file, args)) = args.split_first() else {
+            return Err(usage_error(
+                self.name(),
+                "filename argument required".to_string(),
+            ));
+        };
+        let path = env::var_os("PATH")
+            .filter(|_| !file.contains('/'))
+            .and_then(|paths| {
+                env::split_paths(&paths)
+                    .map(|dir| dir.join(file))
+                    .find(|path| path.is_file())
+            })
+            .unwrap_or_else(|| PathBuf::from(file));
+        let args = (!args.is_empty()).then(|| args.to_vec());
+        shell
+            .source(&path, args)
+            .map_err(|e| ShellError::BuiltinError {
+                name: self.name().to_string(),
+                status: 1,
+                message: format!("{}: {}", file, describe_io_error(&e)),
+            })
+    }
+}
+
+/// Leave the innermost loop, or the innermost `n` with `break n`
+struct BreakCommand;
+
+impl BuiltinCommand for BreakCommand {
+    fn name(&self) -> &'static str {
+        "break"
+    }
+
+    fn execute(
+        &self,
+        args: &[String],
+        shell: &mut Shell,
+        _stdout: &mut dyn Write,
+        _stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError> {
+        let levels = loop_levels(self.name(), args, shell)?;
+        shell.request_loop_control(LoopControl::Break(levels));
+        Ok(0)
+    }
+}
+
+/// Start the next iteration of the innermost loop, or of the `n`th with `continue n`
+struct ContinueCommand;
+
+impl BuiltinCommand for ContinueCommand {
+    fn name(&self) -> &'static str {
+        "continue"
+    }
+
+    fn execute(
+        &self,
+        args: &[String],
+        shell: &mut Shell,
+        _stdout: &mut dyn Write,
+        _stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError> {
+        let levels = loop_levels(self.name(), args, shell)?;
+        shell.request_loop_control(LoopControl::Continue(levels));
+        Ok(0)
+    }
+}
+
+/// How many loops `break`/`continue` applies to: the count argument (default
+/// 1), capped at the number of loops running
+fn loop_levels(name: &str, args: &[String], shell: &Shell) -> Result<usize, ShellError> {
+    let error = |status, message: String| ShellError::BuiltinError {
+        name: name.to_string(),
+        status,
+        message,
+    };
+    if shell.loop_depth() == 0 {
+        return Err(error(
+            0,
+            "only meaningful in a `for' or `while' loop".to_string(),
+        ));
+    }
+    let levels = match args.first() {
+        Some(arg) => arg
+            .parse::<usize>()
+            .map_err(|_| error(2, format!("{}: numeric argument required", arg)))?,
+        None => 1,
+    };
+    if levels == 0 {
+        return Err(error(1, format!("{}: loop count out of range", levels)));
+    }
+    Ok(levels.min(shell.loop_depth()))
+}
+
+/// Evaluate a conditional expression
+///
+/// Usage: `test expr` or `[ expr ]`. The status is 0 if the expression is
+/// true, 1 if it's false and 2 if it can't be evaluated. Supports file tests
+/// (`-e`, `-f`, `-d`, `-x`, ...), string tests (`-z`, `-n`, `=`, `!=`),
+/// integer comparisons (`-eq`, `-lt`, ...), `!`, `-a`, `-o` and parentheses.
+struct TestCommand {
+    /// Registered as `[`, which needs a closing `]`
+    bracket: bool,
+}
+
+impl BuiltinCommand for TestCommand {
+    fn name(&self) -> &'static str {
+        if self.bracket {
+            "["
+        } else {
+            "test"
+        }
+    }
+
+    fn execute(
+        &self,
+        args: &[String],
+        _shell: &mut Shell,
+        _stdout: &mut dyn Write,
+        _stderr: &mut dyn Write,
+    ) -> Result<i32, ShellError> {
+        let args = match args.split_last() {
+            _ if !self.bracket => args,
+            Some((last, rest)) if last == "]" => rest,
+            _ => return Err(usage_error(self.name(), "missing `]'".to_string())),
+        };
+        if args.is_empty() {
+            return Ok(1);
+        }
+        let mut expression = TestExpression {
+            name: self.name(),
+            args,
+            position: 0,
+        };
+        let result = expression.or()?;
+        if let Some(extra) = expression.peek(0) {
+            return Err(usage_error(
+                self.name(),
+                format!("{}: unexpected argument", extra),
+            ));
+        }
+        Ok(i32::from(!result))
+    }
+}
+
+/// Recursive-descent evaluator for the arguments of `test`
+struct TestExpression<'a> {
+    /// `test` or `[`, for error messages
+    name: &'static str,
+    args: &'a [String],
+    position: usize,
+}
+
+impl<'a> TestExpression<'a> {
+    /// The argument `offset` places after the current one
+    fn peek(&self, offset: usize) -> Option<&'a str> {
+        self.args.get(self.position + offset).map(String::as_str)
+    }
+
+    /// `expr -o expr`
+    fn or(&mut self) -> Result<bool, ShellError> {
+        let mut result = self.and()?;
+        while self.peek(0) == Some("-o") {
+            self.position += 1;
+            result |= self.and()?;
+        }
+        Ok(result)
+    }
+
+    /// `expr -a expr`
+    fn and(&mut self) -> Result<bool, ShellError> {
+        let mut result = self.not()?;
+        while self.peek(0) == Some("-a") {
+            self.position += 1;
+            result &= self.not()?;
+        }
+        Ok(result)
+    }
+
+    /// `! expr`
+    fn not(&mut self) -> Result<bool, ShellError> {
+        if self.peek(0) == Some("!") && self.peek(1).is_some() {
+            self.position += 1;
+            return Ok(!self.not()?);
+        }
+        self.primary()
+    }
+
+    /// A binary or unary test, a parenthesized expression, or a string that
+    /// is true if it's not empty
+    fn primary(&mut self) -> Result<bool, ShellError> {
+        let Some(first) = self.peek(0) else {
+            return Err(usage_error(self.name, "argument expected".to_string()));
+        };
+        // A binary operator wins, so `[ -n = -n ]` compares two strings
+        if let (Some(op), Some(right)) = (self.peek(1), self.peek(2)) {
+            if is_binary_test(op) {
+                let result = binary_test(self.name, first, op, right)?;
+                self.position += 3;
+                return Ok(result);
+            }
+        }
+        if first == "(" {
+            self.position += 1;
+            let result = self.or()?;
+            if self.peek(0) != Some(")") {
+                return Err(usage_error(self.name, "missing `)'".to_string()));
+            }
+            self.position += 1;
+            return Ok(result);
+        }
+        if let Some(operand) = self.peek(1).filter(|_| is_unary_test(first)) {
+            let result = unary_test(first, operand);
+            self.position += 2;
+            return Ok(result);
+        }
+        self.position += 1;
+        Ok(!first.is_empty())
+    }
+}
+
+/// Evaluate `left op right` for `test`, or for `[[ ... ]]` as `name`
+pub(crate) fn binary_test(
+    name: &str,
+    left: &str,
+    op: &str,
+    right: &str,
+) -> Result<bool, ShellError> {
+    let integer = |text: &str| {
+        text.trim()
+            .parse::<i64>()
+            .map_err(|_| usage_error(name, format!("{}: integer expression expected", text)))
+    };
+    Ok(match op {
+        "=" | "==" => left == right,
+        "!=" => left != right,
+        "<" => left < right,
+        ">" => left > right,
+        _ => {
+            let (left, right) = (integer(left)?, integer(right)?);
+            match op {
+                "-eq" => left == right,
+                "-ne" => left != right,
+                "-lt" => left < right,
+                "-le" => left <= right,
+                "-gt" => left > right,
+                _ => left >= right,
+            }
+        }
+    })
+}
+
+/// Whether `op` is a binary operator of `test`
+fn is_binary_test(op: &str) -> bool {
+    matches!(
+        op,
+        "=" | "==" | "!=" | "<" | ">" | "-eq" | "-ne" | "-lt" | "-le" | "-gt" | "-ge"
+    )
+}
+
+/// Whether `op` is a unary operator of `test`
+pub(crate) fn is_unary_test(op: &str) -> bool {
+    matches!(
+        op,
+        "-z" | "-n" | "-e" | "-f" | "-d" | "-L" | "-h" | "-s" | "-r" | "-w" | "-x"
+    )
+}
+
+/// Evaluate a unary test of `test`; file tests follow symlinks except `-L`/`-h`
+pub(crate) fn unary_test(op: &str, operand: &str) -> bool {
+    let path = Path::new(operand);
+    match op {
+        "-z" => operand.is_empty(),
+        "-n" => !operand.is_empty(),
+        "-e" => path.exists(),
+        "-f" => path.is_file(),
+        "-d" => path.is_dir(),
+        "-L" | "-h" => path.is_symlink(),
+        "-s" => fs::metadata(path).is_ok_and(|metadata| metadata.len() > 0),
+        "-r" => accessible(operand, libc::R_OK),
+        "-w" => accessible(operand, libc::W_OK),
+        _ => accessible(operand, libc::X_OK),
+    }
+}
+
+/// Whether the shell may access `path` in `mode` (`R_OK`, `W_OK` or `X_OK`)
+fn accessible(path: &str, mode: libc::c_int) -> bool {
+    let Ok(path) = CString::new(path) else {
+        return false;
+    };
+    // SAFETY: `path` is a valid NUL-terminated string
+    unsafe { libc::access(path.as_ptr(), mode) == 0 }
+}
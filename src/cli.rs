@@ -0,0 +1,140 @@
+use crate::shell::ShellOptions;
+use std::path::PathBuf;
+
+/// Usage summary printed by `--help`
+pub const USAGE: &str = "\
+Usage: shelly [OPTIONS] [SCRIPT [ARGS...]]
+       shelly [OPTIONS] -c COMMAND [NAME [ARGS...]]
+
+Options:
+  -c COMMAND     Run COMMAND and exit
+  -i             Force an interactive shell
+  -l, --login    Act as a login shell
+  -r             Run a restricted shell
+      --norc     Don't read the startup rc file
+      --rcfile F Read F instead of the default rc file
+      --dump-ast Print how input is parsed instead of running it
+      --tokenize Print the lexer's token stream instead of running it
+  -h, --help     Show this help and exit
+      --version  Show version information and exit";
+
+/// Parsed command-line arguments for the shelly binary
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Cli {
+    /// Command string given with `-c`
+    pub command: Option<String>,
+    /// Script to run (first positional argument when `-c` isn't used)
+    pub script: Option<PathBuf>,
+    /// Remaining positional arguments (`$0`/`$1`... for `-c`, `$1`... for scripts)
+    pub args: Vec<String>,
+    /// `-i`: force interactive mode
+    pub interactive: bool,
+    /// `-l`/`--login`: act as a login shell
+    pub login: bool,
+    /// `-r`: restricted shell
+    pub restricted: bool,
+    /// `--norc`: skip the rc file
+    pub norc: bool,
+    /// `--rcfile FILE`: alternative rc file
+    pub rcfile: Option<PathBuf>,
+    /// `--dump-ast`: print the parse instead of executing
+    pub dump_ast: bool,
+    /// `--tokenize`: print the token stream instead of executing
+    pub tokenize: bool,
+    /// `-h`/`--help`
+    pub help: bool,
+    /// `--version`
+    pub version: bool,
+}
+
+impl Cli {
+    /// Parse command-line arguments (excluding the program name)
+    ///
+    /// Options are only recognized before the first positional argument;
+    /// everything from the script name on is passed to the script. `--` ends
+    /// option parsing explicitly. Short flags may be combined (`-il`).
+    ///
+    /// # Examples
+    /// ```
+    /// use codecrafters_shell::cli::Cli;
+    ///
+    /// let cli = Cli::parse(["-c", "echo hi", "name", "arg"].map(String::from)).unwrap();
+    /// assert_eq!(cli.command.as_deref(), Some("echo hi"));
+    /// assert_eq!(cli.args, vec!["name", "arg"]);
+    /// ```
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, String> {
+        let mut cli = Cli::default();
+        let mut args = args.into_iter();
+        let mut positional = Vec::new();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--" => {
+                    positional.extend(args.by_ref());
+                    break;
+                }
+                "--login" => cli.login = true,
+                "--norc" => cli.norc = true,
+                "--rcfile" => {
+                    let file = args.next().ok_or("--rcfile: option requires an argument")?;
+                    cli.rcfile = Some(PathBuf::from(file));
+                }
+                "--dump-ast" => cli.dump_ast = true,
+                "--tokenize" => cli.tokenize = true,
+                "--help" => cli.help = true,
+                "--version" => cli.version = true,
+                long if long.starts_with("--") => {
+                    return Err(format!("{}: invalid option", long));
+                }
+                short if short.starts_with('-') && short.len() > 1 => {
+                    for flag in short[1..].chars() {
+                        match flag {
+                            'c' => {
+                                let cmd = args.next().ok_or("-c: option requires an argument")?;
+                                cli.command = Some(cmd);
+                            }
+                            'i' => cli.interactive = true,
+                            'l' => cli.login = true,
+                            'r' => cli.restricted = true,
+                            'h' => cli.help = true,
+                            other => return Err(format!("-{}: invalid option", other)),
+                        }
+                    }
+                }
+                _ => {
+                    positional.push(arg);
+                    positional.extend(args.by_ref());
+                    break;
+                }
+            }
+        }
+
+        if cli.command.is_none() && !positional.is_empty() {
+            cli.script = Some(PathBuf::from(positional.remove(0)));
+        }
+        cli.args = positional;
+        Ok(cli)
+    }
+
+    /// Options for constructing the shell described by these arguments
+    ///
+    /// `-c` and scripts run non-interactively unless `-i` is given.
+    pub fn shell_options(&self) -> ShellOptions {
+        let interactive = if self.interactive {
+            Some(true)
+        } else if self.command.is_some() || self.script.is_some() {
+            Some(false)
+        } else {
+            None
+        };
+
+        ShellOptions {
+            interactive,
+            login: self.login,
+            restricted: self.restricted,
+            norc: self.norc,
+            rcfile: self.rcfile.clone(),
+            args: self.args.clone(),
+        }
+    }
+}
@@ -1,44 +1,740 @@
-use std::path::PathBuf;
+use crate::timing::TimeFormat;
+use std::rc::Rc;
 
 /// Tokens produced by the lexer during command parsing
-#[derive(Debug, PartialEq)]
-enum Token {
-    /// A word or argument (handles quoted strings and escape sequences)
-    Word(String),
-    /// Output redirection (>, >> or 1>, 1>>). Bool indicates append mode
-    OutputRedirect(bool),
-    /// Error redirection (2>, 2>>). Bool indicates append mode
-    ErrorRedirect(bool),
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    /// A word or argument, with quoting and `$` references kept apart for expansion
+    Word(Word),
+    /// Output redirection (`>`, `>>`, `n>`, `n>>`): the fd and whether to append
+    OutputRedirect(i32, bool),
+    /// Input redirection (`<`, `n<`): the fd
+    InputRedirect(i32),
+    /// Fd duplication (`n>&m`, `n<&m`): the fd and the fd it becomes a copy of
+    DupRedirect(i32, i32),
+    /// Closing an fd (`n>&-`, `n<&-`)
+    CloseRedirect(i32),
+    /// `n>&` or `<&` before a word that isn't an fd, which is a syntax error
+    DupToWord,
+    /// Redirect stdout and stderr together (`&>`, `&>>`, `>&`). Bool indicates append mode
+    OutputAndErrorRedirect(bool),
+    /// Here-document (`<<DELIM`, `<<-DELIM`): the fd, the delimiter word,
+    /// whether leading tabs are stripped, and the body read from the lines
+    /// after the command (`None` if the input ended before the delimiter)
+    HereDoc {
+        fd: i32,
+        delimiter: Word,
+        strip_tabs: bool,
+        body: Option<Word>,
+    },
+    /// Here-string (`<<< word`): the fd
+    HereString(i32),
     /// Pipe operator (|)
     Pipe,
     /// Background operator (&)
     Background,
+    /// Logical AND (&&): run the next pipeline only if this one succeeded
+    And,
+    /// Logical OR (||): run the next pipeline only if this one failed
+    Or,
+    /// Command separator (;)
+    Semicolon,
+    /// End of a `case` item (;;)
+    DoubleSemicolon,
+    /// End of a line, which separates commands like `;`
+    Newline,
+    /// Opening parenthesis, as in `name()`
+    LeftParen,
+    /// Closing parenthesis
+    RightParen,
 }
 
-/// Parsed command with its arguments and redirections
-#[derive(Debug)]
+/// Byte range of a token within the original input line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    /// Byte offset of the first character of the token
+    pub start: usize,
+    /// Byte offset just past the last character of the token
+    pub end: usize,
+}
+
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
+/// A token together with the span of input it was read from
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+
+/// Tokenize a command line, returning every token with its source span
+///
+/// This exposes the lexer on its own, which is useful for inspecting how
+/// quoting and escaping were interpreted.
+///
+/// # Examples
+/// ```
+/// use codecrafters_shell::command::{tokenize, Token, Word};
+///
+/// let tokens = tokenize("echo 'a b'");
+/// assert_eq!(tokens[1].token, Token::Word(Word::quoted("a b")));
+/// assert_eq!((tokens[1].span.start, tokens[1].span.end), (5, 10));
+/// ```
+pub fn tokenize(input: &str) -> Vec<SpannedToken> {
+    Lexer::new(input.to_string()).tokenize()
+}
+
+/// One piece of a [`Word`], recording how it was quoted
+#[derive(Debug, Clone, PartialEq)]
+pub enum WordPart {
+    /// Unquoted text
+    Literal(String),
+    /// Quoted or backslash-escaped text, always taken as is
+    Quoted(String),
+    /// A parameter reference such as `$?` or `${HOME}`, replaced during expansion
+    Param {
+        /// The parameter name
+        name: String,
+        /// Whether the reference appeared inside double quotes
+        quoted: bool,
+    },
+    /// A process substitution, replaced by a `/dev/fd` path connected to `command`
+    ProcessSubstitution {
+        /// The command inside the parentheses, as written
+        command: String,
+        /// `>(...)`, written to by the outer command, rather than `<(...)`
+        output: bool,
+    },
+}
+
+/// A shell word before expansion
+///
+/// Quotes are removed by the lexer, but each piece remembers whether it was
+/// quoted so expansion can treat `$HOME` and `'$HOME'` differently.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Word {
+    pub parts: Vec<WordPart>,
+}
+
+impl Word {
+    /// A word standing for exactly `text`, with nothing left to expand
+    pub fn quoted(text: impl Into<String>) -> Self {
+        Word {
+            parts: vec![WordPart::Quoted(text.into())],
+        }
+    }
+
+    /// Whether this is the unquoted text `text`, as operator-like words such as `!` must be
+    pub fn is_unquoted(&self, text: &str) -> bool {
+        self.unquoted_text() == Some(text)
+    }
+
+    /// The word's text if it is a single unquoted literal
+    pub fn unquoted_text(&self) -> Option<&str> {
+        match self.parts.as_slice() {
+            [WordPart::Literal(text)] => Some(text),
+            _ => None,
+        }
+    }
+
+    /// The word's text with quotes removed but nothing expanded, as a
+    /// here-document delimiter is read
+    pub fn literal_text(&self) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                WordPart::Literal(text) | WordPart::Quoted(text) => text.clone(),
+                WordPart::Param { name, .. } => format!("${}", name),
+                WordPart::ProcessSubstitution { command, output } => {
+                    format!("{}({})", if *output { '>' } else { '<' }, command)
+                }
+            })
+            .collect()
+    }
+
+    /// Split a `NAME=value` assignment into the name and the value still to expand
+    ///
+    /// The name and `=` must be unquoted; the value may be quoted, as in
+    /// `GREETING="hello world"`. `NAME+=value` appends, and `NAME[index]=value`
+    /// assigns one element of an array, where the index may use `$` references.
+    /// An array value, `NAME=(a b c)`, is made of the words after this one, so
+    /// it's left to the parser.
+    ///
+    /// # Examples
+    /// ```
+    /// use codecrafters_shell::command::{tokenize, Token};
+    ///
+    /// let Token::Word(word) = &tokenize("list[$i]+=x")[0].token else { panic!() };
+    /// let assignment = word.assignment().unwrap();
+    /// assert_eq!(assignment.name, "list");
+    /// assert_eq!(assignment.index.as_deref(), Some("${i}"));
+    /// assert!(assignment.append);
+    /// ```
+    pub fn assignment(&self) -> Option<Assignment> {
+        // The text up to `=`, which may only contain references inside a subscript
+        let mut head = String::new();
+        for (position, part) in self.parts.iter().enumerate() {
+            match part {
+                WordPart::Literal(text) => {
+                    let Some((before, value)) = text.split_once('=') else {
+                        head.push_str(text);
+                        continue;
+                    };
+                    head.push_str(before);
+                    let (head, append) = match head.strip_suffix('+') {
+                        Some(head) => (head, true),
+                        None => (head.as_str(), false),
+                    };
+                    let (name, index) = match head.split_once('[') {
+                        Some((name, index)) => (name, Some(index.strip_suffix(']')?)),
+                        None if position == 0 => (head, None),
+                        None => return None,
+                    };
+                    if !is_name(name) {
+                        return None;
+                    }
+                    let mut parts = Vec::new();
+                    if !value.is_empty() {
+                        parts.push(WordPart::Literal(value.to_string()));
+                    }
+                    parts.extend(self.parts[position + 1..].iter().cloned());
+                    return Some(Assignment {
+                        name: name.to_string(),
+                        index: index.map(str::to_string),
+                        append,
+                        value: AssignedValue::Scalar(Word { parts }),
+                    });
+                }
+                WordPart::Param {
+                    name,
+                    quoted: false,
+                } if head.contains('[') => head.push_str(&format!("${{{}}}", name)),
+                _ => return None,
+            }
+        }
+        None
+    }
+
+    /// Append a character, merging it into the last part if that has the same quoting
+    fn push(&mut self, ch: char, quoted: bool) {
+        match (self.parts.last_mut(), quoted) {
+            (Some(WordPart::Literal(text)), false) | (Some(WordPart::Quoted(text)), true) => {
+                text.push(ch)
+            }
+            _ if quoted => self.parts.push(WordPart::Quoted(ch.to_string())),
+            _ => self.parts.push(WordPart::Literal(ch.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for Word {
+    /// Write the word back as shell input, quoting wherever the original was quoted
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for part in &self.parts {
+            match part {
+                WordPart::Literal(text) => write!(f, "{}", text)?,
+                WordPart::Quoted(text) => write!(f, "'{}'", text.replace('\'', "'\\''"))?,
+                WordPart::Param {
+                    name,
+                    quoted: false,
+                } => write!(f, "${{{}}}", name)?,
+                WordPart::Param { name, quoted: true } => write!(f, "\"${{{}}}\"", name)?,
+                WordPart::ProcessSubstitution { command, output } => {
+                    write!(f, "{}({})", if *output { '>' } else { '<' }, command)?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A `NAME=value` assignment, before the value is expanded
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assignment {
+    /// The variable assigned
+    pub name: String,
+    /// The subscript of `NAME[index]=value`, as written
+    pub index: Option<String>,
+    /// Whether this is `NAME+=value`, which adds to the current value
+    pub append: bool,
+    /// The value
+    pub value: AssignedValue,
+}
+
+/// The value of an [`Assignment`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum AssignedValue {
+    /// A single word, as in `NAME=value`
+    Scalar(Word),
+    /// The words of `NAME=(a b c)`, each expanding to any number of elements
+    Array(Vec<Word>),
+}
+
+impl Assignment {
+    /// The words of the value, for finding process substitutions
+    fn words(&self) -> &[Word] {
+        match &self.value {
+            AssignedValue::Scalar(word) => std::slice::from_ref(word),
+            AssignedValue::Array(words) => words,
+        }
+    }
+
+    fn words_mut(&mut self) -> &mut [Word] {
+        match &mut self.value {
+            AssignedValue::Scalar(word) => std::slice::from_mut(word),
+            AssignedValue::Array(words) => words,
+        }
+    }
+}
+
+impl std::fmt::Display for Assignment {
+    /// Write the assignment back as shell input
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)?;
+        if let Some(index) = &self.index {
+            write!(f, "[{}]", index)?;
+        }
+        write!(f, "{}=", if self.append { "+" } else { "" })?;
+        match &self.value {
+            AssignedValue::Scalar(word) => write!(f, "{}", word),
+            AssignedValue::Array(words) => {
+                let words: Vec<_> = words.iter().map(Word::to_string).collect();
+                write!(f, "({})", words.join(" "))
+            }
+        }
+    }
+}
+
+/// Whether `name` is a valid variable name: a letter or `_` followed by letters, digits or `_`
+pub fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c == '_' || c.is_ascii_alphabetic())
+        && chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
+
+/// A redirection attached to a command
+///
+/// A command's redirections are applied left to right, so `> out 2>&1` sends
+/// both streams to `out` while `2>&1 > out` leaves stderr where stdout was.
+/// The parser produces targets and here-document bodies as [`Word`]s;
+/// expansion turns them into paths and text.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Redirection<T = Word, B = T> {
+    /// `n< file`: open `path` for reading on `fd`
+    Input { fd: i32, path: T },
+    /// `n> file` or `n>> file`: open `path` for writing on `fd`
+    Output { fd: i32, path: T, append: bool },
+    /// `n>&m` or `n<&m`: make `fd` a copy of `target`
+    Duplicate { fd: i32, target: i32 },
+    /// `n>&-` or `n<&-`: close `fd`
+    Close { fd: i32 },
+    /// `n<<DELIM` or `n<<< word`: feed `body` to `fd`
+    HereDoc { fd: i32, body: B },
+}
+
+impl Redirection {
+    /// The word naming the file to open, if the redirection opens one
+    pub fn target(&self) -> Option<&Word> {
+        match self {
+            Redirection::Input { path, .. } | Redirection::Output { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+
+    fn target_mut(&mut self) -> Option<&mut Word> {
+        match self {
+            Redirection::Input { path, .. } | Redirection::Output { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+}
+
+/// Every part of the file names `redirects` open, for replacing process
+/// substitutions
+pub fn target_parts_mut(redirects: &mut [Redirection]) -> impl Iterator<Item = &mut WordPart> {
+    redirects
+        .iter_mut()
+        .filter_map(Redirection::target_mut)
+        .flat_map(|word| &mut word.parts)
+}
+
+/// Parsed command with its words and redirections
+///
+/// The first word names the command once expanded; the rest are its
+/// arguments.
+#[derive(Debug, Clone, Default)]
 pub struct CommandParts {
-    /// The command name
-    pub command: String,
-    /// Command arguments
-    pub args: Vec<String>,
-    /// Output redirection (file path, append mode)
-    pub output_redirect: Option<(PathBuf, bool)>,
-    /// Error redirection (file path, append mode)
-    pub error_redirect: Option<(PathBuf, bool)>,
+    /// Leading `NAME=value` assignments: environment overrides for this command
+    /// only, or shell variable assignments if there are no words
+    pub env: Vec<Assignment>,
+    /// The command name and arguments, before expansion
+    pub words: Vec<Word>,
+    /// Redirections, in the order they appeared
+    pub redirects: Vec<Redirection>,
+    /// Arguments of `local` written `NAME=(a b c)`, in order; their word in
+    /// `words` is just `NAME=`
+    pub arrays: Vec<Assignment>,
+}
+
+impl CommandParts {
+    /// The command's assignments and words joined back into one line
+    pub fn command_line(&self) -> String {
+        self.env
+            .iter()
+            .map(Assignment::to_string)
+            .chain(self.words.iter().map(Word::to_string))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Whether any word contains a `<(...)` or `>(...)` to start before running
+    pub fn has_process_substitution(&self) -> bool {
+        let targets = self.redirects.iter().filter_map(Redirection::target);
+        self.env
+            .iter()
+            .chain(&self.arrays)
+            .flat_map(Assignment::words)
+            .chain(&self.words)
+            .chain(targets)
+            .flat_map(|word| &word.parts)
+            .any(|part| matches!(part, WordPart::ProcessSubstitution { .. }))
+    }
+
+    /// Every part of the assignment values, words and redirection targets,
+    /// for replacing process substitutions
+    pub fn parts_mut(&mut self) -> impl Iterator<Item = &mut WordPart> {
+        self.env
+            .iter_mut()
+            .chain(&mut self.arrays)
+            .flat_map(Assignment::words_mut)
+            .chain(&mut self.words)
+            .flat_map(|word| &mut word.parts)
+            .chain(target_parts_mut(&mut self.redirects))
+    }
+}
+
+/// A command that can be a stage of a pipeline
+#[derive(Debug)]
+pub enum Command {
+    /// A simple command: assignments, words and redirections
+    Simple(CommandParts),
+    /// `{ list; }`: commands run in the current shell, with the group's
+    /// redirections applying to all of them
+    Group {
+        body: Vec<AndOrList>,
+        redirects: Vec<Redirection>,
+    },
+    /// `( list )`: commands run in a forked copy of the shell, so changes
+    /// to variables, the directory or options don't outlast them
+    Subshell {
+        body: Vec<AndOrList>,
+        redirects: Vec<Redirection>,
+    },
+    /// `name() { list; }`: defines a shell function
+    FunctionDef {
+        name: String,
+        body: Rc<Vec<AndOrList>>,
+    },
+    /// `if list; then list; [elif list; then list;]... [else list;] fi`
+    If {
+        /// The `if` branch followed by any `elif` branches, tried in order
+        branches: Vec<Branch>,
+        /// The `else` body, run if no condition succeeded
+        else_body: Option<Vec<AndOrList>>,
+        redirects: Vec<Redirection>,
+    },
+    /// `for name [in words]; do list; done`
+    For {
+        variable: String,
+        /// The words to loop over; without `in`, the positional parameters
+        words: Option<Vec<Word>>,
+        body: Vec<AndOrList>,
+        redirects: Vec<Redirection>,
+    },
+    /// `while list; do list; done`
+    While {
+        condition: Vec<AndOrList>,
+        body: Vec<AndOrList>,
+        redirects: Vec<Redirection>,
+    },
+    /// `case word in pattern) list;; ... esac`
+    Case {
+        word: Word,
+        items: Vec<CaseItem>,
+        redirects: Vec<Redirection>,
+    },
+    /// `[[ expression ]]`
+    Conditional {
+        condition: Condition,
+        redirects: Vec<Redirection>,
+    },
+}
+
+/// The expression of a [`Command::Conditional`]
+///
+/// Its words are expanded without field splitting or globbing, and only
+/// when they're needed, so `&&` and `||` short-circuit.
+#[derive(Debug)]
+pub enum Condition {
+    /// A word on its own, true if it isn't empty
+    Word(Word),
+    /// A test such as `-f path` or `-z string`
+    Unary { operator: String, operand: Word },
+    /// A comparison such as `a == pattern`, `a =~ regex` or `1 -lt 2`
+    Binary {
+        left: Word,
+        operator: String,
+        right: Word,
+    },
+    /// `! condition`
+    Not(Box<Condition>),
+    /// `condition && condition`
+    And(Box<Condition>, Box<Condition>),
+    /// `condition || condition`
+    Or(Box<Condition>, Box<Condition>),
+}
+
+impl std::fmt::Display for Condition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Condition::Word(word) => write!(f, "{}", word),
+            Condition::Unary { operator, operand } => write!(f, "{} {}", operator, operand),
+            Condition::Binary {
+                left,
+                operator,
+                right,
+            } => write!(f, "{} {} {}", left, operator, right),
+            Condition::Not(condition) => match **condition {
+                Condition::And(..) | Condition::Or(..) => write!(f, "! ( {} )", condition),
+                _ => write!(f, "! {}", condition),
+            },
+            Condition::And(left, right) => {
+                for (index, side) in [left, right].into_iter().enumerate() {
+                    if index > 0 {
+                        write!(f, " && ")?;
+                    }
+                    match **side {
+                        Condition::Or(..) => write!(f, "( {} )", side)?,
+                        _ => write!(f, "{}", side)?,
+                    }
+                }
+                Ok(())
+            }
+            Condition::Or(left, right) => write!(f, "{} || {}", left, right),
+        }
+    }
+}
+
+/// One `pattern|pattern) list;;` item of a [`Command::Case`]
+#[derive(Debug)]
+pub struct CaseItem {
+    /// Glob patterns, any of which selects the item
+    pub patterns: Vec<Word>,
+    /// Commands run when a pattern matches
+    pub body: Vec<AndOrList>,
+}
+
+/// One `if` or `elif` branch of an [`Command::If`]
+#[derive(Debug)]
+pub struct Branch {
+    /// Commands whose exit status decides whether the branch is taken
+    pub condition: Vec<AndOrList>,
+    /// Commands run when the condition succeeds
+    pub body: Vec<AndOrList>,
+}
+
+impl Command {
+    /// The command joined back into one line
+    pub fn command_line(&self) -> String {
+        match self {
+            Command::Simple(parts) => parts.command_line(),
+            Command::Group { body, .. } => format!("{{ {}; }}", list_command_line(body)),
+            Command::Subshell { body, .. } => format!("({})", list_command_line(body)),
+            Command::FunctionDef { name, body } => {
+                format!("{}() {{ {}; }}", name, list_command_line(body))
+            }
+            Command::If {
+                branches,
+                else_body,
+                ..
+            } => {
+                let mut line = String::new();
+                for (index, branch) in branches.iter().enumerate() {
+                    line.push_str(&format!(
+                        "{} {}; then {}; ",
+                        if index == 0 { "if" } else { "elif" },
+                        list_command_line(&branch.condition),
+                        list_command_line(&branch.body)
+                    ));
+                }
+                if let Some(body) = else_body {
+                    line.push_str(&format!("else {}; ", list_command_line(body)));
+                }
+                line + "fi"
+            }
+            Command::For {
+                variable,
+                words,
+                body,
+                ..
+            } => {
+                let words = match words {
+                    Some(words) => words
+                        .iter()
+                        .fold(" in".to_string(), |line, word| format!("{} {}", line, word)),
+                    None => String::new(),
+                };
+                format!(
+                    "for {}{}; do {}; done",
+                    variable,
+                    words,
+                    list_command_line(body)
+                )
+            }
+            Command::While {
+                condition, body, ..
+            } => format!(
+                "while {}; do {}; done",
+                list_command_line(condition),
+                list_command_line(body)
+            ),
+            Command::Case { word, items, .. } => {
+                let items = items
+                    .iter()
+                    .map(|item| {
+                        let patterns = item.patterns.iter().map(Word::to_string);
+                        format!(
+                            " {}) {};;",
+                            patterns.collect::<Vec<_>>().join("|"),
+                            list_command_line(&item.body)
+                        )
+                    })
+                    .collect::<String>();
+                format!("case {} in{} esac", word, items)
+            }
+            Command::Conditional { condition, .. } => format!("[[ {} ]]", condition),
+        }
+    }
+}
+
+/// `;`-separated lists joined back into one line
+fn list_command_line(lists: &[AndOrList]) -> String {
+    lists
+        .iter()
+        .map(AndOrList::command_line)
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Commands connected with `|`, each stage's stdout feeding the next one's stdin
+#[derive(Debug, Default)]
+pub struct Pipeline {
+    /// The stages of the pipeline, in order
+    pub commands: Vec<Command>,
+    /// Whether the pipeline should run in the background (trailing `&`)
+    pub background: bool,
+    /// Whether the exit status is inverted (leading `!`)
+    pub negated: bool,
+    /// Whether to report how long the pipeline took (leading `time`), and how
+    pub timed: Option<TimeFormat>,
+}
+
+impl Pipeline {
+    /// A pipeline with a single command
+    pub fn single(command: CommandParts) -> Self {
+        Pipeline {
+            commands: vec![Command::Simple(command)],
+            ..Default::default()
+        }
+    }
+
+    /// The pipeline's stages joined back into one line, e.g. for job listings
+    pub fn command_line(&self) -> String {
+        self.commands
+            .iter()
+            .map(Command::command_line)
+            .collect::<Vec<_>>()
+            .join(" | ")
+    }
+}
+
+/// How a pipeline is joined to the one before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connector {
+    /// `&&`: run only if the previous pipeline succeeded
+    And,
+    /// `||`: run only if the previous pipeline failed
+    Or,
+}
+
+/// Pipelines joined with `&&` and `||`, evaluated left to right
+///
+/// Each connector looks at the status of whatever ran last, so in
+/// `make && ./run || echo failed` the `echo` runs if either command fails.
+#[derive(Debug, Default)]
+pub struct AndOrList {
+    /// The pipeline that always runs
+    pub first: Pipeline,
+    /// The remaining pipelines with the connector before each
+    pub rest: Vec<(Connector, Pipeline)>,
+}
+
+impl AndOrList {
+    /// The list joined back into one line
+    pub fn command_line(&self) -> String {
+        let mut line = self.first.command_line();
+        for (connector, pipeline) in &self.rest {
+            let operator = match connector {
+                Connector::And => "&&",
+                Connector::Or => "||",
+            };
+            line.push_str(&format!(" {} {}", operator, pipeline.command_line()));
+        }
+        line
+    }
+
+    /// The pipeline that runs last
+    fn last_mut(&mut self) -> &mut Pipeline {
+        match self.rest.last_mut() {
+            Some((_, pipeline)) => pipeline,
+            None => &mut self.first,
+        }
+    }
+}
+
+/// Which quotes the lexer is inside
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Quoting {
+    Unquoted,
+    Single,
+    Double,
 }
 
 /// Lexer that tokenizes shell command input
 struct Lexer {
     position: usize,
     chars: Vec<char>,
+    /// Byte offset of each character, plus the total length at the end
+    offsets: Vec<usize>,
+    /// Set when the input ended inside quotes or right after a `\`
+    incomplete: bool,
 }
 
 impl Lexer {
     fn new(input: String) -> Self {
+        let mut offsets: Vec<usize> = input.char_indices().map(|(i, _)| i).collect();
+        offsets.push(input.len());
         Self {
             position: 0,
             chars: input.chars().collect(),
+            offsets,
+            incomplete: false,
         }
     }
 
@@ -58,126 +754,430 @@ impl Lexer {
         }
     }
 
-    /// Read a word, handling quotes and escape sequences
-    /// Supports single quotes (literal), double quotes (with escapes), and backslash escaping
-    fn read_word(&mut self) -> String {
-        let mut word = String::new();
-        let mut in_quotes = None;
+    /// Byte offset of the current position in the input
+    fn offset(&self) -> usize {
+        self.offsets[self.position]
+    }
+
+    /// Read a word, handling quotes, escape sequences and `$` references
+    ///
+    /// Follows the POSIX quoting rules: outside quotes a backslash keeps the
+    /// next character literal; inside single quotes everything is literal up
+    /// to the closing quote, backslashes included; inside double quotes a
+    /// backslash only escapes `$`, `` ` ``, `"`, `\` and a newline, and `$`
+    /// references are still expanded. A backslash-newline is removed, except
+    /// in single quotes. Quoted pieces next to each other make one word.
+    fn read_word(&mut self) -> Word {
+        let mut word = Word::default();
+        let mut quoting = Quoting::Unquoted;
 
         while let Some(ch) = self.peek() {
-            match ch {
-                '"' | '\'' => {
+            match (quoting, ch) {
+                (Quoting::Single, '\'') | (Quoting::Double, '"') => {
+                    self.advance();
+                    quoting = Quoting::Unquoted;
+                }
+                (Quoting::Single, _) => {
                     self.advance();
-                    match in_quotes {
-                        None => in_quotes = Some(ch),
-                        Some(quote) if quote == ch => in_quotes = None,
-                        Some(_) => word.push(ch),
+                    word.push(ch, true);
+                }
+                (Quoting::Unquoted, '\'' | '"') => {
+                    self.advance();
+                    quoting = if ch == '\'' {
+                        Quoting::Single
+                    } else {
+                        Quoting::Double
+                    };
+                    // Keep `''` and `""` as an (empty) quoted part
+                    word.parts.push(WordPart::Quoted(String::new()));
+                }
+                (Quoting::Unquoted, '\\') => {
+                    self.advance();
+                    match self.advance() {
+                        // A backslash-newline joins the next line onto this one
+                        Some('\n') => self.incomplete |= self.peek().is_none(),
+                        Some(next) => word.push(next, true),
+                        None => self.incomplete = true,
                     }
                 }
-                '\\' => {
+                (Quoting::Double, '\\') => {
                     self.advance();
-                    match in_quotes {
-                        None => {
-                            if let Some(next) = self.advance() {
-                                match next {
-                                    'n' => word.push('n'),
-                                    _ => word.push(next),
-                                }
-                            }
+                    match self.peek() {
+                        Some('\n') => {
+                            self.advance();
+                            self.incomplete |= self.peek().is_none();
                         }
-                        Some(quote_char) => {
-                            word.push('\\');
-                            if let Some(next) = self.advance() {
-                                if quote_char == '"' && (next == '"' || next == '\\') {
-                                    word.pop();
-                                }
-                                word.push(next);
-                            }
+                        Some(next @ ('$' | '`' | '"' | '\\')) => {
+                            self.advance();
+                            word.push(next, true);
                         }
+                        _ => word.push('\\', true),
                     }
                 }
-                ' ' | '\t' if in_quotes.is_none() => break,
-                _ => {
-                    word.push(ch);
+                (_, '$') => {
                     self.advance();
+                    let quoted = quoting == Quoting::Double;
+                    match self.read_param_name() {
+                        Some(name) => word.parts.push(WordPart::Param { name, quoted }),
+                        None => word.push('$', quoted),
+                    }
+                }
+                (Quoting::Unquoted, '<' | '>')
+                    if self.chars.get(self.position + 1) == Some(&'(') =>
+                {
+                    self.advance();
+                    self.advance();
+                    let command = self.read_substituted_command();
+                    word.parts.push(WordPart::ProcessSubstitution {
+                        command,
+                        output: ch == '>',
+                    });
+                }
+                // Operators end a word even without a space before them
+                (
+                    Quoting::Unquoted,
+                    ' ' | '\t' | '\n' | ';' | '(' | ')' | '|' | '&' | '<' | '>',
+                ) => break,
+                (_, _) => {
+                    self.advance();
+                    word.push(ch, quoting == Quoting::Double);
                 }
             }
         }
+        self.incomplete |= quoting != Quoting::Unquoted;
+        // Empty quotes only matter when they are all the word has
+        let empty = |part: &WordPart| matches!(part, WordPart::Quoted(text) if text.is_empty());
+        if word.parts.iter().all(empty) {
+            word.parts.truncate(1);
+        } else {
+            word.parts.retain(|part| !empty(part));
+        }
         word
     }
 
-    /// Tokenize the input string into a sequence of tokens
-    fn tokenize(&mut self) -> Vec<Token> {
+    /// Read the command of a `<(...)` or `>(...)` up to its closing parenthesis
+    ///
+    /// Quotes and nested parentheses are skipped over; the text is kept as
+    /// written, to be parsed when the substitution runs.
+    fn read_substituted_command(&mut self) -> String {
+        let start = self.position;
+        let mut depth = 0;
+        let mut quote = None;
+        while let Some(ch) = self.advance() {
+            match (ch, quote) {
+                ('\\', Some('\'')) => {}
+                ('\\', _) => {
+                    self.advance();
+                }
+                ('\'' | '"', None) => quote = Some(ch),
+                (_, Some(q)) if ch == q => quote = None,
+                (_, Some(_)) => {}
+                ('(', None) => depth += 1,
+                (')', None) if depth == 0 => {
+                    return self.chars[start..self.position - 1].iter().collect();
+                }
+                (')', None) => depth -= 1,
+                _ => {}
+            }
+        }
+        self.incomplete = true;
+        self.chars[start..].iter().collect()
+    }
+
+    /// Read the parameter name after a `$`
+    ///
+    /// Handles `${name}`, special parameters (`$?`, `$$`, `$!`, ...), single
+    /// digit positional parameters and plain names. Returns `None`, consuming
+    /// nothing, if no name follows, in which case the `$` is literal.
+    fn read_param_name(&mut self) -> Option<String> {
+        match self.peek()? {
+            '{' => {
+                let close = self.position + self.braced_len()?;
+                let name = self.chars[self.position + 1..close].iter().collect();
+                self.position = close + 1;
+                Some(name)
+            }
+            ch @ ('?' | '$' | '!' | '#' | '@' | '*' | '-' | '0'..='9') => {
+                self.advance();
+                Some(ch.to_string())
+            }
+            ch if ch == '_' || ch.is_ascii_alphabetic() => {
+                let mut name = String::new();
+                while let Some(ch) = self
+                    .peek()
+                    .filter(|&c| c == '_' || c.is_ascii_alphanumeric())
+                {
+                    name.push(ch);
+                    self.advance();
+                }
+                Some(name)
+            }
+            _ => None,
+        }
+    }
+
+    /// How far the `}` closing the `${` at the current position is, or
+    /// `None` if it isn't closed
+    ///
+    /// Quotes, backslashes and nested `${...}` in an operator's word, as in
+    /// `${name:-"}"}`, are skipped over.
+    fn braced_len(&self) -> Option<usize> {
+        let mut depth = 0;
+        let mut quote = None;
+        let mut chars = self.chars[self.position..].iter().enumerate().skip(1);
+        while let Some((offset, &ch)) = chars.next() {
+            match (ch, quote) {
+                ('\\', Some('\'')) => {}
+                ('\\', _) => {
+                    chars.next();
+                }
+                ('\'' | '"', None) => quote = Some(ch),
+                (_, Some(q)) if ch == q => quote = None,
+                ('$', Some('"') | None)
+                    if self.chars.get(self.position + offset + 1) == Some(&'{') =>
+                {
+                    chars.next();
+                    depth += 1;
+                }
+                (_, Some(_)) => {}
+                ('}', None) if depth == 0 => return Some(offset),
+                ('}', None) => depth -= 1,
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Read a redirection operator starting at `>` or `<`
+    ///
+    /// `fd` is the explicit descriptor written before the operator, if any.
+    fn read_redirect(&mut self, fd: Option<i32>) -> Token {
+        let input = self.advance() == Some('<');
+        let bare_output = fd.is_none() && !input;
+        let fd = fd.unwrap_or(if input { 0 } else { 1 });
+
+        if self.peek() == Some('&') {
+            self.advance();
+            if self.peek() == Some('-') {
+                self.advance();
+                return Token::CloseRedirect(fd);
+            }
+            let mut target = String::new();
+            while let Some(digit) = self.peek().filter(char::is_ascii_digit) {
+                target.push(digit);
+                self.advance();
+            }
+            return match target.parse() {
+                Ok(target) => Token::DupRedirect(fd, target),
+                // `>&file` is another spelling of `&>file`
+                Err(_) if bare_output => Token::OutputAndErrorRedirect(false),
+                Err(_) => Token::DupToWord,
+            };
+        }
+        if input && self.peek() == Some('<') {
+            self.advance();
+            if self.peek() == Some('<') {
+                self.advance();
+                return Token::HereString(fd);
+            }
+            let strip_tabs = self.peek() == Some('-');
+            if strip_tabs {
+                self.advance();
+            }
+            while matches!(self.peek(), Some(' ' | '\t')) {
+                self.advance();
+            }
+            return Token::HereDoc {
+                fd,
+                delimiter: self.read_word(),
+                strip_tabs,
+                body: None,
+            };
+        }
+        if input {
+            return Token::InputRedirect(fd);
+        }
+        let append = self.peek() == Some('>');
+        if append {
+            self.advance();
+        }
+        Token::OutputRedirect(fd, append)
+    }
+
+    /// Read the bodies of the here-documents in `tokens` at `pending`
+    ///
+    /// Called at the end of the line that started them; each body runs up to
+    /// a line consisting of just its delimiter.
+    fn read_here_docs(&mut self, tokens: &mut [SpannedToken], pending: &mut Vec<usize>) {
+        for index in pending.drain(..) {
+            if let Token::HereDoc {
+                delimiter,
+                strip_tabs,
+                body,
+                ..
+            } = &mut tokens[index].token
+            {
+                *body = self.read_here_doc_body(delimiter, *strip_tabs);
+            }
+        }
+    }
+
+    /// Read one here-document body, or `None` if the input ends before the delimiter
+    ///
+    /// With an unquoted delimiter, `$` references in the body are expanded;
+    /// any quoting in the delimiter keeps the body literal.
+    fn read_here_doc_body(&mut self, delimiter: &Word, strip_tabs: bool) -> Option<Word> {
+        let end = delimiter.literal_text();
+        let mut text = String::new();
+        loop {
+            self.peek()?;
+            let mut line = String::new();
+            while let Some(ch) = self.advance().filter(|&ch| ch != '\n') {
+                line.push(ch);
+            }
+            let line = if strip_tabs {
+                line.trim_start_matches('\t')
+            } else {
+                &line
+            };
+            if line == end {
+                break;
+            }
+            text.push_str(line);
+            text.push('\n');
+        }
+        Some(match delimiter.unquoted_text() {
+            Some(_) => here_doc_word(&text),
+            None => Word::quoted(text),
+        })
+    }
+
+    /// Consume the digits starting a token if they're a redirection's fd,
+    /// as in `2>` or `10<`, returning it
+    fn redirect_fd(&mut self) -> Option<i32> {
+        let digits = self.chars[self.position..]
+            .iter()
+            .take_while(|ch| ch.is_ascii_digit())
+            .count();
+        if !matches!(self.chars.get(self.position + digits), Some('>' | '<')) {
+            return None;
+        }
+        let fd = self.chars[self.position..self.position + digits]
+            .iter()
+            .collect::<String>()
+            .parse()
+            .ok()?;
+        self.position += digits;
+        Some(fd)
+    }
+
+    /// Tokenize the input string into a sequence of tokens with their spans
+    fn tokenize(&mut self) -> Vec<SpannedToken> {
         let mut tokens = Vec::new();
+        // Here-documents on the current line, waiting for their bodies
+        let mut pending = Vec::new();
 
         while let Some(ch) = self.peek() {
-            match ch {
+            let start = self.offset();
+            let token = match ch {
                 // Skip whitespace
                 ' ' | '\t' => {
                     self.advance();
+                    None
                 }
 
-                // Handle output redirection: > or >>
-                '>' => {
-                    self.advance();
-                    if self.peek() == Some('>') {
+                // A comment runs to the end of the line
+                '#' => {
+                    while self.peek().is_some_and(|c| c != '\n') {
                         self.advance();
-                        tokens.push(Token::OutputRedirect(true)); // append mode
-                    } else {
-                        tokens.push(Token::OutputRedirect(false)); // overwrite mode
                     }
+                    None
                 }
 
-                // Handle explicit stdout redirection: 1> or 1>>
-                '1' => {
-                    self.advance();
-                    if self.peek() == Some('>') {
-                        self.advance();
-                        if self.peek() == Some('>') {
-                            self.advance();
-                            tokens.push(Token::OutputRedirect(true)); // append mode
-                        } else {
-                            tokens.push(Token::OutputRedirect(false)); // overwrite mode
-                        }
-                    } else {
-                        // Just the number "1", not a redirect
-                        tokens.push(Token::Word("1".to_string()));
-                    }
+                // Process substitution is a word: <(cmd), >(cmd)
+                '>' | '<' if self.chars.get(self.position + 1) == Some(&'(') => {
+                    Some(Token::Word(self.read_word()))
                 }
+                // Redirections with the default fd: >, >>, >&, <, <&
+                '>' | '<' => Some(self.read_redirect(None)),
+
+                // Redirections with an explicit fd, e.g. 2> or 10<; digits
+                // not directly followed by `>` or `<` start an ordinary word
+                '0'..='9' => match self.redirect_fd() {
+                    Some(fd) => Some(self.read_redirect(Some(fd))),
+                    None => Some(Token::Word(self.read_word())),
+                },
 
-                // Handle stderr redirection: 2> or 2>>
-                '2' => {
+                ';' => {
                     self.advance();
-                    if self.peek() == Some('>') {
+                    if self.peek() == Some(';') {
                         self.advance();
-                        if self.peek() == Some('>') {
-                            self.advance();
-                            tokens.push(Token::ErrorRedirect(true));
-                        } else {
-                            tokens.push(Token::ErrorRedirect(false));
-                        }
+                        Some(Token::DoubleSemicolon)
                     } else {
-                        // Just the number "2", not a redirect
-                        tokens.push(Token::Word("2".to_string()));
+                        Some(Token::Semicolon)
                     }
                 }
+                '\n' => {
+                    self.advance();
+                    Some(Token::Newline)
+                }
+                '(' => {
+                    self.advance();
+                    Some(Token::LeftParen)
+                }
+                ')' => {
+                    self.advance();
+                    Some(Token::RightParen)
+                }
 
-                // Pipe operator
+                // Pipe operator or logical OR
                 '|' => {
                     self.advance();
-                    tokens.push(Token::Pipe);
+                    if self.peek() == Some('|') {
+                        self.advance();
+                        Some(Token::Or)
+                    } else {
+                        Some(Token::Pipe)
+                    }
                 }
-                // Background operator
+                // Background operator, logical AND, or &> / &>> to redirect stdout and stderr
                 '&' => {
                     self.advance();
-                    tokens.push(Token::Background);
+                    if self.peek() == Some('&') {
+                        self.advance();
+                        Some(Token::And)
+                    } else if self.peek() == Some('>') {
+                        self.advance();
+                        let append = self.peek() == Some('>');
+                        if append {
+                            self.advance();
+                        }
+                        Some(Token::OutputAndErrorRedirect(append))
+                    } else {
+                        Some(Token::Background)
+                    }
                 }
                 // Regular word or argument
                 _ => {
                     let word = self.read_word();
-                    if !word.is_empty() {
-                        tokens.push(Token::Word(word));
-                    }
+                    (!word.parts.is_empty()).then_some(Token::Word(word))
+                }
+            };
+
+            if let Some(token) = token {
+                let newline = token == Token::Newline;
+                if matches!(token, Token::HereDoc { .. }) {
+                    pending.push(tokens.len());
+                }
+                tokens.push(SpannedToken {
+                    token,
+                    span: Span {
+                        start,
+                        end: self.offset(),
+                    },
+                });
+                if newline {
+                    self.read_here_docs(&mut tokens, &mut pending);
                 }
             }
         }
@@ -185,62 +1185,1068 @@ impl Lexer {
     }
 }
 
+/// Parse the word of a parameter operator, such as the `default` of
+/// `${name:-default}`, where blanks and operators are ordinary characters
+///
+/// # Examples
+/// ```
+/// use codecrafters_shell::command::{operand_word, Word, WordPart};
+///
+/// let word = operand_word("a b 'c d'");
+/// assert_eq!(word.parts[0], WordPart::Literal("a b ".into()));
+/// assert_eq!(word.parts[1], WordPart::Quoted("c d".into()));
+/// ```
+pub fn operand_word(text: &str) -> Word {
+    let mut lexer = Lexer::new(text.to_string());
+    let mut word = Word::default();
+    while let Some(ch) = lexer.peek() {
+        let start = lexer.position;
+        for part in lexer.read_word().parts {
+            match (word.parts.last_mut(), part) {
+                (Some(WordPart::Literal(text)), WordPart::Literal(more))
+                | (Some(WordPart::Quoted(text)), WordPart::Quoted(more)) => text.push_str(&more),
+                (_, part) => word.parts.push(part),
+            }
+        }
+        if lexer.position == start {
+            word.push(ch, false);
+            lexer.advance();
+        }
+    }
+    word
+}
+
+/// Parse here-document text, where `$` references are expanded and a
+/// backslash only escapes `$`, `` ` ``, `\` or a newline
+fn here_doc_word(text: &str) -> Word {
+    let mut lexer = Lexer::new(text.to_string());
+    let mut word = Word::default();
+    while let Some(ch) = lexer.advance() {
+        match ch {
+            '\\' => match lexer.peek() {
+                Some(next @ ('$' | '`' | '\\')) => {
+                    lexer.advance();
+                    word.push(next, true);
+                }
+                Some('\n') => {
+                    lexer.advance();
+                }
+                _ => word.push('\\', true),
+            },
+            '$' => match lexer.read_param_name() {
+                Some(name) => word.parts.push(WordPart::Param { name, quoted: true }),
+                None => word.push('$', true),
+            },
+            _ => word.push(ch, true),
+        }
+    }
+    word
+}
+
+/// Every reserved word the parser recognises where a command starts
+pub(crate) const RESERVED_WORDS: [&str; 18] = [
+    "!", "{", "}", "if", "then", "elif", "else", "fi", "case", "esac", "for", "in", "while", "do",
+    "done", "time", "[[", "]]",
+];
+
+/// Reserved words that close a construct, and so can't start a command
+const CLOSING_WORDS: [&str; 8] = ["then", "elif", "else", "fi", "do", "done", "esac", "}"];
+
+/// Input that couldn't be parsed, and where the problem was found
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// What went wrong, as in "syntax error near unexpected token `|'"
+    pub message: String,
+    /// The offending token, or an empty span at the end of the input
+    pub span: Span,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
 /// Parser that converts tokens into a structured command representation
-pub struct CommandParser;
+pub struct CommandParser {
+    input: String,
+    tokens: Vec<Token>,
+    /// Where each token was read from in the input
+    spans: Vec<Span>,
+    position: usize,
+    /// Set when the input ended inside a construct that was never closed
+    incomplete: bool,
+    /// The first syntax error found
+    error: Option<ParseError>,
+    /// How many `( ... )` subshells the parser is inside
+    subshells: usize,
+}
 
 impl CommandParser {
-    /// Parse a command line string into CommandParts
+    /// Parse a command line string into the lists it contains, separated by
+    /// `;`, `&` or newlines, each made of pipelines joined with `&&`/`||`
+    ///
+    /// # Examples
+    /// ```
+    /// use codecrafters_shell::command::{Command, CommandParser, Connector};
+    ///
+    /// let lists = CommandParser::parse("echo hello | tr a-z A-Z > output.txt || echo failed; pwd").unwrap();
+    /// assert_eq!(lists.len(), 2);
+    /// let pipeline = &lists[0].first;
+    /// assert_eq!(pipeline.commands.len(), 2);
+    /// assert_eq!(pipeline.commands[0].command_line(), "echo hello");
+    /// assert!(matches!(&pipeline.commands[1], Command::Simple(cmd) if cmd.redirects.len() == 1));
+    /// assert_eq!(lists[0].rest[0].0, Connector::Or);
+    ///
+    /// let lists = CommandParser::parse("greet() { echo hi; }").unwrap();
+    /// assert!(matches!(&lists[0].first.commands[0], Command::FunctionDef { name, .. } if name == "greet"));
+    ///
+    /// let error = CommandParser::parse("echo a | | wc").unwrap_err();
+    /// assert_eq!(error.message, "syntax error near unexpected token `|'");
+    /// assert_eq!(error.span.start, 9);
+    /// ```
+    ///
+    /// # Errors
+    /// A [`ParseError`] for the first token that can't go where it is, or
+    /// for input that ends in the middle of a command.
+    pub fn parse(input: &str) -> Result<Vec<AndOrList>, ParseError> {
+        let mut parser = Self::new(input);
+        let lists = parser.parse_list(&[]);
+        match parser.error {
+            Some(error) => Err(error),
+            None if parser.incomplete => Err(ParseError {
+                message: "syntax error: unexpected end of file".to_string(),
+                span: parser.end(),
+            }),
+            None => Ok(lists),
+        }
+    }
+
+    /// Whether `input` ends in the middle of a command, such as inside a
+    /// `{ ... }` group that hasn't been closed, so more lines are needed
     ///
     /// # Examples
     /// ```
     /// use codecrafters_shell::command::CommandParser;
     ///
-    /// let cmd = CommandParser::parse("echo hello > output.txt");
-    /// assert_eq!(cmd.command, "echo");
-    /// assert_eq!(cmd.args, vec!["hello"]);
-    /// assert!(cmd.output_redirect.is_some());
+    /// assert!(CommandParser::is_incomplete("greet() {\n  echo hi\n"));
+    /// assert!(!CommandParser::is_incomplete("greet() {\n  echo hi\n}\n"));
+    /// assert!(CommandParser::is_incomplete("echo 'unclosed"));
+    /// assert!(CommandParser::is_incomplete("echo a \\"));
+    /// assert!(CommandParser::is_incomplete("ls |"));
     /// ```
-    pub fn parse(input: &str) -> CommandParts {
+    pub fn is_incomplete(input: &str) -> bool {
+        let mut parser = Self::new(input);
+        parser.parse_list(&[]);
+        parser.incomplete && parser.error.is_none()
+    }
+
+    fn new(input: &str) -> Self {
         let mut lexer = Lexer::new(input.to_string());
-        let tokens = lexer.tokenize();
+        let (tokens, spans) = lexer
+            .tokenize()
+            .into_iter()
+            .map(|t| (t.token, t.span))
+            .unzip();
+        CommandParser {
+            input: input.to_string(),
+            tokens,
+            spans,
+            position: 0,
+            incomplete: lexer.incomplete,
+            error: None,
+            subshells: 0,
+        }
+    }
+
+    /// An empty span at the end of the input
+    fn end(&self) -> Span {
+        Span {
+            start: self.input.len(),
+            end: self.input.len(),
+        }
+    }
+
+    /// Record a syntax error at the next token, unless one was found already
+    ///
+    /// The rest of the input is skipped, so parsing unwinds without
+    /// finding more.
+    fn unexpected(&mut self) {
+        if self.error.is_none() {
+            let (text, span) = match self.peek() {
+                Some(Token::Newline) => ("newline", self.spans[self.position]),
+                Some(_) => {
+                    let span = self.spans[self.position];
+                    (&self.input[span.start..span.end], span)
+                }
+                None => ("newline", self.end()),
+            };
+            self.error = Some(ParseError {
+                message: format!("syntax error near unexpected token `{}'", text),
+                span,
+            });
+        }
+        self.position = self.tokens.len();
+    }
 
-        let mut command_parts = CommandParts {
-            command: String::new(),
-            args: Vec::new(),
-            output_redirect: None,
-            error_redirect: None,
+    /// Note a command that's missing: the input is incomplete if it ended,
+    /// otherwise the next token is out of place
+    fn missing(&mut self) {
+        if self.peek().is_some() {
+            self.unexpected();
+        } else {
+            self.incomplete = true;
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    /// Whether the next token is the reserved word `word`, such as `{` or `}`
+    fn at_reserved(&self, word: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(w)) if w.is_unquoted(word))
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.peek() == Some(&Token::Newline) {
+            self.position += 1;
+        }
+    }
+
+    /// Move past the reserved word `word` that closes a construct, noting
+    /// the input as incomplete if it ended first
+    fn expect_reserved(&mut self, word: &str) {
+        if self.at_reserved(word) {
+            self.position += 1;
+        } else {
+            self.missing();
+        }
+    }
+
+    /// Move past newlines to the command after `|`, `&&` or `||`, which may be on a later line
+    fn skip_operand(&mut self) {
+        self.skip_newlines();
+        self.incomplete |= self.peek().is_none();
+    }
+
+    /// Parse and-or lists up to the end of the input, or up to one of the
+    /// reserved words in `until` (such as the `}` closing a group)
+    fn parse_list(&mut self, until: &[&str]) -> Vec<AndOrList> {
+        let mut lists = Vec::new();
+        // A `;` may only come right after a command
+        let mut after_command = false;
+        loop {
+            match self.peek() {
+                None => break,
+                Some(Token::Newline) => after_command = false,
+                Some(Token::Semicolon) if after_command => after_command = false,
+                _ if until.iter().any(|word| self.at_reserved(word)) => break,
+                // `;;` ends the body of a `case` item
+                Some(Token::DoubleSemicolon) if until.contains(&"esac") => break,
+                Some(Token::RightParen) if self.subshells > 0 => break,
+                Some(Token::Word(_))
+                | Some(Token::LeftParen)
+                | Some(Token::OutputRedirect(..))
+                | Some(Token::InputRedirect(_))
+                | Some(Token::DupRedirect(..))
+                | Some(Token::CloseRedirect(_))
+                | Some(Token::DupToWord)
+                | Some(Token::OutputAndErrorRedirect(_))
+                | Some(Token::HereDoc { .. })
+                | Some(Token::HereString(_)) => {
+                    // Another command can't follow without a separator
+                    if after_command {
+                        self.unexpected();
+                        break;
+                    }
+                    let mut list = self.parse_and_or();
+                    after_command = !list.last_mut().background;
+                    lists.push(list);
+                    continue;
+                }
+                // Any other operator can't start a command
+                Some(_) => {
+                    self.unexpected();
+                    break;
+                }
+            }
+            self.position += 1;
+        }
+        lists
+    }
+
+    /// Parse pipelines joined with `&&`/`||`, ending at a separator
+    ///
+    /// A trailing `&` runs the last pipeline in the background.
+    fn parse_and_or(&mut self) -> AndOrList {
+        let mut list = AndOrList {
+            first: self.parse_pipeline(),
+            rest: Vec::new(),
         };
+        loop {
+            let connector = match self.peek() {
+                Some(Token::And) => Connector::And,
+                Some(Token::Or) => Connector::Or,
+                Some(Token::Background) => {
+                    self.position += 1;
+                    list.last_mut().background = true;
+                    break;
+                }
+                _ => break,
+            };
+            self.position += 1;
+            self.skip_operand();
+            list.rest.push((connector, self.parse_pipeline()));
+        }
+        list
+    }
 
-        let mut tokens_iter = tokens.into_iter().peekable();
+    /// Parse commands joined with `|`, with an optional leading `time [-p]`
+    /// and `!`
+    fn parse_pipeline(&mut self) -> Pipeline {
+        let mut pipeline = Pipeline::default();
+        if self.at_reserved("time") {
+            self.position += 1;
+            pipeline.timed = Some(TimeFormat::Default);
+            if self.at_reserved("-p") {
+                self.position += 1;
+                pipeline.timed = Some(TimeFormat::Posix);
+            }
+        }
+        // A `!` before the first command inverts the pipeline's exit status
+        if self.at_reserved("!") {
+            self.position += 1;
+            pipeline.negated = true;
+        }
+        // Either may stand alone, timing or negating an empty command
+        let prefixed = pipeline.negated || pipeline.timed.is_some();
+        if prefixed && matches!(self.peek(), None | Some(Token::Semicolon | Token::Newline)) {
+            pipeline
+                .commands
+                .push(Command::Simple(CommandParts::default()));
+            return pipeline;
+        }
+        loop {
+            pipeline.commands.push(self.parse_command());
+            if self.peek() != Some(&Token::Pipe) {
+                break;
+            }
+            self.position += 1;
+            self.skip_operand();
+        }
+        pipeline
+    }
+
+    /// Parse one pipeline stage: a brace group, a subshell, an `if`, a
+    /// `case`, a loop, a function definition or a simple command
+    fn parse_command(&mut self) -> Command {
+        if CLOSING_WORDS.iter().any(|word| self.at_reserved(word)) {
+            self.unexpected();
+            return Command::Simple(CommandParts::default());
+        }
+
+        if self.at_reserved("{") {
+            self.position += 1;
+            let body = self.parse_list(&["}"]);
+            self.expect_reserved("}");
+            let mut redirects = Vec::new();
+            while self.parse_redirect(&mut redirects) {}
+            return Command::Group { body, redirects };
+        }
+
+        if self.peek() == Some(&Token::LeftParen) {
+            self.position += 1;
+            self.subshells += 1;
+            let body = self.parse_list(&[]);
+            self.subshells -= 1;
+            if body.is_empty() {
+                self.missing();
+            } else if self.peek() == Some(&Token::RightParen) {
+                self.position += 1;
+            } else {
+                self.missing();
+            }
+            let mut redirects = Vec::new();
+            while self.parse_redirect(&mut redirects) {}
+            return Command::Subshell { body, redirects };
+        }
+
+        if self.at_reserved("if") {
+            self.position += 1;
+            return self.parse_if();
+        }
+
+        if self.at_reserved("case") {
+            self.position += 1;
+            return self.parse_case();
+        }
+
+        if self.at_reserved("[[") {
+            self.position += 1;
+            let condition = self.parse_condition_or();
+            self.skip_newlines();
+            self.expect_reserved("]]");
+            let mut redirects = Vec::new();
+            while self.parse_redirect(&mut redirects) {}
+            return Command::Conditional {
+                condition,
+                redirects,
+            };
+        }
+
+        if self.at_reserved("for") {
+            self.position += 1;
+            return self.parse_for();
+        }
+
+        if self.at_reserved("while") {
+            self.position += 1;
+            let condition = self.parse_list(&["do"]);
+            let body = self.parse_do_group();
+            let mut redirects = Vec::new();
+            while self.parse_redirect(&mut redirects) {}
+            return Command::While {
+                condition,
+                body,
+                redirects,
+            };
+        }
+
+        if let (Some(Token::Word(name)), Some(Token::LeftParen), Some(Token::RightParen)) = (
+            self.tokens.get(self.position),
+            self.tokens.get(self.position + 1),
+            self.tokens.get(self.position + 2),
+        ) {
+            let name = name.unquoted_text().filter(|_| name.assignment().is_none());
+            if let Some(name) = name.map(str::to_string) {
+                self.position += 3;
+                self.skip_newlines();
+                let body = match self.parse_command() {
+                    Command::Group { body, redirects } if redirects.is_empty() => body,
+                    command => vec![AndOrList {
+                        first: Pipeline {
+                            commands: vec![command],
+                            ..Default::default()
+                        },
+                        rest: Vec::new(),
+                    }],
+                };
+                return Command::FunctionDef {
+                    name,
+                    body: Rc::new(body),
+                };
+            }
+        }
+
+        let command = self.parse_simple_command();
+        if command.words.is_empty() && command.env.is_empty() && command.redirects.is_empty() {
+            self.missing();
+        }
+        Command::Simple(command)
+    }
+
+    /// Parse the rest of an `if` command, after the `if`
+    fn parse_if(&mut self) -> Command {
+        let mut branches = Vec::new();
+        let mut else_body = None;
+        loop {
+            let condition = self.parse_list(&["then"]);
+            self.expect_reserved("then");
+            let body = self.parse_list(&["elif", "else", "fi"]);
+            branches.push(Branch { condition, body });
+            if self.at_reserved("elif") {
+                self.position += 1;
+                continue;
+            }
+            if self.at_reserved("else") {
+                self.position += 1;
+                else_body = Some(self.parse_list(&["fi"]));
+            }
+            self.expect_reserved("fi");
+            break;
+        }
+        let mut redirects = Vec::new();
+        while self.parse_redirect(&mut redirects) {}
+        Command::If {
+            branches,
+            else_body,
+            redirects,
+        }
+    }
+
+    /// Parse the rest of a `for` loop, after the `for`
+    fn parse_for(&mut self) -> Command {
+        let mut variable = String::new();
+        if let Some(Token::Word(word)) = self.peek() {
+            variable = word.literal_text();
+            self.position += 1;
+        }
+        self.skip_newlines();
+        let words = self.at_reserved("in").then(|| {
+            self.position += 1;
+            let mut words = Vec::new();
+            while let Some(Token::Word(word)) = self.peek() {
+                words.push(word.clone());
+                self.position += 1;
+            }
+            words
+        });
+        if self.peek() == Some(&Token::Semicolon) {
+            self.position += 1;
+        }
+        self.skip_newlines();
+        let body = self.parse_do_group();
+        let mut redirects = Vec::new();
+        while self.parse_redirect(&mut redirects) {}
+        Command::For {
+            variable,
+            words,
+            body,
+            redirects,
+        }
+    }
+
+    /// Parse the rest of a `case` command, after the `case`
+    fn parse_case(&mut self) -> Command {
+        let mut word = Word::default();
+        if let Some(Token::Word(subject)) = self.peek() {
+            word = subject.clone();
+            self.position += 1;
+        }
+        self.skip_newlines();
+        self.expect_reserved("in");
 
-        // Process tokens to build command structure
-        while let Some(token) = tokens_iter.next() {
+        let mut items = Vec::new();
+        loop {
+            self.skip_newlines();
+            if self.peek().is_none() || self.at_reserved("esac") {
+                break;
+            }
+            // Each item is `[(]pattern[|pattern]...) list`
+            if self.peek() == Some(&Token::LeftParen) {
+                self.position += 1;
+            }
+            let mut patterns = Vec::new();
+            while let Some(Token::Word(pattern)) = self.peek() {
+                patterns.push(pattern.clone());
+                self.position += 1;
+                if self.peek() != Some(&Token::Pipe) {
+                    break;
+                }
+                self.position += 1;
+            }
+            if self.peek() == Some(&Token::RightParen) {
+                self.position += 1;
+            }
+            let body = self.parse_list(&["esac"]);
+            items.push(CaseItem { patterns, body });
+            if self.peek() != Some(&Token::DoubleSemicolon) {
+                break;
+            }
+            self.position += 1;
+        }
+        self.expect_reserved("esac");
+
+        let mut redirects = Vec::new();
+        while self.parse_redirect(&mut redirects) {}
+        Command::Case {
+            word,
+            items,
+            redirects,
+        }
+    }
+
+    /// Parse `condition || condition` inside `[[ ... ]]`
+    fn parse_condition_or(&mut self) -> Condition {
+        let mut condition = self.parse_condition_and();
+        while self.peek() == Some(&Token::Or) {
+            self.position += 1;
+            let right = self.parse_condition_and();
+            condition = Condition::Or(Box::new(condition), Box::new(right));
+        }
+        condition
+    }
+
+    /// Parse `condition && condition`, which binds tighter than `||`
+    fn parse_condition_and(&mut self) -> Condition {
+        let mut condition = self.parse_condition_not();
+        while self.peek() == Some(&Token::And) {
+            self.position += 1;
+            let right = self.parse_condition_not();
+            condition = Condition::And(Box::new(condition), Box::new(right));
+        }
+        condition
+    }
+
+    /// Parse `! condition`, a parenthesized condition or a single test
+    fn parse_condition_not(&mut self) -> Condition {
+        self.skip_newlines();
+        if self.at_reserved("!") {
+            self.position += 1;
+            return Condition::Not(Box::new(self.parse_condition_not()));
+        }
+        if self.peek() == Some(&Token::LeftParen) {
+            self.position += 1;
+            let condition = self.parse_condition_or();
+            self.skip_newlines();
+            if self.peek() == Some(&Token::RightParen) {
+                self.position += 1;
+            } else {
+                self.missing();
+            }
+            return condition;
+        }
+
+        let Some(first) = self.condition_word() else {
+            self.incomplete |= self.peek().is_none();
+            return Condition::Word(Word::default());
+        };
+        if let Some(operator) = self.condition_operator() {
+            self.position += 1;
+            let right = if operator == "=~" {
+                self.parse_regex_word()
+            } else {
+                self.condition_word().unwrap_or_default()
+            };
+            return Condition::Binary {
+                left: first,
+                operator,
+                right,
+            };
+        }
+        // `-f path`; a lone `-f` is just a non-empty string
+        let unary = first
+            .unquoted_text()
+            .filter(|text| text.len() == 2 && text.starts_with('-'))
+            .map(str::to_string);
+        if let Some(operator) = unary {
+            if let Some(operand) = self.condition_word() {
+                return Condition::Unary { operator, operand };
+            }
+        }
+        Condition::Word(first)
+    }
+
+    /// Take the next word inside `[[ ... ]]`, unless it's the closing `]]`
+    fn condition_word(&mut self) -> Option<Word> {
+        match self.peek() {
+            Some(Token::Word(word)) if !word.is_unquoted("]]") => {
+                let word = word.clone();
+                self.position += 1;
+                Some(word)
+            }
+            _ => None,
+        }
+    }
+
+    /// The binary operator inside `[[ ... ]]` that comes next, if any
+    ///
+    /// `<` and `>` compare strings here rather than redirecting.
+    fn condition_operator(&self) -> Option<String> {
+        match self.peek()? {
+            Token::InputRedirect(0) => Some("<".to_string()),
+            Token::OutputRedirect(1, false) => Some(">".to_string()),
+            Token::Word(word) => word
+                .unquoted_text()
+                .filter(|text| {
+                    [
+                        "=", "==", "!=", "=~", "-eq", "-ne", "-lt", "-le", "-gt", "-ge",
+                    ]
+                    .contains(text)
+                })
+                .map(str::to_string),
+            _ => None,
+        }
+    }
+
+    /// Read the regular expression after `=~`
+    ///
+    /// It runs up to the next blank, so it may contain parentheses and `|`,
+    /// which would otherwise be operators.
+    fn parse_regex_word(&mut self) -> Word {
+        let mut regex = Word::default();
+        let mut depth = 0;
+        let mut previous_end = None;
+        while let Some(token) = self.peek() {
+            let span = self.spans[self.position];
+            if previous_end.is_some_and(|end| end != span.start) {
+                break;
+            }
             match token {
-                Token::Word(word) => {
-                    // First word is the command, rest are arguments
-                    if command_parts.command.is_empty() {
-                        command_parts.command = word;
-                    } else {
-                        command_parts.args.push(word);
-                    }
+                Token::Word(word) if previous_end.is_some() || !word.is_unquoted("]]") => {
+                    regex.parts.extend(word.parts.iter().cloned());
+                }
+                Token::LeftParen => {
+                    depth += 1;
+                    regex.push('(', false);
                 }
-                Token::OutputRedirect(append) => {
-                    // Next token should be the file path
-                    if let Some(Token::Word(path)) = tokens_iter.next() {
-                        command_parts.output_redirect = Some((PathBuf::from(path), append));
+                Token::RightParen if depth > 0 => {
+                    depth -= 1;
+                    regex.push(')', false);
+                }
+                Token::Pipe => regex.push('|', false),
+                _ => break,
+            }
+            previous_end = Some(span.end);
+            self.position += 1;
+        }
+        regex
+    }
+
+    /// Parse a loop body: `do list; done`
+    fn parse_do_group(&mut self) -> Vec<AndOrList> {
+        self.expect_reserved("do");
+        let body = self.parse_list(&["done"]);
+        self.expect_reserved("done");
+        body
+    }
+
+    /// Parse assignments, words and redirections up to the next operator
+    fn parse_simple_command(&mut self) -> CommandParts {
+        let mut command_parts = CommandParts::default();
+        loop {
+            if self.parse_redirect(&mut command_parts.redirects) {
+                continue;
+            }
+            let Some(Token::Word(word)) = self.peek() else {
+                break;
+            };
+            let word = word.clone();
+            self.position += 1;
+            // Assignments are only recognized before the command name
+            if command_parts.words.is_empty() {
+                if let Some(mut assignment) = word.assignment() {
+                    if assignment.value == AssignedValue::Scalar(Word::default()) {
+                        if let Some(words) = self.parse_array_value() {
+                            assignment.value = AssignedValue::Array(words);
+                        }
                     }
+                    command_parts.env.push(assignment);
+                    continue;
                 }
-                Token::ErrorRedirect(append) => {
-                    // Next token should be the file path
-                    if let Some(Token::Word(path)) = tokens_iter.next() {
-                        command_parts.error_redirect = Some((PathBuf::from(path), append));
+            } else if command_parts.words[0].is_unquoted("local") {
+                // `local` takes array assignments as arguments too
+                if let Some(mut assignment) = word.assignment() {
+                    if assignment.value == AssignedValue::Scalar(Word::default()) {
+                        if let Some(words) = self.parse_array_value() {
+                            assignment.value = AssignedValue::Array(words);
+                            command_parts.arrays.push(assignment);
+                        }
                     }
                 }
-                // Pipe and Background tokens are recognized but not yet handled
-                _ => {}
             }
+            command_parts.words.push(word);
         }
-
         command_parts
     }
+
+    /// Parse the `(a b c)` of an array assignment, if it comes right after the `=`
+    ///
+    /// The words may span lines.
+    fn parse_array_value(&mut self) -> Option<Vec<Word>> {
+        let previous = self.spans[self.position - 1];
+        if self.peek() != Some(&Token::LeftParen) || self.spans[self.position].start != previous.end
+        {
+            return None;
+        }
+        self.position += 1;
+        let mut words = Vec::new();
+        loop {
+            match self.peek() {
+                Some(Token::Word(word)) => {
+                    words.push(word.clone());
+                    self.position += 1;
+                }
+                Some(Token::Newline) => self.position += 1,
+                Some(Token::RightParen) => {
+                    self.position += 1;
+                    break;
+                }
+                _ => {
+                    self.missing();
+                    break;
+                }
+            }
+        }
+        Some(words)
+    }
+
+    /// Parse a redirection operator and its target, if one comes next
+    fn parse_redirect(&mut self, redirects: &mut Vec<Redirection>) -> bool {
+        let operator = match self.peek() {
+            Some(
+                token @ (Token::OutputRedirect(..)
+                | Token::InputRedirect(_)
+                | Token::DupRedirect(..)
+                | Token::CloseRedirect(_)
+                | Token::DupToWord
+                | Token::OutputAndErrorRedirect(_)
+                | Token::HereDoc { .. }
+                | Token::HereString(_)),
+            ) => token.clone(),
+            _ => return false,
+        };
+        self.position += 1;
+
+        match operator {
+            Token::DupRedirect(fd, target) => {
+                redirects.push(Redirection::Duplicate { fd, target });
+                return true;
+            }
+            Token::CloseRedirect(fd) => {
+                redirects.push(Redirection::Close { fd });
+                return true;
+            }
+            // Only a descriptor or `-` can follow `2>&` or `<&`
+            Token::DupToWord => {
+                self.unexpected();
+                return true;
+            }
+            Token::HereDoc { fd, body, .. } => {
+                // The body is still to come in later lines
+                self.incomplete |= body.is_none();
+                redirects.push(Redirection::HereDoc {
+                    fd,
+                    body: body.unwrap_or_default(),
+                });
+                return true;
+            }
+            _ => {}
+        }
+        // The next token should be the file path
+        let Some(Token::Word(path)) = self.peek().cloned() else {
+            self.unexpected();
+            return true;
+        };
+        self.position += 1;
+        match operator {
+            Token::OutputRedirect(fd, append) => {
+                redirects.push(Redirection::Output { fd, path, append })
+            }
+            Token::InputRedirect(fd) => redirects.push(Redirection::Input { fd, path }),
+            // The word's value plus a newline becomes the input
+            Token::HereString(fd) => {
+                let mut body = path;
+                body.parts.push(WordPart::Quoted("\n".to_string()));
+                redirects.push(Redirection::HereDoc { fd, body });
+            }
+            // Same as `> path 2>&1`
+            Token::OutputAndErrorRedirect(append) => {
+                redirects.push(Redirection::Output {
+                    fd: 1,
+                    path,
+                    append,
+                });
+                redirects.push(Redirection::Duplicate { fd: 2, target: 1 });
+            }
+            _ => {}
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The words `input` lexes to, ignoring any other tokens
+    fn words(input: &str) -> Vec<Word> {
+        tokenize(input)
+            .into_iter()
+            .filter_map(|spanned| match spanned.token {
+                Token::Word(word) => Some(word),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn texts(input: &str) -> Vec<String> {
+        words(input).iter().map(Word::literal_text).collect()
+    }
+
+    fn literal(text: &str) -> WordPart {
+        WordPart::Literal(text.to_string())
+    }
+
+    fn quoted(text: &str) -> WordPart {
+        WordPart::Quoted(text.to_string())
+    }
+
+    fn param(name: &str, quoted: bool) -> WordPart {
+        WordPart::Param {
+            name: name.to_string(),
+            quoted,
+        }
+    }
+
+    #[test]
+    fn blanks_separate_unquoted_words() {
+        assert_eq!(texts("echo  a\tb"), ["echo", "a", "b"]);
+        assert_eq!(words("abc")[0].parts, [literal("abc")]);
+    }
+
+    #[test]
+    fn single_quotes_keep_everything_literal() {
+        assert_eq!(words("'a b'")[0].parts, [quoted("a b")]);
+        assert_eq!(words("'$HOME'")[0].parts, [quoted("$HOME")]);
+        assert_eq!(words("'\"x\"'")[0].parts, [quoted("\"x\"")]);
+    }
+
+    #[test]
+    fn backslash_is_literal_in_single_quotes() {
+        assert_eq!(texts(r"'a\' b"), [r"a\", "b"]);
+        assert_eq!(texts(r"'a\nb'"), [r"a\nb"]);
+        assert_eq!(texts("'a\\\nb'"), ["a\\\nb"]);
+    }
+
+    #[test]
+    fn double_quotes_expand_parameters() {
+        assert_eq!(
+            words("\"a $x b\"")[0].parts,
+            [quoted("a "), param("x", true), quoted(" b")]
+        );
+        assert_eq!(words("'$x'\"$x\"$x")[0].parts.len(), 3);
+        assert_eq!(words("$x")[0].parts, [param("x", false)]);
+    }
+
+    #[test]
+    fn backslash_in_double_quotes_escapes_only_special_characters() {
+        assert_eq!(texts(r#""\$ \` \" \\""#), [r#"$ ` " \"#]);
+        assert_eq!(texts(r#""\a\n\'""#), [r"\a\n\'"]);
+        assert_eq!(words(r#""\$x""#)[0].parts, [quoted("$x")]);
+    }
+
+    #[test]
+    fn backslash_outside_quotes_quotes_the_next_character() {
+        assert_eq!(texts(r"a\ b c"), ["a b", "c"]);
+        assert_eq!(texts(r"\n\'\\"), [r"n'\"]);
+        assert_eq!(words(r"\$x")[0].parts, [quoted("$"), literal("x")]);
+        assert_eq!(texts(r"a\;b a\|b"), ["a;b", "a|b"]);
+    }
+
+    #[test]
+    fn backslash_newline_continues_the_line() {
+        assert_eq!(texts("ec\\\nho a"), ["echo", "a"]);
+        assert_eq!(texts("\"a\\\nb\""), ["ab"]);
+        assert!(CommandParser::is_incomplete("echo a\\\n"));
+        assert!(!CommandParser::is_incomplete("echo a\\\nb"));
+    }
+
+    #[test]
+    fn adjacent_quoted_pieces_form_one_word() {
+        assert_eq!(texts(r#"a"b c"'d e'f"#), ["ab cd ef"]);
+        assert_eq!(
+            words(r#"a"b"'c'"#)[0].parts,
+            [literal("a"), quoted("b"), quoted("c")]
+        );
+    }
+
+    #[test]
+    fn empty_quotes_make_an_empty_word() {
+        for input in ["''", "\"\"", "\"\"''"] {
+            assert_eq!(words(input), [Word::quoted("")], "{input}");
+        }
+        // but add nothing next to other text
+        assert_eq!(words("a''b")[0].parts, [literal("a"), literal("b")]);
+        assert_eq!(words("''$x")[0].parts, [param("x", false)]);
+    }
+
+    #[test]
+    fn quoted_operators_stay_in_the_word() {
+        assert_eq!(
+            texts("'a|b' \"c;d\" 'e&f' \"<g>\""),
+            ["a|b", "c;d", "e&f", "<g>"]
+        );
+        assert_eq!(texts("a#b '#c'"), ["a#b", "#c"]);
+        assert_eq!(texts("a;b"), ["a", "b"]);
+    }
+
+    #[test]
+    fn digits_are_an_fd_only_right_before_a_redirection() {
+        assert_eq!(
+            texts("2fast2furious 1password a2>b"),
+            ["2fast2furious", "1password", "a2", "b"]
+        );
+        let tokens: Vec<Token> = tokenize("cmd 2>err 12>>log 0<in 3 >out")
+            .into_iter()
+            .map(|spanned| spanned.token)
+            .collect();
+        assert_eq!(tokens[1], Token::OutputRedirect(2, false));
+        assert_eq!(tokens[3], Token::OutputRedirect(12, true));
+        assert_eq!(tokens[5], Token::InputRedirect(0));
+        assert_eq!(
+            tokens[7],
+            Token::Word(Word {
+                parts: vec![literal("3")]
+            })
+        );
+        assert_eq!(tokens[8], Token::OutputRedirect(1, false));
+    }
+
+    fn parse_error(input: &str) -> (String, usize) {
+        let error = CommandParser::parse(input).unwrap_err();
+        (error.message, error.span.start)
+    }
+
+    #[test]
+    fn misplaced_operators_are_syntax_errors() {
+        let near = |token: &str| format!("syntax error near unexpected token `{}'", token);
+        assert_eq!(parse_error("| wc"), (near("|"), 0));
+        assert_eq!(parse_error("ls && || wc"), (near("||"), 6));
+        assert_eq!(parse_error("ls; ; wc"), (near(";"), 4));
+        assert_eq!(parse_error("ls & ; wc"), (near(";"), 5));
+        assert_eq!(parse_error("ls;; wc"), (near(";;"), 2));
+        assert_eq!(parse_error("ls )"), (near(")"), 3));
+        assert_eq!(parse_error("echo > | wc"), (near("|"), 7));
+        assert_eq!(parse_error("echo >"), (near("newline"), 6));
+        assert_eq!(parse_error("{ ls; fi; }"), (near("fi"), 6));
+        assert_eq!(parse_error("if ls; then ls; done"), (near("done"), 16));
+        assert_eq!(parse_error("echo 2>&file"), (near("file"), 8));
+        assert_eq!(parse_error("cat <&in"), (near("in"), 6));
+    }
+
+    #[test]
+    fn dash_after_a_duplication_closes_the_fd() {
+        let kinds =
+            |input: &str| -> Vec<Token> { tokenize(input).into_iter().map(|t| t.token).collect() };
+        assert_eq!(kinds("2>&-")[0], Token::CloseRedirect(2));
+        assert_eq!(kinds("<&-")[0], Token::CloseRedirect(0));
+        assert_eq!(kinds(">&2")[0], Token::DupRedirect(1, 2));
+        assert_eq!(kinds(">&out")[0], Token::OutputAndErrorRedirect(false));
+    }
+
+    #[test]
+    fn unfinished_input_is_an_unexpected_end_of_file() {
+        let eof = "syntax error: unexpected end of file".to_string();
+        assert_eq!(parse_error("ls |"), (eof.clone(), 4));
+        assert_eq!(parse_error("if ls; then"), (eof.clone(), 11));
+        assert_eq!(parse_error("echo 'a"), (eof, 7));
+        assert!(!CommandParser::is_incomplete("ls | ; wc"));
+    }
+
+    #[test]
+    fn separators_may_end_and_repeat_between_commands() {
+        for input in [
+            "ls;",
+            "ls &",
+            "ls & ls",
+            "ls\n\nls\n",
+            "! ;",
+            "time",
+            "case a in a) ;; esac",
+        ] {
+            assert!(CommandParser::parse(input).is_ok(), "{input}");
+        }
+    }
+
+    #[test]
+    fn unclosed_quotes_leave_the_input_incomplete() {
+        assert!(CommandParser::is_incomplete("echo 'a"));
+        assert!(CommandParser::is_incomplete("echo \"a"));
+        assert!(CommandParser::is_incomplete("echo \"a\\\""));
+        assert!(CommandParser::is_incomplete("echo a\\"));
+        assert!(!CommandParser::is_incomplete("echo 'a\\'"));
+        assert_eq!(texts("echo 'a\nb'"), ["echo", "a\nb"]);
+    }
 }
@@ -1,15 +1,28 @@
-use rustyline_derive::{Helper, Highlighter, Hinter, Validator};
+use crate::command::{CommandParser, Word};
+use crate::generator::{Generator, Request};
+use crate::jobs::JobTable;
+use crate::lookup::CommandHashes;
+use crate::menu::CompletionMenu;
+use rustyline::completion::Pair;
+use rustyline::highlight::CmdKind;
+use rustyline::hint::Hint;
+use rustyline::history::SearchDirection;
+use rustyline::validate::{ValidationContext, ValidationResult};
+use rustyline_derive::Helper;
+use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
 use std::env;
-use std::io::Write;
-use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use std::sync::{Arc, RwLock};
-use std::time::Instant;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-/// Track last tab press time for double-tab detection
-static LAST_TAB_TIME: AtomicU64 = AtomicU64::new(0);
-/// Track whether tab was pressed recently
-static TAB_PRESSED: AtomicBool = AtomicBool::new(false);
+/// How soon a second Tab on an ambiguous word must follow the first to
+/// open the menu
+const DOUBLE_TAB: Duration = Duration::from_millis(500);
+
+/// How many times a command must have been run before Tab completes it
+/// over other matches, as [`RustylineHelper::complete_command`] does
+const DOMINANT_USES: usize = 3;
 
 /// Trie (prefix tree) node for efficient command completion
 ///
@@ -40,6 +53,27 @@ impl TrieNode {
         current.word = word;
     }
 
+    /// Remove a word from the trie, pruning branches that become empty
+    ///
+    /// Returns true if this node has no remaining words and can be dropped.
+    fn remove(&mut self, word: &str) -> bool {
+        let mut chars = word.chars();
+        match chars.next() {
+            None => {
+                self.is_end = false;
+                self.word.clear();
+            }
+            Some(ch) => {
+                if let Some(child) = self.children.get_mut(&ch) {
+                    if child.remove(chars.as_str()) {
+                        self.children.remove(&ch);
+                    }
+                }
+            }
+        }
+        !self.is_end && self.children.is_empty()
+    }
+
     /// Find all words with the given prefix
     fn find_prefix(&self, prefix: &str) -> Vec<String> {
         let mut current = self;
@@ -69,156 +103,1668 @@ impl TrieNode {
             Self::collect_words(child, results);
         }
     }
+}
 
-    /// Find the longest common prefix for completion
-    ///
-    /// Returns:
-    /// - If single match: the complete word with a trailing space
-    /// - If multiple matches with common prefix longer than input: the common prefix
-    /// - If double-tab (< 500ms): display all matches and return None
-    /// - Otherwise: return None
-    fn find_common_prefix(&self, prefix: &str) -> Option<String> {
-        let mut matches = self.find_prefix(prefix);
-        if matches.is_empty() {
-            return None;
+/// What pressing Tab does to a word, given every word it could become
+enum Completion {
+    /// Replace it with this text, `true` for a finished word (one not
+    /// ending in `/`, which is a directory still to be completed into)
+    Word(String, bool),
+    /// Nothing to add: offer all the matches if Tab was just pressed
+    Ambiguous(Vec<String>),
+    /// Offer all the matches now
+    Menu(Vec<String>),
+}
+
+/// Complete `typed` given every word it could become
+///
+/// Returns:
+/// - If single match: the match
+/// - If multiple matches with common prefix longer than input: the common prefix
+/// - Otherwise: all the matches, for the menu if Tab is pressed again
+fn complete_from(typed: &str, mut matches: Vec<String>) -> Option<Completion> {
+    if matches.is_empty() {
+        return None;
+    }
+
+    if matches.len() == 1 {
+        let completion = matches.remove(0);
+        let complete = !completion.ends_with('/');
+        return Some(Completion::Word(completion, complete));
+    }
+
+    // Find longest common prefix among all matches
+    matches.sort();
+    let common_prefix = common_prefix(&matches);
+
+    // If we can extend the prefix, do so
+    if common_prefix.len() > typed.len() {
+        return Some(Completion::Word(common_prefix, false));
+    }
+
+    Some(Completion::Ambiguous(matches))
+}
+
+/// The longest prefix that all of `matches` share
+fn common_prefix(matches: &[String]) -> String {
+    let mut common_prefix = matches.first().cloned().unwrap_or_default();
+    for name in matches.iter().skip(1) {
+        while !name.starts_with(&common_prefix) {
+            common_prefix.pop();
+        }
+    }
+    common_prefix
+}
+
+/// Complete a word given command names that match it loosely, best first,
+/// and don't all start with it
+///
+/// A single match replaces the word, and several go straight to the menu in
+/// their ranked order, since there's no common prefix to add.
+fn complete_loosely(ranked: Vec<String>) -> Option<Completion> {
+    match <[String; 1]>::try_from(ranked) {
+        Ok([name]) => Some(Completion::Word(name, true)),
+        Err(ranked) if ranked.is_empty() => None,
+        Err(ranked) => Some(Completion::Menu(ranked)),
+    }
+}
+
+/// The command a history entry runs: its first word that isn't an
+/// assignment
+fn command_name(entry: &str) -> Option<&str> {
+    entry.split_whitespace().find(|word| !word.contains('='))
+}
+
+/// How command names are matched against what's typed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MatchMode {
+    /// Names starting with the text
+    #[default]
+    Prefix,
+    /// Names containing the text
+    Substring,
+    /// Names containing the text's characters in order
+    Fuzzy,
+}
+
+/// How well `name` matches `typed` in `mode`, higher being better, or
+/// `None` if it doesn't match
+///
+/// A substring scores higher the earlier it starts. A fuzzy match scores
+/// each character of `typed`, more where it starts the name or a part of
+/// it after `-`, `_` or `.`, and more again right after the previous one.
+///
+/// # Examples
+/// ```
+/// use codecrafters_shell::completion::{match_score, MatchMode};
+///
+/// assert_eq!(match_score("grep", "gr", MatchMode::Prefix), Some(0));
+/// assert_eq!(match_score("pgrep", "gr", MatchMode::Prefix), None);
+/// assert!(match_score("grep", "gr", MatchMode::Substring) > match_score("pgrep", "gr", MatchMode::Substring));
+///
+/// let best = match_score("git-switch-wrapper", "gsw", MatchMode::Fuzzy);
+/// assert!(best > match_score("gsettings-wrapper", "gsw", MatchMode::Fuzzy));
+/// assert_eq!(match_score("ls", "sl", MatchMode::Fuzzy), None);
+/// ```
+pub fn match_score(name: &str, typed: &str, mode: MatchMode) -> Option<usize> {
+    match mode {
+        MatchMode::Prefix => name.starts_with(typed).then_some(0),
+        MatchMode::Substring => name.find(typed).map(|start| usize::MAX - start),
+        MatchMode::Fuzzy => {
+            let chars: Vec<char> = name.chars().collect();
+            let starts_part = |index: usize| index == 0 || "-_.".contains(chars[index - 1]);
+            // Whether `typed` matches from `start` on
+            let fits = |mut start: usize, typed: &str| {
+                typed.chars().all(|wanted| {
+                    let found = chars[start..].iter().position(|&ch| ch == wanted);
+                    found.inspect(|offset| start += offset + 1).is_some()
+                })
+            };
+
+            let (mut score, mut start, mut last) = (0, 0, None);
+            for (offset, wanted) in typed.char_indices() {
+                let rest = &typed[offset + wanted.len_utf8()..];
+                let mut found = (start..chars.len()).filter(|&index| chars[index] == wanted);
+                let first = found.next()?;
+                // Skip ahead to where a part starts, if the rest still fits after it
+                let index = std::iter::once(first)
+                    .chain(found)
+                    .find(|&index| starts_part(index) && fits(index + 1, rest))
+                    .unwrap_or(first);
+                score += 1;
+                if starts_part(index) {
+                    score += 2;
+                }
+                if last.is_some_and(|last| last + 1 == index) {
+                    score += 1;
+                }
+                last = Some(index);
+                start = index + 1;
+            }
+            Some(score)
         }
+    }
+}
 
-        // Single match: complete with space
-        if matches.len() == 1 {
-            return Some(matches[0].clone() + " ");
+/// The fewest single-character insertions, deletions, substitutions and
+/// swaps of neighbours that turn `a` into `b`
+///
+/// # Examples
+/// ```
+/// use codecrafters_shell::completion::edit_distance;
+///
+/// assert_eq!(edit_distance("gti", "git"), 1);
+/// assert_eq!(edit_distance("grpe", "grep"), 1);
+/// assert_eq!(edit_distance("ct", "cat"), 1);
+/// assert_eq!(edit_distance("kitten", "sitting"), 3);
+/// ```
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    // Rows of the table for the prefixes of `a` two back, one back and now
+    let mut before: Vec<usize> = Vec::new();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        let mut current = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            current[j] = (previous[j] + 1)
+                .min(current[j - 1] + 1)
+                .min(previous[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                current[j] = current[j].min(before[j - 2] + 1);
+            }
         }
+        before = std::mem::replace(&mut previous, current);
+    }
+    previous[b.len()]
+}
 
-        // Find longest common prefix among all matches
-        matches.sort();
-        let mut common_prefix = matches[0].clone();
-        for name in &matches[1..] {
-            while !name.starts_with(&common_prefix) {
-                common_prefix.pop();
+/// The first of `names` closest to the mistyped command `typed`, if one is
+/// near enough to be what was meant
+///
+/// Up to 2 edits are allowed, but fewer than `typed` has characters, so a
+/// one-letter name isn't corrected into any other.
+///
+/// # Examples
+/// ```
+/// use codecrafters_shell::completion::spelling_match;
+///
+/// let names = ["cat", "git", "grep"].map(String::from);
+/// assert_eq!(spelling_match("gti", &names), Some("git"));
+/// assert_eq!(spelling_match("gerp", &names), Some("grep"));
+/// assert_eq!(spelling_match("x", &names), None);
+/// ```
+pub fn spelling_match<'a>(typed: &str, names: &'a [String]) -> Option<&'a str> {
+    let limit = 2.min(typed.chars().count().saturating_sub(1));
+    names
+        .iter()
+        .map(|name| (edit_distance(typed, name), name))
+        .filter(|&(distance, _)| distance > 0 && distance <= limit)
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, name)| name.as_str())
+}
+
+/// Byte offset in `line` where its last word starts
+///
+/// Words end at unquoted, unescaped blanks and at operators such as `|`
+/// and `>`, as the parser splits them.
+fn word_start(line: &str) -> usize {
+    after_last_unquoted(line, |ch| ch.is_whitespace() || ";|&<>()".contains(ch))
+}
+
+/// Where a `$NAME` or `${NAME` reference at the end of `word` starts, and
+/// whether it has the brace
+///
+/// A `$` that's escaped or in single quotes doesn't start one.
+fn variable_reference(word: &str) -> Option<(usize, bool)> {
+    let name = word.len()
+        - word
+            .trim_end_matches(|ch: char| ch.is_ascii_alphanumeric() || ch == '_')
+            .len();
+    let before = &word[..word.len() - name];
+    let (dollar, brace) = match before.strip_suffix("${") {
+        Some(rest) => (rest.len(), true),
+        None => (before.strip_suffix('$')?.len(), false),
+    };
+
+    let mut quote = None;
+    let mut chars = word[..dollar].chars();
+    let mut escaped = false;
+    while let Some(ch) = chars.next() {
+        match (quote, ch) {
+            (None | Some('"'), '\\') => escaped = chars.next().is_none(),
+            (None, '\'' | '"') => quote = Some(ch),
+            (Some(open), _) if ch == open => quote = None,
+            _ => {}
+        }
+    }
+    (!escaped && quote != Some('\'')).then_some((dollar, brace))
+}
+
+/// Byte offset in `line` where its last simple command starts
+fn command_start(line: &str) -> usize {
+    after_last_unquoted(line, |ch| ";|&(".contains(ch))
+}
+
+/// Byte offset just past the last character of `line` that matches
+/// `boundary` outside quotes and not escaped, or 0 if there isn't one
+fn after_last_unquoted(line: &str, boundary: impl Fn(char) -> bool) -> usize {
+    let mut start = 0;
+    let mut quote = None;
+    let mut chars = line.char_indices();
+    while let Some((index, ch)) = chars.next() {
+        match (quote, ch) {
+            (None | Some('"'), '\\') => {
+                chars.next();
             }
+            (None, '\'' | '"') => quote = Some(ch),
+            (Some(open), _) if ch == open => quote = None,
+            (None, _) if boundary(ch) => start = index + ch.len_utf8(),
+            _ => {}
         }
+    }
+    start
+}
 
-        // If we can extend the prefix, do so
-        if common_prefix.len() > prefix.len() {
-            Some(common_prefix)
-        } else {
-            // Handle double-tab: show all matches if pressed within 500ms
-            let now = Instant::now().elapsed().as_millis() as u64;
-            let last_tab = LAST_TAB_TIME.load(Ordering::Relaxed);
-
-            if now - last_tab < 500 {
-                // Double-tab detected: show all matches
-                println!("\n{}", matches.join("  "));
-                print!("$ {}", prefix);
-                let _ = std::io::stdout().flush();
-                TAB_PRESSED.store(false, Ordering::Relaxed);
-            } else {
-                TAB_PRESSED.store(true, Ordering::Relaxed);
+/// Commands that run the command named after their options, with those
+/// of their options that take a value
+const WRAPPERS: [(&str, &[&str]); 7] = [
+    (
+        "sudo",
+        &[
+            "-u", "-g", "-C", "-D", "-h", "-p", "-R", "-r", "-T", "-t", "-U",
+        ],
+    ),
+    ("env", &["-u", "-C", "-S"]),
+    ("nohup", &[]),
+    ("time", &[]),
+    ("nice", &["-n"]),
+    ("exec", &["-a"]),
+    ("command", &[]),
+];
+
+/// The command whose arguments follow `words`, the start of a simple
+/// command, or `None` if the next word is a command name
+///
+/// Leading assignments are skipped, as are wrappers such as `sudo` and
+/// `env` along with their options and the `NAME=value` settings `env`
+/// takes, so `sudo -u root ` is still in command position. The value of a
+/// wrapper's option counts as an argument to the wrapper.
+fn wrapped_command<'a>(words: &[&'a str]) -> Option<&'a str> {
+    let mut words = words.iter().copied();
+    let mut wrapper: Option<(&str, &[&str])> = None;
+    while let Some(word) = words.next() {
+        let is_assignment = word
+            .split_once('=')
+            .is_some_and(|(name, _)| crate::command::is_name(name));
+        if is_assignment && wrapper.is_none_or(|(name, _)| name == "env") {
+            continue;
+        }
+        match wrapper {
+            Some((name, takes_value)) if word.starts_with('-') => {
+                if word == "--" {
+                    wrapper = None;
+                } else if takes_value.contains(&word) && words.next().is_none() {
+                    return Some(name);
+                }
+            }
+            _ => match WRAPPERS.iter().find(|(name, _)| *name == word) {
+                Some(&found) => wrapper = Some(found),
+                None => return Some(word),
+            },
+        }
+    }
+    None
+}
+
+/// File names, relative to the working directory, that `typed` could
+/// complete to, with a `/` after directories
+///
+/// A leading `~/` stands for the home directory. Hidden files only match
+/// once a `.` is typed, and with `directories_only` other files never do.
+pub(crate) fn path_matches(typed: &str, directories_only: bool) -> Vec<String> {
+    if typed == "~" {
+        return vec!["~/".to_string()];
+    }
+    let (dir_part, prefix) = typed.split_at(typed.rfind('/').map_or(0, |slash| slash + 1));
+    let dir = match dir_part.strip_prefix("~/") {
+        Some(rest) => match env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(rest),
+            None => return Vec::new(),
+        },
+        None if dir_part.is_empty() => PathBuf::from("."),
+        None => PathBuf::from(dir_part),
+    };
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
             }
+            let is_dir = entry.path().is_dir();
+            if directories_only && !is_dir {
+                return None;
+            }
+            let slash = if is_dir { "/" } else { "" };
+            Some(format!("{}{}{}", dir_part, name, slash))
+        })
+        .collect()
+}
 
-            LAST_TAB_TIME.store(now, Ordering::Relaxed);
-            None
+/// The last component of a completed path, keeping a directory's `/`
+///
+/// Words that aren't paths are shown whole.
+fn file_name(path: &str) -> &str {
+    let trimmed = path.strip_suffix('/').unwrap_or(path);
+    &path[trimmed.rfind('/').map_or(0, |slash| slash + 1)..]
+}
+
+/// The quote a word opens, if any, and its text with quoting removed
+fn unquote(word: &str) -> (Option<char>, String) {
+    let quote = word.chars().next().filter(|ch| *ch == '\'' || *ch == '"');
+    let body = quote.map_or(word, |_| &word[1..]);
+    let mut text = String::with_capacity(body.len());
+    let mut chars = body.chars();
+    while let Some(ch) = chars.next() {
+        match (quote, ch) {
+            (None, '\\') => text.extend(chars.next()),
+            (Some('"'), '\\') => match chars.next() {
+                Some(next @ ('"' | '\\' | '$' | '`')) => text.push(next),
+                Some(next) => text.extend(['\\', next]),
+                None => text.push('\\'),
+            },
+            _ => text.push(ch),
         }
     }
+    (quote, text)
+}
+
+/// Quote completed `text` for the command line: inside the `quote` the word
+/// opened, closing it if the word is `complete`, or else with backslashes
+fn requote(text: &str, quote: Option<char>, complete: bool) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.extend(quote);
+    for (index, ch) in text.char_indices() {
+        let escape = match quote {
+            Some('\'') => {
+                if ch == '\'' {
+                    quoted.push_str("'\\''");
+                    continue;
+                }
+                false
+            }
+            Some(_) => "\"\\$`".contains(ch),
+            None => {
+                ch.is_whitespace()
+                    || "\\'\"$&|;<>()*?[]{}!`".contains(ch)
+                    || (ch == '#' && index == 0)
+            }
+        };
+        if escape {
+            quoted.push('\\');
+        }
+        quoted.push(ch);
+    }
+    if complete {
+        quoted.extend(quote);
+        quoted.push(' ');
+    }
+    quoted
+}
+
+/// Executable names found in one PATH directory
+///
+/// Only files the shell could run are listed, not subdirectories or data
+/// files. The directory's modification time is recorded so a cached index can be
+/// validated cheaply: a directory only needs rescanning if its mtime changed.
+struct DirIndex {
+    dir: PathBuf,
+    mtime: Option<SystemTime>,
+    names: Vec<String>,
+}
+
+impl DirIndex {
+    /// Scan a directory and record its names and modification time
+    fn scan(dir: PathBuf) -> Self {
+        let mtime = dir_mtime(&dir);
+        let names = std::fs::read_dir(&dir)
+            .map(|entries| {
+                entries
+                    .filter_map(Result::ok)
+                    .filter(|entry| is_executable(&entry.path()))
+                    .filter_map(|entry| entry.file_name().into_string().ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self { dir, mtime, names }
+    }
+}
+
+/// Check whether a path is a regular file the shell could run: one with an
+/// execute bit set, or on Windows one with an extension listed in `PATHEXT`
+pub(crate) fn is_executable(path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::metadata(path)
+            .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+            .unwrap_or(false)
+    }
+    #[cfg(not(unix))]
+    {
+        let extensions = env::var("PATHEXT").unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".to_string());
+        let extension = path.extension().and_then(|ext| ext.to_str());
+        path.is_file()
+            && extension.is_some_and(|ext| {
+                extensions.split(';').any(|known| {
+                    known
+                        .strip_prefix('.')
+                        .is_some_and(|known| known.eq_ignore_ascii_case(ext))
+                })
+            })
+    }
+}
+
+/// Modification time of a directory, if it exists
+fn dir_mtime(dir: &Path) -> Option<SystemTime> {
+    std::fs::metadata(dir).and_then(|m| m.modified()).ok()
+}
+
+/// Header line identifying the on-disk command index format
+///
+/// Version 1 listed every file name, so such an index is rescanned.
+const INDEX_HEADER: &str = "shelly-command-index 2";
+
+/// Location of the persisted command index
+///
+/// Uses `$XDG_CACHE_HOME/shelly/command-index`, falling back to
+/// `~/.cache/shelly/command-index`.
+fn index_cache_path() -> Option<PathBuf> {
+    let cache_dir = env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(cache_dir.join("shelly").join("command-index"))
 }
 
 /// Engine that provides command completion using a Trie for efficiency
 ///
 /// Caches all available commands (built-ins + PATH executables) in a Trie
-/// for fast prefix-based completion.
+/// for fast prefix-based completion. The PATH part of the cache is persisted
+/// across sessions and revalidated against directory mtimes before each
+/// completion, so newly installed commands and changes to PATH show up.
 pub struct CompletionEngine {
     builtin_commands: HashSet<String>,
+    /// Per-directory index of PATH executables
+    path_index: RwLock<Vec<DirIndex>>,
+    /// Names found in PATH, derived from `path_index`
+    path_commands: RwLock<HashSet<String>>,
+    /// Names defined by the shell at runtime (aliases and functions)
+    shell_commands: RwLock<HashSet<String>>,
+    /// Names of shell variables that aren't in the environment
+    shell_variables: RwLock<HashSet<String>>,
+    /// How many times each command has been run, counted from the history
+    usage: RwLock<HashMap<String, usize>>,
     trie: Arc<RwLock<TrieNode>>,
 }
 
 impl CompletionEngine {
     /// Create a new completion engine with the given built-in commands
+    ///
+    /// Loads the persisted command index if there is one, deferring
+    /// validation until the first completion; otherwise scans PATH.
     pub fn new(builtins: HashSet<String>) -> Self {
         let engine = Self {
             builtin_commands: builtins,
+            path_index: RwLock::new(Vec::new()),
+            path_commands: RwLock::new(HashSet::new()),
+            shell_commands: RwLock::new(HashSet::new()),
+            shell_variables: RwLock::new(HashSet::new()),
+            usage: RwLock::new(HashMap::new()),
             trie: Arc::new(RwLock::new(TrieNode::new())),
         };
-        engine.refresh_cache();
+        match Self::load_index() {
+            Some(index) => {
+                *engine.path_index.write().unwrap() = index;
+                engine.rebuild_trie();
+            }
+            None => engine.refresh_cache(),
+        }
         engine
     }
 
     /// Refresh the completion cache by rebuilding the Trie
     ///
     /// Scans all directories in PATH and inserts all executable names
-    /// along with built-in commands into the Trie.
+    /// along with built-in commands and shell-defined names into the Trie.
     pub fn refresh_cache(&self) {
-        let mut trie = self.trie.write().unwrap();
+        let index = Self::path_dirs().into_iter().map(DirIndex::scan).collect();
+        *self.path_index.write().unwrap() = index;
+        self.rebuild_trie();
+    }
+
+    /// Bring the index up to date with PATH and the directories in it
+    ///
+    /// Directories whose mtime is unchanged keep their cached names; new or
+    /// modified directories are rescanned and ones no longer in PATH are
+    /// dropped. That costs a `stat` per directory, so it's done before every
+    /// completion, and the Trie is only rebuilt if something changed.
+    pub fn revalidate(&self) {
+        let mut changed = false;
+        let mut cached: HashMap<PathBuf, DirIndex> =
+            std::mem::take(&mut *self.path_index.write().unwrap())
+                .into_iter()
+                .map(|entry| (entry.dir.clone(), entry))
+                .collect();
+        let dirs = Self::path_dirs();
+        if dirs.len() != cached.len() {
+            changed = true;
+        }
+
+        let index = dirs
+            .into_iter()
+            .map(|dir| match cached.remove(&dir) {
+                Some(entry) if entry.mtime == dir_mtime(&dir) => entry,
+                _ => {
+                    changed = true;
+                    DirIndex::scan(dir)
+                }
+            })
+            .collect();
+
+        *self.path_index.write().unwrap() = index;
+        if changed {
+            self.rebuild_trie();
+        }
+    }
+
+    /// Directories listed in PATH, without duplicates, in order
+    fn path_dirs() -> Vec<PathBuf> {
+        let mut seen = HashSet::new();
+        env::var_os("PATH")
+            .map(|paths| {
+                env::split_paths(&paths)
+                    .filter(|dir| seen.insert(dir.clone()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Rebuild the Trie from builtins, the PATH index and shell-defined names
+    fn rebuild_trie(&self) {
+        let path_commands: HashSet<String> = self
+            .path_index
+            .read()
+            .unwrap()
+            .iter()
+            .flat_map(|entry| entry.names.iter().cloned())
+            .collect();
 
-        // Add built-in commands
-        for cmd in &self.builtin_commands {
+        let mut trie = TrieNode::new();
+        for cmd in self
+            .builtin_commands
+            .iter()
+            .chain(&path_commands)
+            .chain(self.shell_commands.read().unwrap().iter())
+        {
             trie.insert(cmd.clone());
         }
 
-        // Add executables from PATH
-        if let Some(paths) = env::var_os("PATH") {
-            for dir in env::split_paths(&paths) {
-                if let Ok(entries) = std::fs::read_dir(dir) {
-                    for entry in entries.filter_map(Result::ok) {
-                        if let Ok(name) = entry.file_name().into_string() {
-                            trie.insert(name);
-                        }
+        *self.trie.write().unwrap() = trie;
+        *self.path_commands.write().unwrap() = path_commands;
+    }
+
+    /// Load the persisted PATH index from the cache directory
+    fn load_index() -> Option<Vec<DirIndex>> {
+        let contents = std::fs::read_to_string(index_cache_path()?).ok()?;
+        let mut lines = contents.lines();
+        if lines.next() != Some(INDEX_HEADER) {
+            return None;
+        }
+
+        let mut index: Vec<DirIndex> = Vec::new();
+        for line in lines {
+            if let Some(rest) = line.strip_prefix("dir\t") {
+                let (dir, mtime) = rest.rsplit_once('\t')?;
+                let mtime = match mtime {
+                    "-" => None,
+                    stamp => {
+                        let (secs, nanos) = stamp.split_once('.')?;
+                        let since_epoch = Duration::new(secs.parse().ok()?, nanos.parse().ok()?);
+                        Some(UNIX_EPOCH + since_epoch)
                     }
-                }
+                };
+                index.push(DirIndex {
+                    dir: PathBuf::from(dir),
+                    mtime,
+                    names: Vec::new(),
+                });
+            } else {
+                index.last_mut()?.names.push(line.to_string());
+            }
+        }
+        Some(index)
+    }
+
+    /// Persist the PATH index to the cache directory
+    ///
+    /// Entries that can't be represented in the line-based format (names or
+    /// directories containing tabs or newlines) are skipped.
+    pub fn save_index(&self) -> std::io::Result<()> {
+        let Some(path) = index_cache_path() else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let mut out = String::from(INDEX_HEADER);
+        out.push('\n');
+        for entry in self.path_index.read().unwrap().iter() {
+            let Some(dir) = entry.dir.to_str().filter(|d| !d.contains(['\t', '\n'])) else {
+                continue;
+            };
+            let mtime = entry
+                .mtime
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| format!("{}.{}", d.as_secs(), d.subsec_nanos()))
+                .unwrap_or_else(|| "-".to_string());
+            out.push_str(&format!("dir\t{}\t{}\n", dir, mtime));
+            for name in entry.names.iter().filter(|n| !n.contains(['\t', '\n'])) {
+                out.push_str(name);
+                out.push('\n');
             }
         }
+
+        // Write to a temporary file first so concurrent shells never read a partial index
+        let tmp = path.with_extension(format!("tmp.{}", std::process::id()));
+        std::fs::write(&tmp, out)?;
+        std::fs::rename(tmp, path)
+    }
+
+    /// Make a shell-defined command name (alias or function) completable
+    pub fn add_command(&self, name: &str) {
+        if self
+            .shell_commands
+            .write()
+            .unwrap()
+            .insert(name.to_string())
+        {
+            self.trie.write().unwrap().insert(name.to_string());
+        }
+    }
+
+    /// Forget a shell-defined command name
+    ///
+    /// The name stays completable if it is also a builtin or a PATH executable.
+    pub fn remove_command(&self, name: &str) {
+        if !self.shell_commands.write().unwrap().remove(name) {
+            return;
+        }
+        let still_provided = self.builtin_commands.contains(name)
+            || self.path_commands.read().unwrap().contains(name);
+        if !still_provided {
+            self.trie.write().unwrap().remove(name);
+        }
+    }
+
+    /// Make a shell variable's name completable
+    ///
+    /// Environment variables don't need adding; they're read when completing.
+    pub fn add_variable(&self, name: &str) {
+        self.shell_variables
+            .write()
+            .unwrap()
+            .insert(name.to_string());
+    }
+
+    /// Forget a shell variable's name
+    pub fn remove_variable(&self, name: &str) {
+        self.shell_variables.write().unwrap().remove(name);
+    }
+
+    /// Count a use of the command that a history entry runs
+    pub fn record_use(&self, entry: &str) {
+        if let Some(name) = command_name(entry) {
+            *self
+                .usage
+                .write()
+                .unwrap()
+                .entry(name.to_string())
+                .or_insert(0) += 1;
+        }
+    }
+
+    /// How many times `name` has been run, as counted by
+    /// [`record_use`](Self::record_use)
+    pub fn uses(&self, name: &str) -> usize {
+        self.usage.read().unwrap().get(name).copied().unwrap_or(0)
+    }
+
+    /// Whether `name` is a builtin, a shell-defined command or a PATH
+    /// executable, as last indexed
+    pub fn is_command(&self, name: &str) -> bool {
+        self.builtin_commands.contains(name)
+            || self.shell_commands.read().unwrap().contains(name)
+            || self.path_commands.read().unwrap().contains(name)
+    }
+
+    /// Command names starting with `prefix`, after bringing the PATH index
+    /// up to date
+    pub fn commands(&self, prefix: &str) -> Vec<String> {
+        self.revalidate();
+        self.trie.read().unwrap().find_prefix(prefix)
+    }
+
+    /// Names of shell and environment variables starting with `prefix`
+    fn variable_names(&self, prefix: &str) -> Vec<String> {
+        let shell_variables = self.shell_variables.read().unwrap();
+        let environment = env::vars_os().filter_map(|(name, _)| name.into_string().ok());
+        let mut names: Vec<String> = shell_variables
+            .iter()
+            .cloned()
+            .chain(environment)
+            .filter(|name| name.starts_with(prefix))
+            .collect();
+        // Exported shell variables are in both
+        names.sort();
+        names.dedup();
+        names
     }
 }
 
+/// Something a command's arguments can complete to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompletionAction {
+    /// Command names (`complete -c`)
+    Commands,
+    /// Directory names (`complete -d`)
+    Directories,
+    /// File and directory names (`complete -f`)
+    Files,
+    /// Job specs such as `%1`, or a job's process IDs (`complete -j`)
+    Jobs,
+    /// Shell and environment variable names (`complete -v`)
+    Variables,
+}
+
+impl CompletionAction {
+    /// Every action, in the order `complete` takes their options
+    pub const ALL: [CompletionAction; 5] = [
+        CompletionAction::Commands,
+        CompletionAction::Directories,
+        CompletionAction::Files,
+        CompletionAction::Jobs,
+        CompletionAction::Variables,
+    ];
+
+    /// The `complete` option that selects this action
+    pub fn flag(self) -> char {
+        match self {
+            CompletionAction::Commands => 'c',
+            CompletionAction::Directories => 'd',
+            CompletionAction::Files => 'f',
+            CompletionAction::Jobs => 'j',
+            CompletionAction::Variables => 'v',
+        }
+    }
+
+    /// The action selected by `complete -FLAG`
+    pub fn from_flag(flag: char) -> Option<Self> {
+        Self::ALL.into_iter().find(|action| action.flag() == flag)
+    }
+}
+
+/// What a command's arguments complete to
+///
+/// Builtins provide their own through [`BuiltinCommand::completion`], and
+/// users set them with `complete`. Arguments of a command without one
+/// complete to file names.
+///
+/// [`BuiltinCommand::completion`]: crate::builtin::BuiltinCommand::completion
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CompletionSpec {
+    /// Where candidates come from; they're merged if there's more than one
+    pub actions: Vec<CompletionAction>,
+    /// Words offered as they are (`complete -W`)
+    pub words: Vec<String>,
+    /// A shell function printing candidates one per line (`complete -F`)
+    pub function: Option<String>,
+}
+
+impl CompletionSpec {
+    /// A spec completing to what a single `action` gives
+    pub fn new(action: CompletionAction) -> Self {
+        CompletionSpec {
+            actions: vec![action],
+            ..CompletionSpec::default()
+        }
+    }
+}
+
+impl std::fmt::Display for CompletionSpec {
+    /// The spec as `complete` options, such as `-d -v -W 'start stop'`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut flags: Vec<String> = self
+            .actions
+            .iter()
+            .map(|action| format!("-{}", action.flag()))
+            .collect();
+        if !self.words.is_empty() {
+            flags.push(format!("-W {}", Word::quoted(self.words.join(" "))));
+        }
+        if let Some(function) = &self.function {
+            flags.push(format!("-F {}", function));
+        }
+        write!(f, "{}", flags.join(" "))
+    }
+}
+
+/// Completion specs by command name, shared by the shell and the line editor
+pub type CompletionSpecs = Arc<RwLock<HashMap<String, CompletionSpec>>>;
+
 /// Rustyline helper that integrates with the completion engine
 ///
-/// Implements the Completer trait to provide tab completion for commands.
-/// Also derives Helper, Hinter, Highlighter, and Validator for full
-/// rustyline integration.
-#[derive(Helper, Hinter, Highlighter, Validator)]
+/// Implements the Completer trait to provide tab completion for commands,
+/// Hinter to draw the completion menu below the line, Highlighter to color
+/// the line, and Validator to keep reading lines until the command is
+/// complete. Also derives Helper for full rustyline integration.
+#[derive(Helper)]
 pub struct RustylineHelper {
     completion_engine: CompletionEngine,
+    /// The shell's job table, read live when completing job specs
+    jobs: Arc<RwLock<JobTable>>,
+    /// The shell's completion specs, read live to complete arguments
+    specs: CompletionSpecs,
+    /// The shell's command hash, read live when highlighting
+    hashed: CommandHashes,
+    /// The menu of matches, shared with the key bindings that drive it
+    menu: Arc<CompletionMenu>,
+    /// How command names are matched
+    match_mode: RwLock<MatchMode>,
+    /// Runs the functions of `complete -F` specs, while the shell provides one
+    generator: Mutex<Option<Generator>>,
+    /// When Tab was last pressed on a word with nothing to add
+    ambiguous_tab: Mutex<Option<Instant>>,
 }
 
 impl RustylineHelper {
-    /// Create a new helper with the given built-in commands
-    pub fn new(builtins: HashSet<String>) -> Self {
+    /// Create a new helper with the given built-in commands, job table,
+    /// completion specs and command hash
+    pub fn new(
+        builtins: HashSet<String>,
+        jobs: Arc<RwLock<JobTable>>,
+        specs: CompletionSpecs,
+        hashed: CommandHashes,
+    ) -> Self {
         Self {
             completion_engine: CompletionEngine::new(builtins),
+            jobs,
+            specs,
+            hashed,
+            menu: Arc::new(CompletionMenu::default()),
+            match_mode: RwLock::new(MatchMode::default()),
+            ambiguous_tab: Mutex::new(None),
+            generator: Mutex::new(None),
+        }
+    }
+
+    /// Set how command names are matched
+    pub fn set_match_mode(&self, mode: MatchMode) {
+        *self.match_mode.write().unwrap() = mode;
+    }
+
+    /// Replace the generator that runs completion functions, stopping the
+    /// last one
+    pub fn set_generator(&self, generator: Option<Generator>) {
+        *self.generator.lock().unwrap() = generator;
+    }
+
+    /// The completion menu, for key bindings to move through and accept
+    pub fn menu(&self) -> Arc<CompletionMenu> {
+        Arc::clone(&self.menu)
+    }
+
+    /// The completion engine backing this helper
+    pub fn completion_engine(&self) -> &CompletionEngine {
+        &self.completion_engine
+    }
+
+    /// Complete `%N` job specs, or the process IDs of jobs if `word` starts
+    /// with a digit, annotated with each job's command line
+    fn complete_job_specs(&self, word: &str) -> Vec<Pair> {
+        job_candidates(&self.jobs.read().unwrap(), word)
+            .into_iter()
+            .map(|(spec, command)| Pair {
+                display: format!("{}  {}", spec, command),
+                replacement: spec + " ",
+            })
+            .collect()
+    }
+
+    /// The candidates rustyline gets for `completion` of `line[start..pos]`
+    ///
+    /// `replacement` turns a match into the text that replaces the word,
+    /// given whether it's finished, and `shown` gives how it's listed in the
+    /// menu. When the matches go to the menu, the text already typed is the
+    /// only candidate, so the line is redrawn with the menu below it.
+    fn candidates(
+        &self,
+        line: &str,
+        pos: usize,
+        start: usize,
+        completion: Option<Completion>,
+        replacement: impl Fn(&str, bool) -> String,
+        shown: impl Fn(&str) -> String,
+    ) -> Vec<Pair> {
+        match completion {
+            Some(Completion::Word(text, complete)) => {
+                let completion = replacement(&text, complete);
+                vec![Pair {
+                    display: completion.clone(),
+                    replacement: completion,
+                }]
+            }
+            Some(Completion::Ambiguous(matches)) if self.is_double_tab() => self.candidates(
+                line,
+                pos,
+                start,
+                Some(Completion::Menu(matches)),
+                replacement,
+                shown,
+            ),
+            Some(Completion::Menu(matches)) => {
+                let entries = matches
+                    .iter()
+                    .map(|text| Pair {
+                        display: shown(text),
+                        replacement: replacement(text, !text.ends_with('/')),
+                    })
+                    .collect();
+                self.menu.open(line, pos, start, entries);
+                vec![Pair {
+                    display: line[start..pos].to_string(),
+                    replacement: line[start..pos].to_string(),
+                }]
+            }
+            Some(Completion::Ambiguous(_)) | None => vec![],
+        }
+    }
+
+    /// Note a Tab on a word with nothing to add, returning whether the last
+    /// one came within [`DOUBLE_TAB`]
+    fn is_double_tab(&self) -> bool {
+        let now = Instant::now();
+        let last = self.ambiguous_tab.lock().unwrap().replace(now);
+        last.is_some_and(|last| now.duration_since(last) < DOUBLE_TAB)
+    }
+
+    /// Complete a command name from `matches`, which all start with
+    /// `typed`, as [`complete_from`] does but preferring commands run often
+    ///
+    /// When there's no common prefix to add, a match run at least
+    /// [`DOMINANT_USES`] times, and twice as often as any other, is taken
+    /// at once. The menu lists the most used first.
+    fn complete_command(&self, typed: &str, matches: Vec<String>) -> Option<Completion> {
+        let engine = &self.completion_engine;
+        if matches.len() > 1 && common_prefix(&matches).len() == typed.len() {
+            let mut uses: Vec<(usize, &String)> = matches
+                .iter()
+                .map(|name| (engine.uses(name), name))
+                .collect();
+            uses.sort_by_key(|&(uses, _)| std::cmp::Reverse(uses));
+            let ((most, name), (next, _)) = (uses[0], uses[1]);
+            if most >= DOMINANT_USES && most >= 2 * next {
+                return Some(Completion::Word(name.clone(), true));
+            }
+        }
+        match complete_from(typed, matches)? {
+            Completion::Ambiguous(mut names) => {
+                names.sort_by_key(|name| std::cmp::Reverse(engine.uses(name)));
+                Some(Completion::Ambiguous(names))
+            }
+            word => Some(word),
+        }
+    }
+
+    /// Command names matching `typed` in `mode`, best first
+    ///
+    /// Names are ordered by [`match_score`], and those that score the same
+    /// by how often they've been run.
+    fn ranked_commands(&self, typed: &str, mode: MatchMode) -> Vec<String> {
+        let engine = &self.completion_engine;
+        let names = engine.trie.read().unwrap().find_prefix("");
+        let mut ranked: Vec<(usize, usize, String)> = names
+            .into_iter()
+            .filter_map(|name| {
+                let score = match_score(&name, typed, mode)?;
+                Some((score, engine.uses(&name), name))
+            })
+            .collect();
+        ranked.sort_by(|a, b| b.0.cmp(&a.0).then(b.1.cmp(&a.1)).then(a.2.cmp(&b.2)));
+        ranked.into_iter().map(|(_, _, name)| name).collect()
+    }
+
+    /// Words that an argument starting with `typed` can complete to, from
+    /// what `spec` lists
+    fn argument_matches(&self, typed: &str, spec: &CompletionSpec) -> Vec<String> {
+        let mut matches = Vec::new();
+        for action in &spec.actions {
+            match action {
+                CompletionAction::Commands => matches.extend(
+                    self.completion_engine
+                        .trie
+                        .read()
+                        .unwrap()
+                        .find_prefix(typed),
+                ),
+                CompletionAction::Directories => matches.extend(path_matches(typed, true)),
+                CompletionAction::Files => matches.extend(path_matches(typed, false)),
+                CompletionAction::Jobs => {
+                    let jobs = self.jobs.read().unwrap();
+                    matches.extend(
+                        job_candidates(&jobs, typed)
+                            .into_iter()
+                            .map(|(spec, _)| spec),
+                    )
+                }
+                CompletionAction::Variables => {
+                    matches.extend(self.completion_engine.variable_names(typed))
+                }
+            }
         }
+        matches.extend(
+            spec.words
+                .iter()
+                .filter(|word| word.starts_with(typed))
+                .cloned(),
+        );
+        matches.sort();
+        matches.dedup();
+        matches
     }
+
+    /// What the function of a `complete -F` spec prints for `request`,
+    /// keeping lines that start with the word being completed
+    fn generated_matches(&self, request: &Request) -> Vec<String> {
+        let mut generator = self.generator.lock().unwrap();
+        let Some(generator) = generator.as_mut() else {
+            return Vec::new();
+        };
+        let mut matches = generator.call(request);
+        matches.retain(|line| line.starts_with(&request.word));
+        matches
+    }
+}
+
+/// Job specs or process IDs in `jobs` starting with `word`, with their
+/// commands
+pub(crate) fn job_candidates(jobs: &JobTable, word: &str) -> Vec<(String, String)> {
+    let by_pid = word.starts_with(|ch: char| ch.is_ascii_digit());
+    jobs.jobs()
+        .iter()
+        .flat_map(|job| {
+            let specs = if by_pid {
+                job.pids.iter().map(u32::to_string).collect()
+            } else {
+                vec![format!("%{}", job.id)]
+            };
+            specs.into_iter().map(|spec| (spec, job.command.clone()))
+        })
+        .filter(|(spec, _)| spec.starts_with(word))
+        .collect()
+}
+
+/// Names of executables in PATH starting with `prefix`, scanning each
+/// directory, for shells without a line editor to keep an index
+pub(crate) fn path_commands(prefix: &str) -> Vec<String> {
+    CompletionEngine::path_dirs()
+        .into_iter()
+        .flat_map(|dir| DirIndex::scan(dir).names)
+        .filter(|name| name.starts_with(prefix))
+        .collect()
 }
 
 impl rustyline::completion::Completer for RustylineHelper {
-    type Candidate = String;
+    type Candidate = Pair;
 
     /// Provide completion candidates for the word at the cursor position
     ///
-    /// Extracts the word being typed, searches the Trie for matches,
-    /// and returns the completion suggestion.
+    /// Extracts the word being typed; a command name is looked up in the
+    /// Trie (or matched loosely, in the other [`MatchMode`]s), and anything
+    /// else is completed as its command's spec says.
+    /// The word may start with a quote, and the completion is quoted the
+    /// same way, or backslash-escaped if it isn't quoted.
+    ///
+    /// When Tab is pressed twice and there's nothing to add, the matches go
+    /// to the menu, and the word itself is the only candidate so the line
+    /// is redrawn with the menu below it.
     fn complete(
         &self,
         line: &str,
         pos: usize,
         _ctx: &rustyline::Context<'_>,
     ) -> rustyline::Result<(usize, Vec<Self::Candidate>)> {
-        // Find the start of the current word (after last whitespace)
-        let (word_start, word) = line[..pos]
-            .char_indices()
-            .rev()
-            .find(|(_, c)| c.is_whitespace())
-            .map(|(i, _)| (i + 1, &line[i + 1..pos]))
-            .unwrap_or((0, &line[..pos]));
-
-        // Get completion from the Trie
-        if let Some(completion) = self
-            .completion_engine
-            .trie
-            .read()
-            .unwrap()
-            .find_common_prefix(word)
-        {
-            Ok((word_start, vec![completion]))
+        if let Some((start, choice)) = self.menu.take_choice(line, pos) {
+            return Ok((start, vec![choice]));
+        }
+        let word_start = word_start(&line[..pos]);
+        let word = &line[word_start..pos];
+
+        // `$NAME` and `${NAME` complete to variable names anywhere in a word
+        if let Some((dollar, brace)) = variable_reference(word) {
+            let start = word_start + dollar;
+            let (open, close) = if brace { ("${", "}") } else { ("$", "") };
+            let typed = &line[start + open.len()..pos];
+            let names = self.completion_engine.variable_names(typed);
+            let candidates = self.candidates(
+                line,
+                pos,
+                start,
+                complete_from(typed, names),
+                |name, complete| format!("{}{}{}", open, name, if complete { close } else { "" }),
+                |name| format!("{}{}", open, name),
+            );
+            return Ok((start, candidates));
+        }
+
+        self.completion_engine.revalidate();
+
+        // Arguments to job-control builtins complete to job specs
+        let before = &line[..word_start];
+        let words: Vec<&str> = before[command_start(before)..].split_whitespace().collect();
+        let command = wrapped_command(&words);
+        let redirect_target = before.trim_end().ends_with(['<', '>']);
+
+        // Command names come from the Trie, unless they're paths like `./run`
+        let (quote, typed) = unquote(word);
+        let mode = *self.match_mode.read().unwrap();
+        let completion = if command.is_none() && !word.contains('/') {
+            let matches = if mode == MatchMode::Prefix || typed.is_empty() {
+                self.completion_engine
+                    .trie
+                    .read()
+                    .unwrap()
+                    .find_prefix(&typed)
+            } else {
+                self.ranked_commands(&typed, mode)
+            };
+            if matches.iter().all(|name| name.starts_with(typed.as_str())) {
+                self.complete_command(&typed, matches)
+            } else {
+                complete_loosely(matches)
+            }
         } else {
-            Ok((word_start, vec![]))
+            let spec = command
+                .filter(|_| !redirect_target)
+                .and_then(|command| self.specs.read().unwrap().get(command).cloned())
+                .unwrap_or_else(|| CompletionSpec::new(CompletionAction::Files));
+            // Job specs alone are listed with their commands
+            if spec.actions == [CompletionAction::Jobs] {
+                return Ok((word_start, self.complete_job_specs(word)));
+            }
+            let mut matches = self.argument_matches(&typed, &spec);
+            if let (Some(function), Some(command)) = (&spec.function, command) {
+                matches.extend(self.generated_matches(&Request {
+                    function: function.clone(),
+                    command: command.to_string(),
+                    word: typed.clone(),
+                    previous: words.last().unwrap_or(&"").to_string(),
+                    line: line.to_string(),
+                    point: pos,
+                }));
+                matches.sort();
+                matches.dedup();
+            }
+            complete_from(&typed, matches)
+        };
+        let candidates = self.candidates(
+            line,
+            pos,
+            word_start,
+            completion,
+            |text, complete| requote(text, quote, complete),
+            |text| file_name(text).to_string(),
+        );
+        Ok((word_start, candidates))
+    }
+}
+
+impl rustyline::hint::Hinter for RustylineHelper {
+    type Hint = LineHint;
+
+    /// The completion menu while it's open on this line, otherwise the
+    /// rest of the newest history entry that starts with the line
+    ///
+    /// Suggestions only appear with the cursor at the end of the line, and
+    /// not for entries spanning several lines.
+    fn hint(&self, line: &str, pos: usize, ctx: &rustyline::Context<'_>) -> Option<LineHint> {
+        if let Some(menu) = self.menu.render(line, pos) {
+            return Some(LineHint::Menu(menu));
+        }
+        if line.is_empty() || pos < line.len() {
+            return None;
+        }
+        // While browsing, search back from the entry being shown
+        let history = ctx.history();
+        let start = if ctx.history_index() == history.len() {
+            ctx.history_index().saturating_sub(1)
+        } else {
+            ctx.history_index()
+        };
+        let found = history
+            .starts_with(line, start, SearchDirection::Reverse)
+            .ok()??;
+        let rest = &found.entry[line.len()..];
+        if rest.is_empty() || rest.contains('\n') {
+            return None;
+        }
+        Some(LineHint::Suggestion {
+            display: format!("\x1b[2m{}\x1b[0m", rest),
+            rest: rest.to_string(),
+        })
+    }
+}
+
+impl rustyline::highlight::Highlighter for RustylineHelper {
+    /// Color the line as [`highlight`](crate::highlight::highlight) does,
+    /// with commands found in the command hash, among builtins, aliases,
+    /// functions and PATH executables, or as executable files if they
+    /// contain a `/`
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        let found = |name: &str| {
+            if name.contains('/') {
+                return is_executable(Path::new(name));
+            }
+            if self.hashed.read().unwrap().get(name).is_some() {
+                return true;
+            }
+            let engine = &self.completion_engine;
+            engine.is_command(name) || {
+                // It may have been installed since PATH was last checked
+                engine.revalidate();
+                engine.is_command(name)
+            }
+        };
+        Cow::Owned(crate::highlight::highlight(line, found))
+    }
+
+    /// Redraw the line on every change, as one character can recolor others
+    fn highlight_char(&self, _line: &str, _pos: usize, _kind: CmdKind) -> bool {
+        true
+    }
+}
+
+impl rustyline::validate::Validator for RustylineHelper {
+    /// Let Enter start a new line while the command is unfinished: an open
+    /// quote, here-document or compound command, a trailing `|`, `&&` or
+    /// `\`, or anything else the parser needs more input for. The lines
+    /// are edited, and kept in the history, as one entry.
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        if CommandParser::is_incomplete(ctx.input()) {
+            Ok(ValidationResult::Incomplete)
+        } else {
+            Ok(ValidationResult::Valid(None))
+        }
+    }
+}
+
+/// What's drawn after the line being edited
+pub enum LineHint {
+    /// The completion menu, below the line
+    Menu(String),
+    /// The rest of a command from the history, dimmed, for → or End to
+    /// accept
+    Suggestion { display: String, rest: String },
+}
+
+impl Hint for LineHint {
+    fn display(&self) -> &str {
+        match self {
+            LineHint::Menu(text) => text,
+            LineHint::Suggestion { display, .. } => display,
+        }
+    }
+
+    /// Only a suggestion can be put into the line
+    fn completion(&self) -> Option<&str> {
+        match self {
+            LineHint::Menu(_) => None,
+            LineHint::Suggestion { rest, .. } => Some(rest),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::menu::Step;
+    use rustyline::completion::Completer;
+    use rustyline::hint::Hinter;
+    use rustyline::history::{DefaultHistory, History};
+
+    /// A helper completing `commands`, besides whatever is in PATH
+    fn helper(commands: &[&str]) -> RustylineHelper {
+        let engine = CompletionEngine {
+            builtin_commands: commands.iter().map(|name| name.to_string()).collect(),
+            path_index: RwLock::new(Vec::new()),
+            path_commands: RwLock::new(HashSet::new()),
+            shell_commands: RwLock::new(HashSet::new()),
+            shell_variables: RwLock::new(HashSet::new()),
+            usage: RwLock::new(HashMap::new()),
+            trie: Arc::new(RwLock::new(TrieNode::new())),
+        };
+        engine.rebuild_trie();
+        RustylineHelper {
+            completion_engine: engine,
+            jobs: Arc::new(RwLock::new(JobTable::new())),
+            specs: CompletionSpecs::default(),
+            hashed: CommandHashes::default(),
+            menu: Arc::new(CompletionMenu::default()),
+            match_mode: RwLock::new(MatchMode::default()),
+            generator: Mutex::new(None),
+            ambiguous_tab: Mutex::new(None),
         }
     }
+
+    /// Where Tab replaces the end of `line`, and what it puts there
+    fn complete(helper: &RustylineHelper, line: &str) -> (usize, Vec<String>) {
+        let history = DefaultHistory::new();
+        let context = rustyline::Context::new(&history);
+        let (start, candidates) = helper.complete(line, line.len(), &context).unwrap();
+        let replacements = candidates.into_iter().map(|pair| pair.replacement);
+        (start, replacements.collect())
+    }
+
+    /// A fresh directory for a test, removed first if a run left it behind
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir =
+            env::temp_dir().join(format!("shelly-completion-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn completes_file_names_quoted_as_typed() {
+        let dir = scratch_dir("files");
+        std::fs::write(dir.join("my file.txt"), "").unwrap();
+        std::fs::write(dir.join(".hidden"), "").unwrap();
+        std::fs::create_dir(dir.join("sub")).unwrap();
+        let helper = helper(&[]);
+        let dir = dir.display().to_string();
+
+        let line = format!("cat {}/my", dir);
+        let expected = format!("{}/my\\ file.txt ", dir);
+        assert_eq!(complete(&helper, &line), (4, vec![expected]));
+        let line = format!("cat '{}/my", dir);
+        let expected = format!("'{}/my file.txt' ", dir);
+        assert_eq!(complete(&helper, &line), (4, vec![expected]));
+        // A directory is left open to complete into
+        let line = format!("cat {}/s", dir);
+        assert_eq!(complete(&helper, &line).1, [format!("{}/sub/", dir)]);
+
+        let typed = format!("{}/", dir);
+        let mut all = path_matches(&typed, false);
+        all.sort();
+        assert_eq!(
+            all,
+            [format!("{}/my file.txt", dir), format!("{}/sub/", dir)]
+        );
+        assert_eq!(path_matches(&typed, true), [format!("{}/sub/", dir)]);
+        assert_eq!(
+            path_matches(&format!("{}/.h", dir), false),
+            [format!("{}/.hidden", dir)]
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn arguments_complete_as_their_commands_spec_says() {
+        let helper = helper(&["qqdeploy"]);
+        let words = CompletionSpec {
+            words: vec!["start".into(), "stop".into(), "restart".into()],
+            ..CompletionSpec::default()
+        };
+        let mut specs = helper.specs.write().unwrap();
+        specs.insert("myctl".into(), words);
+        specs.insert(
+            "qqhelp".into(),
+            CompletionSpec::new(CompletionAction::Commands),
+        );
+        drop(specs);
+
+        assert_eq!(complete(&helper, "myctl r"), (6, vec!["restart ".into()]));
+        // Nothing to add to `st` until Tab is pressed again
+        assert_eq!(complete(&helper, "myctl st"), (6, vec![]));
+        assert_eq!(
+            complete(&helper, "qqhelp qqd"),
+            (7, vec!["qqdeploy ".into()])
+        );
+        // A redirection target is a file whatever the command
+        let dir = scratch_dir("redirect");
+        std::fs::write(dir.join("run.log"), "").unwrap();
+        let line = format!("myctl > {}/r", dir.display());
+        let expected = format!("{}/run.log ", dir.display());
+        assert_eq!(complete(&helper, &line), (8, vec![expected]));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn second_tab_opens_a_menu_of_the_matches() {
+        let helper = helper(&[]);
+        let words = CompletionSpec {
+            words: vec!["start".into(), "stop".into()],
+            ..CompletionSpec::default()
+        };
+        helper.specs.write().unwrap().insert("myctl".into(), words);
+        let line = "myctl st";
+
+        assert_eq!(complete(&helper, line), (6, vec![]));
+        assert!(!helper.menu.is_open(line, line.len()));
+        // The word is redrawn as it is, with the menu below it
+        assert_eq!(complete(&helper, line), (6, vec!["st".into()]));
+        let menu = helper.menu.render(line, line.len()).unwrap();
+        assert!(
+            menu.contains("start") && menu.contains("stop"),
+            "{:?}",
+            menu
+        );
+
+        helper.menu.select(Step::Next);
+        helper.menu.accept();
+        assert_eq!(complete(&helper, line), (6, vec!["stop ".into()]));
+        assert!(!helper.menu.is_open(line, line.len()));
+    }
+
+    #[test]
+    fn loose_modes_rank_names_matching_anywhere() {
+        let names = [
+            "git-switch-wrapper",
+            "gsettings-wrapper",
+            "grep",
+            "frep",
+            "pgrep",
+        ];
+        let ranking = helper(&names);
+        let ranked = |typed, mode| ranking.ranked_commands(typed, mode);
+
+        assert_eq!(
+            ranked("gsw", MatchMode::Fuzzy),
+            ["git-switch-wrapper", "gsettings-wrapper"]
+        );
+        assert_eq!(
+            ranked("rep", MatchMode::Substring),
+            ["frep", "grep", "pgrep"]
+        );
+        // Equally good matches go by how often they've been run
+        ranking.completion_engine.record_use("grep -r x");
+        assert_eq!(
+            ranked("rep", MatchMode::Substring),
+            ["grep", "frep", "pgrep"]
+        );
+        assert_eq!(ranked("rep", MatchMode::Prefix), Vec::<String>::new());
+
+        // One loose match replaces the word; several go straight to the menu
+        let loose = helper(&["qqzzgrep-tool", "qqzzgrep-other"]);
+        loose.set_match_mode(MatchMode::Substring);
+        assert_eq!(
+            complete(&loose, "zzgrep-t"),
+            (0, vec!["qqzzgrep-tool ".into()])
+        );
+        assert_eq!(complete(&loose, "zzgrep"), (0, vec!["zzgrep".into()]));
+        assert!(loose.menu.is_open("zzgrep", 6));
+    }
+
+    #[test]
+    fn suggests_the_rest_of_the_newest_matching_command() {
+        let helper = helper(&[]);
+        let mut history = DefaultHistory::new();
+        for line in ["git status", "git stash pop", "for x in a\ndo echo\ndone"] {
+            history.add(line).unwrap();
+        }
+        let context = rustyline::Context::new(&history);
+        let suggestion = |line: &str, pos| match helper.hint(line, pos, &context)? {
+            LineHint::Suggestion { rest, .. } => Some(rest),
+            LineHint::Menu(_) => None,
+        };
+
+        assert_eq!(suggestion("git st", 6).as_deref(), Some("ash pop"));
+        assert_eq!(suggestion("git statu", 9).as_deref(), Some("s"));
+        // Only with the cursor at the end, and never spanning lines
+        assert_eq!(suggestion("git st", 3), None);
+        assert_eq!(suggestion("for", 3), None);
+        assert_eq!(suggestion("git status", 10), None);
+    }
+
+    #[test]
+    fn completes_variable_names_after_a_dollar() {
+        let helper = helper(&[]);
+        helper.completion_engine.add_variable("QQ_TEST_NAME");
+        helper.completion_engine.add_variable("QQ_TEST_OTHER");
+
+        assert_eq!(
+            complete(&helper, "echo $QQ_TEST_N"),
+            (5, vec!["$QQ_TEST_NAME".into()])
+        );
+        assert_eq!(
+            complete(&helper, "echo \"${QQ_TEST_N"),
+            (6, vec!["${QQ_TEST_NAME}".into()])
+        );
+        assert_eq!(
+            complete(&helper, "cd a/$QQ_TEST_N"),
+            (5, vec!["$QQ_TEST_NAME".into()])
+        );
+        assert_eq!(
+            complete(&helper, "echo ${QQ_TE"),
+            (5, vec!["${QQ_TEST_".into()])
+        );
+        // Not in single quotes or after a backslash
+        assert_eq!(
+            complete(&helper, "echo '$QQ_TEST_N").1,
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            complete(&helper, "echo \\$QQ_TEST_N").1,
+            Vec::<String>::new()
+        );
+
+        helper.completion_engine.remove_variable("QQ_TEST_NAME");
+        assert_eq!(complete(&helper, "echo $QQ_TEST_N").1, Vec::<String>::new());
+    }
+
+    #[test]
+    fn trie_finds_words_by_prefix_and_prunes_removed_ones() {
+        let mut trie = TrieNode::new();
+        for word in ["git", "gitk", "grep"] {
+            trie.insert(word.to_string());
+        }
+        let mut found = trie.find_prefix("gi");
+        found.sort();
+        assert_eq!(found, ["git", "gitk"]);
+
+        assert!(!trie.remove("git"));
+        assert_eq!(trie.find_prefix("gi"), ["gitk"]);
+        trie.remove("gitk");
+        assert!(!trie.children[&'g'].children.contains_key(&'i'));
+        assert!(trie.remove("grep"));
+    }
+
+    #[test]
+    fn commands_run_most_often_come_first() {
+        let helper = helper(&["qqdeploy", "qqdebug", "qqdelete"]);
+        let engine = &helper.completion_engine;
+        assert_eq!(complete(&helper, "qqde").1, Vec::<String>::new());
+
+        for _ in 0..3 {
+            engine.record_use("QQ=1 qqdeploy --prod");
+        }
+        assert_eq!(engine.uses("qqdeploy"), 3);
+        assert_eq!(complete(&helper, "qqde").1, ["qqdeploy "]);
+
+        // Not twice as often as the next, so the menu lists them by use
+        engine.record_use("qqdebug");
+        engine.record_use("qqdebug");
+        *helper.ambiguous_tab.lock().unwrap() = None;
+        assert_eq!(complete(&helper, "qqde").1, Vec::<String>::new());
+        complete(&helper, "qqde");
+        let menu = helper.menu.render("qqde", 4).unwrap();
+        let at = |name| menu.find(name).unwrap();
+        assert!(
+            at("qqdeploy") < at("qqdebug") && at("qqdebug") < at("qqdelete"),
+            "{:?}",
+            menu
+        );
+    }
+
+    #[test]
+    fn double_tab_needs_two_presses_close_together() {
+        let (first, second) = (helper(&[]), helper(&[]));
+        assert!(!first.is_double_tab());
+        assert!(first.is_double_tab());
+        // Each helper keeps its own time
+        assert!(!second.is_double_tab());
+
+        let long_ago = Instant::now().checked_sub(DOUBLE_TAB * 2).unwrap();
+        *first.ambiguous_tab.lock().unwrap() = Some(long_ago);
+        assert!(!first.is_double_tab());
+        assert!(first.is_double_tab());
+    }
+
+    #[test]
+    fn wrappers_leave_the_next_word_a_command() {
+        assert_eq!(wrapped_command(&["sudo", "-u", "root"]), None);
+        assert_eq!(wrapped_command(&["sudo", "-u"]), Some("sudo"));
+        assert_eq!(
+            wrapped_command(&["env", "-i", "A=1", "nice", "-n", "5"]),
+            None
+        );
+        assert_eq!(wrapped_command(&["X=1", "time", "git", "log"]), Some("git"));
+        assert_eq!(wrapped_command(&["sudo", "--", "-x"]), Some("-x"));
+
+        let helper = helper(&["qqdeploy"]);
+        let targets = CompletionSpec {
+            words: vec!["staging".into(), "production".into()],
+            ..CompletionSpec::default()
+        };
+        helper
+            .specs
+            .write()
+            .unwrap()
+            .insert("qqdeploy".into(), targets);
+        assert_eq!(
+            complete(&helper, "sudo -u root qqde"),
+            (13, vec!["qqdeploy ".into()])
+        );
+        assert_eq!(complete(&helper, "env A=1 nohup qqde").1, ["qqdeploy "]);
+        assert_eq!(complete(&helper, "sudo qqdeploy st").1, ["staging "]);
+        assert_eq!(
+            complete(&helper, "ls | time qqdeploy pro").1,
+            ["production "]
+        );
+    }
 }
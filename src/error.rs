@@ -1,10 +1,14 @@
+use crate::command::ParseError;
 use std::fmt;
 use std::io;
+use std::path::PathBuf;
 
 /// Error types for shell operations
 ///
 /// Provides structured error handling for various shell failures
 /// including I/O errors, command execution errors, and environment issues.
+/// Every error belongs to an [`ErrorCategory`], which decides the exit
+/// status reported for the failed command.
 #[derive(Debug)]
 pub enum ShellError {
     /// I/O operation failed
@@ -21,23 +25,214 @@ pub enum ShellError {
     DirectoryNotFound(String),
     /// Change directory failed (path, error message)
     CdError(String, String),
+    /// Operation not permitted in a restricted shell
+    Restricted(String),
+    /// Input couldn't be parsed
+    ParseError(ParseError),
+    /// Word expansion failed (bad substitution, ambiguous redirect, ...)
+    ExpansionError(String),
+    /// A variable that isn't set was expanded under `set -u`
+    UnboundVariable(String),
+    /// `${NAME:?message}` found NAME unset or empty
+    ParameterNotSet { name: String, message: String },
+    /// A readonly variable was assigned to
+    ReadonlyVariable(String),
+    /// A redirection target couldn't be opened
+    RedirectError { path: PathBuf, source: io::Error },
+    /// A job control operation failed (no such job, ...)
+    JobError(String),
+    /// A builtin failed with its own exit status
+    BuiltinError {
+        name: String,
+        status: i32,
+        message: String,
+    },
+    /// A command was found but couldn't be started
+    SpawnError { command: String, source: io::Error },
+    /// An error annotated with where in the input it happened
+    Located {
+        location: Location,
+        error: Box<ShellError>,
+    },
+}
+
+/// Where in the shell's input an error happened
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Location {
+    /// Script being run, if any
+    pub script: Option<String>,
+    /// 1-based line number within the script or command input
+    pub line: Option<usize>,
+}
+
+/// Broad classes of [`ShellError`], each mapped to one exit status
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCategory {
+    /// Syntax errors in the input
+    Syntax,
+    /// The command couldn't be found
+    NotFound,
+    /// The command was found but couldn't be run
+    NotExecutable,
+    /// A builtin reported its own status
+    Builtin(i32),
+    /// Any other runtime failure
+    Runtime,
+}
+
+impl ErrorCategory {
+    /// Exit status reported for errors in this category
+    pub fn exit_status(self) -> i32 {
+        match self {
+            ErrorCategory::Syntax => 2,
+            ErrorCategory::NotFound => 127,
+            ErrorCategory::NotExecutable => 126,
+            ErrorCategory::Builtin(status) => status,
+            ErrorCategory::Runtime => 1,
+        }
+    }
+}
+
+impl ShellError {
+    /// The category this error belongs to
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            ShellError::ParseError(_) => ErrorCategory::Syntax,
+            ShellError::CommandNotFound(_) => ErrorCategory::NotFound,
+            ShellError::SpawnError { source, .. } if source.kind() == io::ErrorKind::NotFound => {
+                ErrorCategory::NotFound
+            }
+            ShellError::IoError(err) | ShellError::SpawnError { source: err, .. }
+                if err.kind() == io::ErrorKind::PermissionDenied =>
+            {
+                ErrorCategory::NotExecutable
+            }
+            ShellError::BuiltinError { status, .. } => ErrorCategory::Builtin(*status),
+            ShellError::Located { error, .. } => error.category(),
+            _ => ErrorCategory::Runtime,
+        }
+    }
+
+    /// Exit status of a command that failed with this error
+    ///
+    /// # Examples
+    /// ```
+    /// use codecrafters_shell::error::ShellError;
+    ///
+    /// assert_eq!(ShellError::CommandNotFound("frob".into()).exit_status(), 127);
+    /// assert_eq!(ShellError::JobError("%3: no such job".into()).exit_status(), 1);
+    /// ```
+    pub fn exit_status(&self) -> i32 {
+        self.category().exit_status()
+    }
+
+    /// The command that wasn't found, if that's what this error is
+    ///
+    /// # Examples
+    /// ```
+    /// use codecrafters_shell::error::{Location, ShellError};
+    ///
+    /// let err = ShellError::CommandNotFound("gti".into()).at(Location::default());
+    /// assert_eq!(err.missing_command(), Some("gti"));
+    /// ```
+    pub fn missing_command(&self) -> Option<&str> {
+        match self {
+            ShellError::CommandNotFound(command) => Some(command),
+            ShellError::Located { error, .. } => error.missing_command(),
+            _ => None,
+        }
+    }
+
+    /// Attach the input position the error happened at
+    ///
+    /// # Examples
+    /// ```
+    /// use codecrafters_shell::error::{Location, ShellError};
+    ///
+    /// let location = Location {
+    ///     script: Some("deploy.sh".into()),
+    ///     line: Some(3),
+    /// };
+    /// let err = ShellError::CommandNotFound("./build".into()).at(location);
+    /// assert_eq!(err.to_string(), "shelly: line 3: deploy.sh: ./build: command not found");
+    /// ```
+    pub fn at(self, location: Location) -> ShellError {
+        ShellError::Located {
+            location,
+            error: Box::new(self),
+        }
+    }
+}
+
+/// Describe an I/O error the way the C library does, without Rust's `(os error N)` suffix
+pub fn describe_io_error(err: &io::Error) -> String {
+    let text = err.to_string();
+    match text.find(" (os error") {
+        Some(end) => text[..end].to_string(),
+        None => text,
+    }
 }
 
 impl fmt::Display for ShellError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             ShellError::IoError(err) => write!(f, "IO error: {}", err),
-            ShellError::CommandNotFound(cmd) => write!(f, "Command not found: {}", cmd),
+            ShellError::CommandNotFound(cmd) => write!(f, "{}: command not found", cmd),
             ShellError::ExecutionError(err) => write!(f, "Execution error: {}", err),
             ShellError::EditorError(err) => write!(f, "Editor error: {}", err),
             ShellError::EnvVarNotFound(var) => write!(f, "Environment variable not found: {}", var),
             ShellError::DirectoryNotFound(dir) => write!(f, "Directory not found: {}", dir),
             ShellError::CdError(path, msg) => write!(f, "cd: {}: {}", path, msg),
+            ShellError::Restricted(msg) => write!(f, "{}", msg),
+            ShellError::ParseError(err) => write!(f, "{}", err),
+            ShellError::ExpansionError(msg) => write!(f, "{}", msg),
+            // Special and positional parameters are shown with their `$`
+            ShellError::UnboundVariable(name) if name.bytes().all(|b| b.is_ascii_digit()) => {
+                write!(f, "${}: unbound variable", name)
+            }
+            ShellError::UnboundVariable(name) => write!(f, "{}: unbound variable", name),
+            ShellError::ParameterNotSet { name, message } => write!(f, "{}: {}", name, message),
+            ShellError::ReadonlyVariable(name) => write!(f, "{}: readonly variable", name),
+            ShellError::RedirectError { path, source } => {
+                write!(f, "{}: {}", path.display(), describe_io_error(source))
+            }
+            ShellError::JobError(msg) => write!(f, "{}", msg),
+            ShellError::BuiltinError { name, message, .. } => write!(f, "{}: {}", name, message),
+            ShellError::SpawnError { command, source } => {
+                write!(f, "{}: {}", command, describe_io_error(source))
+            }
+            ShellError::Located { location, error } => {
+                write!(f, "shelly: ")?;
+                if let Some(line) = location.line {
+                    write!(f, "line {}: ", line)?;
+                }
+                if let Some(script) = &location.script {
+                    write!(f, "{}: ", script)?;
+                }
+                write!(f, "{}", error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ShellError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ShellError::IoError(err) => Some(err),
+            ShellError::RedirectError { source, .. } | ShellError::SpawnError { source, .. } => {
+                Some(source)
+            }
+            ShellError::Located { error, .. } => Some(error.as_ref()),
+            _ => None,
         }
     }
 }
 
-impl std::error::Error for ShellError {}
+impl From<ParseError> for ShellError {
+    fn from(err: ParseError) -> Self {
+        ShellError::ParseError(err)
+    }
+}
 
 /// Auto-convert io::Error to ShellError for convenience
 impl From<io::Error> for ShellError {
@@ -0,0 +1,756 @@
+use crate::command::{
+    is_name, operand_word, tokenize, AssignedValue, Assignment, CommandParts, Redirection, Token,
+    Word, WordPart,
+};
+use crate::error::ShellError;
+use crate::glob::{self, Replace};
+use crate::regex;
+use crate::shell::Shell;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::path::PathBuf;
+
+/// A command after expansion, ready to run
+#[derive(Debug, Default)]
+pub struct ExpandedCommand {
+    /// The command name; empty if every word expanded to nothing
+    pub command: String,
+    /// Command arguments
+    pub args: Vec<String>,
+    /// Expanded `NAME=value` assignments
+    pub env: Vec<(String, String)>,
+    /// Redirections with their targets and here-document bodies expanded, in
+    /// the order they appeared
+    pub redirects: Vec<Redirection<PathBuf, String>>,
+    /// Elements of the `NAME=(a b c)` arguments of `local`, by name, in order
+    pub arrays: Vec<(String, Vec<String>)>,
+}
+
+/// Replace aliases in command position with their values
+///
+/// Runs on the raw line before it is parsed. Only an unquoted first word of
+/// a command is looked up (after any assignments or `!`), and an alias is
+/// never expanded again inside its own value, so `alias ls='ls -F'` works.
+pub fn expand_aliases(line: &str, aliases: &HashMap<String, String>) -> String {
+    expand_aliases_within(line, aliases, &mut Vec::new())
+}
+
+fn expand_aliases_within(
+    line: &str,
+    aliases: &HashMap<String, String>,
+    expanding: &mut Vec<String>,
+) -> String {
+    let mut expanded = String::new();
+    let mut copied = 0;
+    let mut command_position = true;
+    let mut redirect_target = false;
+    // Inside `[[ ... ]]`, where `&&` and `||` don't start a command
+    let mut conditional = false;
+
+    for spanned in tokenize(line) {
+        if conditional {
+            conditional = !matches!(&spanned.token, Token::Word(word) if word.is_unquoted("]]"));
+            continue;
+        }
+        match &spanned.token {
+            Token::Word(_) if redirect_target => redirect_target = false,
+            Token::Word(word) if command_position => {
+                // Assignments and these reserved words keep the next word in command position
+                if word.assignment().is_some()
+                    || [
+                        "!", "{", "if", "then", "elif", "else", "while", "do", "time",
+                    ]
+                    .iter()
+                    .any(|w| word.is_unquoted(w))
+                {
+                    continue;
+                }
+                command_position = false;
+                conditional = word.is_unquoted("[[");
+                let Some(name) = word.unquoted_text() else {
+                    continue;
+                };
+                let Some(value) = aliases
+                    .get(name)
+                    .filter(|_| !expanding.iter().any(|n| n == name))
+                else {
+                    continue;
+                };
+                expanded.push_str(&line[copied..spanned.span.start]);
+                expanding.push(name.to_string());
+                expanded.push_str(&expand_aliases_within(value, aliases, expanding));
+                expanding.pop();
+                copied = spanned.span.end;
+            }
+            Token::Word(_) => {}
+            Token::OutputRedirect(..)
+            | Token::InputRedirect(_)
+            | Token::OutputAndErrorRedirect(_)
+            | Token::HereString(_) => redirect_target = true,
+            Token::DupRedirect(..)
+            | Token::CloseRedirect(_)
+            | Token::DupToWord
+            | Token::HereDoc { .. } => {}
+            Token::Pipe
+            | Token::Background
+            | Token::And
+            | Token::Or
+            | Token::Semicolon
+            | Token::DoubleSemicolon
+            | Token::Newline
+            | Token::LeftParen
+            | Token::RightParen => command_position = true,
+        }
+    }
+    expanded.push_str(&line[copied..]);
+    expanded
+}
+
+/// Expand every word and redirection target of `cmd`
+///
+/// Expansion happens just before the command runs, so `$?` sees the status
+/// of whatever ran before it on the same line.
+pub fn expand_command(
+    shell: &mut Shell,
+    cmd: &CommandParts,
+) -> Result<ExpandedCommand, ShellError> {
+    let mut fields = Vec::with_capacity(cmd.words.len());
+    for word in &cmd.words {
+        fields.extend(expand_word(shell, word)?);
+    }
+    let mut fields = fields.into_iter();
+
+    let env = cmd
+        .env
+        .iter()
+        .map(|assignment| expand_prefix(shell, assignment))
+        .collect::<Result<_, ShellError>>()?;
+
+    let redirects = expand_redirections(shell, &cmd.redirects)?;
+
+    let mut arrays = Vec::with_capacity(cmd.arrays.len());
+    for assignment in &cmd.arrays {
+        let mut elements = Vec::new();
+        if let AssignedValue::Array(words) = &assignment.value {
+            for word in words {
+                elements.extend(expand_word(shell, word)?);
+            }
+        }
+        arrays.push((assignment.name.clone(), elements));
+    }
+
+    Ok(ExpandedCommand {
+        command: fields.next().unwrap_or_default(),
+        args: fields.collect(),
+        env,
+        redirects,
+        arrays,
+    })
+}
+
+/// Expand a `NAME=value` prefix, which sets NAME for one command only
+///
+/// Only a plain value can be given this way, though `NAME+=value` still
+/// adds to the current one.
+fn expand_prefix(
+    shell: &mut Shell,
+    assignment: &Assignment,
+) -> Result<(String, String), ShellError> {
+    let name = &assignment.name;
+    let (AssignedValue::Scalar(value), None) = (&assignment.value, &assignment.index) else {
+        return Err(ShellError::ExpansionError(format!(
+            "{}: arrays can't be assigned for a single command",
+            name
+        )));
+    };
+    let mut value = expand_assignment(shell, value)?;
+    if assignment.append {
+        value.insert_str(0, &shell.variable(name).unwrap_or_default());
+    }
+    Ok((name.clone(), value))
+}
+
+/// Expand one word into the fields it produces
+///
+/// A leading `~` is replaced by a home directory first. An unquoted field
+/// that expands to nothing (`$UNSET`) is dropped entirely, while a quoted
+/// one (`"$UNSET"`) still produces an empty field. `${NAME[@]}` gives a
+/// field per element, joined to any text around it at either end, and the
+/// value of an unquoted reference is split into fields on `$IFS` (blanks if
+/// it's unset, nothing if it's empty), as [`split_fields`] does. Unquoted
+/// `*`, `?` and `[` make a field a pattern that is replaced by the paths it
+/// matches; a pattern matching nothing is left as it was.
+pub fn expand_word(shell: &mut Shell, word: &Word) -> Result<Vec<String>, ShellError> {
+    let ifs = shell.variable("IFS").unwrap_or_else(|| " \t\n".to_string());
+    let ifs = Some(ifs.as_str()).filter(|ifs| !ifs.is_empty());
+    let fields = expand_parts(shell, &expand_tilde(shell, word), glob::escape, ifs)?;
+    let mut expanded = Vec::new();
+    for field in fields {
+        if glob::has_wildcards(&field.pattern) {
+            let paths = glob::expand(&field.pattern, shell.current_dir());
+            if !paths.is_empty() {
+                expanded.extend(paths);
+                continue;
+            }
+        }
+        if !field.text.is_empty() || field.kept {
+            expanded.push(field.text);
+        }
+    }
+    Ok(expanded)
+}
+
+/// Expand a word that always stands for a single string, such as an assignment's value
+///
+/// No globbing is done, and an empty result is kept. Array elements are
+/// joined with spaces.
+pub fn expand_value(shell: &mut Shell, word: &Word) -> Result<String, ShellError> {
+    Ok(joined(
+        expand_parts(shell, word, glob::escape, None)?,
+        |field| field.text,
+    ))
+}
+
+/// Evaluate an array subscript: a number, or a variable holding one (`i`,
+/// `$i` or `${i}`), where an unset variable counts as 0
+pub fn expand_index(shell: &Shell, index: &str) -> Result<i64, ShellError> {
+    let text = index.trim();
+    let value = match text.strip_prefix('$') {
+        Some(param) => {
+            let param = param.strip_prefix('{').and_then(|p| p.strip_suffix('}'));
+            shell.param(param.unwrap_or(&text[1..])).unwrap_or_default()
+        }
+        None if is_name(text) => shell.variable(text).unwrap_or_default(),
+        None => text.to_string(),
+    };
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(0);
+    }
+    value
+        .parse()
+        .map_err(|_| ShellError::ExpansionError(format!("{}: bad array subscript", index)))
+}
+
+/// Replace a leading `~` with `$HOME`, or `~user` with that user's home directory
+///
+/// The prefix runs up to the first `/` and must be entirely unquoted, so
+/// `"~"`, `\~` and `~$USER` stay as they are, as does `~user` for an unknown
+/// user. The directory is inserted as quoted text, so it isn't globbed.
+fn expand_tilde<'a>(shell: &Shell, word: &'a Word) -> Cow<'a, Word> {
+    let Some((WordPart::Literal(first), rest)) = word.parts.split_first() else {
+        return Cow::Borrowed(word);
+    };
+    let Some(prefix) = first.strip_prefix('~') else {
+        return Cow::Borrowed(word);
+    };
+    let (user, path) = prefix.split_at(prefix.find('/').unwrap_or(prefix.len()));
+    if path.is_empty() && !rest.is_empty() {
+        return Cow::Borrowed(word);
+    }
+    let home = match user {
+        "" => shell.variable("HOME"),
+        user => user_home(user),
+    };
+    let Some(home) = home else {
+        return Cow::Borrowed(word);
+    };
+
+    let mut parts = vec![WordPart::Quoted(home)];
+    if !path.is_empty() {
+        parts.push(WordPart::Literal(path.to_string()));
+    }
+    parts.extend(rest.iter().cloned());
+    Cow::Owned(Word { parts })
+}
+
+/// Look up a user's home directory in the password database
+fn user_home(user: &str) -> Option<String> {
+    let name = CString::new(user).ok()?;
+    // SAFETY: `name` is a valid C string; getpwnam returns null or a pointer
+    // to a record that stays valid until the next password database call,
+    // and it is copied out before then
+    unsafe {
+        let entry = libc::getpwnam(name.as_ptr());
+        if entry.is_null() {
+            return None;
+        }
+        Some(
+            CStr::from_ptr((*entry).pw_dir)
+                .to_string_lossy()
+                .into_owned(),
+        )
+    }
+}
+
+/// Expand a `case` pattern, keeping quoted characters literal
+pub fn expand_pattern(shell: &mut Shell, word: &Word) -> Result<String, ShellError> {
+    let fields = expand_parts(shell, &expand_tilde(shell, word), glob::escape, None)?;
+    Ok(joined(fields, |field| field.pattern))
+}
+
+/// Expand the regular expression of `[[ string =~ regex ]]`, keeping quoted
+/// characters literal
+pub fn expand_regex(shell: &mut Shell, word: &Word) -> Result<String, ShellError> {
+    let fields = expand_parts(shell, &expand_tilde(shell, word), regex::escape, None)?;
+    Ok(joined(fields, |field| field.pattern))
+}
+
+/// Expand the value of a `NAME=value` assignment: like [`expand_value`], but
+/// a leading `~` is expanded too
+pub fn expand_assignment(shell: &mut Shell, value: &Word) -> Result<String, ShellError> {
+    expand_value(shell, &expand_tilde(shell, value))
+}
+
+/// One field of an expanded word
+#[derive(Debug, Default)]
+struct Field {
+    /// The text
+    text: String,
+    /// The text with quoted characters escaped, for matching as a pattern
+    pattern: String,
+    /// Whether the field stays even if it's empty: it has a quoted part, or
+    /// came out of splitting on a separator that isn't a blank
+    kept: bool,
+}
+
+/// Substitute the parameters in a word
+///
+/// Returns the fields, each as text and as a pattern with quoted characters
+/// escaped by `escape`. There is one field unless an array is expanded with
+/// `${NAME[@]}`, or `ifs` is given to split unquoted references on.
+fn expand_parts(
+    shell: &mut Shell,
+    word: &Word,
+    escape: fn(&str) -> String,
+    ifs: Option<&str>,
+) -> Result<Vec<Field>, ShellError> {
+    let mut fields = vec![Field::default()];
+    // Whether a separator ended the last field, so any more text starts another
+    let mut separated = false;
+    for part in &word.parts {
+        let split = ifs.filter(|_| matches!(part, WordPart::Param { quoted: false, .. }));
+        let (values, is_quoted) = match part {
+            WordPart::Literal(text) => (vec![text.clone()], false),
+            WordPart::Quoted(text) => (vec![text.clone()], true),
+            WordPart::Param { name, quoted } => (expand_param(shell, name, *quoted)?, *quoted),
+            WordPart::ProcessSubstitution { .. } => {
+                return Err(ShellError::ExpansionError(format!(
+                    "{}: process substitution is only supported in simple commands",
+                    word
+                )));
+            }
+        };
+        for (position, value) in values.into_iter().enumerate() {
+            if position > 0 {
+                fields.push(Field::default());
+                separated = false;
+            }
+            let (pieces, leading, trailing) = match split {
+                Some(ifs) => split_fields(&value, ifs),
+                None => (vec![value], false, false),
+            };
+            separated |= leading;
+            for (index, piece) in pieces.into_iter().enumerate() {
+                if index > 0 || (separated && !fields.last().unwrap().text.is_empty()) {
+                    fields.push(Field::default());
+                }
+                separated = false;
+                let field = fields.last_mut().unwrap();
+                field.text.push_str(&piece);
+                if is_quoted {
+                    field.pattern.push_str(&escape(&piece));
+                } else {
+                    field.pattern.push_str(&piece);
+                }
+                field.kept |= is_quoted || split.is_some();
+            }
+            separated |= trailing;
+        }
+    }
+    Ok(fields)
+}
+
+/// Split the value of an unquoted reference into fields on the characters
+/// of `ifs`
+///
+/// Runs of blanks in `ifs` count as one separator and are trimmed from both
+/// ends; any other character in `ifs` ends a field by itself, so `a::b`
+/// has an empty field in the middle. Also returns whether the value starts
+/// with a blank separator and whether it ends with any separator, which
+/// part it from the text around it.
+fn split_fields(value: &str, ifs: &str) -> (Vec<String>, bool, bool) {
+    let separator = |c: char| ifs.contains(c);
+    let blank = |c: char| separator(c) && c.is_whitespace();
+    let mut rest = value.trim_matches(blank);
+    let mut fields = Vec::new();
+    while !rest.is_empty() {
+        let end = rest.find(separator).unwrap_or(rest.len());
+        fields.push(rest[..end].to_string());
+        rest = rest[end..].trim_start_matches(blank);
+        if let Some(after) = rest.strip_prefix(|c: char| separator(c) && !c.is_whitespace()) {
+            rest = after.trim_start_matches(blank);
+        }
+    }
+    (fields, value.starts_with(blank), value.ends_with(separator))
+}
+
+/// A parameter's value, before any operator is applied
+enum ParamValue {
+    /// A single value, `None` if unset
+    One(Option<String>),
+    /// An array's elements, from `${NAME[@]}` or `$@`, or `${NAME[*]}` or
+    /// `$*` if `joined`
+    List(Vec<String>, bool),
+}
+
+impl ParamValue {
+    /// Whether `${NAME:-word}` (`null_is_unset`) or `${NAME-word}` would
+    /// take the value rather than the word
+    fn is_set(&self, null_is_unset: bool) -> bool {
+        match self {
+            ParamValue::One(Some(value)) => !null_is_unset || !value.is_empty(),
+            ParamValue::One(None) => false,
+            ParamValue::List(elements, _) => !elements.is_empty(),
+        }
+    }
+
+    /// Change the value, or each element
+    fn map(self, change: impl Fn(&str) -> String) -> ParamValue {
+        match self {
+            ParamValue::One(value) => ParamValue::One(value.map(|value| change(&value))),
+            ParamValue::List(elements, joined) => {
+                ParamValue::List(elements.iter().map(|value| change(value)).collect(), joined)
+            }
+        }
+    }
+
+    /// The values the reference expands to; `${NAME[*]}` in double quotes
+    /// joins the elements with spaces
+    fn into_values(self, quoted: bool) -> Vec<String> {
+        match self {
+            ParamValue::One(value) => vec![value.unwrap_or_default()],
+            ParamValue::List(elements, true) if quoted => vec![elements.join(" ")],
+            ParamValue::List(elements, _) => elements,
+        }
+    }
+}
+
+/// The values of a `${...}` reference: one, or an array's elements for
+/// `${NAME[@]}` and the positional parameters for `$@`
+///
+/// `${NAME[*]}` and `$*` are the same unquoted, and the elements joined by
+/// spaces in double quotes. `${NAME[index]}` is one element, `${#NAME}` the length of
+/// the value and `${#NAME[@]}` the number of elements. An operator after
+/// the name changes the value, as [`apply_operator`] describes.
+fn expand_param(shell: &mut Shell, name: &str, quoted: bool) -> Result<Vec<String>, ShellError> {
+    if let Some(target) = name.strip_prefix('#').filter(|target| !target.is_empty()) {
+        let (reference, operator) = split_reference(target);
+        if !operator.is_empty() {
+            return Err(bad_substitution(name));
+        }
+        let length = match lookup(shell, reference, name)? {
+            ParamValue::One(None) if shell.flags().nounset => {
+                return Err(ShellError::UnboundVariable(reference.to_string()));
+            }
+            ParamValue::One(value) => value.unwrap_or_default().chars().count(),
+            ParamValue::List(elements, _) => elements.len(),
+        };
+        return Ok(vec![length.to_string()]);
+    }
+
+    let (reference, operator) = split_reference(name);
+    let value = lookup(shell, reference, name)?;
+    if operator.is_empty() {
+        // `$@` and `$*` are never unset, only empty
+        if !value.is_set(false) && shell.flags().nounset && !matches!(reference, "@" | "*") {
+            return Err(ShellError::UnboundVariable(reference.to_string()));
+        }
+        return Ok(value.into_values(quoted));
+    }
+    apply_operator(shell, reference, value, operator, quoted)
+        .unwrap_or_else(|| Err(bad_substitution(name)))
+}
+
+/// Apply the operator after a parameter's name, or `None` if there's no such operator
+///
+/// `${NAME:-word}` is `word` if NAME is unset or empty, and `${NAME:=word}`
+/// assigns it too; `${NAME:?word}` fails with `word` as the message, and
+/// `${NAME:+word}` is `word` only if NAME is set and not empty. Without the
+/// `:`, only an unset NAME counts. `#` and `##` remove the shortest and
+/// longest start matching a pattern, `%` and `%%` the end, and
+/// `/pattern/string` replaces the first match (`//` every match, `/#` one at
+/// the start, `/%` one at the end). `:offset:length` takes part of the value,
+/// counting back from the end for a negative offset or length.
+fn apply_operator(
+    shell: &mut Shell,
+    reference: &str,
+    value: ParamValue,
+    operator: &str,
+    quoted: bool,
+) -> Option<Result<Vec<String>, ShellError>> {
+    let (null_is_unset, rest) = match operator.strip_prefix(':') {
+        Some(rest) => (true, rest),
+        None => (false, operator),
+    };
+    let op = rest.chars().next()?;
+    let word = &rest[op.len_utf8()..];
+    if matches!(op, '-' | '=' | '?' | '+') {
+        let set = value.is_set(null_is_unset);
+        return Some(match op {
+            '-' | '=' | '?' if set => Ok(value.into_values(quoted)),
+            '-' => expand_operand(shell, word).map(|word| vec![word]),
+            '=' => expand_operand(shell, word).and_then(|word| {
+                assign_param(shell, reference, &word)?;
+                Ok(vec![word])
+            }),
+            '?' => {
+                let message = match (word.is_empty(), null_is_unset) {
+                    (false, _) => expand_operand(shell, word),
+                    (true, true) => Ok("parameter null or not set".to_string()),
+                    (true, false) => Ok("parameter not set".to_string()),
+                };
+                message.and_then(|message| {
+                    Err(ShellError::ParameterNotSet {
+                        name: reference.to_string(),
+                        message,
+                    })
+                })
+            }
+            _ if set => expand_operand(shell, word).map(|word| vec![word]),
+            _ => Ok(vec![String::new()]),
+        });
+    }
+
+    if !value.is_set(false) && shell.flags().nounset && !matches!(reference, "@" | "*") {
+        return Some(Err(ShellError::UnboundVariable(reference.to_string())));
+    }
+    let changed = match (null_is_unset, op) {
+        (true, _) => {
+            // The positional parameters are counted from `$0`
+            let value = match value {
+                ParamValue::List(mut elements, joined) if matches!(reference, "@" | "*") => {
+                    elements.insert(0, shell.param("0").unwrap_or_default());
+                    ParamValue::List(elements, joined)
+                }
+                value => value,
+            };
+            substring(shell, value, rest)
+        }
+        (false, '#' | '%') => {
+            let longest = word.starts_with(op);
+            let pattern = if longest { &word[1..] } else { word };
+            expand_pattern(shell, &operand_word(pattern)).map(|pattern| {
+                value.map(|text| glob::remove_match(&pattern, text, op == '%', longest))
+            })
+        }
+        (false, '/') => {
+            let (mode, word) = match word.chars().next() {
+                Some('/') => (Replace::All, &word[1..]),
+                Some('#') => (Replace::Prefix, &word[1..]),
+                Some('%') => (Replace::Suffix, &word[1..]),
+                _ => (Replace::First, word),
+            };
+            let (pattern, replacement) = split_replacement(word);
+            expand_pattern(shell, &operand_word(pattern)).and_then(|pattern| {
+                let replacement = expand_operand(shell, replacement)?;
+                Ok(value.map(|text| glob::replace(&pattern, text, &replacement, mode)))
+            })
+        }
+        _ => return None,
+    };
+    Some(changed.map(|value| value.into_values(quoted)))
+}
+
+/// `${NAME:offset:length}`: the characters of a value, or the elements of an array
+fn substring(shell: &Shell, value: ParamValue, range: &str) -> Result<ParamValue, ShellError> {
+    let (offset, length) = match range.split_once(':') {
+        Some((offset, length)) => (offset, Some(length)),
+        None => (range, None),
+    };
+    let offset = expand_index(shell, offset)?;
+    let length = length
+        .map(|length| expand_index(shell, length))
+        .transpose()?;
+    // The part of `len` items the offset and length select
+    let bounds = |len: usize| -> Result<(usize, usize), ShellError> {
+        let len = len as i64;
+        let start = if offset < 0 { len + offset } else { offset };
+        if start < 0 || start > len {
+            return Ok((0, 0));
+        }
+        let end = match length {
+            Some(length) if length < 0 => len + length,
+            Some(length) => (start + length).min(len),
+            None => len,
+        };
+        if end < start {
+            return Err(ShellError::ExpansionError(format!(
+                "{}: substring expression < 0",
+                range.rsplit(':').next().unwrap_or_default()
+            )));
+        }
+        Ok((start as usize, end as usize))
+    };
+    Ok(match value {
+        ParamValue::One(value) => {
+            let chars: Vec<char> = value.unwrap_or_default().chars().collect();
+            let (start, end) = bounds(chars.len())?;
+            ParamValue::One(Some(chars[start..end].iter().collect()))
+        }
+        ParamValue::List(elements, joined) => {
+            let (start, end) = bounds(elements.len())?;
+            ParamValue::List(elements[start..end].to_vec(), joined)
+        }
+    })
+}
+
+/// Split the word of `${NAME/pattern/string}` at the first unescaped `/`
+fn split_replacement(word: &str) -> (&str, &str) {
+    let mut escaped = false;
+    for (position, ch) in word.char_indices() {
+        match ch {
+            '/' if !escaped => return (&word[..position], &word[position + 1..]),
+            '\\' => escaped = !escaped,
+            _ => escaped = false,
+        }
+    }
+    (word, "")
+}
+
+/// Assign the word of `${NAME:=word}`, which only works for variables
+fn assign_param(shell: &mut Shell, reference: &str, value: &str) -> Result<(), ShellError> {
+    match reference
+        .strip_suffix(']')
+        .and_then(|name| name.split_once('['))
+    {
+        Some((array, index)) => {
+            let index = expand_index(shell, index)?;
+            shell.set_element(array, index, value)
+        }
+        None if is_name(reference) => shell.set_variable(reference, value),
+        None => Err(ShellError::ExpansionError(format!(
+            "${}: cannot assign in this way",
+            reference
+        ))),
+    }
+}
+
+/// Expand the word of an operator such as `${NAME:-word}` into one string
+fn expand_operand(shell: &mut Shell, word: &str) -> Result<String, ShellError> {
+    expand_assignment(shell, &operand_word(word))
+}
+
+/// Split the inside of `${...}` into the parameter's name, with any
+/// subscript, and the operator after it
+fn split_reference(name: &str) -> (&str, &str) {
+    let len = match name.chars().next() {
+        Some('?' | '$' | '!' | '#' | '@' | '*' | '-') => 1,
+        Some('0'..='9') => name
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(name.len()),
+        Some(c) if c == '_' || c.is_ascii_alphabetic() => {
+            let end = name
+                .find(|c: char| c != '_' && !c.is_ascii_alphanumeric())
+                .unwrap_or(name.len());
+            let subscript = name[end..]
+                .strip_prefix('[')
+                .and_then(|rest| rest.find(']'));
+            end + subscript.map_or(0, |close| close + 2)
+        }
+        _ => 0,
+    };
+    name.split_at(len)
+}
+
+/// Look up a parameter, an array element (`NAME[index]`) or all of an array's
+/// elements (`NAME[@]`)
+fn lookup(shell: &Shell, reference: &str, name: &str) -> Result<ParamValue, ShellError> {
+    if let Some((array, subscript)) = reference
+        .strip_suffix(']')
+        .and_then(|reference| reference.split_once('['))
+    {
+        let elements = || shell.value(array).map(|value| value.elements());
+        return Ok(match subscript {
+            "@" | "*" => ParamValue::List(elements().unwrap_or_default(), subscript == "*"),
+            index => ParamValue::One(shell.element(array, expand_index(shell, index)?)),
+        });
+    }
+    if !is_valid_param(reference) {
+        return Err(bad_substitution(name));
+    }
+    Ok(match reference {
+        "@" | "*" => ParamValue::List(shell.positional().to_vec(), reference == "*"),
+        _ => ParamValue::One(shell.param(reference)),
+    })
+}
+
+fn bad_substitution(name: &str) -> ShellError {
+    ShellError::ExpansionError(format!("${{{}}}: bad substitution", name))
+}
+
+/// The fields of an expansion joined into one string, as where no splitting happens
+fn joined(fields: Vec<Field>, part: fn(Field) -> String) -> String {
+    fields.into_iter().map(part).collect::<Vec<_>>().join(" ")
+}
+
+/// Expand the targets and here-document bodies of `redirects`
+pub fn expand_redirections(
+    shell: &mut Shell,
+    redirects: &[Redirection],
+) -> Result<Vec<Redirection<PathBuf, String>>, ShellError> {
+    redirects
+        .iter()
+        .map(|redirect| expand_redirection(shell, redirect))
+        .collect()
+}
+
+/// Expand a redirection's target, which must come out as exactly one word
+///
+/// A here-document's body is expanded as a single string.
+fn expand_redirection(
+    shell: &mut Shell,
+    redirect: &Redirection,
+) -> Result<Redirection<PathBuf, String>, ShellError> {
+    Ok(match redirect {
+        Redirection::Input { fd, path } => Redirection::Input {
+            fd: *fd,
+            path: redirect_target(shell, path)?,
+        },
+        Redirection::Output { fd, path, append } => Redirection::Output {
+            fd: *fd,
+            path: redirect_target(shell, path)?,
+            append: *append,
+        },
+        Redirection::Duplicate { fd, target } => Redirection::Duplicate {
+            fd: *fd,
+            target: *target,
+        },
+        Redirection::Close { fd } => Redirection::Close { fd: *fd },
+        Redirection::HereDoc { fd, body } => Redirection::HereDoc {
+            fd: *fd,
+            body: expand_value(shell, body)?,
+        },
+    })
+}
+
+/// Expand a redirection target into the one path it must name
+fn redirect_target(shell: &mut Shell, word: &Word) -> Result<PathBuf, ShellError> {
+    match expand_word(shell, word)?.as_slice() {
+        [path] => Ok(PathBuf::from(path)),
+        _ => Err(ShellError::ExpansionError(format!(
+            "{}: ambiguous redirect",
+            word
+        ))),
+    }
+}
+
+/// Whether `name` can be looked up: a special parameter, a digit or a variable name
+fn is_valid_param(name: &str) -> bool {
+    match name.chars().next() {
+        Some('?' | '$' | '!' | '#' | '@' | '*' | '-') => name.len() == 1,
+        Some('0'..='9') => name.chars().all(|c| c.is_ascii_digit()),
+        _ => is_name(name),
+    }
+}
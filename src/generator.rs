@@ -0,0 +1,291 @@
+//! Running the shell functions of `complete -F` specs
+//!
+//! The line editor can't run shell code while the shell waits for it to
+//! return a line, so before such a prompt the shell forks a copy of itself
+//! that waits on a pipe. Each request names a function and the word being
+//! completed; the copy runs the function with its output going back up
+//! another pipe, followed by a NUL byte. The copy sees the shell as it was
+//! when the prompt was drawn and is killed once the next prompt needs one.
+//!
+//! The copy never runs a function itself: it forks a worker that does, and
+//! forks a fresh one whenever a worker is killed. A function that doesn't
+//! answer within [`CALL_TIMEOUT`] has its worker killed, so a stuck
+//! function costs one Tab press rather than the prompt.
+
+use crate::error::ShellError;
+use crate::shell::Shell;
+use std::io::{self, BufRead, BufReader, PipeReader, PipeWriter, Read, Write};
+use std::os::fd::AsRawFd;
+use std::time::{Duration, Instant};
+
+/// How long a completion function gets to print its matches
+pub const CALL_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// What a completion function is asked, as bash passes it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    /// The function to run
+    pub function: String,
+    /// The command whose argument is being completed, `$1`
+    pub command: String,
+    /// The word being completed, `$2`
+    pub word: String,
+    /// The word before it, `$3`
+    pub previous: String,
+    /// The whole line, `$COMP_LINE`
+    pub line: String,
+    /// Byte offset of the cursor in the line, `$COMP_POINT`
+    pub point: usize,
+}
+
+impl Request {
+    /// Write the request as NUL-terminated fields
+    fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        let point = self.point.to_string();
+        for field in [
+            &self.function,
+            &self.command,
+            &self.word,
+            &self.previous,
+            &self.line,
+            &point,
+        ] {
+            writer.write_all(field.as_bytes())?;
+            writer.write_all(b"\0")?;
+        }
+        writer.flush()
+    }
+
+    /// Read a request written by [`write_to`](Self::write_to), or `None`
+    /// once the pipe is closed
+    fn read_from(reader: &mut impl BufRead) -> Option<Self> {
+        let mut field = || {
+            let mut bytes = Vec::new();
+            reader.read_until(0, &mut bytes).ok()?;
+            bytes.pop().filter(|&end| end == 0)?;
+            String::from_utf8(bytes).ok()
+        };
+        Some(Request {
+            function: field()?,
+            command: field()?,
+            word: field()?,
+            previous: field()?,
+            line: field()?,
+            point: field()?.parse().ok()?,
+        })
+    }
+}
+
+/// A forked copy of the shell that runs completion functions
+#[derive(Debug)]
+pub struct Generator {
+    pid: libc::pid_t,
+    /// The copy's current worker, which runs the functions
+    worker: libc::pid_t,
+    requests: PipeWriter,
+    responses: PipeReader,
+    /// Pids of the workers the copy starts, each leading its own group
+    workers: PipeReader,
+}
+
+impl Generator {
+    /// Fork a copy of `shell` to run completion functions in
+    pub fn spawn(shell: &mut Shell) -> Result<Self, ShellError> {
+        let (request_reader, requests) = io::pipe()?;
+        let (responses, response_writer) = io::pipe()?;
+        let (workers, worker_writer) = io::pipe()?;
+
+        // SAFETY: the child only uses its own copy of the shell and leaves
+        // with `_exit`, never returning into the caller
+        let pid = unsafe { libc::fork() };
+        if pid == -1 {
+            return Err(io::Error::last_os_error().into());
+        }
+        if pid == 0 {
+            drop((requests, responses, workers));
+            // SAFETY: dup2 onto standard descriptors of this process, from
+            // the pipe and from /dev/null, which is opened first
+            unsafe {
+                libc::dup2(response_writer.as_raw_fd(), 1);
+                let null = libc::open(c"/dev/null".as_ptr(), libc::O_RDWR);
+                if null != -1 {
+                    libc::dup2(null, 0);
+                    libc::dup2(null, 2);
+                }
+            }
+            drop(response_writer);
+            shell.detach();
+            supervise(shell, BufReader::new(request_reader), worker_writer);
+        }
+        drop(worker_writer);
+        let mut generator = Generator {
+            pid,
+            worker: -1,
+            requests,
+            responses,
+            workers,
+        };
+        generator.worker = generator.next_worker();
+        Ok(generator)
+    }
+
+    /// Run the function `request` names, returning the lines it printed
+    ///
+    /// Nothing is returned if the copy of the shell has gone away, or if the
+    /// function hasn't finished within [`CALL_TIMEOUT`]; its worker is then
+    /// killed and replaced.
+    pub fn call(&mut self, request: &Request) -> Vec<String> {
+        if request.write_to(&mut self.requests).is_err() {
+            return Vec::new();
+        }
+        let Some(output) = read_response(&mut self.responses, CALL_TIMEOUT) else {
+            self.restart_worker();
+            return Vec::new();
+        };
+        String::from_utf8_lossy(&output)
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Kill the worker and wait for the copy to start another, dropping
+    /// whatever the old one had printed
+    fn restart_worker(&mut self) {
+        if self.worker > 0 {
+            // SAFETY: signalling the group of the worker the copy reported
+            unsafe { libc::kill(-self.worker, libc::SIGKILL) };
+        }
+        self.worker = self.next_worker();
+        let mut discarded = [0; 4096];
+        while wait_readable(&self.responses, Duration::ZERO) {
+            if !matches!(self.responses.read(&mut discarded), Ok(1..)) {
+                break;
+            }
+        }
+    }
+
+    /// The pid of the next worker the copy starts, or -1 if it has gone
+    fn next_worker(&mut self) -> libc::pid_t {
+        let mut pid = [0; size_of::<libc::pid_t>()];
+        if !wait_readable(&self.workers, CALL_TIMEOUT) || self.workers.read_exact(&mut pid).is_err()
+        {
+            return -1;
+        }
+        libc::pid_t::from_ne_bytes(pid)
+    }
+}
+
+/// Run workers in the forked copy until the shell closes the request pipe
+///
+/// Each worker is a fork of the copy, so one that is killed mid-function is
+/// replaced by another that sees the same shell.
+fn supervise(shell: &mut Shell, mut requests: BufReader<PipeReader>, mut workers: PipeWriter) -> ! {
+    loop {
+        // SAFETY: the worker only uses its own copy of the shell and leaves
+        // with `_exit`
+        let worker = unsafe { libc::fork() };
+        if worker == 0 {
+            drop(workers);
+            // Its own group, so killing it takes the commands it runs along
+            // SAFETY: moving this process into a new group
+            unsafe { libc::setpgid(0, 0) };
+            while let Some(request) = Request::read_from(&mut requests) {
+                shell.run_completion_function(&request);
+                let mut stdout = io::stdout();
+                let _ = stdout.write_all(b"\0").and_then(|()| stdout.flush());
+            }
+            // SAFETY: ends the forked worker without running the parent's cleanup
+            unsafe { libc::_exit(0) };
+        }
+        // Set from both sides, so the group exists before anyone signals it
+        // SAFETY: moving our own child into its new group
+        unsafe { libc::setpgid(worker, worker) };
+        if worker == -1 || workers.write_all(&worker.to_ne_bytes()).is_err() {
+            break;
+        }
+        let mut raw_status = 0;
+        // SAFETY: waiting on the worker just forked
+        unsafe { libc::waitpid(worker, &mut raw_status, 0) };
+        // A worker only exits by itself once the shell has gone
+        if !libc::WIFSIGNALED(raw_status) {
+            break;
+        }
+    }
+    // SAFETY: ends the forked child without running the parent's cleanup
+    unsafe { libc::_exit(0) }
+}
+
+/// Read one NUL-terminated response, or `None` if it doesn't all arrive
+/// within `timeout` or the pipe is closed first
+fn read_response(reader: &mut PipeReader, timeout: Duration) -> Option<Vec<u8>> {
+    let deadline = Instant::now() + timeout;
+    let mut output = Vec::new();
+    let mut chunk = [0; 4096];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if !wait_readable(reader, remaining) {
+            return None;
+        }
+        match reader.read(&mut chunk) {
+            Ok(0) | Err(_) => return None,
+            Ok(count) => output.extend_from_slice(&chunk[..count]),
+        }
+        if output.last() == Some(&0) {
+            output.pop();
+            return Some(output);
+        }
+    }
+}
+
+/// Whether `reader` has something to read (or has been closed) within `timeout`
+fn wait_readable(reader: &impl AsRawFd, timeout: Duration) -> bool {
+    let mut poll = libc::pollfd {
+        fd: reader.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let timeout = timeout.as_millis().try_into().unwrap_or(libc::c_int::MAX);
+    // SAFETY: polling one descriptor we own, through a pollfd on our stack
+    unsafe { libc::poll(&mut poll, 1, timeout) > 0 }
+}
+
+impl Drop for Generator {
+    /// Kill the copy of the shell and its worker, which may be stuck in a
+    /// function, and reap the copy
+    fn drop(&mut self) {
+        // SAFETY: signalling the processes we forked, and waiting on ours
+        unsafe {
+            if self.worker > 0 {
+                libc::kill(-self.worker, libc::SIGKILL);
+            }
+            libc::kill(self.pid, libc::SIGKILL);
+            let mut raw_status = 0;
+            libc::waitpid(self.pid, &mut raw_status, 0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn responses_end_at_a_nul() {
+        let (mut reader, mut writer) = io::pipe().unwrap();
+        writer.write_all(b"start\nstop\n\0").unwrap();
+        let response = read_response(&mut reader, Duration::from_secs(1));
+        assert_eq!(response.as_deref(), Some(&b"start\nstop\n"[..]));
+    }
+
+    #[test]
+    fn responses_give_up_after_the_timeout() {
+        let (mut reader, mut writer) = io::pipe().unwrap();
+        writer.write_all(b"partial").unwrap();
+        let started = Instant::now();
+        assert_eq!(read_response(&mut reader, Duration::from_millis(100)), None);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        drop(writer);
+        assert_eq!(read_response(&mut reader, Duration::from_secs(1)), None);
+    }
+}
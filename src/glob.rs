@@ -0,0 +1,274 @@
+use std::fs;
+use std::path::Path;
+
+/// Whether `pattern` contains an unescaped `*`, `?` or `[`
+pub fn has_wildcards(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => {
+                chars.next();
+            }
+            '*' | '?' | '[' => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Escape `text` so every character in it matches only itself
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for ch in text.chars() {
+        if matches!(ch, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(ch);
+    }
+    escaped
+}
+
+/// Match a single file name against a pattern
+///
+/// `*` matches any run of characters, `?` any one character, and `[...]`
+/// one character from a set (`[a-z]`, negated with `[!...]` or `[^...]`).
+/// A backslash makes the next character literal.
+///
+/// # Examples
+/// ```
+/// use codecrafters_shell::glob::matches;
+///
+/// assert!(matches("*.rs", "main.rs"));
+/// assert!(matches("file[0-9].?xt", "file3.txt"));
+/// assert!(!matches("[!a-c]*", "bin"));
+/// assert!(!matches("\\*.rs", "main.rs"));
+/// ```
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    match_from(&pattern, &name)
+}
+
+fn match_from(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).any(|skip| match_from(&pattern[1..], &name[skip..])),
+        Some('?') => !name.is_empty() && match_from(&pattern[1..], &name[1..]),
+        Some('[') => match (name.first(), bracket_len(&pattern[1..])) {
+            (Some(&ch), Some(len)) => {
+                bracket_matches(&pattern[1..=len], ch)
+                    && match_from(&pattern[len + 2..], &name[1..])
+            }
+            // An unterminated `[` is an ordinary character
+            (Some('['), None) => match_from(&pattern[1..], &name[1..]),
+            _ => false,
+        },
+        Some('\\') if pattern.len() > 1 => {
+            name.first() == Some(&pattern[1]) && match_from(&pattern[2..], &name[1..])
+        }
+        Some(ch) => name.first() == Some(ch) && match_from(&pattern[1..], &name[1..]),
+    }
+}
+
+/// What `${name#pattern}`, `${name%%pattern}` and the like leave of `text`:
+/// the shortest (or `longest`) start, or end if `suffix`, that `pattern`
+/// matches is removed
+///
+/// # Examples
+/// ```
+/// use codecrafters_shell::glob::remove_match;
+///
+/// assert_eq!(remove_match("*.", "a.tar.gz", false, false), "tar.gz");
+/// assert_eq!(remove_match(".*", "a.tar.gz", true, true), "a");
+/// assert_eq!(remove_match("x", "a.tar.gz", false, true), "a.tar.gz");
+/// ```
+pub fn remove_match(pattern: &str, text: &str, suffix: bool, longest: bool) -> String {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let len = text.len();
+    let matches_len = |n: usize| {
+        let matched = if suffix { &text[len - n..] } else { &text[..n] };
+        match_from(&pattern, matched)
+    };
+    let found = if longest {
+        (0..=len).rev().find(|&n| matches_len(n))
+    } else {
+        (0..=len).find(|&n| matches_len(n))
+    };
+    let n = found.unwrap_or(0);
+    let kept = if suffix { &text[..len - n] } else { &text[n..] };
+    kept.iter().collect()
+}
+
+/// Where `${name/pattern/replacement}` and its variants replace matches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Replace {
+    /// The first match (`/`)
+    First,
+    /// Every match (`//`)
+    All,
+    /// A match at the start (`/#`)
+    Prefix,
+    /// A match at the end (`/%`)
+    Suffix,
+}
+
+/// Replace the longest matches of `pattern` in `text` with `replacement`
+///
+/// An empty pattern matches nothing, except at the start or end.
+///
+/// # Examples
+/// ```
+/// use codecrafters_shell::glob::{replace, Replace};
+///
+/// assert_eq!(replace("c*", "abcdef", "Z", Replace::First), "abZ");
+/// assert_eq!(replace("[ace]", "abcdef", "-", Replace::All), "-b-d-f");
+/// assert_eq!(replace("ab", "abab", "X", Replace::Suffix), "abX");
+/// ```
+pub fn replace(pattern: &str, text: &str, replacement: &str, mode: Replace) -> String {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    // The end of the longest match starting at `start`
+    let longest_match = |start: usize| {
+        (start..=text.len())
+            .rev()
+            .find(|&end| match_from(&pattern, &text[start..end]))
+    };
+
+    let mut replaced = String::new();
+    match mode {
+        Replace::Prefix => match longest_match(0) {
+            Some(end) => {
+                replaced.push_str(replacement);
+                replaced.extend(&text[end..]);
+            }
+            None => replaced.extend(&text),
+        },
+        Replace::Suffix => {
+            match (0..=text.len()).find(|&start| match_from(&pattern, &text[start..])) {
+                Some(start) => {
+                    replaced.extend(&text[..start]);
+                    replaced.push_str(replacement);
+                }
+                None => replaced.extend(&text),
+            }
+        }
+        Replace::First | Replace::All => {
+            let mut start = 0;
+            while start < text.len() {
+                match longest_match(start).filter(|&end| end > start) {
+                    Some(end) => {
+                        replaced.push_str(replacement);
+                        start = end;
+                        if mode == Replace::First {
+                            break;
+                        }
+                    }
+                    None => {
+                        replaced.push(text[start]);
+                        start += 1;
+                    }
+                }
+            }
+            replaced.extend(&text[start..]);
+        }
+    }
+    replaced
+}
+
+/// Length of a bracket expression's body (the part after `[`, before `]`)
+///
+/// A `]` right after the opening `[` (or `[!`) is part of the set.
+fn bracket_len(body: &[char]) -> Option<usize> {
+    let mut i = usize::from(matches!(body.first(), Some('!' | '^')));
+    if body.get(i) == Some(&']') {
+        i += 1;
+    }
+    while i < body.len() {
+        match body[i] {
+            ']' => return Some(i),
+            '\\' => i += 2,
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+/// Whether `ch` is in the set described by a bracket expression's body
+fn bracket_matches(body: &[char], ch: char) -> bool {
+    let (negated, mut set) = match body.split_first() {
+        Some(('!' | '^', rest)) => (true, rest),
+        _ => (false, body),
+    };
+    let mut found = false;
+    while let Some((&first, rest)) = set.split_first() {
+        let (low, rest) = match (first, rest) {
+            ('\\', [escaped, rest @ ..]) => (*escaped, rest),
+            _ => (first, rest),
+        };
+        let (high, rest) = match rest {
+            ['-', high, rest @ ..] if *high != ']' => (*high, rest),
+            _ => (low, rest),
+        };
+        found |= (low..=high).contains(&ch);
+        set = rest;
+    }
+    found != negated
+}
+
+/// Expand a pattern into the paths it matches, relative to `cwd`
+///
+/// Each `/`-separated component is matched against the entries of the
+/// directories matched so far. Names starting with `.` only match a pattern
+/// that starts with a literal `.`. Returns the matches sorted, or nothing if
+/// no path matches.
+pub fn expand(pattern: &str, cwd: &Path) -> Vec<String> {
+    let (mut paths, rest) = match pattern.strip_prefix('/') {
+        Some(rest) => (vec!["/".to_string()], rest),
+        None => (vec![String::new()], pattern),
+    };
+    let components: Vec<&str> = rest.split('/').collect();
+
+    for (index, component) in components.iter().enumerate() {
+        let mut next = Vec::new();
+        for prefix in &paths {
+            if !has_wildcards(component) {
+                next.push(format!("{}{}", prefix, unescape(component)));
+                continue;
+            }
+            let dir = cwd.join(if prefix.is_empty() { "." } else { prefix });
+            let Ok(entries) = fs::read_dir(dir) else {
+                continue;
+            };
+            let mut names: Vec<String> = entries
+                .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+                .filter(|name| !name.starts_with('.') || component.starts_with('.'))
+                .filter(|name| matches(component, name))
+                .collect();
+            names.sort();
+            next.extend(names.into_iter().map(|name| format!("{}{}", prefix, name)));
+        }
+        paths = next;
+
+        if index + 1 < components.len() {
+            paths.retain(|path| cwd.join(path).is_dir());
+            paths.iter_mut().for_each(|path| path.push('/'));
+        }
+    }
+
+    paths.retain(|path| cwd.join(path).symlink_metadata().is_ok());
+    paths
+}
+
+/// Drop the backslashes from an escaped pattern
+fn unescape(pattern: &str) -> String {
+    let mut text = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => text.extend(chars.next()),
+            _ => text.push(ch),
+        }
+    }
+    text
+}
@@ -0,0 +1,191 @@
+//! Syntax highlighting of the line being edited
+//!
+//! The line is split with the parser's own lexer, so what's colored as one
+//! word or operator is what will run as one.
+
+use crate::command::{tokenize, Token};
+
+/// SGR code for a command that can be run
+const FOUND: &str = "32";
+/// SGR code for a command word that names nothing the shell can run
+const NOT_FOUND: &str = "31";
+/// SGR code for reserved words such as `if` and `done`
+const RESERVED: &str = "1";
+/// SGR code for quoted text
+const STRING: &str = "33";
+/// SGR code for operators and redirections
+const OPERATOR: &str = "36";
+
+/// Reserved words after which the next word is still a command
+const COMMAND_PREFIXES: [&str; 9] = [
+    "!", "{", "if", "then", "elif", "else", "while", "do", "time",
+];
+
+/// The other reserved words the parser knows
+const OTHER_RESERVED: [&str; 8] = ["fi", "done", "esac", "}", "for", "case", "[[", "]]"];
+
+/// `line` with ANSI colors added
+///
+/// Command words are green if `found` says they can be run and red if not,
+/// reserved words are bold, quoted text is yellow, and operators and
+/// redirections are cyan. Assignments before a command and everything else
+/// are left as typed.
+///
+/// # Examples
+/// ```
+/// use codecrafters_shell::highlight::highlight;
+///
+/// let found = |name: &str| name == "echo";
+/// assert_eq!(
+///     highlight("echo 'hi' | nope", found),
+///     "\x1b[32mecho\x1b[0m \x1b[33m'hi'\x1b[0m \x1b[36m|\x1b[0m \x1b[31mnope\x1b[0m"
+/// );
+/// assert_eq!(
+///     highlight("X=1 if echo", found),
+///     "X=1 \x1b[1mif\x1b[0m \x1b[32mecho\x1b[0m"
+/// );
+/// ```
+pub fn highlight(line: &str, found: impl Fn(&str) -> bool) -> String {
+    let mut colored = String::with_capacity(line.len() * 2);
+    let mut copied = 0;
+    let mut command_position = true;
+    let mut redirect_target = false;
+
+    for spanned in tokenize(line) {
+        let (start, end) = (spanned.span.start, spanned.span.end);
+        // A here-document's span can take in the lines of its body
+        if start < copied || end > line.len() || line[start..end].contains('\n') && end - start > 1
+        {
+            continue;
+        }
+        colored.push_str(&line[copied..start]);
+        copied = end;
+        let text = &line[start..end];
+        match &spanned.token {
+            Token::Word(_) if redirect_target => {
+                redirect_target = false;
+                colored.push_str(&color_strings(text));
+            }
+            Token::Word(word) if command_position => {
+                if word.assignment().is_some() {
+                    colored.push_str(text);
+                } else if COMMAND_PREFIXES.iter().any(|w| word.is_unquoted(w)) {
+                    colored.push_str(&paint(RESERVED, text));
+                } else if OTHER_RESERVED.iter().any(|w| word.is_unquoted(w)) {
+                    command_position = false;
+                    colored.push_str(&paint(RESERVED, text));
+                } else {
+                    command_position = false;
+                    let name = word.literal_text();
+                    let code = if found(&name) { FOUND } else { NOT_FOUND };
+                    colored.push_str(&paint(code, text));
+                }
+            }
+            Token::Word(word) => {
+                if OTHER_RESERVED.iter().any(|w| word.is_unquoted(w)) {
+                    colored.push_str(&paint(RESERVED, text));
+                } else {
+                    colored.push_str(&color_strings(text));
+                }
+            }
+            Token::OutputRedirect(..)
+            | Token::InputRedirect(_)
+            | Token::OutputAndErrorRedirect(_)
+            | Token::HereString(_) => {
+                redirect_target = true;
+                colored.push_str(&paint(OPERATOR, text));
+            }
+            Token::DupRedirect(..)
+            | Token::CloseRedirect(_)
+            | Token::DupToWord
+            | Token::HereDoc { .. } => {
+                colored.push_str(&paint(OPERATOR, text));
+            }
+            Token::Newline => {
+                command_position = true;
+                colored.push_str(text);
+            }
+            Token::Pipe
+            | Token::Background
+            | Token::And
+            | Token::Or
+            | Token::Semicolon
+            | Token::DoubleSemicolon
+            | Token::LeftParen
+            | Token::RightParen => {
+                command_position = true;
+                colored.push_str(&paint(OPERATOR, text));
+            }
+        }
+    }
+    colored.push_str(&line[copied..]);
+    colored
+}
+
+/// `text` in the color `code`
+fn paint(code: &str, text: &str) -> String {
+    format!("\x1b[{}m{}\x1b[0m", code, text)
+}
+
+/// A word with its quoted parts colored, including the quotes; a quote
+/// left open colors the rest of the word
+fn color_strings(word: &str) -> String {
+    let mut colored = String::with_capacity(word.len());
+    let mut chars = word.char_indices();
+    while let Some((start, ch)) = chars.next() {
+        match ch {
+            '\\' => {
+                colored.push(ch);
+                colored.extend(chars.next().map(|(_, ch)| ch));
+            }
+            '\'' | '"' => {
+                let mut end = word.len();
+                while let Some((index, next)) = chars.next() {
+                    if next == '\\' && ch == '"' {
+                        chars.next();
+                    } else if next == ch {
+                        end = index + 1;
+                        break;
+                    }
+                }
+                colored.push_str(&paint(STRING, &word[start..end]));
+            }
+            _ => colored.push(ch),
+        }
+    }
+    colored
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `line` highlighted with only `ls` and `grep` found
+    fn colored(line: &str) -> String {
+        highlight(line, |name| name == "ls" || name == "grep")
+    }
+
+    #[test]
+    fn commands_follow_operators_and_reserved_words() {
+        assert_eq!(
+            colored("ls && nope; if grep x\nthen ls; fi"),
+            "\x1b[32mls\x1b[0m \x1b[36m&&\x1b[0m \x1b[31mnope\x1b[0m\x1b[36m;\x1b[0m \
+             \x1b[1mif\x1b[0m \x1b[32mgrep\x1b[0m x\n\x1b[1mthen\x1b[0m \x1b[32mls\x1b[0m\
+             \x1b[36m;\x1b[0m \x1b[1mfi\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn redirection_targets_and_quotes_are_not_commands() {
+        assert_eq!(
+            colored("> out ls 2>&1"),
+            "\x1b[36m>\x1b[0m out \x1b[32mls\x1b[0m \x1b[36m2>&1\x1b[0m"
+        );
+        assert_eq!(
+            colored(r#"grep a"b\"c"d 'open"#),
+            "\x1b[32mgrep\x1b[0m a\x1b[33m\"b\\\"c\"\x1b[0md \x1b[33m'open\x1b[0m"
+        );
+        // The quoted name is still looked up as the command it runs
+        assert_eq!(colored("'ls' -l"), "\x1b[32m'ls'\x1b[0m -l");
+    }
+}
@@ -0,0 +1,676 @@
+//! Command history: the line editor's history, its file, and `!`
+//! references to earlier commands
+
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::ffi::{CStr, CString};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rustyline::history::{History, SearchDirection, SearchResult};
+
+use crate::command::{tokenize, Token};
+use crate::error::ShellError;
+
+/// How many entries are kept when `HISTSIZE` or `HISTFILESIZE` isn't set
+pub const DEFAULT_SIZE: usize = 500;
+
+/// The history file used when `HISTFILE` isn't set:
+/// `$XDG_STATE_HOME/shelly/history`, or `~/.shelly_history` without
+/// `XDG_STATE_HOME`
+///
+/// # Examples
+/// ```
+/// use codecrafters_shell::history::default_file;
+/// use std::path::PathBuf;
+///
+/// assert_eq!(
+///     default_file(Some("/home/ada/.local/state"), Some("/home/ada")),
+///     Some(PathBuf::from("/home/ada/.local/state/shelly/history"))
+/// );
+/// assert_eq!(
+///     default_file(None, Some("/home/ada")),
+///     Some(PathBuf::from("/home/ada/.shelly_history"))
+/// );
+/// assert_eq!(default_file(Some(""), None), None);
+/// ```
+pub fn default_file(state_home: Option<&str>, home: Option<&str>) -> Option<PathBuf> {
+    match (state_home.filter(|dir| !dir.is_empty()), home) {
+        (Some(dir), _) => Some(Path::new(dir).join("shelly").join("history")),
+        (None, Some(home)) => Some(Path::new(home).join(".shelly_history")),
+        (None, None) => None,
+    }
+}
+
+/// `time` in the local time zone, formatted as by `strftime(3)`
+///
+/// # Examples
+/// ```
+/// use codecrafters_shell::history::format_time;
+/// use std::time::{Duration, UNIX_EPOCH};
+///
+/// // Midsummer 2024, which is in 2024 in every time zone
+/// let time = UNIX_EPOCH + Duration::from_secs(1_719_000_000);
+/// assert_eq!(format_time(time, "%Y:"), "2024:");
+/// ```
+pub fn format_time(time: SystemTime, format: &str) -> String {
+    let Ok(format) = CString::new(format) else {
+        return String::new();
+    };
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs()) as libc::time_t;
+    let mut formatted = [0 as libc::c_char; 256];
+    // SAFETY: localtime_r fills in the zeroed struct it's given, and strftime
+    // writes at most `formatted.len()` bytes, NUL included, or returns 0
+    unsafe {
+        let mut local: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&seconds, &mut local).is_null() {
+            return String::new();
+        }
+        let length = libc::strftime(
+            formatted.as_mut_ptr(),
+            formatted.len(),
+            format.as_ptr(),
+            &local,
+        );
+        if length == 0 {
+            return String::new();
+        }
+        CStr::from_ptr(formatted.as_ptr())
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+/// How Ctrl+R matches the text typed against history entries
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SearchMode {
+    /// The entry contains the text
+    #[default]
+    Substring,
+    /// The entry contains the text's characters in order, as [`fuzzy_find`]
+    Fuzzy,
+}
+
+/// Where `term` matches `entry` loosely: the byte offset of the first
+/// matching character, or `None` if there's no match
+///
+/// The characters of `term` have to appear in `entry` in order, but not
+/// necessarily next to each other; a place where they do is preferred. An
+/// all-lowercase `term` ignores case.
+///
+/// # Examples
+/// ```
+/// use codecrafters_shell::history::fuzzy_find;
+///
+/// assert_eq!(fuzzy_find("git status", "gst"), Some(0));
+/// assert_eq!(fuzzy_find("cargo test --workspace", "test"), Some(6));
+/// assert_eq!(fuzzy_find("make Release", "release"), Some(5));
+/// assert_eq!(fuzzy_find("make release", "Release"), None);
+/// assert_eq!(fuzzy_find("ls -a", "sl"), None);
+/// ```
+pub fn fuzzy_find(entry: &str, term: &str) -> Option<usize> {
+    let ignore_case = !term.chars().any(char::is_uppercase);
+    let same = |ch: char, wanted: char| {
+        ch == wanted || (ignore_case && ch.to_lowercase().eq(wanted.to_lowercase()))
+    };
+    let contiguous = entry.char_indices().find(|&(start, _)| {
+        let mut rest = entry[start..].chars();
+        term.chars()
+            .all(|wanted| rest.next().is_some_and(|ch| same(ch, wanted)))
+    });
+    if let Some((start, _)) = contiguous {
+        return Some(start);
+    }
+    let mut chars = entry.char_indices();
+    let mut first = None;
+    for wanted in term.chars() {
+        let (index, _) = chars.find(|&(_, ch)| same(ch, wanted))?;
+        first.get_or_insert(index);
+    }
+    first
+}
+
+/// A command entered at the prompt
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    /// The command line, which may span several lines
+    pub command: String,
+    /// When it was entered, if known
+    pub time: Option<SystemTime>,
+}
+
+/// The commands entered at the prompt, as the line editor's history
+///
+/// The file holds one entry per line, with newlines and backslashes in an
+/// entry escaped as `\n` and `\\`, and a leading `#` as `\#`. As in
+/// bash, an entry's timestamp is on a `#` line of its own before it, in
+/// seconds since the epoch. [`History::append`] only adds the
+/// entries this session hasn't written yet, with the file locked, so shells
+/// running at the same time interleave their commands rather than
+/// overwriting each other's. The file is then cut down to its newest
+/// [`set_max_file_len`](Self::set_max_file_len) entries.
+#[derive(Debug)]
+pub struct ShellHistory {
+    entries: VecDeque<HistoryEntry>,
+    /// Most entries kept in memory (`HISTSIZE`)
+    max_len: usize,
+    /// Most entries kept in the file (`HISTFILESIZE`)
+    max_file_len: usize,
+    /// How many of the newest entries haven't been written to the file
+    unsaved: usize,
+    search_mode: SearchMode,
+    ignore_dups: bool,
+    ignore_space: bool,
+}
+
+impl ShellHistory {
+    /// An empty history keeping [`DEFAULT_SIZE`] entries
+    pub fn new() -> Self {
+        ShellHistory {
+            entries: VecDeque::new(),
+            max_len: DEFAULT_SIZE,
+            max_file_len: DEFAULT_SIZE,
+            unsaved: 0,
+            search_mode: SearchMode::default(),
+            ignore_dups: true,
+            ignore_space: false,
+        }
+    }
+
+    /// Keep at most `len` entries in the history file
+    pub fn set_max_file_len(&mut self, len: usize) {
+        self.max_file_len = len;
+    }
+
+    /// Match Ctrl+R searches as `mode` says
+    pub fn set_search_mode(&mut self, mode: SearchMode) {
+        self.search_mode = mode;
+    }
+
+    /// The entries, oldest first
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &HistoryEntry> + '_ {
+        self.entries.iter()
+    }
+
+    /// Delete the entry at `index`, counting from 0 for the oldest
+    pub fn remove(&mut self, index: usize) -> Option<HistoryEntry> {
+        let entry = self.entries.remove(index)?;
+        if index >= self.entries.len() + 1 - self.unsaved {
+            self.unsaved -= 1;
+        }
+        Some(entry)
+    }
+
+    fn push(&mut self, entry: HistoryEntry) {
+        if self.max_len == 0 {
+            return;
+        }
+        if self.entries.len() >= self.max_len {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    /// Write `entries` to the end of the locked `file`, then drop the oldest
+    /// entries beyond the file size limit
+    fn write_to<'a>(
+        &self,
+        file: &mut File,
+        entries: impl Iterator<Item = &'a HistoryEntry>,
+    ) -> io::Result<()> {
+        let new: String = entries.map(encode).collect();
+        file.write_all(new.as_bytes())?;
+        let mut contents = String::new();
+        file.seek(SeekFrom::Start(0))?;
+        file.read_to_string(&mut contents)?;
+        let stored = parse(&contents);
+        if stored.len() > self.max_file_len {
+            let kept: String = stored[stored.len() - self.max_file_len..]
+                .iter()
+                .map(encode)
+                .collect();
+            // The file is in append mode, so this lands at the new end, 0
+            file.set_len(0)?;
+            file.write_all(kept.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn search_match(
+        &self,
+        term: &str,
+        start: usize,
+        dir: SearchDirection,
+        test: impl Fn(&str) -> Option<usize>,
+    ) -> Option<SearchResult<'_>> {
+        if term.is_empty() || start >= self.entries.len() {
+            return None;
+        }
+        let found = |idx: usize| {
+            let entry = &self.entries[idx].command;
+            test(entry).map(|pos| SearchResult {
+                entry: Cow::Borrowed(entry.as_str()),
+                idx,
+                pos,
+            })
+        };
+        match dir {
+            SearchDirection::Reverse => (0..=start).rev().find_map(found),
+            SearchDirection::Forward => (start..self.entries.len()).find_map(found),
+        }
+    }
+}
+
+impl Default for ShellHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl History for ShellHistory {
+    fn get(&self, index: usize, _: SearchDirection) -> rustyline::Result<Option<SearchResult<'_>>> {
+        Ok(self.entries.get(index).map(|entry| SearchResult {
+            entry: Cow::Borrowed(entry.command.as_str()),
+            idx: index,
+            pos: 0,
+        }))
+    }
+
+    fn add(&mut self, line: &str) -> rustyline::Result<bool> {
+        self.add_owned(line.to_string())
+    }
+
+    fn add_owned(&mut self, line: String) -> rustyline::Result<bool> {
+        let ignored = line.is_empty()
+            || self.max_len == 0
+            || (self.ignore_space && line.starts_with(char::is_whitespace))
+            || (self.ignore_dups && self.entries.back().is_some_and(|last| last.command == line));
+        if ignored {
+            return Ok(false);
+        }
+        self.push(HistoryEntry {
+            command: line,
+            time: Some(SystemTime::now()),
+        });
+        self.unsaved = (self.unsaved + 1).min(self.entries.len());
+        Ok(true)
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn set_max_len(&mut self, len: usize) -> rustyline::Result<()> {
+        self.max_len = len;
+        let excess = self.entries.len().saturating_sub(len);
+        self.entries.drain(..excess);
+        self.unsaved = self.unsaved.min(self.entries.len());
+        Ok(())
+    }
+
+    fn ignore_dups(&mut self, yes: bool) -> rustyline::Result<()> {
+        self.ignore_dups = yes;
+        Ok(())
+    }
+
+    fn ignore_space(&mut self, yes: bool) {
+        self.ignore_space = yes;
+    }
+
+    /// Replace the contents of the file at `path` with this history
+    fn save(&mut self, path: &Path) -> rustyline::Result<()> {
+        let mut file = open_locked(path)?;
+        file.set_len(0)?;
+        self.write_to(&mut file, self.entries.iter())?;
+        self.unsaved = 0;
+        Ok(())
+    }
+
+    /// Add the entries not yet written to the end of the file at `path`
+    fn append(&mut self, path: &Path) -> rustyline::Result<()> {
+        let mut file = open_locked(path)?;
+        let new = self.entries.iter().skip(self.entries.len() - self.unsaved);
+        self.write_to(&mut file, new)?;
+        self.unsaved = 0;
+        Ok(())
+    }
+
+    /// Add the newest entries of the file at `path`
+    fn load(&mut self, path: &Path) -> rustyline::Result<()> {
+        for entry in parse(&fs::read_to_string(path)?) {
+            self.push(entry);
+        }
+        self.unsaved = 0;
+        Ok(())
+    }
+
+    fn clear(&mut self) -> rustyline::Result<()> {
+        self.entries.clear();
+        self.unsaved = 0;
+        Ok(())
+    }
+
+    fn search(
+        &self,
+        term: &str,
+        start: usize,
+        dir: SearchDirection,
+    ) -> rustyline::Result<Option<SearchResult<'_>>> {
+        Ok(match self.search_mode {
+            SearchMode::Substring => self.search_match(term, start, dir, |entry| entry.find(term)),
+            SearchMode::Fuzzy => {
+                self.search_match(term, start, dir, |entry| fuzzy_find(entry, term))
+            }
+        })
+    }
+
+    fn starts_with(
+        &self,
+        term: &str,
+        start: usize,
+        dir: SearchDirection,
+    ) -> rustyline::Result<Option<SearchResult<'_>>> {
+        Ok(self.search_match(term, start, dir, |entry| {
+            entry.starts_with(term).then_some(term.len())
+        }))
+    }
+}
+
+/// Open the history file at `path` for appending, creating it (and its
+/// directory) if needed, and wait for an exclusive lock on it
+///
+/// The lock is released when the file is closed.
+fn open_locked(path: &Path) -> io::Result<File> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    let file = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)?;
+    // SAFETY: flock only operates on the open descriptor
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(file)
+}
+
+/// The entries stored in the contents of a history file
+fn parse(contents: &str) -> Vec<HistoryEntry> {
+    let mut entries = Vec::new();
+    let mut time = None;
+    for line in contents.lines() {
+        let timestamp = line
+            .strip_prefix('#')
+            .and_then(|seconds| seconds.parse::<u64>().ok());
+        if let Some(seconds) = timestamp {
+            time = Some(UNIX_EPOCH + Duration::from_secs(seconds));
+        } else if !line.is_empty() {
+            entries.push(HistoryEntry {
+                command: decode(line),
+                time: time.take(),
+            });
+        }
+    }
+    entries
+}
+
+/// An entry as it's stored in the history file, ending in a newline
+fn encode(entry: &HistoryEntry) -> String {
+    let mut encoded = String::new();
+    if let Some(seconds) = entry
+        .time
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+    {
+        encoded.push_str(&format!("#{}\n", seconds.as_secs()));
+    }
+    if entry.command.starts_with('#') {
+        encoded.push('\\');
+    }
+    encoded.push_str(&entry.command.replace('\\', "\\\\").replace('\n', "\\n"));
+    encoded.push('\n');
+    encoded
+}
+
+/// The entry stored in a line of the history file
+fn decode(line: &str) -> String {
+    let mut entry = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(ch) = chars.next() {
+        match (ch, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                entry.push('\n');
+                chars.next();
+            }
+            ('\\', Some(escaped @ ('\\' | '#'))) => {
+                entry.push(escaped);
+                chars.next();
+            }
+            _ => entry.push(ch),
+        }
+    }
+    entry
+}
+
+/// Replace history references in a line typed at the prompt
+///
+/// `!!` is the previous command, `!N` command number `N` (`!-N` the `N`th
+/// most recent), `!prefix` the latest command starting with `prefix`, and
+/// `!$` the last word of the previous command. A `!` inside single quotes,
+/// after a backslash or `$`, or before a blank, `=`, `(` or `"` is left as
+/// it is. `history` holds the earlier commands, oldest first.
+///
+/// # Examples
+/// ```
+/// use codecrafters_shell::history::expand;
+/// use std::borrow::Cow;
+///
+/// let history = ["ls -l /tmp".to_string(), "git status".to_string()];
+/// assert_eq!(expand("sudo !!", &history).unwrap(), "sudo git status");
+/// assert_eq!(expand("cd !$; !1", &history).unwrap(), "cd status; ls -l /tmp");
+/// assert_eq!(expand("!ls | wc", &history).unwrap(), "ls -l /tmp | wc");
+/// assert_eq!(expand("!-2 && !g", &history).unwrap(), "ls -l /tmp && git status");
+/// assert_eq!(expand("echo '!!' \\!! ! $! \"hi!\"", &history).unwrap(), "echo '!!' \\!! ! $! \"hi!\"");
+/// assert!(matches!(expand("echo hi!", &history).unwrap(), Cow::Borrowed(_)));
+/// assert!(expand("!make", &history).is_err());
+/// ```
+pub fn expand<'a>(line: &'a str, history: &[String]) -> Result<Cow<'a, str>, ShellError> {
+    if !line.contains('!') {
+        return Ok(Cow::Borrowed(line));
+    }
+    let mut expanded = String::with_capacity(line.len());
+    let mut quote = None;
+    let mut rest = line;
+    let mut changed = false;
+    while let Some(ch) = rest.chars().next() {
+        rest = &rest[ch.len_utf8()..];
+        match ch {
+            '\\' if quote != Some('\'') => {
+                expanded.push(ch);
+                if let Some(next) = rest.chars().next() {
+                    expanded.push(next);
+                    rest = &rest[next.len_utf8()..];
+                }
+                continue;
+            }
+            '\'' | '"' if quote.is_none() => quote = Some(ch),
+            _ if quote == Some(ch) => quote = None,
+            '!' if quote != Some('\'') && !expanded.ends_with('$') => {
+                if let Some((text, length)) = event(rest, history)? {
+                    expanded.push_str(&text);
+                    rest = &rest[length..];
+                    changed = true;
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        expanded.push(ch);
+    }
+    Ok(if changed {
+        Cow::Owned(expanded)
+    } else {
+        Cow::Borrowed(line)
+    })
+}
+
+/// The text of the reference at the start of `spec`, which follows a `!`,
+/// and how many bytes of `spec` it used
+///
+/// `None` if `spec` doesn't start with a reference, so the `!` is literal.
+fn event(spec: &str, history: &[String]) -> Result<Option<(String, usize)>, ShellError> {
+    let not_found =
+        |event: &str| ShellError::ExpansionError(format!("!{}: event not found", event));
+    let length = match spec.chars().next() {
+        None => return Ok(None),
+        Some('!' | '$') => 1,
+        Some('-') => 1 + spec[1..].bytes().take_while(u8::is_ascii_digit).count(),
+        Some(ch) if ch.is_whitespace() || "=(\"".contains(ch) => return Ok(None),
+        Some(_) => spec
+            .find(|ch: char| ch.is_whitespace() || ";|&<>()'\"".contains(ch))
+            .unwrap_or(spec.len()),
+    };
+    let event = &spec[..length];
+    if event.is_empty() || event == "-" {
+        return Ok(None);
+    }
+    let command = match event {
+        "!" | "$" => history.last(),
+        _ if event.starts_with('-') => event[1..]
+            .parse::<usize>()
+            .ok()
+            .and_then(|back| history.len().checked_sub(back))
+            .and_then(|index| history.get(index)),
+        _ if event.bytes().all(|byte| byte.is_ascii_digit()) => event
+            .parse::<usize>()
+            .ok()
+            .and_then(|number| history.get(number.checked_sub(1)?)),
+        _ => history
+            .iter()
+            .rev()
+            .find(|command| command.starts_with(event)),
+    };
+    let command = command.ok_or_else(|| not_found(event))?;
+    let text = if event == "$" {
+        last_word(command).to_string()
+    } else {
+        command.clone()
+    };
+    Ok(Some((text, length)))
+}
+
+/// The last word of `command` as it was written
+fn last_word(command: &str) -> &str {
+    tokenize(command)
+        .iter()
+        .rev()
+        .find(|token| matches!(token.token, Token::Word(_)))
+        .map_or("", |token| &command[token.span.start..token.span.end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A path for a test's history file, with no file there yet
+    fn scratch_file(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("shelly-history-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn commands(history: &ShellHistory) -> Vec<&str> {
+        history.iter().map(|entry| entry.command.as_str()).collect()
+    }
+
+    #[test]
+    fn file_round_trips_escaped_entries() {
+        let path = scratch_file("round-trip");
+        let mut history = ShellHistory::new();
+        for line in [
+            "echo one",
+            "for x in a b\ndo echo $x\ndone",
+            r"printf 'a\nb\\'",
+            "#1 not a time",
+        ] {
+            history.add(line).unwrap();
+        }
+        history.save(&path).unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.contains("for x in a b\\ndo echo $x\\ndone\n"));
+        assert!(contents.contains("printf 'a\\\\nb\\\\\\\\'\n"));
+        assert!(contents.contains("\n\\#1 not a time\n"));
+
+        let mut loaded = ShellHistory::new();
+        loaded.load(&path).unwrap();
+        assert_eq!(commands(&loaded), commands(&history));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn appending_keeps_other_shells_entries_within_the_size_limit() {
+        let path = scratch_file("append");
+        let (mut first, mut second) = (ShellHistory::new(), ShellHistory::new());
+        first.set_max_file_len(3);
+        second.set_max_file_len(3);
+        first.add("one").unwrap();
+        first.append(&path).unwrap();
+        second.add("two").unwrap();
+        second.append(&path).unwrap();
+        first.add("three").unwrap();
+        first.add("four").unwrap();
+        // Only what this shell hasn't written yet goes in
+        first.append(&path).unwrap();
+
+        let mut loaded = ShellHistory::new();
+        loaded.load(&path).unwrap();
+        assert_eq!(commands(&loaded), ["two", "three", "four"]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn fuzzy_search_finds_the_newest_entry_with_the_characters_in_order() {
+        let mut history = ShellHistory::new();
+        for line in ["git status", "cargo test --workspace", "git stash", "ls"] {
+            history.add(line).unwrap();
+        }
+        let found = |history: &ShellHistory, term, start, dir| {
+            let result = history.search(term, start, dir).unwrap()?;
+            Some((result.idx, result.pos))
+        };
+
+        // Substring search, the default, needs the text as typed
+        assert_eq!(found(&history, "gst", 3, SearchDirection::Reverse), None);
+        history.set_search_mode(SearchMode::Fuzzy);
+        assert_eq!(
+            found(&history, "gst", 3, SearchDirection::Reverse),
+            Some((2, 0))
+        );
+        assert_eq!(
+            found(&history, "gtu", 3, SearchDirection::Reverse),
+            Some((0, 0))
+        );
+        assert_eq!(
+            found(&history, "gsh", 0, SearchDirection::Forward),
+            Some((2, 0))
+        );
+        assert_eq!(
+            found(&history, "test", 3, SearchDirection::Reverse),
+            Some((1, 6))
+        );
+        assert_eq!(found(&history, "zz", 3, SearchDirection::Reverse), None);
+    }
+}
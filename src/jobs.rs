@@ -0,0 +1,376 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Read;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+/// How long to wait for a finished job's output to drain before reporting it
+const OUTPUT_DRAIN_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Most bytes a [`JobOutput`] holds; anything a job writes past this before
+/// it's shown is dropped
+pub const JOB_OUTPUT_LIMIT: usize = 1 << 20;
+
+/// Output captured from a background job
+///
+/// When an interactive shell buffers job output, a background job's stdout
+/// and stderr are collected here as raw bytes instead of scribbling over the
+/// prompt, and shown on request (`jobs -o`) or when the job finishes. At most
+/// [`JOB_OUTPUT_LIMIT`] bytes are kept, so a job like `yes &` can't grow the
+/// shell without bound; the capture keeps reading so the job never blocks.
+#[derive(Debug, Clone, Default)]
+pub struct JobOutput(Arc<OutputBuffer>);
+
+#[derive(Debug, Default)]
+struct OutputBuffer {
+    bytes: Mutex<Vec<u8>>,
+    /// Number of capture threads still reading
+    open_readers: Mutex<usize>,
+    readers_closed: Condvar,
+}
+
+impl JobOutput {
+    /// Create an empty output buffer
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Collect everything read from `reader` into this buffer on a helper thread
+    pub fn capture<R: Read + Send + 'static>(&self, mut reader: R) {
+        let buffer = Arc::clone(&self.0);
+        *buffer.open_readers.lock().unwrap() += 1;
+        std::thread::spawn(move || {
+            let mut chunk = [0u8; 4096];
+            while let Ok(n) = reader.read(&mut chunk) {
+                if n == 0 {
+                    break;
+                }
+                buffer.append(&chunk[..n]);
+            }
+            *buffer.open_readers.lock().unwrap() -= 1;
+            buffer.readers_closed.notify_all();
+        });
+    }
+
+    /// Append bytes produced by the shell itself (e.g. a builtin stage)
+    pub fn push(&self, bytes: &[u8]) {
+        self.0.append(bytes);
+    }
+
+    /// Take the output collected so far, leaving the buffer empty
+    pub fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.0.bytes.lock().unwrap())
+    }
+
+    /// Wait briefly for the capture threads to reach end of file
+    ///
+    /// Called once a job has exited so its last output isn't lost. The wait is
+    /// bounded because a process the job left behind may keep the pipe open.
+    pub fn drain(&self) {
+        let readers = self.0.open_readers.lock().unwrap();
+        let _ = self
+            .0
+            .readers_closed
+            .wait_timeout_while(readers, OUTPUT_DRAIN_TIMEOUT, |open| *open > 0);
+    }
+}
+
+impl OutputBuffer {
+    /// Add as much of `bytes` as fits under [`JOB_OUTPUT_LIMIT`]
+    fn append(&self, bytes: &[u8]) {
+        let mut buffer = self.bytes.lock().unwrap();
+        let room = JOB_OUTPUT_LIMIT.saturating_sub(buffer.len());
+        buffer.extend_from_slice(&bytes[..bytes.len().min(room)]);
+    }
+}
+
+/// Whether a job is running, stopped, or finished
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobState {
+    /// At least one of the job's processes hasn't exited yet
+    Running,
+    /// The job was stopped by a signal and can be resumed with `fg` or `bg`
+    Stopped,
+    /// Every process has exited; holds the status of the last one
+    Done(i32),
+}
+
+impl fmt::Display for JobState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobState::Running => write!(f, "Running"),
+            JobState::Stopped => write!(f, "Stopped"),
+            JobState::Done(0) => write!(f, "Done"),
+            JobState::Done(status) => write!(f, "Exit {}", status),
+        }
+    }
+}
+
+/// A job launched by the shell
+#[derive(Debug, Clone)]
+pub struct Job {
+    /// Job number as shown to the user (`%1`, `%2`, ...)
+    pub id: usize,
+    /// Process ID of the job's last process, which decides its status
+    pub pid: u32,
+    /// Process IDs of every process in the job, in pipeline order
+    pub pids: Vec<u32>,
+    /// Process group of the job (the PID of its first process)
+    pub pgid: u32,
+    /// The command line that started the job
+    pub command: String,
+    /// Output the job produced that hasn't been shown yet
+    pub output: JobOutput,
+    /// Whether the job is running, stopped, or done
+    pub state: JobState,
+    /// Whether `disown -h` spared the job the SIGHUP sent on exit
+    pub no_hangup: bool,
+    /// Processes that haven't been reaped yet
+    remaining: Vec<u32>,
+    /// Status of each process in `pids` once it has been reaped
+    statuses: Vec<Option<i32>>,
+}
+
+impl Job {
+    /// Reap whichever of the job's processes have changed state
+    ///
+    /// With `block` set, waits until the job has either finished or stopped.
+    fn update(&mut self, block: bool) {
+        if matches!(self.state, JobState::Done(_)) {
+            return;
+        }
+        let mut stopped = false;
+        let mut continued = false;
+        let pids = &self.pids;
+        let statuses = &mut self.statuses;
+        self.remaining
+            .retain(|&process| match wait_for(process, block) {
+                Some(WaitEvent::Exited(status)) => {
+                    if let Some(index) = pids.iter().position(|&pid| pid == process) {
+                        statuses[index] = Some(status);
+                    }
+                    false
+                }
+                Some(WaitEvent::Stopped) => {
+                    stopped = true;
+                    true
+                }
+                Some(WaitEvent::Continued) => {
+                    continued = true;
+                    true
+                }
+                None => true,
+            });
+
+        if self.remaining.is_empty() {
+            let last_status = self.statuses.last().copied().flatten();
+            self.state = JobState::Done(last_status.unwrap_or(0));
+        } else if stopped {
+            self.state = JobState::Stopped;
+        } else if continued {
+            self.state = JobState::Running;
+        }
+    }
+
+    /// The status of each process, in pipeline order; 0 for any not
+    /// reaped yet
+    pub fn statuses(&self) -> Vec<i32> {
+        self.statuses
+            .iter()
+            .map(|status| status.unwrap_or(0))
+            .collect()
+    }
+
+    /// Send `signal` to every process in the job's process group
+    pub fn signal(&self, signal: libc::c_int) -> std::io::Result<()> {
+        // SAFETY: kill has no memory-safety preconditions
+        if unsafe { libc::kill(-(self.pgid as libc::pid_t), signal) } == 0 {
+            Ok(())
+        } else {
+            Err(std::io::Error::last_os_error())
+        }
+    }
+}
+
+/// A change in a child process's state reported by `waitpid`
+enum WaitEvent {
+    /// The process exited; holds its shell exit status
+    Exited(i32),
+    /// The process was stopped by a signal
+    Stopped,
+    /// The process was resumed with SIGCONT
+    Continued,
+}
+
+/// Wait for a state change in `pid`, or just check for one unless `block` is set
+///
+/// A process that is no longer our child (already reaped elsewhere) counts as
+/// having exited with status 0.
+fn wait_for(pid: u32, block: bool) -> Option<WaitEvent> {
+    let mut flags = libc::WUNTRACED | libc::WCONTINUED;
+    if !block {
+        flags |= libc::WNOHANG;
+    }
+    let mut raw_status = 0;
+    // SAFETY: waitpid only inspects the given child
+    let result = unsafe { libc::waitpid(pid as libc::pid_t, &mut raw_status, flags) };
+    match result {
+        0 => None,
+        -1 => Some(WaitEvent::Exited(0)),
+        _ if libc::WIFSTOPPED(raw_status) => Some(WaitEvent::Stopped),
+        _ if libc::WIFCONTINUED(raw_status) => Some(WaitEvent::Continued),
+        _ => Some(WaitEvent::Exited(wait_status_code(raw_status))),
+    }
+}
+
+/// Wait for `pid` to exit and return its shell status
+///
+/// A process that is no longer our child counts as having exited with
+/// status 0, as in [`wait_for`].
+pub(crate) fn wait_exit(pid: u32) -> i32 {
+    let mut raw_status = 0;
+    // SAFETY: waitpid only inspects the given child
+    while unsafe { libc::waitpid(pid as libc::pid_t, &mut raw_status, 0) } == -1 {
+        if std::io::Error::last_os_error().kind() != std::io::ErrorKind::Interrupted {
+            return 0;
+        }
+    }
+    wait_status_code(raw_status)
+}
+
+/// Convert a raw `waitpid` status into a shell status (`128 + signal` for signal deaths)
+pub(crate) fn wait_status_code(raw_status: libc::c_int) -> i32 {
+    if libc::WIFEXITED(raw_status) {
+        libc::WEXITSTATUS(raw_status)
+    } else if libc::WIFSIGNALED(raw_status) {
+        128 + libc::WTERMSIG(raw_status)
+    } else {
+        1
+    }
+}
+
+/// Table of jobs owned by the shell
+///
+/// Job ids are allocated as the smallest number greater than every id in use,
+/// matching how other shells number jobs.
+#[derive(Debug, Default)]
+pub struct JobTable {
+    jobs: Vec<Job>,
+    /// Statuses of the processes of jobs already taken out as finished, by
+    /// process ID, until `wait` asks for them
+    reaped: HashMap<u32, i32>,
+    /// Processes of disowned jobs that haven't exited yet
+    disowned: Vec<u32>,
+}
+
+impl JobTable {
+    /// Create an empty job table
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a job made of the processes `pids` and return its job id
+    ///
+    /// # Panics
+    /// Panics if `pids` is empty.
+    pub fn add(&mut self, pids: Vec<u32>, command: String, output: JobOutput) -> usize {
+        let id = self.jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+        let pid = *pids.last().expect("a job needs at least one process");
+        self.jobs.push(Job {
+            id,
+            pid,
+            pgid: pids[0],
+            remaining: pids.clone(),
+            statuses: vec![None; pids.len()],
+            pids,
+            command,
+            output,
+            state: JobState::Running,
+            no_hangup: false,
+        });
+        id
+    }
+
+    /// Check every job for processes that have exited or stopped, without blocking
+    pub fn poll(&mut self) {
+        for job in &mut self.jobs {
+            job.update(false);
+        }
+        self.disowned
+            .retain(|&pid| !matches!(wait_for(pid, false), Some(WaitEvent::Exited(_))));
+    }
+
+    /// Wait until job `id` finishes or stops, returning its new state
+    pub fn wait(&mut self, id: usize) -> Option<JobState> {
+        let job = self.jobs.iter_mut().find(|job| job.id == id)?;
+        // A resumed job first reports that it continued; keep waiting past that
+        job.update(true);
+        while job.state == JobState::Running {
+            job.update(true);
+        }
+        Some(job.state)
+    }
+
+    /// Remove and return the jobs that have finished
+    pub fn take_finished(&mut self) -> Vec<Job> {
+        let (finished, remaining) = std::mem::take(&mut self.jobs)
+            .into_iter()
+            .partition(|job| matches!(job.state, JobState::Done(_)));
+        self.jobs = remaining;
+        for job in &finished {
+            self.reaped
+                .extend(job.pids.iter().copied().zip(job.statuses()));
+        }
+        finished
+    }
+
+    /// The status of process `pid` of a job [taken out as
+    /// finished](Self::take_finished), forgetting it
+    pub fn take_reaped(&mut self, pid: u32) -> Option<i32> {
+        self.reaped.remove(&pid)
+    }
+
+    /// Resolve a job spec (`%N`, `N`, `%%`, `%+`) to a job id
+    ///
+    /// With no spec, the current job (the most recently started one) is used.
+    pub fn resolve(&self, spec: Option<&str>) -> Option<usize> {
+        match spec {
+            None | Some("%%") | Some("%+") => self.jobs.last().map(|job| job.id),
+            Some(spec) => {
+                let id = spec.strip_prefix('%').unwrap_or(spec).parse().ok()?;
+                self.get(id).map(|job| job.id)
+            }
+        }
+    }
+
+    /// Look up a job by id for modification
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Job> {
+        self.jobs.iter_mut().find(|job| job.id == id)
+    }
+
+    /// Remove a job by id, returning it if it existed
+    pub fn remove(&mut self, id: usize) -> Option<Job> {
+        let index = self.jobs.iter().position(|job| job.id == id)?;
+        Some(self.jobs.remove(index))
+    }
+
+    /// Remove job `id` without waiting for it, returning it if it existed
+    ///
+    /// Its processes are still reaped by [`poll`](Self::poll) once they
+    /// exit, but never reported.
+    pub fn disown(&mut self, id: usize) -> Option<Job> {
+        let job = self.remove(id)?;
+        self.disowned.extend(&job.remaining);
+        Some(job)
+    }
+
+    /// Look up a job by id
+    pub fn get(&self, id: usize) -> Option<&Job> {
+        self.jobs.iter().find(|job| job.id == id)
+    }
+
+    /// All jobs, ordered by job id
+    pub fn jobs(&self) -> &[Job] {
+        &self.jobs
+    }
+}
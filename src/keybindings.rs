@@ -0,0 +1,244 @@
+use crate::completion::RustylineHelper;
+use crate::history::ShellHistory;
+use crate::menu::{CompletionMenu, Step};
+use crate::signals;
+use rustyline::{
+    Cmd, ConditionalEventHandler, Editor, Event, EventContext, EventHandler, KeyCode, KeyEvent,
+    Modifiers, Movement, RepeatCount,
+};
+use std::collections::hash_map::RandomState;
+use std::fs::File;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+
+/// Install shelly's custom key bindings on the line editor
+///
+/// - `Alt-Enter` inserts a newline, so a multi-line command can be edited as
+///   one block before running it.
+/// - `Ctrl-X Ctrl-E` (`edit-and-execute-command`) opens the current buffer in
+///   `$VISUAL`/`$EDITOR` and loads the edited text back into the prompt.
+/// - `End` and `Ctrl-E` at the end of the line accept the suggestion from
+///   history, as `→` does.
+/// - While the completion menu is open, Tab/Shift-Tab and the arrow keys
+///   move through it, Enter takes the selected match and Ctrl-G closes it.
+pub fn bind_keys(editor: &mut Editor<RustylineHelper, ShellHistory>) {
+    editor.bind_sequence(
+        KeyEvent(KeyCode::Enter, Modifiers::ALT),
+        EventHandler::Simple(Cmd::Newline),
+    );
+    editor.bind_sequence(
+        Event::KeySeq(vec![KeyEvent::ctrl('X'), KeyEvent::ctrl('E')]),
+        EventHandler::Conditional(Box::new(EditAndExecute)),
+    );
+
+    for key in [KeyEvent(KeyCode::End, Modifiers::NONE), KeyEvent::ctrl('E')] {
+        editor.bind_sequence(key, EventHandler::Conditional(Box::new(AcceptSuggestion)));
+    }
+
+    let Some(menu) = editor.helper().map(RustylineHelper::menu) else {
+        return;
+    };
+    let menu_keys = [
+        (
+            KeyEvent(KeyCode::Tab, Modifiers::NONE),
+            MenuAction::Select(Step::Next),
+        ),
+        (
+            KeyEvent(KeyCode::Right, Modifiers::NONE),
+            MenuAction::Select(Step::Next),
+        ),
+        (
+            KeyEvent(KeyCode::BackTab, Modifiers::NONE),
+            MenuAction::Select(Step::Previous),
+        ),
+        (
+            KeyEvent(KeyCode::Left, Modifiers::NONE),
+            MenuAction::Select(Step::Previous),
+        ),
+        (
+            KeyEvent(KeyCode::Down, Modifiers::NONE),
+            MenuAction::Select(Step::Down),
+        ),
+        (
+            KeyEvent(KeyCode::Up, Modifiers::NONE),
+            MenuAction::Select(Step::Up),
+        ),
+        (
+            KeyEvent(KeyCode::Enter, Modifiers::NONE),
+            MenuAction::Accept,
+        ),
+        (KeyEvent::ctrl('G'), MenuAction::Close),
+    ];
+    for (key, action) in menu_keys {
+        let handler = MenuKey {
+            menu: Arc::clone(&menu),
+            action,
+        };
+        editor.bind_sequence(key, EventHandler::Conditional(Box::new(handler)));
+    }
+}
+
+/// Accept the suggestion shown after the line, if there is one and the
+/// cursor is at the end; otherwise the key moves to the end as usual
+struct AcceptSuggestion;
+
+impl ConditionalEventHandler for AcceptSuggestion {
+    fn handle(
+        &self,
+        _evt: &Event,
+        _n: RepeatCount,
+        _positive: bool,
+        ctx: &EventContext,
+    ) -> Option<Cmd> {
+        let at_end = ctx.pos() == ctx.line().len();
+        (at_end && ctx.hint_text().is_some()).then_some(Cmd::CompleteHint)
+    }
+}
+
+/// What a key does in the completion menu
+#[derive(Debug, Clone, Copy)]
+enum MenuAction {
+    Select(Step),
+    Accept,
+    Close,
+}
+
+/// A key that drives the completion menu while it's open, and otherwise
+/// keeps its usual binding
+struct MenuKey {
+    menu: Arc<CompletionMenu>,
+    action: MenuAction,
+}
+
+impl ConditionalEventHandler for MenuKey {
+    fn handle(
+        &self,
+        _evt: &Event,
+        _n: RepeatCount,
+        _positive: bool,
+        ctx: &EventContext,
+    ) -> Option<Cmd> {
+        if !self.menu.is_open(ctx.line(), ctx.pos()) {
+            return None;
+        }
+        match self.action {
+            MenuAction::Select(step) => {
+                self.menu.select(step);
+                Some(Cmd::Repaint)
+            }
+            // Completing again puts the chosen match in place of the word
+            MenuAction::Accept => {
+                self.menu.accept();
+                Some(Cmd::Complete)
+            }
+            MenuAction::Close => {
+                self.menu.close();
+                Some(Cmd::Repaint)
+            }
+        }
+    }
+}
+
+/// `edit-and-execute-command`: edit the current buffer in an external editor
+///
+/// The edited text replaces the whole buffer, newlines included, so it can be
+/// reviewed and run with Enter. If the editor fails the buffer is left alone.
+struct EditAndExecute;
+
+impl ConditionalEventHandler for EditAndExecute {
+    fn handle(
+        &self,
+        _evt: &Event,
+        _n: RepeatCount,
+        _positive: bool,
+        ctx: &EventContext,
+    ) -> Option<Cmd> {
+        match edit_in_external_editor(ctx.line()) {
+            Some(text) => Some(Cmd::Replace(Movement::WholeBuffer, Some(text))),
+            None => Some(Cmd::Noop),
+        }
+    }
+}
+
+/// Open `text` in the user's editor and return what they saved
+fn edit_in_external_editor(text: &str) -> Option<String> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    // $EDITOR may carry its own arguments, e.g. `code --wait`
+    let mut words = editor.split_whitespace();
+    let program = words.next()?;
+
+    let (path, mut file) = create_temp_script(&std::env::temp_dir()).ok()?;
+    let written = file.write_all(text.as_bytes());
+    drop(file);
+    let status = written.and_then(|()| {
+        let mut process = Command::new(program);
+        process.args(words).arg(&path);
+        // The editor runs in the shell's process group, at the terminal, so
+        // it gets the job-control signals the prompt ignores back
+        signals::restore_in_child(&mut process, false);
+        process.status()
+    });
+    let edited = std::fs::read_to_string(&path);
+    let _ = std::fs::remove_file(&path);
+
+    if !status.ok()?.success() {
+        return None;
+    }
+    Some(edited.ok()?.trim_end_matches('\n').to_string())
+}
+
+/// Create a new file in `dir` that only the user can read and write, under
+/// a name that can't be guessed ahead of time
+///
+/// A file that already exists is never opened, so a link planted at the
+/// name can't redirect what's written.
+fn create_temp_script(dir: &Path) -> io::Result<(PathBuf, File)> {
+    let state = RandomState::new();
+    for attempt in 0..100 {
+        let mut hasher = state.build_hasher();
+        hasher.write_u32(std::process::id());
+        hasher.write_u32(attempt);
+        let path = dir.join(format!("shelly-edit-{:016x}.sh", hasher.finish()));
+        let created = File::options()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path);
+        match created {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(io::ErrorKind::AlreadyExists.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn temp_scripts_are_private_and_never_reuse_a_name() {
+        let dir = std::env::temp_dir().join(format!("shelly-keybindings-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (first, _) = create_temp_script(&dir).unwrap();
+        let (second, _) = create_temp_script(&dir).unwrap();
+        assert_ne!(first, second);
+        let mode = std::fs::metadata(&first).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(first
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .starts_with("shelly-edit-"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
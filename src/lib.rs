@@ -1,5 +1,25 @@
 pub mod builtin;
+pub mod cli;
 pub mod command;
 pub mod completion;
 pub mod error;
+pub mod expand;
+pub mod generator;
+pub mod glob;
+pub mod highlight;
+pub mod history;
+pub mod jobs;
+pub mod keybindings;
+pub mod limits;
+pub mod lookup;
+pub mod menu;
+pub mod printf;
+pub mod prompt;
+pub mod redirect;
+pub mod regex;
 pub mod shell;
+pub mod signals;
+pub mod substitution;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod timing;
@@ -0,0 +1,159 @@
+//! Resource limits for `ulimit`
+//!
+//! Limits are set on the shell process itself, so every command it starts
+//! afterwards inherits them. Sizes are shown and given in the units `ulimit`
+//! uses (1024-byte blocks or kilobytes) and stored in bytes.
+
+use std::io;
+
+/// A resource `ulimit` can show and change
+#[derive(Debug, Clone, Copy)]
+pub struct Limit {
+    /// The option selecting it, as in `ulimit -n`
+    pub option: char,
+    /// What `ulimit -a` calls it
+    pub description: &'static str,
+    /// The unit values are given in, if it isn't a plain count
+    pub unit: Option<&'static str>,
+    /// Bytes (or microseconds, seconds, ...) per unit
+    pub factor: u64,
+    resource: libc::__rlimit_resource_t,
+}
+
+/// Every limit, in the order `ulimit -a` lists them
+pub const LIMITS: [Limit; 16] = [
+    limit(
+        'R',
+        "real-time non-blocking time",
+        Some("microseconds"),
+        1,
+        libc::RLIMIT_RTTIME,
+    ),
+    limit(
+        'c',
+        "core file size",
+        Some("blocks"),
+        1024,
+        libc::RLIMIT_CORE,
+    ),
+    limit(
+        'd',
+        "data seg size",
+        Some("kbytes"),
+        1024,
+        libc::RLIMIT_DATA,
+    ),
+    limit('e', "scheduling priority", None, 1, libc::RLIMIT_NICE),
+    limit('f', "file size", Some("blocks"), 1024, libc::RLIMIT_FSIZE),
+    limit('i', "pending signals", None, 1, libc::RLIMIT_SIGPENDING),
+    limit(
+        'l',
+        "max locked memory",
+        Some("kbytes"),
+        1024,
+        libc::RLIMIT_MEMLOCK,
+    ),
+    limit(
+        'm',
+        "max memory size",
+        Some("kbytes"),
+        1024,
+        libc::RLIMIT_RSS,
+    ),
+    limit('n', "open files", None, 1, libc::RLIMIT_NOFILE),
+    limit(
+        'q',
+        "POSIX message queues",
+        Some("bytes"),
+        1,
+        libc::RLIMIT_MSGQUEUE,
+    ),
+    limit('r', "real-time priority", None, 1, libc::RLIMIT_RTPRIO),
+    limit('s', "stack size", Some("kbytes"), 1024, libc::RLIMIT_STACK),
+    limit('t', "cpu time", Some("seconds"), 1, libc::RLIMIT_CPU),
+    limit('u', "max user processes", None, 1, libc::RLIMIT_NPROC),
+    limit('v', "virtual memory", Some("kbytes"), 1024, libc::RLIMIT_AS),
+    limit('x', "file locks", None, 1, libc::RLIMIT_LOCKS),
+];
+
+const fn limit(
+    option: char,
+    description: &'static str,
+    unit: Option<&'static str>,
+    factor: u64,
+    resource: libc::__rlimit_resource_t,
+) -> Limit {
+    Limit {
+        option,
+        description,
+        unit,
+        factor,
+        resource,
+    }
+}
+
+/// The limit `ulimit -OPTION` selects
+///
+/// # Examples
+/// ```
+/// use codecrafters_shell::limits;
+///
+/// assert_eq!(limits::find('n').unwrap().description, "open files");
+/// assert!(limits::find('z').is_none());
+/// ```
+pub fn find(option: char) -> Option<&'static Limit> {
+    LIMITS.iter().find(|limit| limit.option == option)
+}
+
+impl Limit {
+    /// The soft and hard limits, in units; `None` is unlimited
+    pub fn get(&self) -> io::Result<(Option<u64>, Option<u64>)> {
+        let limits = self.read()?;
+        Ok((self.units(limits.rlim_cur), self.units(limits.rlim_max)))
+    }
+
+    /// Change the soft limit, the hard limit, or both, to `value` units
+    /// (`None` for unlimited)
+    ///
+    /// Raising the hard limit needs privileges, and the soft limit can't
+    /// go above the hard one.
+    pub fn set(&self, value: Option<u64>, soft: bool, hard: bool) -> io::Result<()> {
+        let mut limits = self.read()?;
+        let value = match value {
+            Some(units) => units
+                .checked_mul(self.factor)
+                .filter(|&bytes| bytes != libc::RLIM_INFINITY)
+                .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?,
+            None => libc::RLIM_INFINITY,
+        };
+        if soft {
+            limits.rlim_cur = value;
+        }
+        if hard {
+            limits.rlim_max = value;
+        }
+        // SAFETY: setrlimit only reads the struct it's given
+        if unsafe { libc::setrlimit(self.resource, &limits) } == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    fn read(&self) -> io::Result<libc::rlimit> {
+        let mut limits = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        // SAFETY: getrlimit only fills in the struct it's given
+        if unsafe { libc::getrlimit(self.resource, &mut limits) } == 0 {
+            Ok(limits)
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    fn units(&self, value: libc::rlim_t) -> Option<u64> {
+        (value != libc::RLIM_INFINITY).then(|| value / self.factor)
+    }
+}
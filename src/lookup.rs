@@ -0,0 +1,119 @@
+//! Finding commands in PATH, remembering where they were found
+//!
+//! Like bash, the shell keeps a table of the commands it has run from PATH
+//! and where each one is, so running it again doesn't search every
+//! directory. An entry is checked before it's used, so a command that has
+//! been moved or deleted is searched for afresh, and assigning PATH empties
+//! the table.
+
+use crate::completion::is_executable;
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+/// Search PATH for a file named `name` that the shell could run
+///
+/// Directories and files without execute permission are passed over, as
+/// the kernel would refuse to run them.
+pub fn find_in_path(name: &str) -> Option<PathBuf> {
+    env::var_os("PATH").and_then(|paths| {
+        env::split_paths(&paths).find_map(|dir| {
+            let path = dir.join(name);
+            is_executable(&path).then_some(path)
+        })
+    })
+}
+
+/// A command in the [`CommandHash`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hashed {
+    /// Where the command was found
+    pub path: PathBuf,
+    /// How many times it has been run from there
+    pub hits: usize,
+}
+
+/// The table of where commands were found in PATH, by name
+///
+/// # Examples
+/// ```
+/// use codecrafters_shell::lookup::CommandHash;
+///
+/// let mut hash = CommandHash::default();
+/// let path = hash.lookup("sh").expect("sh is in PATH");
+/// assert_eq!(hash.get("sh"), Some(path.as_path()));
+/// assert_eq!(hash.entries()[0].1.hits, 1);
+/// assert!(hash.remove("sh"));
+/// assert_eq!(hash.get("sh"), None);
+/// ```
+#[derive(Debug, Default)]
+pub struct CommandHash {
+    entries: HashMap<String, Hashed>,
+}
+
+/// The command hash, shared by the shell and the line editor
+pub type CommandHashes = Arc<RwLock<CommandHash>>;
+
+impl CommandHash {
+    /// Where `name` runs from, counting a hit
+    ///
+    /// A remembered path is used while it's still executable; otherwise
+    /// PATH is searched and what's found remembered.
+    pub fn lookup(&mut self, name: &str) -> Option<PathBuf> {
+        if let Some(entry) = self.entries.get_mut(name) {
+            if is_executable(&entry.path) {
+                entry.hits += 1;
+                return Some(entry.path.clone());
+            }
+        }
+        let path = self.remember(name)?;
+        if let Some(entry) = self.entries.get_mut(name) {
+            entry.hits = 1;
+        }
+        Some(path)
+    }
+
+    /// Search PATH for `name` and remember where it is, without counting a
+    /// hit, as `hash NAME` does
+    pub fn remember(&mut self, name: &str) -> Option<PathBuf> {
+        let Some(path) = find_in_path(name) else {
+            self.entries.remove(name);
+            return None;
+        };
+        self.entries.insert(
+            name.to_string(),
+            Hashed {
+                path: path.clone(),
+                hits: 0,
+            },
+        );
+        Some(path)
+    }
+
+    /// Where `name` was last found, if it's remembered
+    pub fn get(&self, name: &str) -> Option<&Path> {
+        self.entries.get(name).map(|entry| entry.path.as_path())
+    }
+
+    /// Forget `name`, returning whether it was remembered
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.entries.remove(name).is_some()
+    }
+
+    /// Forget every command
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// The remembered commands, sorted by name
+    pub fn entries(&self) -> Vec<(&str, &Hashed)> {
+        let mut entries: Vec<(&str, &Hashed)> = self
+            .entries
+            .iter()
+            .map(|(name, entry)| (name.as_str(), entry))
+            .collect();
+        entries.sort_by_key(|&(name, _)| name);
+        entries
+    }
+}
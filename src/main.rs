@@ -1,12 +1,114 @@
+use codecrafters_shell::cli::{Cli, USAGE};
+use codecrafters_shell::command::{tokenize, CommandParser};
+use codecrafters_shell::error::{describe_io_error, ShellError};
 use codecrafters_shell::shell::Shell;
+use std::io::{self, BufRead, Write};
 
 fn main() {
-    match Shell::new() {
+    let mut cli = match Cli::parse(std::env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(msg) => {
+            eprintln!("shelly: {}\n{}", msg, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    // login(1) marks a login shell with a `-` before the program name
+    if std::env::args()
+        .next()
+        .is_some_and(|name| name.starts_with('-'))
+    {
+        cli.login = true;
+    }
+
+    if cli.help {
+        println!("{}", USAGE);
+        return;
+    }
+    if cli.version {
+        println!("shelly {}", env!("CARGO_PKG_VERSION"));
+        return;
+    }
+
+    if cli.dump_ast || cli.tokenize {
+        match inspect(&cli) {
+            // A reader such as `head` stopping early isn't an error
+            Err(e) if e.kind() != io::ErrorKind::BrokenPipe => {
+                eprintln!("shelly: {}", describe_io_error(&e));
+                std::process::exit(1);
+            }
+            _ => return,
+        }
+    }
+
+    let status = match Shell::with_options(cli.shell_options()) {
         Ok(mut shell) => {
-            if let Err(e) = shell.run() {
-                eprintln!("Shell error: {:?}", e);
+            if let Some(script) = &cli.script {
+                match shell.run_script(script) {
+                    Ok(status) => status,
+                    Err(ShellError::IoError(e)) => {
+                        eprintln!("shelly: {}: {}", script.display(), describe_io_error(&e));
+                        127
+                    }
+                    Err(e) => {
+                        eprintln!("shelly: {}: {}", script.display(), e);
+                        127
+                    }
+                }
+            } else {
+                let result = match &cli.command {
+                    Some(cmd) => shell.run_command_string(cmd),
+                    None => shell.run(),
+                };
+                result.unwrap_or_else(|e| {
+                    eprintln!("Shell error: {:?}", e);
+                    1
+                })
+            }
+        }
+        Err(e) => {
+            eprintln!("Failed to initialize shell: {:?}", e);
+            1
+        }
+    };
+    std::process::exit(status);
+}
+
+/// Print the tokens (`--tokenize`) and syntax tree (`--dump-ast`) of each
+/// input line without executing it
+fn inspect(cli: &Cli) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    for line in inspection_input(cli.command.clone()) {
+        if cli.tokenize {
+            for spanned in tokenize(&line) {
+                writeln!(
+                    stdout,
+                    "{:<10} {:?}",
+                    spanned.span.to_string(),
+                    spanned.token
+                )?;
+            }
+        }
+        if cli.dump_ast {
+            match CommandParser::parse(&line) {
+                Ok(lists) => writeln!(stdout, "{:#?}", lists)?,
+                Err(e) => eprintln!("shelly: {}", e),
             }
         }
-        Err(e) => eprintln!("Failed to initialize shell: {:?}", e),
     }
+    stdout.flush()
+}
+
+/// Collect the lines to inspect for `--dump-ast`/`--tokenize` without executing them
+///
+/// Input comes from the `-c` string if given, otherwise from stdin line by line.
+fn inspection_input(command_string: Option<String>) -> Vec<String> {
+    let lines: Vec<String> = match command_string {
+        Some(cmd) => vec![cmd],
+        None => io::stdin().lock().lines().map_while(Result::ok).collect(),
+    };
+    lines
+        .into_iter()
+        .filter(|line| !line.trim().is_empty())
+        .collect()
 }
@@ -0,0 +1,223 @@
+//! The completion menu shown when Tab is pressed twice on an ambiguous word
+//!
+//! Matches are laid out in columns below the prompt as a rustyline hint, so
+//! the line being edited is never overwritten. Tab and the arrow keys move
+//! the selection, Enter puts the selected match in place of the word, and
+//! Ctrl-G or editing the line closes the menu.
+
+use rustyline::completion::Pair;
+use std::sync::Mutex;
+
+/// The most rows of matches shown at once; longer lists are paged
+const MAX_ROWS: usize = 10;
+
+/// Which way a key moves the selection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// To the next match, wrapping around at the end
+    Next,
+    /// To the previous match
+    Previous,
+    /// Down one row, to the match below
+    Down,
+    /// Up one row
+    Up,
+}
+
+/// The open menu, if there is one
+///
+/// The shell's completer opens it and the line editor's key bindings and
+/// hinter read it, so it sits behind a lock that all of them share.
+///
+/// # Examples
+/// ```
+/// use codecrafters_shell::menu::{CompletionMenu, Step};
+/// use rustyline::completion::Pair;
+///
+/// let entries = ["alpha1", "alpha2"].map(|name| Pair {
+///     display: name.to_string(),
+///     replacement: format!("{} ", name),
+/// });
+/// let menu = CompletionMenu::default();
+/// menu.open("cat al", 6, 4, entries.to_vec());
+/// menu.select(Step::Next);
+/// menu.accept();
+///
+/// let (start, choice) = menu.take_choice("cat al", 6).unwrap();
+/// assert_eq!((start, choice.replacement.as_str()), (4, "alpha2 "));
+/// assert!(!menu.is_open("cat al", 6));
+/// ```
+#[derive(Default)]
+pub struct CompletionMenu {
+    state: Mutex<Option<MenuState>>,
+}
+
+struct MenuState {
+    /// The line the menu was opened on; changing it closes the menu
+    line: String,
+    /// Byte offset of the cursor in `line`
+    pos: usize,
+    /// Byte offset where the word being completed starts
+    start: usize,
+    /// Each match as listed and as it replaces the word
+    entries: Vec<Pair>,
+    selected: usize,
+    /// Whether Enter chose the selected match
+    chosen: bool,
+    /// Matches per row, as last drawn
+    columns: usize,
+}
+
+impl CompletionMenu {
+    /// Offer `entries` as replacements for `line[start..pos]`
+    pub fn open(&self, line: &str, pos: usize, start: usize, entries: Vec<Pair>) {
+        *self.state.lock().unwrap() = Some(MenuState {
+            line: line.to_string(),
+            pos,
+            start,
+            entries,
+            selected: 0,
+            chosen: false,
+            columns: 1,
+        });
+    }
+
+    /// Close the menu without choosing anything
+    pub fn close(&self) {
+        *self.state.lock().unwrap() = None;
+    }
+
+    /// Whether the menu is open on this line, with the cursor where it was
+    ///
+    /// A menu left open on a line that has since changed is closed.
+    pub fn is_open(&self, line: &str, pos: usize) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let open = state
+            .as_ref()
+            .is_some_and(|menu| menu.line == line && menu.pos == pos);
+        if !open {
+            *state = None;
+        }
+        open
+    }
+
+    /// Move the selection
+    pub fn select(&self, step: Step) {
+        if let Some(menu) = self.state.lock().unwrap().as_mut() {
+            let count = menu.entries.len();
+            let columns = menu.columns.min(count);
+            menu.selected = match step {
+                Step::Next => (menu.selected + 1) % count,
+                Step::Previous => (menu.selected + count - 1) % count,
+                Step::Down if menu.selected + columns < count => menu.selected + columns,
+                Step::Down => menu.selected % columns,
+                Step::Up if menu.selected >= columns => menu.selected - columns,
+                Step::Up => {
+                    let last_row = (count - 1) / columns * columns;
+                    let below = last_row + menu.selected % columns;
+                    if below < count {
+                        below
+                    } else {
+                        below - columns
+                    }
+                }
+            };
+        }
+    }
+
+    /// Choose the selected match, for the next completion to put in place
+    pub fn accept(&self) {
+        if let Some(menu) = self.state.lock().unwrap().as_mut() {
+            menu.chosen = true;
+        }
+    }
+
+    /// Close the menu and return the match chosen on this line, with where
+    /// the word it replaces starts, if one was chosen
+    pub fn take_choice(&self, line: &str, pos: usize) -> Option<(usize, Pair)> {
+        if !self.is_open(line, pos) {
+            return None;
+        }
+        let mut state = self.state.lock().unwrap();
+        if !state.as_ref()?.chosen {
+            return None;
+        }
+        let menu = state.take()?;
+        let entry = menu.entries.into_iter().nth(menu.selected)?;
+        Some((menu.start, entry))
+    }
+
+    /// The menu as drawn below the line, if it's open on this line
+    pub fn render(&self, line: &str, pos: usize) -> Option<String> {
+        if !self.is_open(line, pos) {
+            return None;
+        }
+        let mut state = self.state.lock().unwrap();
+        let menu = state.as_mut()?;
+        let (text, columns) = layout(&menu.entries, menu.selected, terminal_width());
+        menu.columns = columns;
+        Some(text)
+    }
+}
+
+/// Lay out `entries` in as many columns as fit in `width`, filling rows
+/// left to right, with the `selected` one in reverse video
+///
+/// Only the page of [`MAX_ROWS`] rows holding the selection is shown, with
+/// a line saying where it is in the list. Returns the text, which starts
+/// with a newline, and the number of columns.
+fn layout(entries: &[Pair], selected: usize, width: usize) -> (String, usize) {
+    let cell = entries
+        .iter()
+        .map(|entry| entry.display.chars().count())
+        .max()
+        .unwrap_or(0)
+        + 2;
+    // Leave the last column free so a full row doesn't wrap
+    let columns = (width.saturating_sub(1) / cell).clamp(1, entries.len().max(1));
+    let rows = entries.len().div_ceil(columns);
+    let first = selected / columns / MAX_ROWS * MAX_ROWS;
+    let last = (first + MAX_ROWS).min(rows);
+
+    let mut text = String::new();
+    for row in first..last {
+        text.push('\n');
+        let cells = entries.iter().enumerate().skip(row * columns).take(columns);
+        let mut line = String::new();
+        for (index, entry) in cells {
+            let padding = cell - entry.display.chars().count();
+            if index == selected {
+                line.push_str(&format!("\x1b[7m{}\x1b[0m", entry.display));
+            } else {
+                line.push_str(&entry.display);
+            }
+            line.push_str(&" ".repeat(padding));
+        }
+        text.push_str(line.trim_end());
+    }
+    if rows > MAX_ROWS {
+        text.push_str(&format!(
+            "\n-- rows {} to {} of {} --",
+            first + 1,
+            last,
+            rows
+        ));
+    }
+    (text, columns)
+}
+
+/// Columns in the terminal, or 80 if it can't be told
+fn terminal_width() -> usize {
+    // SAFETY: TIOCGWINSZ only fills in the zeroed winsize it's given
+    let size = unsafe {
+        let mut size: libc::winsize = std::mem::zeroed();
+        if libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) != 0 {
+            size = std::mem::zeroed();
+        }
+        size
+    };
+    match size.ws_col {
+        0 => 80,
+        columns => columns as usize,
+    }
+}
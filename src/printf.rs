@@ -0,0 +1,441 @@
+//! Formatting for the `printf` builtin, and the backslash escapes it shares
+//! with `echo -e`
+//!
+//! The format is reused until every argument has been consumed, as in other
+//! shells, and a conversion with no argument left uses an empty string or
+//! zero.
+
+/// Which backslash escapes [`unescape`] knows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EscapeStyle {
+    /// A `printf` format: `\NNN` is an octal byte
+    Format,
+    /// A `printf %b` argument: `\NNN` or `\0NNN` is an octal byte and `\c`
+    /// ends the output
+    Argument,
+    /// `echo -e`: `\0NNN` is an octal byte and `\c` ends the output
+    Echo,
+}
+
+/// `text` with its backslash escapes replaced, and whether a `\c` asked for
+/// the output to stop there
+///
+/// Every style knows `\a`, `\b`, `\e`, `\f`, `\n`, `\r`, `\t`, `\v`, `\\` and
+/// `\xHH`; anything else is left as it was.
+///
+/// # Examples
+/// ```
+/// use codecrafters_shell::printf::{unescape, EscapeStyle};
+///
+/// assert_eq!(unescape(r"a\tb\x41\101", EscapeStyle::Format), (b"a\tbAA".to_vec(), false));
+/// assert_eq!(unescape(r"\0101\q\cgone", EscapeStyle::Echo), (b"A\\q".to_vec(), true));
+/// assert_eq!(unescape(r"\101\0101", EscapeStyle::Argument), (b"AA".to_vec(), false));
+/// ```
+pub fn unescape(text: &str, style: EscapeStyle) -> (Vec<u8>, bool) {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let byte = bytes[index];
+        index += 1;
+        if byte != b'\\' || index == bytes.len() {
+            out.push(byte);
+            continue;
+        }
+        let escape = bytes[index];
+        index += 1;
+        let simple = match escape {
+            b'a' => Some(0x07),
+            b'b' => Some(0x08),
+            b'e' | b'E' => Some(0x1b),
+            b'f' => Some(0x0c),
+            b'n' => Some(b'\n'),
+            b'r' => Some(b'\r'),
+            b't' => Some(b'\t'),
+            b'v' => Some(0x0b),
+            b'\\' => Some(b'\\'),
+            _ => None,
+        };
+        if let Some(simple) = simple {
+            out.push(simple);
+            continue;
+        }
+        match (escape, style) {
+            (b'c', EscapeStyle::Argument | EscapeStyle::Echo) => return (out, true),
+            (b'x', _) if bytes.get(index).is_some_and(u8::is_ascii_hexdigit) => {
+                let (value, used) = digits(&bytes[index..], 16, 2);
+                out.push(value);
+                index += used;
+            }
+            (b'0', EscapeStyle::Argument | EscapeStyle::Echo) => {
+                let (value, used) = digits(&bytes[index..], 8, 3);
+                out.push(value);
+                index += used;
+            }
+            (b'0'..=b'7', EscapeStyle::Format) | (b'1'..=b'7', EscapeStyle::Argument) => {
+                let (value, used) = digits(&bytes[index - 1..], 8, 3);
+                out.push(value);
+                index += used - 1;
+            }
+            _ => out.extend_from_slice(&[b'\\', escape]),
+        }
+    }
+    (out, false)
+}
+
+/// The value of up to `most` leading digits of `bytes` in `radix`, and how
+/// many there were
+fn digits(bytes: &[u8], radix: u32, most: usize) -> (u8, usize) {
+    let mut value: u32 = 0;
+    let mut used = 0;
+    for &byte in bytes.iter().take(most) {
+        let Some(digit) = (byte as char).to_digit(radix) else {
+            break;
+        };
+        value = value * radix + digit;
+        used += 1;
+    }
+    (value as u8, used)
+}
+
+/// What [`printf`] produced, with a message for each argument it couldn't
+/// use
+#[derive(Debug, Default, PartialEq)]
+pub struct Formatted {
+    /// The output bytes
+    pub output: Vec<u8>,
+    /// Problems with the arguments, such as a number that isn't one
+    pub errors: Vec<String>,
+}
+
+/// A conversion's flags, width and precision
+#[derive(Debug, Default)]
+struct Spec {
+    left: bool,
+    zero: bool,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+/// Format `args` as `format` says, as the `printf` builtin does
+///
+/// Conversions are `%s`, `%b` (the argument's escapes replaced), `%c`,
+/// `%d`/`%i`, `%u`, `%o`, `%x`/`%X`, `%f`, `%e`, `%g` (and their capitals)
+/// and `%%`, with the flags `-`, `0`, `+`, space and `#`, a width and a
+/// precision, either of which may be `*` to take it from the arguments. A
+/// numeric argument may be decimal, `0x` hex, `0` octal or a quote followed
+/// by a character, which gives the character's code.
+///
+/// # Examples
+/// ```
+/// use codecrafters_shell::printf::printf;
+///
+/// let args = ["a", "1", "bb", "22"].map(String::from);
+/// assert_eq!(printf("[%-3s|%03d]\n", &args).output, b"[a  |001]\n[bb |022]\n");
+/// assert_eq!(printf("%x %.2f %5.1e\n", &["255".into(), "3.14159".into(), "1234".into()]).output, b"ff 3.14 1.2e+03\n");
+///
+/// let formatted = printf("%d", &["12abc".into()]);
+/// assert_eq!(formatted.output, b"12");
+/// assert_eq!(formatted.errors, ["12abc: invalid number"]);
+/// ```
+pub fn printf(format: &str, args: &[String]) -> Formatted {
+    let mut formatted = Formatted::default();
+    let mut args = args.iter();
+    loop {
+        let before = args.len();
+        let (stop, converted) = format_once(format, &mut args, &mut formatted);
+        // The format is used again while it consumes arguments
+        if stop || !converted || args.len() == 0 || args.len() == before {
+            return formatted;
+        }
+    }
+}
+
+/// Format the arguments once through `format`, returning whether a `\c`
+/// stopped the output and whether the format has any conversions
+fn format_once(
+    format: &str,
+    args: &mut std::slice::Iter<String>,
+    formatted: &mut Formatted,
+) -> (bool, bool) {
+    let mut converted = false;
+    let mut rest = format;
+    while let Some(percent) = rest.find('%') {
+        formatted
+            .output
+            .extend(unescape(&rest[..percent], EscapeStyle::Format).0);
+        let mut spec = Spec::default();
+        let mut end = rest.len() - percent - 1;
+        let mut conversion = None;
+        let mut precision = None::<String>;
+        let mut width = String::new();
+        for (offset, ch) in rest[percent + 1..].char_indices() {
+            match ch {
+                '-' if width.is_empty() && precision.is_none() => spec.left = true,
+                '0' if width.is_empty() && precision.is_none() => spec.zero = true,
+                '+' if width.is_empty() && precision.is_none() => spec.plus = true,
+                ' ' if width.is_empty() && precision.is_none() => spec.space = true,
+                '#' if width.is_empty() && precision.is_none() => spec.alternate = true,
+                '.' if precision.is_none() => precision = Some(String::new()),
+                '0'..='9' | '*' => match &mut precision {
+                    Some(digits) => digits.push(ch),
+                    None => width.push(ch),
+                },
+                _ => {
+                    conversion = Some(ch);
+                    end = offset + ch.len_utf8();
+                    break;
+                }
+            }
+        }
+        rest = &rest[percent + 1 + end..];
+        let Some(conversion) = conversion else {
+            formatted.output.push(b'%');
+            break;
+        };
+        if conversion == '%' {
+            formatted.output.push(b'%');
+            continue;
+        }
+        converted = true;
+        spec.width = star_or_number(&width, args, formatted);
+        spec.precision = precision.map(|digits| star_or_number(&digits, args, formatted));
+        if conversion == 'b' {
+            let argument = args.next().map_or("", String::as_str);
+            let (text, stop) = unescape(argument, EscapeStyle::Argument);
+            let text = String::from_utf8_lossy(&text);
+            formatted.output.extend(pad_text(&text, &spec).into_bytes());
+            if stop {
+                return (true, converted);
+            }
+            continue;
+        }
+        match convert(conversion, &spec, args, formatted) {
+            Some(text) => formatted.output.extend(text.into_bytes()),
+            None => {
+                formatted
+                    .errors
+                    .push(format!("%{}: invalid format character", conversion));
+                return (true, converted);
+            }
+        }
+    }
+    formatted
+        .output
+        .extend(unescape(rest, EscapeStyle::Format).0);
+    (false, converted)
+}
+
+/// A width or precision: its digits, or the next argument for `*`
+fn star_or_number(
+    text: &str,
+    args: &mut std::slice::Iter<String>,
+    formatted: &mut Formatted,
+) -> usize {
+    if text.contains('*') {
+        let value = integer_argument(args.next(), formatted);
+        return value.max(0) as usize;
+    }
+    text.parse().unwrap_or(0)
+}
+
+/// Text for one conversion, or `None` if `conversion` isn't one
+fn convert(
+    conversion: char,
+    spec: &Spec,
+    args: &mut std::slice::Iter<String>,
+    formatted: &mut Formatted,
+) -> Option<String> {
+    let text = match conversion {
+        's' => pad_text(args.next().map_or("", String::as_str), spec),
+        'c' => {
+            let ch = args.next().and_then(|arg| arg.chars().next());
+            pad_text(&ch.map(String::from).unwrap_or_default(), spec)
+        }
+        'd' | 'i' => {
+            let value = integer_argument(args.next(), formatted);
+            let sign = sign(value < 0, spec);
+            pad_number(sign, "", &value.unsigned_abs().to_string(), spec, true)
+        }
+        'u' | 'o' | 'x' | 'X' => {
+            // Negative numbers wrap around, as in C
+            let value = integer_argument(args.next(), formatted) as u64;
+            let (digits, prefix) = match conversion {
+                'o' => (format!("{:o}", value), "0"),
+                'x' => (format!("{:x}", value), "0x"),
+                'X' => (format!("{:X}", value), "0X"),
+                _ => (value.to_string(), ""),
+            };
+            let prefix = if spec.alternate && value != 0 {
+                prefix
+            } else {
+                ""
+            };
+            pad_number("", prefix, &digits, spec, true)
+        }
+        'f' | 'F' | 'e' | 'E' | 'g' | 'G' => {
+            let value = float_argument(args.next(), formatted);
+            let precision = spec.precision.unwrap_or(6);
+            let digits = match conversion {
+                'f' | 'F' => format!("{:.*}", precision, value.abs()),
+                'e' | 'E' => exponential(value.abs(), precision),
+                _ => general(value.abs(), precision, spec.alternate),
+            };
+            let digits = if conversion.is_ascii_uppercase() {
+                digits.to_uppercase()
+            } else {
+                digits
+            };
+            let sign = sign(value.is_sign_negative() && value != 0.0, spec);
+            pad_number(sign, "", &digits, spec, false)
+        }
+        _ => return None,
+    };
+    Some(text)
+}
+
+/// The sign to put before a number
+fn sign(negative: bool, spec: &Spec) -> &'static str {
+    match (negative, spec.plus, spec.space) {
+        (true, _, _) => "-",
+        (false, true, _) => "+",
+        (false, false, true) => " ",
+        _ => "",
+    }
+}
+
+/// `text` cut to the precision and padded to the width with spaces
+fn pad_text(text: &str, spec: &Spec) -> String {
+    let text: String = match spec.precision {
+        Some(precision) => text.chars().take(precision).collect(),
+        None => text.to_string(),
+    };
+    let padding = " ".repeat(spec.width.saturating_sub(text.chars().count()));
+    if spec.left {
+        text + &padding
+    } else {
+        padding + &text
+    }
+}
+
+/// A number's sign, prefix and digits, padded to the width
+///
+/// An `integer`'s precision is the least number of digits to show.
+fn pad_number(sign: &str, prefix: &str, digits: &str, spec: &Spec, integer: bool) -> String {
+    let mut digits = digits.to_string();
+    if let (true, Some(precision)) = (integer, spec.precision) {
+        if digits == "0" && precision == 0 {
+            digits.clear();
+        }
+        digits = format!("{:0>width$}", digits, width = precision);
+    }
+    let length = sign.len() + prefix.len() + digits.len();
+    let padding = spec.width.saturating_sub(length);
+    // Zeros go between the sign and the digits, unless an integer has a precision
+    let zero = spec.zero && !spec.left && !(integer && spec.precision.is_some());
+    if spec.left {
+        format!("{}{}{}{}", sign, prefix, digits, " ".repeat(padding))
+    } else if zero {
+        format!("{}{}{}{}", sign, prefix, "0".repeat(padding), digits)
+    } else {
+        format!("{}{}{}{}", " ".repeat(padding), sign, prefix, digits)
+    }
+}
+
+/// `value` as C's `%e` writes it, with at least two exponent digits
+fn exponential(value: f64, precision: usize) -> String {
+    let text = format!("{:.*e}", precision, value);
+    let (mantissa, exponent) = text.split_once('e').unwrap_or((&text, "0"));
+    let exponent: i32 = exponent.parse().unwrap_or(0);
+    let sign = if exponent < 0 { '-' } else { '+' };
+    format!("{}e{}{:02}", mantissa, sign, exponent.abs())
+}
+
+/// `value` as C's `%g` writes it: `%e` for very large or small numbers and
+/// `%f` otherwise, in `precision` significant digits, with trailing zeros
+/// removed unless `alternate`
+fn general(value: f64, precision: usize, alternate: bool) -> String {
+    let precision = precision.max(1);
+    let exponent: i32 = exponential(value, precision - 1)
+        .split_once('e')
+        .and_then(|(_, exponent)| exponent.parse().ok())
+        .unwrap_or(0);
+    let text = if exponent < -4 || exponent >= precision as i32 {
+        exponential(value, precision - 1)
+    } else {
+        format!("{:.*}", (precision as i32 - 1 - exponent) as usize, value)
+    };
+    if alternate || !text.contains('.') {
+        return text;
+    }
+    let (number, exponent) = match text.find('e') {
+        Some(at) => text.split_at(at),
+        None => (text.as_str(), ""),
+    };
+    format!(
+        "{}{}",
+        number.trim_end_matches('0').trim_end_matches('.'),
+        exponent
+    )
+}
+
+/// The integer an argument stands for, noting an error for one that isn't
+/// a number; a missing argument is 0
+fn integer_argument(arg: Option<&String>, formatted: &mut Formatted) -> i64 {
+    let Some(arg) = arg else {
+        return 0;
+    };
+    if let Some(quoted) = arg.strip_prefix(['\'', '"']) {
+        return quoted.chars().next().map_or(0, |ch| ch as i64);
+    }
+    let text = arg.trim_start();
+    let (negative, unsigned) = match text.strip_prefix('-') {
+        Some(unsigned) => (true, unsigned),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let (radix, digits) =
+        if let Some(hex) = unsigned.strip_prefix("0x").or(unsigned.strip_prefix("0X")) {
+            (16, hex)
+        } else if unsigned.len() > 1 && unsigned.starts_with('0') {
+            (8, &unsigned[1..])
+        } else {
+            (10, unsigned)
+        };
+    let valid = digits
+        .find(|ch: char| !ch.is_digit(radix))
+        .unwrap_or(digits.len());
+    if valid < digits.len() || digits.is_empty() {
+        formatted.errors.push(format!("{}: invalid number", arg));
+    }
+    let value = i64::from_str_radix(&digits[..valid], radix).unwrap_or(0);
+    if negative {
+        -value
+    } else {
+        value
+    }
+}
+
+/// The floating-point number an argument stands for, noting an error for
+/// one that isn't a number; a missing argument is 0
+fn float_argument(arg: Option<&String>, formatted: &mut Formatted) -> f64 {
+    let Some(arg) = arg else {
+        return 0.0;
+    };
+    if let Some(quoted) = arg.strip_prefix(['\'', '"']) {
+        return quoted.chars().next().map_or(0.0, |ch| ch as u32 as f64);
+    }
+    let text = arg.trim();
+    if let Ok(value) = text.parse() {
+        return value;
+    }
+    // Use the longest prefix that is a number
+    let value = (1..text.len())
+        .rev()
+        .filter(|&end| text.is_char_boundary(end))
+        .find_map(|end| text[..end].parse().ok());
+    formatted.errors.push(format!("{}: invalid number", arg));
+    value.unwrap_or(0.0)
+}
@@ -0,0 +1,413 @@
+//! Rendering `PS1`-style prompt templates
+//!
+//! Templates use bash's backslash escapes (`\u`, `\h`, `\w`, `\$`, ...) plus
+//! named colors such as `\{green}`. `\{status}` picks green or red by the
+//! last command's exit status, and `\g` shows the current git branch.
+
+use std::ffi::CStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How long a repository's dirty state is reused while HEAD and the index
+/// stay the same, so edits to files show up soon without running
+/// `git status` before every prompt
+const DIRTY_STATE_TTL: Duration = Duration::from_secs(5);
+
+/// What a prompt template can show
+#[derive(Debug, Clone)]
+pub struct PromptContext<'a> {
+    /// User name, for `\u`
+    pub user: &'a str,
+    /// Host name, for `\h` (up to the first `.`) and `\H`
+    pub host: &'a str,
+    /// Working directory, for `\w` and `\W`
+    pub cwd: &'a Path,
+    /// Home directory, abbreviated to `~` at the start of `\w`
+    pub home: Option<&'a str>,
+    /// Exit status of the last command, for `\?` and `\{status}`
+    pub status: i32,
+    /// Whether the user is root, making `\$` a `#`
+    pub root: bool,
+    /// The repository the working directory is in, for `\g`
+    pub git: Option<&'a GitStatus>,
+    /// Directories saved by `pushd`, most recent first, for `\S`
+    pub dirs: &'a [PathBuf],
+}
+
+/// Branch and dirty state of a git repository
+#[derive(Debug, Clone, PartialEq)]
+pub struct GitStatus {
+    /// The checked-out branch, or the abbreviated commit on a detached HEAD
+    pub branch: String,
+    /// Whether tracked files have uncommitted changes
+    pub dirty: bool,
+}
+
+/// Looks up the [`GitStatus`] of the working directory for the prompt
+///
+/// The branch is read from `HEAD` directly, which is cheap. Finding out
+/// whether the tree is dirty means running `git status`, which can be slow
+/// in large repositories, so its answer is kept until `HEAD` or the index
+/// changes, the directory moves to another repository, or
+/// [`DIRTY_STATE_TTL`] passes.
+#[derive(Debug, Default)]
+pub struct GitCache {
+    entry: Option<GitCacheEntry>,
+}
+
+#[derive(Debug)]
+struct GitCacheEntry {
+    git_dir: PathBuf,
+    head: String,
+    index_modified: Option<SystemTime>,
+    checked: Instant,
+    dirty: bool,
+}
+
+impl GitCache {
+    /// The status of the repository containing `cwd`, or `None` outside one
+    pub fn status(&mut self, cwd: &Path) -> Option<GitStatus> {
+        let git_dir = find_git_dir(cwd)?;
+        let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+        let index_modified = modified(&git_dir.join("index"));
+        let fresh = self.entry.as_ref().is_some_and(|entry| {
+            entry.git_dir == git_dir
+                && entry.head == head
+                && entry.index_modified == index_modified
+                && entry.checked.elapsed() < DIRTY_STATE_TTL
+        });
+        if !fresh {
+            let dirty = is_dirty(cwd);
+            self.entry = Some(GitCacheEntry {
+                index_modified: modified(&git_dir.join("index")),
+                git_dir,
+                head: head.clone(),
+                checked: Instant::now(),
+                dirty,
+            });
+        }
+        Some(GitStatus {
+            branch: branch_name(&head),
+            dirty: self.entry.as_ref().is_some_and(|entry| entry.dirty),
+        })
+    }
+}
+
+/// The git directory of the repository containing `dir`
+///
+/// A `.git` file, as in worktrees and submodules, points to the real one.
+fn find_git_dir(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors().find_map(|dir| {
+        let dot_git = dir.join(".git");
+        if dot_git.is_dir() {
+            return Some(dot_git);
+        }
+        let link = fs::read_to_string(&dot_git).ok()?;
+        Some(dir.join(link.strip_prefix("gitdir:")?.trim()))
+    })
+}
+
+/// The branch named by the contents of `HEAD`, or the first 7 digits of
+/// the commit it holds when detached
+fn branch_name(head: &str) -> String {
+    let head = head.trim();
+    match head.strip_prefix("ref: ") {
+        Some(reference) => reference
+            .strip_prefix("refs/heads/")
+            .unwrap_or(reference)
+            .to_string(),
+        None => head.chars().take(7).collect(),
+    }
+}
+
+/// Whether tracked files in the repository at `dir` have uncommitted changes
+///
+/// Untracked files don't count, which keeps the check fast. If `git` can't
+/// be run the tree is taken to be clean.
+fn is_dirty(dir: &Path) -> bool {
+    Command::new("git")
+        .args([
+            "--no-optional-locks",
+            "status",
+            "--porcelain",
+            "--untracked-files=no",
+        ])
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .is_ok_and(|output| output.status.success() && !output.stdout.is_empty())
+}
+
+/// When the file at `path` was last modified, if it exists
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Expand the escapes in `template`
+///
+/// | Escape | Expands to |
+/// |---|---|
+/// | `\u` | user name |
+/// | `\h`, `\H` | host name, short or full |
+/// | `\w`, `\W` | working directory (`~` for home), or just its last component |
+/// | `\$` | `#` for root, otherwise `$` |
+/// | `\?` | last exit status |
+/// | `\g` | ` (branch)` in a git repository, ` (branch*)` with uncommitted changes, otherwise nothing |
+/// | `\S` | ` [dir dir]` listing the directories saved by `pushd`, `~` for home, otherwise nothing |
+/// | `\n`, `\\`, `\e`, `\a` | newline, backslash, escape, bell |
+/// | `\[`, `\]` | nothing (marks non-printing text in bash) |
+/// | `\{name}` | ANSI style: `black`, `red`, `green`, `yellow`, `blue`, `magenta`, `cyan`, `white`, `bold`, `dim`, `reset`, or `status` |
+///
+/// Anything else is kept as written.
+///
+/// # Examples
+/// ```
+/// use codecrafters_shell::prompt::{render, GitStatus, PromptContext};
+/// use std::path::Path;
+///
+/// let context = PromptContext {
+///     user: "ada",
+///     host: "box.example.com",
+///     cwd: Path::new("/home/ada/src"),
+///     home: Some("/home/ada"),
+///     status: 0,
+///     root: false,
+///     git: None,
+///     dirs: &[],
+/// };
+/// assert_eq!(render(r"\u@\h:\w\$ ", &context), "ada@box:~/src$ ");
+/// assert_eq!(render(r"\{status}\W\{reset} ", &context), "\x1b[32msrc\x1b[0m ");
+///
+/// let git = GitStatus { branch: "main".to_string(), dirty: true };
+/// let context = PromptContext { git: Some(&git), ..context };
+/// assert_eq!(render(r"\W\g\$ ", &context), "src (main*)$ ");
+///
+/// let dirs = ["/home/ada".into(), "/tmp".into()];
+/// let context = PromptContext { dirs: &dirs, ..context };
+/// assert_eq!(render(r"\W\S\$ ", &context), "src [~ /tmp]$ ");
+/// ```
+pub fn render(template: &str, context: &PromptContext) -> String {
+    let mut prompt = String::with_capacity(template.len());
+    let mut chars = template.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            prompt.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('u') => prompt.push_str(context.user),
+            Some('h') => prompt.push_str(context.host.split('.').next().unwrap_or_default()),
+            Some('H') => prompt.push_str(context.host),
+            Some('w') => prompt.push_str(&abbreviate_home(context.cwd, context.home)),
+            Some('W') => match context.cwd.file_name() {
+                Some(name) if context.home != context.cwd.to_str() => {
+                    prompt.push_str(&name.to_string_lossy())
+                }
+                _ => prompt.push_str(&abbreviate_home(context.cwd, context.home)),
+            },
+            Some('$') => prompt.push(if context.root { '#' } else { '$' }),
+            Some('?') => prompt.push_str(&context.status.to_string()),
+            Some('g') => {
+                if let Some(git) = context.git {
+                    let dirty = if git.dirty { "*" } else { "" };
+                    prompt.push_str(&format!(" ({}{})", git.branch, dirty));
+                }
+            }
+            Some('S') if !context.dirs.is_empty() => {
+                let dirs: Vec<String> = context
+                    .dirs
+                    .iter()
+                    .map(|dir| abbreviate_home(dir, context.home))
+                    .collect();
+                prompt.push_str(&format!(" [{}]", dirs.join(" ")));
+            }
+            Some('S') => {}
+            Some('n') => prompt.push('\n'),
+            Some('e') => prompt.push('\x1b'),
+            Some('a') => prompt.push('\x07'),
+            Some('\\') => prompt.push('\\'),
+            Some('[' | ']') => {}
+            Some('{') => {
+                let rest = chars.as_str();
+                let style = rest
+                    .find('}')
+                    .and_then(|end| Some((style_code(&rest[..end], context.status)?, end)));
+                match style {
+                    Some((code, end)) => {
+                        prompt.push_str(&format!("\x1b[{}m", code));
+                        chars = rest[end + 1..].chars();
+                    }
+                    None => prompt.push_str("\\{"),
+                }
+            }
+            Some(other) => {
+                prompt.push('\\');
+                prompt.push(other);
+            }
+            None => prompt.push('\\'),
+        }
+    }
+    prompt
+}
+
+/// `cwd` with a leading home directory replaced by `~`
+pub(crate) fn abbreviate_home(cwd: &Path, home: Option<&str>) -> String {
+    let home = home.filter(|home| !home.is_empty() && *home != "/");
+    match home.and_then(|home| cwd.strip_prefix(home).ok()) {
+        Some(rest) if rest.as_os_str().is_empty() => "~".to_string(),
+        Some(rest) => format!("~/{}", rest.display()),
+        None => cwd.display().to_string(),
+    }
+}
+
+/// The SGR code of a named style, with `status` choosing green or red
+fn style_code(name: &str, status: i32) -> Option<&'static str> {
+    Some(match name {
+        "reset" => "0",
+        "bold" => "1",
+        "dim" => "2",
+        "black" => "30",
+        "red" => "31",
+        "green" => "32",
+        "yellow" => "33",
+        "blue" => "34",
+        "magenta" => "35",
+        "cyan" => "36",
+        "white" => "37",
+        "status" if status == 0 => "32",
+        "status" => "31",
+        _ => return None,
+    })
+}
+
+/// The name of the effective user, from the password database
+pub fn user_name() -> String {
+    // SAFETY: getpwuid returns null or a record that stays valid until the
+    // next password database call, and the name is copied out before then
+    unsafe {
+        let entry = libc::getpwuid(libc::geteuid());
+        if entry.is_null() {
+            return String::new();
+        }
+        CStr::from_ptr((*entry).pw_name)
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+/// The machine's host name
+pub fn host_name() -> String {
+    let mut name = [0 as libc::c_char; 256];
+    // SAFETY: gethostname writes at most `name.len() - 1` bytes, so the
+    // last byte stays NUL and the result is always terminated
+    unsafe {
+        if libc::gethostname(name.as_mut_ptr(), name.len() - 1) != 0 {
+            return String::new();
+        }
+        CStr::from_ptr(name.as_ptr()).to_string_lossy().into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context() -> PromptContext<'static> {
+        PromptContext {
+            user: "ada",
+            host: "box.example.com",
+            cwd: Path::new("/home/ada"),
+            home: Some("/home/ada"),
+            status: 0,
+            root: false,
+            git: None,
+            dirs: &[],
+        }
+    }
+
+    #[test]
+    fn expands_bash_escapes() {
+        let context = context();
+        assert_eq!(
+            render(r"\u@\H \W \w\$", &context),
+            "ada@box.example.com ~ ~$"
+        );
+        assert_eq!(render(r"[\?]\n> ", &context), "[0]\n> ");
+        assert_eq!(render(r"\[\e[1m\]x\a\\", &context), "\x1b[1mx\x07\\");
+
+        let context = PromptContext {
+            cwd: Path::new("/"),
+            home: Some("/"),
+            status: 2,
+            root: true,
+            ..context
+        };
+        assert_eq!(render(r"\w \W \? \$ ", &context), "/ / 2 # ");
+    }
+
+    #[test]
+    fn keeps_what_it_does_not_know() {
+        let context = context();
+        assert_eq!(render(r"\q \{nope}x \{red", &context), r"\q \{nope}x \{red");
+        assert_eq!(render("end\\", &context), "end\\");
+    }
+
+    #[test]
+    fn status_style_follows_the_last_exit_status() {
+        let failed = PromptContext {
+            status: 1,
+            ..context()
+        };
+        assert_eq!(render(r"\{status}x", &context()), "\x1b[32mx");
+        assert_eq!(render(r"\{status}x\{reset}", &failed), "\x1b[31mx\x1b[0m");
+        assert_eq!(render(r"\{bold}\{cyan}", &failed), "\x1b[1m\x1b[36m");
+    }
+
+    /// A fresh directory for a test, removed first if a run left it behind
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("shelly-prompt-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn git_status_reads_the_branch_from_head() {
+        let repo = scratch_dir("repo");
+        let src = repo.join("src");
+        fs::create_dir_all(repo.join(".git")).unwrap();
+        fs::create_dir_all(&src).unwrap();
+        fs::write(repo.join(".git/HEAD"), "ref: refs/heads/main\n").unwrap();
+
+        let mut cache = GitCache::default();
+        // Not a real repository, so `git status` fails and it counts as clean
+        let clean = |branch: &str| GitStatus {
+            branch: branch.to_string(),
+            dirty: false,
+        };
+        assert_eq!(cache.status(&src), Some(clean("main")));
+        fs::write(repo.join(".git/HEAD"), "0123456789abcdef\n").unwrap();
+        assert_eq!(cache.status(&repo), Some(clean("0123456")));
+
+        // A worktree's `.git` file points to the real git directory
+        let worktree = scratch_dir("worktree");
+        fs::write(
+            worktree.join(".git"),
+            format!("gitdir: {}\n", repo.join(".git").display()),
+        )
+        .unwrap();
+        assert_eq!(cache.status(&worktree), Some(clean("0123456")));
+
+        let outside = scratch_dir("outside");
+        assert_eq!(cache.status(&outside), None);
+        for dir in [repo, worktree, outside] {
+            fs::remove_dir_all(dir).unwrap();
+        }
+    }
+}
@@ -0,0 +1,319 @@
+use crate::command::Redirection;
+use crate::error::ShellError;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::rc::Rc;
+
+/// Lowest descriptor used for files the shell opens for redirections
+///
+/// Redirections name single-digit descriptors, so keeping the shell's own
+/// copies above them means applying one redirection can never clobber the
+/// file another one still needs.
+pub(crate) const FIRST_SHELL_FD: libc::c_int = 10;
+
+/// A redirection with its file opened, ready to be applied
+#[derive(Debug)]
+pub enum OpenRedirect {
+    /// Make `fd` refer to the opened file
+    File(i32, File),
+    /// Make the first fd a copy of the second
+    Duplicate(i32, i32),
+    /// Close the fd
+    Close(i32),
+}
+
+/// Open every file named by `redirects`, in order
+///
+/// Fails on the first file that can't be opened, or on a duplication whose
+/// target isn't open (anything but 0-2 or an fd redirected earlier, unless
+/// it was closed since).
+pub fn open_redirections(
+    redirects: &[Redirection<PathBuf, String>],
+) -> Result<Vec<OpenRedirect>, ShellError> {
+    let mut open_fds = vec![0, 1, 2];
+    let mut opened = Vec::with_capacity(redirects.len());
+    for redirect in redirects {
+        let open = match redirect {
+            Redirection::Input { fd, path } => OpenRedirect::File(*fd, open_input(path)?),
+            Redirection::Output { fd, path, append } => {
+                OpenRedirect::File(*fd, open_output(path, *append)?)
+            }
+            Redirection::Duplicate { fd, target } => {
+                if !open_fds.contains(target) {
+                    return Err(ShellError::RedirectError {
+                        path: target.to_string().into(),
+                        source: io::Error::from_raw_os_error(libc::EBADF),
+                    });
+                }
+                OpenRedirect::Duplicate(*fd, *target)
+            }
+            Redirection::Close { fd } => OpenRedirect::Close(*fd),
+            Redirection::HereDoc { fd, body } => OpenRedirect::File(*fd, here_doc_pipe(body)?),
+        };
+        match &open {
+            OpenRedirect::File(fd, _) | OpenRedirect::Duplicate(fd, _) => open_fds.push(*fd),
+            OpenRedirect::Close(fd) => open_fds.retain(|open| open != fd),
+        }
+        opened.push(open);
+    }
+    Ok(opened)
+}
+
+/// Apply opened redirections in a child process just before it execs
+///
+/// They run after the child's stdio has been wired to pipes, so `2>&1` in a
+/// pipeline stage sends stderr into the pipe as well.
+pub fn apply_in_child(process: &mut Command, redirects: Vec<OpenRedirect>) {
+    if redirects.is_empty() {
+        return;
+    }
+    // SAFETY: the hook only calls dup2 and close, which are async-signal-safe
+    unsafe {
+        process.pre_exec(move || apply(&redirects));
+    }
+}
+
+/// Point this process's descriptors where `redirects` say, in order
+///
+/// Used in forked children, which run commands with the redirections in
+/// place rather than exec'ing one program.
+pub fn apply(redirects: &[OpenRedirect]) -> io::Result<()> {
+    for redirect in redirects {
+        let (fd, source) = match redirect {
+            OpenRedirect::File(fd, file) => (*fd, file.as_raw_fd()),
+            OpenRedirect::Duplicate(fd, target) => (*fd, *target),
+            OpenRedirect::Close(fd) => {
+                // SAFETY: closing a descriptor the command asked to close;
+                // one that isn't open is fine
+                unsafe { libc::close(*fd) };
+                continue;
+            }
+        };
+        // SAFETY: dup2 only replaces `fd`, and `source` stays open
+        if unsafe { libc::dup2(source, fd) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Where a builtin's output descriptor points once its redirections are applied
+#[derive(Debug, Clone)]
+pub enum BuiltinSink {
+    /// The shell's stdout (or the next pipeline stage)
+    Stdout,
+    /// The shell's stderr
+    Stderr,
+    /// A redirection target
+    File(Rc<File>),
+    /// Output collected for the next pipeline stage or an enclosing capture
+    Buffer(Rc<RefCell<Vec<u8>>>),
+    /// A descriptor closed with `>&-`, which fails every write
+    Closed,
+}
+
+impl BuiltinSink {
+    /// Resolve where a builtin's stdout (fd 1) ends up
+    ///
+    /// Returns `None` if fd 1 was pointed at something that can't be written,
+    /// such as stdin.
+    pub fn for_stdout(redirects: Vec<OpenRedirect>) -> Option<BuiltinSink> {
+        Self::for_stdio(redirects).0
+    }
+
+    /// Resolve where a builtin's stdout and stderr (fds 1 and 2) end up
+    ///
+    /// Either is `None` if it was pointed at something that can't be written.
+    pub fn for_stdio(redirects: Vec<OpenRedirect>) -> (Option<BuiltinSink>, Option<BuiltinSink>) {
+        let mut sinks = HashMap::from([(1, BuiltinSink::Stdout), (2, BuiltinSink::Stderr)]);
+        for redirect in redirects {
+            match redirect {
+                OpenRedirect::File(fd, file) => {
+                    sinks.insert(fd, BuiltinSink::File(Rc::new(file)));
+                }
+                OpenRedirect::Duplicate(fd, target) => match sinks.get(&target).cloned() {
+                    Some(sink) => {
+                        sinks.insert(fd, sink);
+                    }
+                    None => {
+                        sinks.remove(&fd);
+                    }
+                },
+                OpenRedirect::Close(fd) => {
+                    sinks.insert(fd, BuiltinSink::Closed);
+                }
+            }
+        }
+        (sinks.remove(&1), sinks.remove(&2))
+    }
+
+    /// Write a builtin's output to this sink, returning what belongs on stdout
+    pub fn deliver(&self, output: Vec<u8>) -> io::Result<Vec<u8>> {
+        match self {
+            BuiltinSink::Stdout => Ok(output),
+            BuiltinSink::Stderr => {
+                io::stderr().write_all(&output)?;
+                Ok(Vec::new())
+            }
+            BuiltinSink::File(file) => {
+                (&**file).write_all(&output)?;
+                Ok(Vec::new())
+            }
+            BuiltinSink::Buffer(buffer) => {
+                buffer.borrow_mut().extend_from_slice(&output);
+                Ok(Vec::new())
+            }
+            BuiltinSink::Closed if output.is_empty() => Ok(Vec::new()),
+            BuiltinSink::Closed => Err(io::Error::from_raw_os_error(libc::EBADF)),
+        }
+    }
+}
+
+/// Writing to a sink sends the bytes to its destination right away
+impl Write for BuiltinSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            BuiltinSink::Stdout => io::stdout().write(buf),
+            BuiltinSink::Stderr => io::stderr().write(buf),
+            BuiltinSink::File(file) => (&**file).write(buf),
+            BuiltinSink::Buffer(buffer) => {
+                buffer.borrow_mut().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            BuiltinSink::Closed => Err(io::Error::from_raw_os_error(libc::EBADF)),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            BuiltinSink::Stdout => io::stdout().flush(),
+            BuiltinSink::Stderr => io::stderr().flush(),
+            BuiltinSink::File(_) | BuiltinSink::Buffer(_) | BuiltinSink::Closed => Ok(()),
+        }
+    }
+}
+
+/// Open an input redirection source for reading
+fn open_input(path: &Path) -> Result<File, ShellError> {
+    File::open(path)
+        .and_then(above_user_fds)
+        .map_err(|source| redirect_error(path, source))
+}
+
+/// A pipe whose read end yields `body`, for a here-document or here-string
+fn here_doc_pipe(body: &str) -> Result<File, ShellError> {
+    Ok(pipe_from(body.as_bytes().to_vec())?)
+}
+
+/// A pipe whose read end yields `bytes`
+///
+/// The bytes are written from a helper thread, so more than the pipe's
+/// buffer holds can't block the shell before the reader starts.
+pub fn pipe_from(bytes: Vec<u8>) -> io::Result<File> {
+    let (reader, mut writer) = io::pipe()?;
+    std::thread::spawn(move || {
+        // The reader may stop without reading it all
+        let _ = writer.write_all(&bytes);
+    });
+    above_user_fds(File::from(OwnedFd::from(reader)))
+}
+
+/// The shell's own stdin, pointed somewhere else while commands run inside
+/// the shell
+///
+/// Builtins, and the commands in functions, groups and loops, read the
+/// shell's fd 0, so the previous pipeline stage or an input redirection is
+/// put there while they run. Dropping this puts the original back.
+#[derive(Debug)]
+pub struct StdinSwap {
+    saved: OwnedFd,
+}
+
+impl StdinSwap {
+    /// Point the shell's stdin at `source` until the swap is dropped
+    pub fn to(source: &impl AsRawFd) -> io::Result<Self> {
+        // SAFETY: F_DUPFD_CLOEXEC returns a new descriptor we take ownership
+        // of, and dup2 only replaces fd 0, which the copy keeps open
+        unsafe {
+            let saved = libc::fcntl(0, libc::F_DUPFD_CLOEXEC, FIRST_SHELL_FD);
+            if saved == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            let saved = OwnedFd::from_raw_fd(saved);
+            if libc::dup2(source.as_raw_fd(), 0) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(StdinSwap { saved })
+        }
+    }
+
+    /// Apply whichever of `redirects` points fd 0 somewhere, if any does
+    pub fn for_redirects(redirects: &[OpenRedirect]) -> io::Result<Option<Self>> {
+        let mut source = None;
+        let mut closed = None;
+        for (index, redirect) in redirects.iter().enumerate() {
+            match redirect {
+                OpenRedirect::File(0, file) => source = Some(file.as_raw_fd()),
+                // A write-only /dev/null fails reads like a closed fd, and
+                // keeps fd 0 from being reused by the next file the shell opens
+                OpenRedirect::Close(0) => {
+                    let null = File::options().write(true).open("/dev/null")?;
+                    source = Some(closed.insert(null).as_raw_fd());
+                }
+                OpenRedirect::Duplicate(0, target) => {
+                    // An fd redirected earlier in the list means its file
+                    let mut earlier = redirects[..index].iter().rev();
+                    let file = earlier.find_map(|earlier| match earlier {
+                        OpenRedirect::File(fd, file) if fd == target => Some(file.as_raw_fd()),
+                        _ => None,
+                    });
+                    source = Some(file.unwrap_or(*target));
+                }
+                _ => {}
+            }
+        }
+        source.map(|fd| Self::to(&fd)).transpose()
+    }
+}
+
+impl Drop for StdinSwap {
+    fn drop(&mut self) {
+        // SAFETY: dup2 onto fd 0 from the copy this swap owns
+        unsafe { libc::dup2(self.saved.as_raw_fd(), 0) };
+    }
+}
+
+/// Open a redirection target for writing, truncating it unless `append` is set
+fn open_output(path: &Path, append: bool) -> Result<File, ShellError> {
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .append(append)
+        .truncate(!append)
+        .open(path)
+        .and_then(above_user_fds)
+        .map_err(|source| redirect_error(path, source))
+}
+
+fn redirect_error(path: &Path, source: io::Error) -> ShellError {
+    ShellError::RedirectError {
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+/// Move `file` to a close-on-exec descriptor at or above [`FIRST_SHELL_FD`]
+fn above_user_fds(file: File) -> io::Result<File> {
+    // SAFETY: F_DUPFD_CLOEXEC returns a new descriptor we take ownership of
+    let fd = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_DUPFD_CLOEXEC, FIRST_SHELL_FD) };
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
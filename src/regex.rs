@@ -0,0 +1,93 @@
+//! POSIX extended regular expressions, for `[[ string =~ regex ]]`
+//!
+//! Matching is left to the C library's `regcomp`/`regexec`, so the syntax is
+//! the platform's ERE syntax, as in other shells.
+
+use std::ffi::{CStr, CString};
+use std::mem::MaybeUninit;
+
+/// Escape `text` so every character in it matches only itself
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for ch in text.chars() {
+        if "\\^$.|?*+()[]{}".contains(ch) {
+            escaped.push('\\');
+        }
+        escaped.push(ch);
+    }
+    escaped
+}
+
+/// Search `text` for the first match of `pattern`
+///
+/// Returns `Ok(None)` if nothing matched. Otherwise the first element is the
+/// matched text and the rest are the capture groups in order, `None` for a
+/// group that didn't take part in the match (or nothing, if no later group
+/// did either). An invalid pattern is an `Err` with the C library's
+/// description.
+///
+/// # Examples
+/// ```
+/// use codecrafters_shell::regex::captures;
+///
+/// let groups = captures("([a-z]+)-([0-9]+)", "id: abc-42").unwrap().unwrap();
+/// assert_eq!(groups[0].as_deref(), Some("abc-42"));
+/// assert_eq!(groups[1].as_deref(), Some("abc"));
+/// assert_eq!(groups[2].as_deref(), Some("42"));
+/// assert!(captures("^x", "abc").unwrap().is_none());
+/// assert!(captures("(", "abc").is_err());
+/// ```
+pub fn captures(pattern: &str, text: &str) -> Result<Option<Vec<Option<String>>>, String> {
+    let pattern_c = CString::new(pattern).map_err(|_| "pattern contains a NUL byte")?;
+    let Ok(text_c) = CString::new(text) else {
+        return Ok(None);
+    };
+
+    let mut regex = MaybeUninit::<libc::regex_t>::uninit();
+    // SAFETY: regcomp initializes `regex` when it returns 0
+    let code = unsafe { libc::regcomp(regex.as_mut_ptr(), pattern_c.as_ptr(), libc::REG_EXTENDED) };
+    if code != 0 {
+        let mut message = [0 as libc::c_char; 256];
+        // SAFETY: regerror writes a NUL-terminated message of at most the given length
+        let message = unsafe {
+            libc::regerror(code, regex.as_ptr(), message.as_mut_ptr(), message.len());
+            CStr::from_ptr(message.as_ptr())
+        };
+        return Err(message.to_string_lossy().into_owned());
+    }
+    // SAFETY: regcomp succeeded
+    let mut regex = unsafe { regex.assume_init() };
+
+    // The group count isn't exposed portably; every group opens with a `(`
+    let slots = pattern.matches('(').count() + 1;
+    let mut matches = vec![
+        libc::regmatch_t {
+            rm_so: -1,
+            rm_eo: -1
+        };
+        slots
+    ];
+    // SAFETY: `matches` has room for `slots` entries, and `regex` is freed
+    // exactly once after its last use
+    let found = unsafe {
+        let code = libc::regexec(&regex, text_c.as_ptr(), slots, matches.as_mut_ptr(), 0);
+        libc::regfree(&mut regex);
+        code == 0
+    };
+    if !found {
+        return Ok(None);
+    }
+
+    let mut groups: Vec<Option<String>> = matches
+        .iter()
+        .map(|m| {
+            let (start, end) = (usize::try_from(m.rm_so).ok()?, m.rm_eo as usize);
+            Some(String::from_utf8_lossy(&text.as_bytes()[start..end]).into_owned())
+        })
+        .collect();
+    // Some slots may belong to a `(` that doesn't open a group, such as `\(`
+    while groups.len() > 1 && groups.last() == Some(&None) {
+        groups.pop();
+    }
+    Ok(Some(groups))
+}
@@ -1,86 +1,682 @@
-use crate::builtin::BuiltinRegistry;
-use crate::command::{CommandParser, CommandParts};
-use crate::completion::RustylineHelper;
-use crate::error::ShellError;
-use rustyline::history::FileHistory;
-use rustyline::Editor;
-use std::collections::HashSet;
-use std::io::Write;
-use std::path::PathBuf;
+use crate::builtin::{self, BuiltinCommand, BuiltinRegistry};
+use crate::command::{
+    is_name, target_parts_mut, AndOrList, AssignedValue, Assignment, Branch, CaseItem,
+    Command as ShellCommand, CommandParser, CommandParts, Condition, Connector, ParseError,
+    Pipeline, Redirection, Word, WordPart,
+};
+use crate::completion::{
+    self, CompletionAction, CompletionSpec, CompletionSpecs, MatchMode, RustylineHelper,
+};
+use crate::error::{describe_io_error, Location, ShellError};
+use crate::expand::{self, ExpandedCommand};
+use crate::generator::{Generator, Request};
+use crate::glob;
+use crate::history::{self, HistoryEntry, SearchMode, ShellHistory};
+use crate::jobs::{wait_exit, JobOutput, JobState, JobTable};
+use crate::keybindings;
+use crate::lookup::{self, CommandHash, CommandHashes};
+use crate::prompt::{self, GitCache, PromptContext};
+use crate::redirect::{self, open_redirections, BuiltinSink, StdinSwap};
+use crate::regex;
+use crate::signals;
+use crate::substitution::Substitution;
+use crate::timing::{Stopwatch, TimeFormat, Times};
+use rustyline::history::History;
+use rustyline::{CompletionType, Config, Editor};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{IsTerminal, Read, Write};
+use std::mem::ManuallyDrop;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::rc::Rc;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Profile sourced by every login shell, before the user's `~/.shelly_profile`
+const SYSTEM_PROFILE: &str = "/etc/shelly_profile";
+
+/// Variables a restricted shell makes readonly, as rbash does, so its
+/// commands can't come from anywhere else
+pub const RESTRICTED_VARIABLES: [&str; 3] = ["PATH", "SHELL", "ENV"];
+
+/// Options controlling how a [`Shell`] is constructed
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ShellOptions {
+    /// Whether the shell is interactive; `None` decides by checking whether
+    /// stdin and stderr are terminals. `-i` forces `Some(true)`.
+    pub interactive: Option<bool>,
+    /// Act as a login shell (`-l`/`--login`, or started as `-shelly`):
+    /// source the profile files first and `~/.shelly_logout` on exit
+    pub login: bool,
+    /// Restricted shell (`-r`): no `cd`, no `/` in command names, no output
+    /// redirection, and [`RESTRICTED_VARIABLES`] are readonly
+    pub restricted: bool,
+    /// Skip the startup rc file (`--norc`)
+    pub norc: bool,
+    /// Alternative startup rc file (`--rcfile`)
+    pub rcfile: Option<PathBuf>,
+    /// Positional arguments passed after the script or `-c` command
+    pub args: Vec<String>,
+}
+
+/// Shell options turned on and off with `set`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OptionFlags {
+    /// Exit when a command fails outside a condition (`-e`, `errexit`)
+    pub errexit: bool,
+    /// Treat expanding an unset variable as an error (`-u`, `nounset`)
+    pub nounset: bool,
+    /// Print each simple command to stderr before running it (`-x`, `xtrace`)
+    pub xtrace: bool,
+    /// Give a pipeline the status of its last failing stage (`-o pipefail`)
+    pub pipefail: bool,
+    /// Send SIGHUP to the remaining jobs when an interactive shell exits
+    /// (`-o huponexit`)
+    pub huponexit: bool,
+}
+
+impl OptionFlags {
+    /// Each option's long name, its letter (if it has one) and whether it's set
+    pub fn list(&self) -> [(&'static str, Option<char>, bool); 5] {
+        [
+            ("errexit", Some('e'), self.errexit),
+            ("huponexit", None, self.huponexit),
+            ("nounset", Some('u'), self.nounset),
+            ("pipefail", None, self.pipefail),
+            ("xtrace", Some('x'), self.xtrace),
+        ]
+    }
+
+    /// The flag for the option with the long name or letter given, if
+    /// there's one
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "errexit" | "e" => Some(&mut self.errexit),
+            "huponexit" => Some(&mut self.huponexit),
+            "nounset" | "u" => Some(&mut self.nounset),
+            "pipefail" => Some(&mut self.pipefail),
+            "xtrace" | "x" => Some(&mut self.xtrace),
+            _ => None,
+        }
+    }
+}
+
+/// The value of a shell-local variable
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// A plain string
+    Scalar(String),
+    /// An indexed array, as set by `NAME=(a b c)`; indices may have gaps
+    Array(BTreeMap<usize, String>),
+}
+
+impl Value {
+    /// The string a plain `$NAME` gives: the value, or an array's element 0
+    pub fn scalar(&self) -> Option<&str> {
+        match self {
+            Value::Scalar(value) => Some(value),
+            Value::Array(elements) => elements.get(&0).map(String::as_str),
+        }
+    }
+
+    /// The elements, in index order; a scalar is an array of one
+    pub fn elements(&self) -> Vec<String> {
+        match self {
+            Value::Scalar(value) => vec![value.clone()],
+            Value::Array(elements) => elements.values().cloned().collect(),
+        }
+    }
+
+    /// The value as an array, with a scalar as element 0
+    fn into_array(self) -> BTreeMap<usize, String> {
+        match self {
+            Value::Scalar(value) => BTreeMap::from([(0, value)]),
+            Value::Array(elements) => elements,
+        }
+    }
+}
+
+impl std::fmt::Display for Value {
+    /// Write the value as the shell would read it back, `(a b)` for arrays
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Scalar(value) => write!(f, "{}", Word::quoted(value.as_str())),
+            Value::Array(elements) => {
+                let elements: Vec<_> = elements
+                    .iter()
+                    .map(|(index, value)| format!("[{}]={}", index, Word::quoted(value.as_str())))
+                    .collect();
+                write!(f, "({})", elements.join(" "))
+            }
+        }
+    }
+}
+
+/// A variable as it was before `local` shadowed it, to put back when the
+/// function returns
+#[derive(Debug)]
+struct SavedVariable {
+    name: String,
+    local: Option<Value>,
+    exported: Option<OsString>,
+}
+
+/// How `break` or `continue` leaves the loops it's in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoopControl {
+    /// Leave this many enclosing loops
+    Break(usize),
+    /// Leave one less than this many loops, then start the next iteration
+    /// of the loop reached
+    Continue(usize),
+}
 
 /// The main shell structure that manages command execution and interactive input
 pub struct Shell {
     /// Current working directory
     current_dir: PathBuf,
-    /// Registry of built-in commands
-    builtin_registry: BuiltinRegistry,
-    /// Rustyline editor with history and completion support
-    editor: Editor<RustylineHelper, FileHistory>,
+    /// Directories saved by `pushd`, most recent first, below `current_dir`
+    dir_stack: Vec<PathBuf>,
+    /// Registry of built-in commands (shared so builtins can borrow the shell mutably)
+    builtin_registry: Rc<BuiltinRegistry>,
+    /// Rustyline editor with history and completion support (interactive shells only)
+    editor: Option<Editor<RustylineHelper, ShellHistory>>,
+    /// Jobs started by this shell (shared with the completer)
+    jobs: Arc<RwLock<JobTable>>,
+    /// Where commands run from PATH were found (shared with the highlighter)
+    hashed: CommandHashes,
+    /// What commands' arguments complete to, shared with the line editor
+    completions: CompletionSpecs,
+    /// Options the shell was started with
+    options: ShellOptions,
+    /// Exit status of the last command that ran
+    last_status: i32,
+    /// Status requested by `exit`; the shell stops reading input once set
+    exit_status: Option<i32>,
+    /// Position of the line being executed, used to give errors context
+    location: Location,
+    /// Process ID of the shell (`$$`), kept by subshells and pipeline stages
+    pid: u32,
+    /// Process ID of the most recent background job (`$!`)
+    last_background_pid: Option<u32>,
+    /// Shell-local variables; exported ones live in the process environment
+    variables: HashMap<String, Value>,
+    /// Aliases defined with `alias`, by name
+    aliases: HashMap<String, String>,
+    /// Functions defined with `name() { ...; }`, by name
+    functions: HashMap<String, Rc<Vec<AndOrList>>>,
+    /// Name of the shell or the `-c` command's name argument (`$0`)
+    name: String,
+    /// Positional parameters (`$1`, `$2`, ...) of the running function, or
+    /// the arguments given after a `-c` command
+    positional: Vec<String>,
+    /// Number of function calls currently running
+    function_depth: usize,
+    /// Wall-clock time taken by the last command line run at the prompt
+    last_duration: Option<Duration>,
+    /// Git status shown in the prompt, kept between prompts
+    git_cache: GitCache,
+    /// Number of files being run by `source`
+    source_depth: usize,
+    /// Status passed to `return`; set while leaving the current function
+    /// or sourced file
+    returning: Option<i32>,
+    /// Number of loops running in the current function (or outside any)
+    loop_depth: usize,
+    /// Set by `break`/`continue` while leaving loop bodies
+    loop_control: Option<LoopControl>,
+    /// Buffers collecting the output of commands run inside the shell for a
+    /// pipeline stage or redirection; output goes to the innermost one
+    captures: Vec<Vec<u8>>,
+    /// Process substitutions started for the pipelines currently running
+    substitutions: Vec<Substitution>,
+    /// Elements of the `NAME=(a b c)` arguments of the running builtin
+    array_args: Vec<(String, Vec<String>)>,
+    /// Options set with `set`
+    flags: OptionFlags,
+    /// For each function call running, innermost last, the variables its
+    /// `local` commands shadowed
+    scopes: Vec<Vec<SavedVariable>>,
+    /// Names of the variables that can't be assigned
+    readonly: HashSet<String>,
+    /// Number of conditions, `!` pipelines and `&&`/`||` operands other
+    /// than the last running, inside which `set -e` doesn't apply
+    errexit_ignored: usize,
 }
 
 impl Shell {
-    /// Create a new Shell instance
+    /// Create a new Shell instance, detecting whether it is interactive
+    pub fn new() -> Result<Self, ShellError> {
+        Self::with_options(ShellOptions::default())
+    }
+
+    /// Create a new Shell instance with the given options
     ///
     /// Initializes the shell with:
     /// - Current working directory
     /// - Built-in command registry
-    /// - Rustyline editor with tab completion and history
-    pub fn new() -> Result<Self, ShellError> {
-        let current_dir = std::env::current_dir().map_err(ShellError::IoError)?;
+    /// - Rustyline editor with tab completion and history, if interactive
+    pub fn with_options(options: ShellOptions) -> Result<Self, ShellError> {
+        let physical_dir = std::env::current_dir().map_err(ShellError::IoError)?;
+        // Keep an inherited PWD that names the same directory by way of
+        // symbolic links, as the logical working directory
+        let current_dir = std::env::var_os("PWD")
+            .map(PathBuf::from)
+            .filter(|pwd| {
+                pwd.is_absolute() && std::fs::canonicalize(pwd).ok().as_ref() == Some(&physical_dir)
+            })
+            .unwrap_or(physical_dir);
+        std::env::set_var("PWD", &current_dir);
         let builtin_registry = BuiltinRegistry::default();
+        let jobs = Arc::new(RwLock::new(JobTable::new()));
+        let hashed = Arc::new(RwLock::new(CommandHash::default()));
+        let completions = Arc::new(RwLock::new(builtin_registry.completion_specs()));
+
+        let interactive = options
+            .interactive
+            .unwrap_or_else(|| std::io::stdin().is_terminal() && std::io::stderr().is_terminal());
 
-        // Collect built-in command names for tab completion
-        let builtins: HashSet<String> = builtin_registry.get_command_names().into_iter().collect();
+        // Non-interactive shells skip the prompt, history and completion machinery
+        let editor = if interactive {
+            // Collect built-in command names for tab completion
+            let builtins: HashSet<String> =
+                builtin_registry.get_command_names().into_iter().collect();
 
-        // Set up editor with completion helper
-        let helper = RustylineHelper::new(builtins);
-        let mut editor = Editor::new().map_err(|e| ShellError::EditorError(e.to_string()))?;
-        editor.set_helper(Some(helper));
+            // Set up editor with completion helper
+            let helper = RustylineHelper::new(
+                builtins,
+                Arc::clone(&jobs),
+                Arc::clone(&completions),
+                Arc::clone(&hashed),
+            );
+            // Tab completes in place; the completer opens its own menu of matches
+            let config = Config::builder()
+                .completion_type(CompletionType::List)
+                .build();
+            let mut editor = Editor::with_history(config, ShellHistory::new())
+                .map_err(|e| ShellError::EditorError(e.to_string()))?;
+            editor.set_helper(Some(helper));
+            keybindings::bind_keys(&mut editor);
+            signals::init_job_control();
+            Some(editor)
+        } else {
+            None
+        };
 
-        // Load command history from file (ignore errors if file doesn't exist)
-        let _ = editor.load_history("history.txt");
+        let readonly = if options.restricted {
+            RESTRICTED_VARIABLES.map(String::from).into()
+        } else {
+            HashSet::new()
+        };
 
         Ok(Self {
             current_dir,
-            builtin_registry,
+            dir_stack: Vec::new(),
+            builtin_registry: Rc::new(builtin_registry),
             editor,
+            jobs,
+            hashed,
+            completions,
+            options,
+            last_status: 0,
+            exit_status: None,
+            location: Location::default(),
+            pid: std::process::id(),
+            last_background_pid: None,
+            variables: HashMap::new(),
+            aliases: HashMap::new(),
+            functions: HashMap::new(),
+            name: "shelly".to_string(),
+            positional: Vec::new(),
+            function_depth: 0,
+            source_depth: 0,
+            last_duration: None,
+            git_cache: GitCache::default(),
+            returning: None,
+            loop_depth: 0,
+            loop_control: None,
+            captures: Vec::new(),
+            array_args: Vec::new(),
+            substitutions: Vec::new(),
+            flags: OptionFlags::default(),
+            errexit_ignored: 0,
+            scopes: Vec::new(),
+            readonly,
         })
     }
 
+    /// Whether the shell is running interactively
+    pub fn is_interactive(&self) -> bool {
+        self.editor.is_some()
+    }
+
+    /// Run the shell until EOF or `exit`, returning the shell's exit status
+    ///
+    /// Interactive shells source the rc file and run the REPL; otherwise
+    /// commands are read from stdin line by line without a prompt.
+    pub fn run(&mut self) -> Result<i32, ShellError> {
+        self.start_login();
+        if self.editor.is_some() {
+            self.source_rc_file();
+            if self.exit_status.is_some() {
+                return Ok(self.logout());
+            }
+            // After the rc file, which may set HISTFILE and HISTSIZE
+            self.load_history();
+            self.run_interactive()
+        } else {
+            self.run_stdin()
+        }
+    }
+
+    /// Prepare a login shell (`-l`, or started as `-shelly`) before it runs
+    /// anything: export `$SHELL` as this shelly, then source
+    /// `/etc/shelly_profile` and `~/.shelly_profile`, either of which may
+    /// be missing
+    fn start_login(&mut self) {
+        if !self.options.login {
+            return;
+        }
+        if let Ok(path) = std::env::current_exe() {
+            self.export_value("SHELL", Some(&path.display().to_string()));
+        }
+        self.source_startup_file(Path::new(SYSTEM_PROFILE), false);
+        if let Some(home) = self.variable("HOME") {
+            self.source_startup_file(&Path::new(&home).join(".shelly_profile"), false);
+        }
+    }
+
+    /// Source `~/.shelly_logout` if this is a login shell, as it exits,
+    /// returning the status to exit with
+    ///
+    /// The file runs even after `exit`, and doesn't change the status.
+    fn logout(&mut self) -> i32 {
+        let status = self.final_status();
+        if self.options.login {
+            self.exit_status = None;
+            if let Some(home) = self.variable("HOME") {
+                self.source_startup_file(&Path::new(&home).join(".shelly_logout"), false);
+            }
+        }
+        status
+    }
+
+    /// Source the startup rc file before the first prompt
+    ///
+    /// The file is the one given with `--rcfile`, else `$SHELLY_RC`, else
+    /// `~/.shellyrc`; `--norc` skips it. A missing `~/.shellyrc` is fine, but
+    /// a file named explicitly has to exist.
+    fn source_rc_file(&mut self) {
+        if self.options.norc {
+            return;
+        }
+        let explicit = self.options.rcfile.clone().or_else(|| {
+            self.variable("SHELLY_RC")
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from)
+        });
+        let path = match (&explicit, self.variable("HOME")) {
+            (Some(path), _) => path.clone(),
+            (None, Some(home)) => Path::new(&home).join(".shellyrc"),
+            (None, None) => return,
+        };
+        self.source_startup_file(&path, explicit.is_some());
+    }
+
+    /// Source a file the shell reads on its own, reporting any error except
+    /// a missing file that isn't `required`
+    fn source_startup_file(&mut self, path: &Path, required: bool) {
+        match self.source(path, None) {
+            Err(e) if required || e.kind() != std::io::ErrorKind::NotFound => {
+                let _ = writeln!(
+                    std::io::stderr(),
+                    "shelly: {}: {}",
+                    path.display(),
+                    describe_io_error(&e)
+                );
+            }
+            _ => {}
+        }
+    }
+
+    /// The prompt rendered from the template in `variable` (`PS1`),
+    /// or `default` if it isn't set
+    fn prompt(&mut self, variable: &str, default: &str) -> String {
+        let Some(template) = self.variable(variable) else {
+            return default.to_string();
+        };
+        let user = self.variable("USER").unwrap_or_else(prompt::user_name);
+        let host = prompt::host_name();
+        let home = self.variable("HOME");
+        // Only look for a repository when the prompt shows it
+        let git = template
+            .contains("\\g")
+            .then(|| self.git_cache.status(&self.current_dir))
+            .flatten();
+        let context = PromptContext {
+            user: &user,
+            host: &host,
+            cwd: &self.current_dir,
+            home: home.as_deref(),
+            status: self.last_status,
+            // SAFETY: geteuid has no preconditions and can't fail
+            root: unsafe { libc::geteuid() } == 0,
+            git: git.as_ref(),
+            dirs: &self.dir_stack,
+        };
+        prompt::render(&template, &context)
+    }
+
+    /// Commands entered at the prompt, oldest first
+    ///
+    /// Empty for a non-interactive shell, which keeps no history.
+    pub fn history(&self) -> Vec<HistoryEntry> {
+        match &self.editor {
+            Some(editor) => editor.history().iter().cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Delete the history entry at `index`, counting from 0 for the oldest,
+    /// returning whether there was one
+    pub fn delete_history_entry(&mut self, index: usize) -> bool {
+        self.editor
+            .as_mut()
+            .is_some_and(|editor| editor.history_mut().remove(index).is_some())
+    }
+
+    /// Forget every history entry
+    pub fn clear_history(&mut self) {
+        if let Some(editor) = self.editor.as_mut() {
+            let _ = editor.clear_history();
+        }
+    }
+
+    /// The history file: `$HISTFILE` if it's set, otherwise
+    /// [`history::default_file`]
+    fn history_file(&self) -> Option<PathBuf> {
+        match self.variable("HISTFILE") {
+            Some(path) if path.is_empty() => None,
+            Some(path) => Some(PathBuf::from(path)),
+            None => history::default_file(
+                self.variable("XDG_STATE_HOME").as_deref(),
+                self.variable("HOME").as_deref(),
+            ),
+        }
+    }
+
+    /// The history size limit in `variable`, as bash reads `HISTSIZE` and
+    /// `HISTFILESIZE`: a negative number means no limit, and anything that
+    /// isn't a number leaves the default
+    fn history_limit(&self, variable: &str, default: usize) -> usize {
+        match self
+            .variable(variable)
+            .map(|value| value.trim().parse::<i64>())
+        {
+            Some(Ok(limit)) => usize::try_from(limit).unwrap_or(usize::MAX),
+            _ => default,
+        }
+    }
+
+    /// Apply `$HISTSIZE`, `$HISTFILESIZE` and `$SHELLY_HISTORY_SEARCH` (`fuzzy`
+    /// or `substring`, for Ctrl+R) to the editor's history
+    fn apply_history_settings(&mut self) {
+        let size = self.history_limit("HISTSIZE", history::DEFAULT_SIZE);
+        let file_size = self.history_limit("HISTFILESIZE", size);
+        let search_mode = match self.variable("SHELLY_HISTORY_SEARCH").as_deref() {
+            Some("fuzzy") => SearchMode::Fuzzy,
+            _ => SearchMode::Substring,
+        };
+        if let Some(editor) = self.editor.as_mut() {
+            let history = editor.history_mut();
+            let _ = history.set_max_len(size);
+            history.set_max_file_len(file_size);
+            history.set_search_mode(search_mode);
+        }
+    }
+
+    /// Apply `$SHELLY_COMPLETION` (`substring` or `fuzzy`; otherwise command
+    /// names match by prefix) to the line editor
+    fn apply_completion_settings(&self) {
+        let mode = match self.variable("SHELLY_COMPLETION").as_deref() {
+            Some("substring") => MatchMode::Substring,
+            Some("fuzzy") => MatchMode::Fuzzy,
+            _ => MatchMode::Prefix,
+        };
+        if let Some(helper) = self.helper() {
+            helper.set_match_mode(mode);
+        }
+    }
+
+    /// Read the history file into the editor's history, counting the
+    /// commands in it for completion to rank
+    fn load_history(&mut self) {
+        self.apply_history_settings();
+        let path = self.history_file();
+        if let (Some(editor), Some(path)) = (self.editor.as_mut(), path) {
+            let _ = editor.load_history(&path);
+        }
+        if let Some(helper) = self.helper() {
+            for entry in self.history() {
+                helper.completion_engine().record_use(&entry.command);
+            }
+        }
+    }
+
+    /// Add the commands entered since the last save to the history file
+    fn save_history(&mut self) {
+        self.apply_history_settings();
+        let path = self.history_file();
+        if let (Some(editor), Some(path)) = (self.editor.as_mut(), path) {
+            let _ = editor.append_history(&path);
+        }
+    }
+
+    /// Remember how long the last command line took, and report it on
+    /// stderr if that was at least `$REPORTTIME` seconds
+    fn record_times(&mut self, times: Times) {
+        self.last_duration = Some(times.real);
+        let threshold = self
+            .variable("REPORTTIME")
+            .and_then(|seconds| seconds.trim().parse::<f64>().ok())
+            .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok());
+        if threshold.is_some_and(|threshold| times.real >= threshold) {
+            let _ = write!(std::io::stderr(), "{}", times.report(TimeFormat::Default));
+        }
+    }
+
+    /// How long the last command line typed at the prompt took to run
+    pub fn last_duration(&self) -> Option<Duration> {
+        self.last_duration
+    }
+
     /// Main REPL (Read-Eval-Print Loop) for the shell
     ///
     /// Continuously reads user input, parses and executes commands,
-    /// and displays output until interrupted or EOF.
-    pub fn run(&mut self) -> Result<(), ShellError> {
+    /// and displays output until EOF (Ctrl+D) or `exit`. Ctrl+C only abandons
+    /// the current line or interrupts the command in the foreground.
+    fn run_interactive(&mut self) -> Result<i32, ShellError> {
         loop {
-            let prompt = "$ ";
-            match self.editor.readline(prompt) {
-                Ok(line) => {
-                    let line = line.trim();
-                    if line.is_empty() {
+            self.notify_finished_jobs();
+            self.apply_completion_settings();
+            self.start_generator();
+            let prompt = self.prompt("PS1", "$ ");
+            let Some(editor) = self.editor.as_mut() else {
+                break;
+            };
+            // The helper's validator keeps an unfinished command open over
+            // several lines
+            match editor.readline(&prompt) {
+                Ok(input) => {
+                    let input = input.trim();
+                    if input.is_empty() {
                         continue;
                     }
+                    let history: Vec<String> = editor
+                        .history()
+                        .iter()
+                        .map(|entry| entry.command.clone())
+                        .collect();
+                    let input = match history::expand(input, &history) {
+                        Ok(Cow::Borrowed(input)) => input.to_string(),
+                        // Show what will run, as bash does
+                        Ok(Cow::Owned(expanded)) => {
+                            let _ = writeln!(std::io::stdout(), "{}", expanded);
+                            expanded
+                        }
+                        Err(e) => {
+                            let _ = writeln!(std::io::stderr(), "{}", e);
+                            self.last_status = 1;
+                            continue;
+                        }
+                    };
+                    let input = input.as_str();
+
+                    // A multi-line buffer is one history entry, so Up recalls the whole block
+                    let _ = editor.add_history_entry(input);
+                    if let Some(helper) = editor.helper() {
+                        helper.completion_engine().record_use(input);
+                    }
 
-                    // Add to history
-                    let _ = self.editor.add_history_entry(line);
+                    signals::take_interrupt();
 
-                    // Parse and execute command
-                    let cmd = CommandParser::parse(line);
-                    match self.execute_command(cmd) {
-                        Ok(output) => {
-                            if !output.is_empty() {
-                                println!("{}", output);
-                            }
+                    for command in complete_commands(input.lines().map(|line| Ok(line.to_string())))
+                    {
+                        let Ok((_, command)) = command else {
+                            break;
+                        };
+                        let command = command.trim();
+                        if !command.is_empty() {
+                            let stopwatch = Stopwatch::start();
+                            self.execute_line(command);
+                            self.record_times(stopwatch.elapsed());
                         }
-                        Err(e) => println!("Error: {}", e),
+                        if self.exit_status.is_some() || signals::interrupted() {
+                            break;
+                        }
+                    }
+                    if signals::take_interrupt() {
+                        self.last_status = 128 + libc::SIGINT;
                     }
 
-                    // Save history after each command
-                    let _ = self.editor.save_history("history.txt");
+                    // Save history after each command, so other sessions can see it
+                    self.save_history();
+                    if self.exit_status.is_some() {
+                        break;
+                    }
+                }
+                // Ctrl+C abandons the line being edited, as in other shells
+                Err(rustyline::error::ReadlineError::Interrupted) => {
+                    self.last_status = 130;
                 }
-                // Handle Ctrl+C or Ctrl+D
-                Err(rustyline::error::ReadlineError::Interrupted)
-                | Err(rustyline::error::ReadlineError::Eof) => {
+                // Ctrl+D at an empty prompt
+                Err(rustyline::error::ReadlineError::Eof) => {
                     break;
                 }
                 Err(e) => {
@@ -88,108 +684,2324 @@ impl Shell {
                 }
             }
         }
-        Ok(())
+
+        // Persist the command index so the next session starts with a warm cache
+        if let Some(helper) = self.helper() {
+            let _ = helper.completion_engine().save_index();
+        }
+        let status = self.logout();
+        if self.flags.huponexit {
+            self.hang_up_jobs();
+        }
+        Ok(status)
+    }
+
+    /// Send SIGHUP to every job not marked with `disown -h`, continuing
+    /// stopped ones so they see it
+    fn hang_up_jobs(&self) {
+        let jobs = self.jobs.read().unwrap();
+        for job in jobs.jobs().iter().filter(|job| !job.no_hangup) {
+            let _ = job.signal(libc::SIGHUP);
+            if job.state == JobState::Stopped {
+                let _ = job.signal(libc::SIGCONT);
+            }
+        }
     }
 
-    /// Execute a built-in command with output/error redirection support
-    fn execute_builtin(&mut self, cmd: &CommandParts) -> Result<String, ShellError> {
-        if let Some(builtin) = self.builtin_registry.get_command(&cmd.command) {
-            let result = builtin.execute(&cmd.args, &self.current_dir)?;
+    /// Read and execute commands from stdin until EOF, without a prompt
+    fn run_stdin(&mut self) -> Result<i32, ShellError> {
+        self.run_lines(stdin_lines())
+    }
+
+    /// Read a script file and execute it, returning its exit status
+    ///
+    /// The shell runs as a plain interpreter: no prompt, history or line
+    /// editor. A `#!` line at the top is an ordinary comment, so scripts can
+    /// name shelly as their interpreter.
+    pub fn run_script(&mut self, path: &Path) -> Result<i32, ShellError> {
+        let contents = std::fs::read_to_string(path)?;
+        self.start_login();
+        self.location.script = Some(path.display().to_string());
+        // The script is `$0` and the arguments after it `$1`, `$2`, ...
+        self.name = path.display().to_string();
+        self.positional = self.options.args.clone();
+        self.run_lines(contents.lines().map(|line| Ok(line.to_string())))
+    }
 
-            // Update current_dir after cd command
-            if cmd.command == "cd" {
-                self.current_dir = std::env::current_dir().unwrap_or(self.current_dir.clone());
+    /// Execute non-interactive input line by line, returning the final status
+    ///
+    /// Lines are gathered until they form complete commands, so a function
+    /// body or here-document can span several lines; errors name the line a
+    /// command started on.
+    fn run_lines(
+        &mut self,
+        lines: impl Iterator<Item = std::io::Result<String>>,
+    ) -> Result<i32, ShellError> {
+        for command in complete_commands(lines) {
+            let (line, input) = command?;
+            self.run_input(line, &input);
+            if self.exit_status.is_some() {
+                return Ok(self.logout());
             }
+        }
+        self.notify_finished_jobs();
+        Ok(self.logout())
+    }
 
-            // Handle output/error redirection
-            match (&cmd.output_redirect, &cmd.error_redirect) {
-                (Some((path, append)), _) => {
-                    // Redirect stdout to file
-                    let mut file = if *append {
-                        std::fs::OpenOptions::new()
-                            .append(true)
-                            .create(true)
-                            .open(path)?
-                    } else {
-                        std::fs::File::create(path)?
-                    };
-                    writeln!(file, "{}", result)?;
-                    Ok(String::new())
+    /// Run the commands of the file at `path` in this shell, for `source`
+    ///
+    /// Variables, aliases, functions and directory changes persist
+    /// afterwards. Given `args`, the file sees them as its positional
+    /// parameters. `return` leaves the file early. Returns the status of the
+    /// last command run, or 0 if there was none.
+    pub fn source(&mut self, path: &Path, args: Option<Vec<String>>) -> std::io::Result<i32> {
+        let contents = std::fs::read_to_string(path)?;
+        let caller_args = args.map(|args| std::mem::replace(&mut self.positional, args));
+        let caller_location = std::mem::replace(
+            &mut self.location,
+            Location {
+                script: Some(path.display().to_string()),
+                line: None,
+            },
+        );
+        let caller_loops = std::mem::take(&mut self.loop_depth);
+        self.source_depth += 1;
+        self.last_status = 0;
+
+        for command in complete_commands(contents.lines().map(|line| Ok(line.to_string()))) {
+            let Ok((line, input)) = command else {
+                break;
+            };
+            self.location.line = Some(line);
+            let input = input.trim();
+            if !input.is_empty() {
+                self.execute_line(input);
+            }
+            if self.unwinding() {
+                break;
+            }
+        }
+
+        self.source_depth -= 1;
+        self.loop_depth = caller_loops;
+        self.location = caller_location;
+        if let Some(args) = caller_args {
+            self.positional = args;
+        }
+        if let Some(status) = self.returning.take() {
+            self.last_status = status;
+        }
+        Ok(self.last_status)
+    }
+
+    /// Execute one complete piece of non-interactive input starting at `line`
+    fn run_input(&mut self, line: usize, input: &str) {
+        self.location.line = Some(line);
+        let input = input.trim();
+        if !input.is_empty() {
+            self.execute_line(input);
+        }
+        self.notify_finished_jobs();
+    }
+
+    /// Status the shell exits with: the one given to `exit`, else the last command's
+    fn final_status(&self) -> i32 {
+        self.exit_status.unwrap_or(self.last_status)
+    }
+
+    /// The completion helper, if the shell is interactive
+    fn helper(&self) -> Option<&RustylineHelper> {
+        self.editor.as_ref().and_then(|editor| editor.helper())
+    }
+
+    /// Run a command string non-interactively (used by `-c`), returning its exit status
+    pub fn run_command_string(&mut self, input: &str) -> Result<i32, ShellError> {
+        self.start_login();
+        // `shelly -c CMD NAME ARGS...` runs CMD with `$0` set to NAME
+        if let Some((name, args)) = self.options.args.split_first() {
+            self.name = name.clone();
+            self.positional = args.to_vec();
+        }
+        self.run_lines(input.lines().map(|line| Ok(line.to_string())))
+    }
+
+    /// Parse and execute one line of input, printing its output or error
+    ///
+    /// Errors go to stderr. When running a script or other non-interactive
+    /// input they're prefixed with the line (and script) they came from.
+    fn execute_line(&mut self, line: &str) {
+        let line = expand::expand_aliases(line, &self.aliases);
+        match CommandParser::parse(&line) {
+            Ok(lists) => self.execute_list(&lists),
+            Err(e) => self.report_syntax_error(&line, e),
+        }
+    }
+
+    /// Print a syntax error in `input`, none of which is run
+    ///
+    /// An interactive shell shows the offending line with a caret under the
+    /// error; any other shell exits, as bash does.
+    fn report_syntax_error(&mut self, input: &str, e: ParseError) {
+        let start = input[..e.span.start].rfind('\n').map_or(0, |i| i + 1);
+        let end = input[start..].find('\n').map_or(input.len(), |i| start + i);
+        let column = input[start..e.span.start].chars().count();
+        // A multi-line command's error is on the line the token is on
+        let location = Location {
+            line: (self.location.line).map(|line| line + input[..start].matches('\n').count()),
+            ..self.location.clone()
+        };
+        let mut error = ShellError::from(e);
+        if location.line.is_some() {
+            error = error.at(location);
+        }
+        let mut stderr = std::io::stderr();
+        let _ = writeln!(stderr, "{}", error);
+        if self.is_interactive() {
+            let _ = writeln!(stderr, "{}\n{}^", &input[start..end], " ".repeat(column));
+        } else {
+            self.request_exit(error.exit_status());
+        }
+        self.last_status = error.exit_status();
+    }
+
+    /// Run `;`-separated lists in order, stopping early on `exit` or `return`
+    fn execute_list(&mut self, lists: &[AndOrList]) {
+        for list in lists {
+            if self.unwinding() {
+                break;
+            }
+            self.execute_and_or(list);
+        }
+    }
+
+    /// Run `body` in a forked copy of the shell, so nothing it changes
+    /// (variables, the directory, options, an `exit`) reaches this one
+    ///
+    /// The copy applies `redirects` to its own descriptors, so they work for
+    /// every fd, in order. Unless it can print straight to stdout (`direct`,
+    /// with no output being collected) its stdout comes back through a
+    /// pipe: collected here if `direct`, otherwise returned for the next
+    /// stage.
+    fn execute_subshell(
+        &mut self,
+        body: &[AndOrList],
+        redirects: &[redirect::OpenRedirect],
+        direct: bool,
+    ) -> Result<Vec<u8>, ShellError> {
+        let pipe = if direct && self.captures.is_empty() {
+            None
+        } else {
+            Some(std::io::pipe()?)
+        };
+        let _ = std::io::stdout().flush();
+        // SAFETY: the child only uses its own copy of the shell and leaves
+        // with `_exit`, never returning into the caller
+        let pid = unsafe { libc::fork() };
+        if pid == -1 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        if pid == 0 {
+            if let Some((reader, writer)) = pipe {
+                drop(reader);
+                // SAFETY: dup2 onto this process's stdout from the pipe it owns
+                unsafe { libc::dup2(writer.as_raw_fd(), 1) };
+            }
+            let status = match redirect::apply(redirects) {
+                Ok(()) => {
+                    self.detach();
+                    self.execute_list(body);
+                    self.final_status()
                 }
-                (_, Some((path, _))) => {
-                    // Create error redirect file (built-ins don't typically write to stderr)
-                    let _ = std::fs::File::create(path);
-                    Ok(result)
+                Err(e) => {
+                    eprintln!("{}", self.locate(e.into()));
+                    1
                 }
-                _ => Ok(result),
+            };
+            let _ = std::io::stdout().flush();
+            // SAFETY: ends the forked child without running the parent's cleanup
+            unsafe { libc::_exit(status) };
+        }
+        let mut output = Vec::new();
+        if let Some((mut reader, writer)) = pipe {
+            drop(writer);
+            reader.read_to_end(&mut output)?;
+        }
+        self.last_status = wait_exit(pid as u32);
+        if direct {
+            self.write_output(&output);
+            output.clear();
+        }
+        Ok(output)
+    }
+
+    /// Run the body of the first branch whose condition succeeds, or the
+    /// `else` body if none does
+    ///
+    /// The status is that of the body that ran, or 0 if none did.
+    fn execute_if(&mut self, branches: &[Branch], else_body: Option<&[AndOrList]>) {
+        for branch in branches {
+            self.execute_condition(&branch.condition);
+            if self.unwinding() {
+                return;
             }
-        } else {
-            Ok(String::new())
+            if self.last_status == 0 {
+                self.execute_list(&branch.body);
+                return;
+            }
+        }
+        match else_body {
+            Some(body) => self.execute_list(body),
+            None => self.last_status = 0,
         }
     }
 
-    /// Execute an external command (not a built-in)
+    /// Whether `exit`, `return`, `break`/`continue` or Ctrl+C asked to skip
+    /// the rest of the commands
+    fn unwinding(&self) -> bool {
+        self.exit_status.is_some()
+            || self.returning.is_some()
+            || self.loop_control.is_some()
+            || signals::interrupted()
+    }
+
+    /// Run the body of the first `case` item with a pattern matching `subject`
     ///
-    /// Spawns a child process and waits for it to complete.
-    /// Handles stdout and stderr redirection if specified.
-    fn execute_external(&self, cmd: &CommandParts) -> Result<String, ShellError> {
-        let mut process = std::process::Command::new(&cmd.command);
-        process.args(&cmd.args).current_dir(&self.current_dir);
+    /// `patterns` holds each item's expanded patterns. The status is that of
+    /// the body that ran, or 0 if no pattern matched.
+    fn execute_case(&mut self, subject: &str, items: &[CaseItem], patterns: &[Vec<String>]) {
+        self.last_status = 0;
+        let matched = items.iter().zip(patterns).find(|(_, patterns)| {
+            patterns
+                .iter()
+                .any(|pattern| glob::matches(pattern, subject))
+        });
+        if let Some((item, _)) = matched {
+            self.execute_list(&item.body);
+        }
+    }
 
-        // Set up stdout redirection if specified
-        if let Some((path, append)) = &cmd.output_redirect {
-            let file = if *append {
-                std::fs::OpenOptions::new()
-                    .append(true)
-                    .create(true)
-                    .open(path)?
-            } else {
-                std::fs::File::create(path)?
-            };
-            process.stdout(file);
+    /// Evaluate a `[[ ... ]]` expression, setting the status to 0 if it's
+    /// true and 1 if it's false
+    fn execute_conditional(&mut self, condition: &Condition) -> Result<(), ShellError> {
+        let result = self.evaluate_condition(condition)?;
+        self.last_status = i32::from(!result);
+        Ok(())
+    }
+
+    /// Whether `condition` holds, expanding only the words it needs
+    ///
+    /// `==` and `!=` match the right side as a glob pattern, and `=~` as a
+    /// regular expression whose match and groups are stored in the array
+    /// `BASH_REMATCH`
+    fn evaluate_condition(&mut self, condition: &Condition) -> Result<bool, ShellError> {
+        Ok(match condition {
+            Condition::Word(word) => !expand::expand_assignment(self, word)?.is_empty(),
+            Condition::Unary { operator, operand } => {
+                if !builtin::is_unary_test(operator) {
+                    return Err(ShellError::BuiltinError {
+                        name: "[[".to_string(),
+                        status: 2,
+                        message: format!("{}: unary operator expected", operator),
+                    });
+                }
+                builtin::unary_test(operator, &expand::expand_assignment(self, operand)?)
+            }
+            Condition::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                let left = expand::expand_assignment(self, left)?;
+                match operator.as_str() {
+                    "=" | "==" => glob::matches(&expand::expand_pattern(self, right)?, &left),
+                    "!=" => !glob::matches(&expand::expand_pattern(self, right)?, &left),
+                    "=~" => {
+                        let pattern = expand::expand_regex(self, right)?;
+                        self.match_regex(&pattern, &left)?
+                    }
+                    op => {
+                        let right = expand::expand_assignment(self, right)?;
+                        builtin::binary_test("[[", &left, op, &right)?
+                    }
+                }
+            }
+            Condition::Not(condition) => !self.evaluate_condition(condition)?,
+            Condition::And(left, right) => {
+                self.evaluate_condition(left)? && self.evaluate_condition(right)?
+            }
+            Condition::Or(left, right) => {
+                self.evaluate_condition(left)? || self.evaluate_condition(right)?
+            }
+        })
+    }
+
+    /// Match `text` against the regular expression `pattern` for `=~`,
+    /// making `BASH_REMATCH` an array of the match and its groups (empty if
+    /// it didn't match)
+    fn match_regex(&mut self, pattern: &str, text: &str) -> Result<bool, ShellError> {
+        let groups =
+            regex::captures(pattern, text).map_err(|message| ShellError::BuiltinError {
+                name: "[[".to_string(),
+                status: 2,
+                message: format!("{}: {}", pattern, message),
+            })?;
+        let matched = groups.is_some();
+        let groups = groups
+            .unwrap_or_default()
+            .into_iter()
+            .map(Option::unwrap_or_default);
+        self.store_array("BASH_REMATCH", groups.enumerate().collect());
+        Ok(matched)
+    }
+
+    /// Run `body` once for each of `values`, assigned in turn to `variable`
+    ///
+    /// The status is that of the last command run, or 0 if the body never ran.
+    fn execute_for(&mut self, variable: &str, values: Vec<String>, body: &[AndOrList]) {
+        self.last_status = 0;
+        self.loop_depth += 1;
+        for value in values {
+            if let Err(e) = self.set_variable(variable, &value) {
+                self.last_status = self.report_error(e);
+                break;
+            }
+            self.execute_list(body);
+            if !self.next_iteration() {
+                break;
+            }
         }
+        self.loop_depth -= 1;
+    }
 
-        // Set up stderr redirection if specified
-        if let Some((path, append)) = &cmd.error_redirect {
-            let file = if *append {
-                std::fs::OpenOptions::new()
-                    .append(true)
-                    .create(true)
-                    .open(path)?
-            } else {
-                std::fs::File::create(path)?
-            };
-            process.stderr(file);
+    /// Run `body` for as long as `condition` succeeds
+    ///
+    /// The status is that of the last body command run, or 0 if the body never ran.
+    fn execute_while(&mut self, condition: &[AndOrList], body: &[AndOrList]) {
+        let mut status = 0;
+        self.loop_depth += 1;
+        loop {
+            self.execute_condition(condition);
+            if self.last_status != 0 && !self.unwinding() {
+                break;
+            }
+            if !self.next_iteration() {
+                // `break` in the condition ends the loop with its status
+                status = self.last_status;
+                break;
+            }
+            self.execute_list(body);
+            status = self.last_status;
+            if !self.next_iteration() {
+                break;
+            }
+        }
+        self.loop_depth -= 1;
+        if !self.unwinding() {
+            self.last_status = status;
+        }
+    }
+
+    /// Whether the innermost loop should carry on after running its body,
+    /// consuming a `break`/`continue` aimed at it
+    fn next_iteration(&mut self) -> bool {
+        match self.loop_control.take() {
+            None => !self.unwinding(),
+            Some(LoopControl::Continue(1)) => !self.unwinding(),
+            Some(LoopControl::Break(1)) => false,
+            Some(LoopControl::Break(levels)) => {
+                self.loop_control = Some(LoopControl::Break(levels - 1));
+                false
+            }
+            Some(LoopControl::Continue(levels)) => {
+                self.loop_control = Some(LoopControl::Continue(levels - 1));
+                false
+            }
         }
+    }
+
+    /// Run the condition of an `if` or `while`, where `set -e` doesn't apply
+    fn execute_condition(&mut self, condition: &[AndOrList]) {
+        self.errexit_ignored += 1;
+        self.execute_list(condition);
+        self.errexit_ignored -= 1;
+    }
 
-        // Spawn process and wait for completion
-        match process.spawn() {
-            Ok(mut child) => {
-                child
-                    .wait()
-                    .map_err(|e| ShellError::ExecutionError(e.to_string()))?;
-                Ok(String::new())
+    /// Run pipelines joined with `&&`/`||`, skipping those the last status rules out
+    fn execute_and_or(&mut self, list: &AndOrList) {
+        self.run_and_or_operand(&list.first, list.rest.is_empty());
+        for (index, (connector, pipeline)) in list.rest.iter().enumerate() {
+            if self.unwinding() {
+                break;
             }
-            Err(_) => {
-                println!("{}: command not found", cmd.command);
-                Ok(String::new())
+            let succeeded = self.last_status == 0;
+            let last = index + 1 == list.rest.len();
+            match connector {
+                Connector::And if succeeded => self.run_and_or_operand(pipeline, last),
+                Connector::Or if !succeeded => self.run_and_or_operand(pipeline, last),
+                _ => {}
             }
         }
     }
 
-    /// Execute a command, dispatching to either built-in or external execution
+    /// Run one pipeline of an `&&`/`||` list
     ///
-    /// Built-in commands are checked first for efficiency.
-    fn execute_command(&mut self, cmd: CommandParts) -> Result<String, ShellError> {
-        if cmd.command.is_empty() {
-            return Ok(String::new());
+    /// With `set -e`, the shell exits if the `last` pipeline fails, unless
+    /// it's negated with `!` or inside a condition. Failures anywhere in the
+    /// other pipelines are left for the list to handle.
+    fn run_and_or_operand(&mut self, pipeline: &Pipeline, last: bool) {
+        let checked = last && !pipeline.negated;
+        if !checked {
+            self.errexit_ignored += 1;
+        }
+        self.run_pipeline(pipeline);
+        if !checked {
+            self.errexit_ignored -= 1;
+        } else if self.flags.errexit
+            && self.errexit_ignored == 0
+            && self.last_status != 0
+            && !self.unwinding()
+        {
+            self.request_exit(self.last_status);
+        }
+    }
+
+    /// Run a pipeline, printing its output or error and recording its status
+    ///
+    /// A pipeline run with `time` reports how long it took on stderr.
+    fn run_pipeline(&mut self, pipeline: &Pipeline) {
+        let stopwatch = pipeline.timed.map(|format| (format, Stopwatch::start()));
+        match self.execute_pipeline(pipeline) {
+            Ok(output) => self.write_output(&output),
+            Err(e) => {
+                // A non-interactive shell gives up on an unset variable under
+                // `set -u`, or one `${NAME:?}` requires
+                let unset = matches!(
+                    e,
+                    ShellError::UnboundVariable(_) | ShellError::ParameterNotSet { .. }
+                );
+                if unset && !self.is_interactive() {
+                    self.request_exit(127);
+                }
+                self.last_status = self.report_error(e);
+            }
+        }
+        if pipeline.negated {
+            self.last_status = i32::from(self.last_status == 0);
+        }
+        if let Some((format, stopwatch)) = stopwatch {
+            let _ = write!(std::io::stderr(), "{}", stopwatch.elapsed().report(format));
+        }
+    }
+
+    /// Write command output to stdout, or to the output being collected
+    fn write_output(&mut self, output: &[u8]) {
+        match self.captures.last_mut() {
+            Some(buffer) => buffer.extend_from_slice(output),
+            None => {
+                let mut stdout = std::io::stdout().lock();
+                let _ = stdout.write_all(output);
+                let _ = stdout.flush();
+            }
+        }
+    }
+
+    /// Print a command error to stderr and return the status it maps to
+    ///
+    /// A command that isn't found gets a suggestion if a known name is
+    /// close to it.
+    fn report_error(&self, e: ShellError) -> i32 {
+        self.write_error(e, &mut std::io::stderr())
+    }
+
+    /// Write a command error, and any suggestion, to `stderr` and return the
+    /// status it maps to
+    fn write_error(&self, e: ShellError, stderr: &mut dyn Write) -> i32 {
+        let e = self.locate(e);
+        let _ = writeln!(stderr, "{}", e);
+        let suggestion = e
+            .missing_command()
+            .and_then(|name| self.spelling_suggestion(name, true));
+        if let Some(suggestion) = suggestion {
+            let _ = writeln!(stderr, "shelly: did you mean `{}`?", suggestion);
+        }
+        e.exit_status()
+    }
+
+    /// Report that `cmd` couldn't be started on its own fd 2, as its
+    /// redirections leave it, the way a builtin's errors are, and set the
+    /// status
+    ///
+    /// Returns what ended up on its stdout (with `2>&1`), for the next stage.
+    fn report_spawn_error(&mut self, cmd: &ExpandedCommand, e: ShellError) -> Vec<u8> {
+        let stderr = match open_redirections(&cmd.redirects) {
+            Ok(opened) => BuiltinSink::for_stdio(opened).1,
+            Err(_) => Some(BuiltinSink::Stderr),
+        };
+        let mut message = Vec::new();
+        self.last_status = self.write_error(e, &mut message);
+        stderr
+            .map_or(Ok(Vec::new()), |sink| sink.deliver(message))
+            .unwrap_or_default()
+    }
+
+    /// The command name closest to the mistyped `name`, from builtins,
+    /// functions, PATH and, if `aliases` is set, aliases
+    ///
+    /// Only interactive shells suggest anything, from the line editor's
+    /// command index; scripts would have to rescan PATH on every typo.
+    /// Among names as close as each other, the most used wins.
+    fn spelling_suggestion(&self, name: &str, aliases: bool) -> Option<String> {
+        let engine = self.helper()?.completion_engine();
+        if name.contains('/') {
+            return None;
         }
+        let mut names = engine.commands("");
+        if !aliases {
+            names.retain(|name| !self.aliases.contains_key(name));
+        }
+        names.sort_by_key(|name| std::cmp::Reverse(engine.uses(name)));
+        completion::spelling_match(name, &names).map(str::to_string)
+    }
+
+    /// Offer to run a mistyped command as the name closest to it, when
+    /// `$SHELLY_CORRECT` is set (to anything but empty or `0`) in an
+    /// interactive shell
+    ///
+    /// Aliases aren't offered, since they're expanded before commands are
+    /// looked up. The answer is read from the terminal; `y` runs the
+    /// correction.
+    fn correct_command(&self, mut cmd: ExpandedCommand) -> ExpandedCommand {
+        let enabled = self
+            .variable("SHELLY_CORRECT")
+            .is_some_and(|value| !value.is_empty() && value != "0");
+        let known = self.functions.contains_key(&cmd.command)
+            || self.builtin_registry.is_builtin(&cmd.command)
+            || lookup::find_in_path(&cmd.command).is_some();
+        if !enabled || known || !self.is_interactive() || !std::io::stdin().is_terminal() {
+            return cmd;
+        }
+        let Some(correction) = self.spelling_suggestion(&cmd.command, false) else {
+            return cmd;
+        };
+        let mut stderr = std::io::stderr();
+        let _ = write!(
+            stderr,
+            "shelly: correct `{}` to `{}` [y/N]? ",
+            cmd.command, correction
+        );
+        let _ = stderr.flush();
+        let mut answer = String::new();
+        let _ = std::io::stdin().read_line(&mut answer);
+        if answer.trim().eq_ignore_ascii_case("y") {
+            cmd.command = correction;
+        }
+        cmd
+    }
 
-        // Check if it's a built-in command first
-        if self.builtin_registry.is_builtin(&cmd.command) {
-            self.execute_builtin(&cmd)
+    /// Attach the current line (and script) to an error, if it's known
+    fn locate(&self, e: ShellError) -> ShellError {
+        if self.location.line.is_some() {
+            e.at(self.location.clone())
         } else {
-            self.execute_external(&cmd)
+            e
         }
     }
+
+    /// Whether the output of a background job that isn't redirected is
+    /// collected in its [`JobOutput`] rather than going straight to the
+    /// terminal
+    ///
+    /// Only an interactive shell with `$SHELLY_JOB_OUTPUT` set to `buffer`
+    /// collects it (for `jobs -o`, `fg` and the `Done` notice), or a job
+    /// whose output belongs to an enclosing capture.
+    fn buffers_job_output(&self) -> bool {
+        !self.captures.is_empty()
+            || (self.is_interactive()
+                && self.variable("SHELLY_JOB_OUTPUT").as_deref() == Some("buffer"))
+    }
+
+    /// Reap background jobs that have finished and report them
+    ///
+    /// Interactive shells print a `Done` notice with the job's captured
+    /// output; other shells just pass the output through to stdout.
+    fn notify_finished_jobs(&mut self) {
+        let finished = {
+            let mut jobs = self.jobs.write().unwrap();
+            jobs.poll();
+            jobs.take_finished()
+        };
+        let interactive = self.is_interactive();
+        let mut stdout = std::io::stdout().lock();
+        for job in finished {
+            if interactive {
+                let _ = writeln!(stdout, "[{}]  {}  {}", job.id, job.state, job.command);
+            }
+            job.output.drain();
+            let _ = stdout.write_all(&job.output.take());
+        }
+        let _ = stdout.flush();
+    }
+
+    /// Process ID of the most recently started background job (`$!`)
+    pub fn last_background_pid(&self) -> Option<u32> {
+        self.last_background_pid
+    }
+
+    /// Exit status of the last command that ran (`$?`)
+    pub fn last_status(&self) -> i32 {
+        self.last_status
+    }
+
+    /// Value of the parameter `name`, or `None` if it is unset
+    ///
+    /// Special parameters are `?` (last exit status), `$` (the shell's process
+    /// ID, which subshells share), `!` (process ID of the last background
+    /// job), `-` (the letters of the options set, with `i` if interactive),
+    /// `0` (the shell's name), and the positional parameters: `1`, `2`, ...,
+    /// `#` (how many) and `@`/`*` (all of them). Any other name is looked up
+    /// as a variable.
+    pub fn param(&self, name: &str) -> Option<String> {
+        match name {
+            "?" => Some(self.last_status.to_string()),
+            "$" => Some(self.pid.to_string()),
+            "!" => self.last_background_pid.map(|pid| pid.to_string()),
+            "-" => {
+                let letters = self.flags.list().into_iter();
+                let mut set: String = letters
+                    .filter_map(|(_, letter, on)| letter.filter(|_| on))
+                    .collect();
+                if self.is_interactive() {
+                    set.push('i');
+                }
+                Some(set)
+            }
+            "0" => Some(self.name.clone()),
+            "#" => Some(self.positional.len().to_string()),
+            "@" | "*" => Some(self.positional.join(" ")),
+            _ if name.bytes().all(|b| b.is_ascii_digit()) => name
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| self.positional.get(n.checked_sub(1)?).cloned()),
+            _ => self.variable(name),
+        }
+    }
+
+    /// Value of the variable `name`, local to the shell or exported
+    ///
+    /// For an array, this is element 0, as `$NAME` gives.
+    pub fn variable(&self, name: &str) -> Option<String> {
+        match self.variables.get(name) {
+            Some(value) => value.scalar().map(str::to_string),
+            None => std::env::var(name).ok(),
+        }
+    }
+
+    /// The whole value of the variable `name`, array or not
+    pub fn value(&self, name: &str) -> Option<Value> {
+        self.variables
+            .get(name)
+            .cloned()
+            .or_else(|| std::env::var(name).ok().map(Value::Scalar))
+    }
+
+    /// Element `index` of the array `name`, counting back from the end if
+    /// negative; a variable that isn't an array only has element 0
+    pub fn element(&self, name: &str, index: i64) -> Option<String> {
+        let elements = self.value(name)?.into_array();
+        let index = if index < 0 {
+            let last = *elements.keys().next_back()?;
+            last.checked_add_signed(index as isize + 1)?
+        } else {
+            usize::try_from(index).ok()?
+        };
+        elements.get(&index).cloned()
+    }
+
+    /// Every variable, shell-local and exported, sorted by name
+    pub fn variables(&self) -> Vec<(String, Value)> {
+        let mut variables: HashMap<String, Value> = std::env::vars()
+            .map(|(name, value)| (name, Value::Scalar(value)))
+            .collect();
+        variables.extend(self.variables.clone());
+        let mut variables: Vec<_> = variables.into_iter().collect();
+        variables.sort_by(|(a, _), (b, _)| a.cmp(b));
+        variables
+    }
+
+    /// Assign a variable, keeping it exported if it already was
+    ///
+    /// A readonly variable is left as it is, with an error. Assigning PATH
+    /// empties the command hash.
+    pub fn set_variable(&mut self, name: &str, value: &str) -> Result<(), ShellError> {
+        if self.readonly.contains(name) {
+            return Err(ShellError::ReadonlyVariable(name.to_string()));
+        }
+        self.assign_variable(name, value);
+        Ok(())
+    }
+
+    /// Assign a variable whether or not it's readonly, for the values the
+    /// shell sets itself
+    fn assign_variable(&mut self, name: &str, value: &str) {
+        if name == "PATH" {
+            self.hashed.write().unwrap().clear();
+        }
+        if let Some(Value::Array(elements)) = self.variables.get_mut(name) {
+            elements.insert(0, value.to_string());
+        } else if std::env::var_os(name).is_some() || self.shadows_export(name) {
+            std::env::set_var(name, value);
+        } else {
+            if let Some(helper) = self.helper() {
+                helper.completion_engine().add_variable(name);
+            }
+            self.variables
+                .insert(name.to_string(), Value::Scalar(value.to_string()));
+        }
+    }
+
+    /// Assign element `index` of the array `name`, making it an array if it
+    /// isn't one; a negative index counts back from the end
+    ///
+    /// An exported variable made an array stops being exported.
+    pub fn set_element(&mut self, name: &str, index: i64, value: &str) -> Result<(), ShellError> {
+        if self.readonly.contains(name) {
+            return Err(ShellError::ReadonlyVariable(name.to_string()));
+        }
+        let mut elements = self.value(name).map(Value::into_array).unwrap_or_default();
+        let position = if index < 0 {
+            let end = elements.keys().next_back().map_or(0, |last| last + 1);
+            end.checked_add_signed(index as isize)
+        } else {
+            usize::try_from(index).ok()
+        };
+        let Some(position) = position else {
+            return Err(ShellError::ExpansionError(format!(
+                "{}[{}]: bad array subscript",
+                name, index
+            )));
+        };
+        elements.insert(position, value.to_string());
+        self.store_array(name, elements);
+        Ok(())
+    }
+
+    /// Make `name` an array of `values`, or add them after its last element
+    /// if `append` is set
+    pub fn set_array(
+        &mut self,
+        name: &str,
+        values: Vec<String>,
+        append: bool,
+    ) -> Result<(), ShellError> {
+        if self.readonly.contains(name) {
+            return Err(ShellError::ReadonlyVariable(name.to_string()));
+        }
+        let mut elements = if append {
+            self.value(name).map(Value::into_array).unwrap_or_default()
+        } else {
+            BTreeMap::new()
+        };
+        let start = elements.keys().next_back().map_or(0, |last| last + 1);
+        elements.extend((start..).zip(values));
+        self.store_array(name, elements);
+        Ok(())
+    }
+
+    /// Keep an array in the shell, in place of any value `name` had
+    fn store_array(&mut self, name: &str, elements: BTreeMap<usize, String>) {
+        if name == "PATH" {
+            self.hashed.write().unwrap().clear();
+        }
+        std::env::remove_var(name);
+        if let Some(helper) = self.helper() {
+            helper.completion_engine().add_variable(name);
+        }
+        self.variables
+            .insert(name.to_string(), Value::Array(elements));
+    }
+
+    /// Remove a variable from the shell and the environment, returning
+    /// whether it was set
+    ///
+    /// It stops completing, and unsetting PATH empties the command hash.
+    /// Readonly variables can't be unset.
+    pub fn unset_variable(&mut self, name: &str) -> Result<bool, ShellError> {
+        if self.readonly.contains(name) {
+            return Err(ShellError::ReadonlyVariable(name.to_string()));
+        }
+        if name == "PATH" {
+            self.hashed.write().unwrap().clear();
+        }
+        let exported = std::env::var_os(name).is_some();
+        if exported {
+            std::env::remove_var(name);
+        }
+        let local = self.variables.remove(name).is_some();
+        if let Some(helper) = self.helper() {
+            helper.completion_engine().remove_variable(name);
+        }
+        Ok(exported || local)
+    }
+
+    /// Remove element `index` of the array `name`, counting back from the
+    /// end if it's negative, returning whether it was set
+    ///
+    /// Element 0 of a plain variable is the variable itself.
+    pub fn unset_element(&mut self, name: &str, index: i64) -> Result<bool, ShellError> {
+        if self.readonly.contains(name) {
+            return Err(ShellError::ReadonlyVariable(name.to_string()));
+        }
+        match self.value(name) {
+            Some(Value::Array(mut elements)) => {
+                let position = if index < 0 {
+                    let last = elements.keys().next_back().copied();
+                    last.and_then(|last| last.checked_add_signed(index as isize + 1))
+                } else {
+                    usize::try_from(index).ok()
+                };
+                let removed = position.is_some_and(|position| elements.remove(&position).is_some());
+                self.store_array(name, elements);
+                Ok(removed)
+            }
+            Some(Value::Scalar(_)) if index == 0 || index == -1 => self.unset_variable(name),
+            _ => Ok(false),
+        }
+    }
+
+    /// Make `name` local to the running function, so the value it had is put
+    /// back when the function returns
+    ///
+    /// The variable starts out unset unless given a `value`, which may be an
+    /// array, and `readonly` makes it readonly until the function returns.
+    /// Declaring it again in the same call keeps its value. A local
+    /// shadowing an exported variable is exported too.
+    pub fn declare_local(
+        &mut self,
+        name: &str,
+        value: Option<Value>,
+        readonly: bool,
+    ) -> Result<(), ShellError> {
+        if self.readonly.contains(name) {
+            return Err(ShellError::ReadonlyVariable(name.to_string()));
+        }
+        let Some(scope) = self.scopes.last_mut() else {
+            return Err(ShellError::BuiltinError {
+                name: "local".to_string(),
+                status: 1,
+                message: "can only be used in a function".to_string(),
+            });
+        };
+        if !scope.iter().any(|saved| saved.name == name) {
+            scope.push(SavedVariable {
+                name: name.to_string(),
+                local: self.variables.get(name).cloned(),
+                exported: std::env::var_os(name),
+            });
+            self.unset_variable(name)?;
+        }
+        match value {
+            Some(Value::Scalar(value)) => self.assign_variable(name, &value),
+            Some(Value::Array(elements)) => self.store_array(name, elements),
+            None => {}
+        }
+        if readonly {
+            self.readonly.insert(name.to_string());
+        }
+        Ok(())
+    }
+
+    /// Take the elements of the running builtin's next `name=(a b c)`
+    /// argument, whose word reached it as `name=`
+    pub fn take_array_arg(&mut self, name: &str) -> Option<Vec<String>> {
+        let position = self.array_args.iter().position(|(arg, _)| arg == name)?;
+        Some(self.array_args.remove(position).1)
+    }
+
+    /// Whether `name` is a local of the running function hiding an exported
+    /// variable, so assigning it exports the local value
+    fn shadows_export(&self, name: &str) -> bool {
+        self.scopes.last().is_some_and(|scope| {
+            scope
+                .iter()
+                .any(|saved| saved.name == name && saved.exported.is_some())
+        })
+    }
+
+    /// The variables `local` declared in the running function, with their
+    /// values if they're set, or `None` outside a function
+    pub fn locals(&self) -> Option<Vec<(String, Option<Value>)>> {
+        let scope = self.scopes.last()?;
+        Some(
+            scope
+                .iter()
+                .map(|saved| (saved.name.clone(), self.value(&saved.name)))
+                .collect(),
+        )
+    }
+
+    /// Put back a variable saved as its shell-local value and its value in
+    /// the environment
+    fn restore_variable(&mut self, name: &str, local: Option<Value>, exported: Option<OsString>) {
+        if name == "PATH" {
+            self.hashed.write().unwrap().clear();
+        }
+        let set = exported.is_some() || local.is_some();
+        match exported {
+            Some(value) => std::env::set_var(name, value),
+            None => std::env::remove_var(name),
+        }
+        match local {
+            Some(value) => self.variables.insert(name.to_string(), value),
+            None => self.variables.remove(name),
+        };
+        if let Some(helper) = self.helper() {
+            if set {
+                helper.completion_engine().add_variable(name);
+            } else {
+                helper.completion_engine().remove_variable(name);
+            }
+        }
+    }
+
+    /// Export `name` so child processes see it, assigning `value` first if given
+    ///
+    /// Exporting a variable that isn't set does nothing.
+    pub fn export_variable(&mut self, name: &str, value: Option<&str>) -> Result<(), ShellError> {
+        if value.is_some() && self.readonly.contains(name) {
+            return Err(ShellError::ReadonlyVariable(name.to_string()));
+        }
+        self.export_value(name, value);
+        Ok(())
+    }
+
+    /// Export a variable whether or not it's readonly, for the values the
+    /// shell sets itself
+    fn export_value(&mut self, name: &str, value: Option<&str>) {
+        if name == "PATH" && value.is_some() {
+            self.hashed.write().unwrap().clear();
+        }
+        // Arrays stay in the shell; only their element 0 can be assigned
+        if let Some(Value::Array(elements)) = self.variables.get_mut(name) {
+            if let Some(value) = value {
+                elements.insert(0, value.to_string());
+            }
+            return;
+        }
+        let local = self.variables.remove(name);
+        let local = local.and_then(|local| local.scalar().map(str::to_string));
+        if let Some(value) = value.map(str::to_string).or(local) {
+            std::env::set_var(name, value);
+        }
+    }
+
+    /// Make `name` readonly, assigning `value` first if given
+    ///
+    /// Marking a variable that's already readonly again is fine, as long
+    /// as it isn't given a new value.
+    pub fn make_readonly(&mut self, name: &str, value: Option<&str>) -> Result<(), ShellError> {
+        if let Some(value) = value {
+            self.set_variable(name, value)?;
+        }
+        self.readonly.insert(name.to_string());
+        Ok(())
+    }
+
+    /// The readonly variables in name order, with their values if they're set
+    pub fn readonly_variables(&self) -> Vec<(String, Option<Value>)> {
+        let mut names: Vec<&String> = self.readonly.iter().collect();
+        names.sort();
+        names
+            .into_iter()
+            .map(|name| (name.clone(), self.value(name)))
+            .collect()
+    }
+
+    /// The options set with `set`
+    pub fn flags(&self) -> OptionFlags {
+        self.flags
+    }
+
+    /// The options set with `set`, for changing
+    pub fn flags_mut(&mut self) -> &mut OptionFlags {
+        &mut self.flags
+    }
+
+    /// The positional parameters, `$1` onwards
+    pub fn positional(&self) -> &[String] {
+        &self.positional
+    }
+
+    /// Replace the positional parameters, as `set -- ARGS...` does
+    pub fn set_positional(&mut self, args: Vec<String>) {
+        self.positional = args;
+    }
+
+    /// Ask the shell to exit with `status` once the current command finishes
+    pub fn request_exit(&mut self, status: i32) {
+        self.exit_status = Some(status);
+    }
+
+    /// Current working directory of the shell, as reached through any
+    /// symbolic links
+    pub fn current_dir(&self) -> &Path {
+        &self.current_dir
+    }
+
+    /// Make `dir` the working directory of the shell
+    ///
+    /// Unless `physical` is set, a relative `dir` is taken from the logical
+    /// working directory with `..` removing the component before it, so
+    /// `cd ..` leaves a symbolic link the way it came in. With `physical`,
+    /// or if the logical path can't be used, the working directory is the
+    /// one the system reports, with links resolved. `PWD` and `OLDPWD` are
+    /// exported with the new and previous directories.
+    pub fn change_dir(&mut self, dir: &Path, physical: bool) -> std::io::Result<()> {
+        let logical = (!physical).then(|| logical_path(&self.current_dir, dir));
+        let current = match logical {
+            Some(logical) if std::env::set_current_dir(&logical).is_ok() => logical,
+            _ => {
+                std::env::set_current_dir(dir)?;
+                std::env::current_dir().unwrap_or_else(|_| dir.to_path_buf())
+            }
+        };
+        let previous = std::mem::replace(&mut self.current_dir, current);
+        self.export_value("OLDPWD", Some(&previous.to_string_lossy()));
+        let pwd = self.current_dir.to_string_lossy().into_owned();
+        self.export_value("PWD", Some(&pwd));
+        Ok(())
+    }
+
+    /// The directories saved by `pushd`, most recent first, not counting
+    /// the working directory
+    pub fn dir_stack(&self) -> &[PathBuf] {
+        &self.dir_stack
+    }
+
+    /// Replace the saved directories
+    pub fn set_dir_stack(&mut self, dirs: Vec<PathBuf>) {
+        self.dir_stack = dirs;
+    }
+
+    /// Where the commands run from PATH were found
+    pub fn command_hash(&self) -> &CommandHashes {
+        &self.hashed
+    }
+
+    /// The shell's job table
+    pub fn jobs(&self) -> &Arc<RwLock<JobTable>> {
+        &self.jobs
+    }
+
+    /// Make the arguments of `name` complete as `spec` says
+    pub fn set_completion(&mut self, name: &str, spec: CompletionSpec) {
+        self.completions
+            .write()
+            .unwrap()
+            .insert(name.to_string(), spec);
+    }
+
+    /// The completion spec for `name`'s arguments, if there is one
+    pub fn completion(&self, name: &str) -> Option<CompletionSpec> {
+        self.completions.read().unwrap().get(name).cloned()
+    }
+
+    /// Every completion spec, sorted by command name
+    pub fn completions(&self) -> Vec<(String, CompletionSpec)> {
+        let mut specs: Vec<(String, CompletionSpec)> = self
+            .completions
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, spec)| (name.clone(), spec.clone()))
+            .collect();
+        specs.sort_by(|a, b| a.0.cmp(&b.0));
+        specs
+    }
+
+    /// Drop the completion spec for `name`, returning whether it had one
+    pub fn remove_completion(&mut self, name: &str) -> bool {
+        self.completions.write().unwrap().remove(name).is_some()
+    }
+
+    /// What `spec` offers for `word`, as `compgen` lists them
+    ///
+    /// Command names come from the line editor's index in an interactive
+    /// shell, and otherwise from the shell's own names and a scan of PATH.
+    /// Functions of `-F` specs aren't run.
+    pub fn completion_matches(&self, word: &str, spec: &CompletionSpec) -> Vec<String> {
+        let mut matches: Vec<String> = Vec::new();
+        for action in &spec.actions {
+            match action {
+                CompletionAction::Commands => match self.helper() {
+                    Some(helper) => matches.extend(helper.completion_engine().commands(word)),
+                    None => {
+                        let names = (self.builtin_registry.get_command_names().into_iter())
+                            .chain(self.aliases.keys().cloned())
+                            .chain(self.functions.keys().cloned())
+                            .filter(|name| name.starts_with(word));
+                        matches.extend(names.chain(completion::path_commands(word)));
+                    }
+                },
+                CompletionAction::Directories => {
+                    matches.extend(completion::path_matches(word, true))
+                }
+                CompletionAction::Files => matches.extend(completion::path_matches(word, false)),
+                CompletionAction::Jobs => {
+                    let jobs = self.jobs.read().unwrap();
+                    let candidates = completion::job_candidates(&jobs, word);
+                    matches.extend(candidates.into_iter().map(|(spec, _)| spec));
+                }
+                CompletionAction::Variables => {
+                    let environment =
+                        std::env::vars_os().filter_map(|(name, _)| name.into_string().ok());
+                    let names = (self.variables.keys().cloned())
+                        .chain(environment)
+                        .filter(|name| name.starts_with(word));
+                    matches.extend(names);
+                }
+            }
+        }
+        matches.extend(spec.words.iter().filter(|w| w.starts_with(word)).cloned());
+        matches.sort();
+        matches.dedup();
+        matches
+    }
+
+    /// Start a [`Generator`] for the coming prompt if a completion spec
+    /// runs a function, stopping the one started for the last prompt
+    fn start_generator(&mut self) {
+        let Some(helper) = self.helper() else {
+            return;
+        };
+        helper.set_generator(None);
+        let wanted =
+            (self.completions.read().unwrap().values()).any(|spec| spec.function.is_some());
+        if wanted {
+            let generator = Generator::spawn(self).ok();
+            if let Some(helper) = self.helper() {
+                helper.set_generator(generator);
+            }
+        }
+    }
+
+    /// Drop every completion spec
+    pub fn clear_completions(&mut self) {
+        self.completions.write().unwrap().clear();
+    }
+
+    /// Whether `name` is a function defined in this shell
+    pub fn is_function(&self, name: &str) -> bool {
+        self.functions.contains_key(name)
+    }
+
+    /// Whether a function is currently running
+    pub fn in_function(&self) -> bool {
+        self.function_depth > 0
+    }
+
+    /// Whether `return` can be used: inside a function or a sourced file
+    pub fn can_return(&self) -> bool {
+        self.function_depth > 0 || self.source_depth > 0
+    }
+
+    /// Number of loops `break` and `continue` can leave
+    pub fn loop_depth(&self) -> usize {
+        self.loop_depth
+    }
+
+    /// Leave loops as `break`/`continue` asks once the current command finishes
+    pub fn request_loop_control(&mut self, control: LoopControl) {
+        self.loop_control = Some(control);
+    }
+
+    /// Leave the running function with `status` once the current command finishes
+    pub fn request_return(&mut self, status: i32) {
+        self.returning = Some(status);
+    }
+
+    /// Define or replace a function and make its name tab-completable
+    fn define_function(&mut self, name: &str, body: Rc<Vec<AndOrList>>) {
+        self.functions.insert(name.to_string(), body);
+        self.add_completion_command(name);
+    }
+
+    /// Remove a function, returning whether it existed
+    ///
+    /// Its name stops completing unless an alias has the same name.
+    pub fn remove_function(&mut self, name: &str) -> bool {
+        let existed = self.functions.remove(name).is_some();
+        if existed && !self.aliases.contains_key(name) {
+            self.remove_completion_command(name);
+        }
+        existed
+    }
+
+    /// Run a function's body with `args` as its positional parameters
+    fn call_function(&mut self, body: &[AndOrList], args: Vec<String>) {
+        let caller_args = std::mem::replace(&mut self.positional, args);
+        // Loops around the call can't be left from inside the function
+        let caller_loops = std::mem::take(&mut self.loop_depth);
+        self.function_depth += 1;
+        self.scopes.push(Vec::new());
+        self.execute_list(body);
+        for saved in self.scopes.pop().unwrap_or_default().into_iter().rev() {
+            self.readonly.remove(&saved.name);
+            self.restore_variable(&saved.name, saved.local, saved.exported);
+        }
+        self.function_depth -= 1;
+        self.loop_depth = caller_loops;
+        self.positional = caller_args;
+        if let Some(status) = self.returning.take() {
+            self.last_status = status;
+        }
+    }
+
+    /// Run commands inside the shell process as one pipeline stage
+    ///
+    /// When the stage writes to the shell's own stdout (`direct`, without
+    /// redirections) the commands print as they run. Otherwise their output is
+    /// collected and handled like a builtin's: written to the redirection
+    /// target, or returned for the next stage.
+    fn run_in_shell(
+        &mut self,
+        redirects: &[Redirection<PathBuf, String>],
+        direct: bool,
+        run: impl FnOnce(&mut Self),
+    ) -> Result<Vec<u8>, ShellError> {
+        if direct && redirects.is_empty() {
+            run(self);
+            return Ok(Vec::new());
+        }
+        let opened = open_redirections(redirects)?;
+        let _stdin = StdinSwap::for_redirects(&opened)?;
+        let sink = BuiltinSink::for_stdout(opened);
+        self.captures.push(Vec::new());
+        run(self);
+        let output = self.captures.pop().unwrap_or_default();
+        match sink {
+            Some(sink) => Ok(sink.deliver(output)?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Defined aliases, by name
+    pub fn aliases(&self) -> &HashMap<String, String> {
+        &self.aliases
+    }
+
+    /// Define or replace an alias and make its name tab-completable
+    pub fn set_alias(&mut self, name: &str, value: &str) {
+        self.aliases.insert(name.to_string(), value.to_string());
+        self.add_completion_command(name);
+    }
+
+    /// Remove an alias, returning whether it existed
+    ///
+    /// Its name stops completing unless a function has the same name.
+    pub fn remove_alias(&mut self, name: &str) -> bool {
+        let existed = self.aliases.remove(name).is_some();
+        if existed && !self.functions.contains_key(name) {
+            self.remove_completion_command(name);
+        }
+        existed
+    }
+
+    /// Make a shell-defined command name (alias or function) tab-completable
+    pub fn add_completion_command(&self, name: &str) {
+        if let Some(helper) = self.helper() {
+            helper.completion_engine().add_command(name);
+        }
+    }
+
+    /// Remove a shell-defined command name from tab completion
+    pub fn remove_completion_command(&self, name: &str) {
+        if let Some(helper) = self.helper() {
+            helper.completion_engine().remove_command(name);
+        }
+    }
+
+    /// Rebuild the completion index from PATH right away, and forget where
+    /// commands were found
+    pub fn rehash(&self) {
+        self.hashed.write().unwrap().clear();
+        if let Some(helper) = self.helper() {
+            helper.completion_engine().refresh_cache();
+        }
+    }
+
+    /// Register an additional built-in command, replacing any with the same name
+    ///
+    /// Its name becomes tab-completable, and its arguments complete as its
+    /// [`BuiltinCommand::completion`] says.
+    ///
+    /// # Panics
+    /// Panics if called while a builtin is executing.
+    pub fn register_builtin(&mut self, command: Box<dyn BuiltinCommand>) {
+        let name = command.name().to_string();
+        if let Some(spec) = command.completion() {
+            self.set_completion(&name, spec);
+        }
+        Rc::get_mut(&mut self.builtin_registry)
+            .expect("builtins can't be registered while a builtin is running")
+            .register(command);
+        self.add_completion_command(&name);
+    }
+
+    /// Check if a command name refers to a built-in
+    pub fn is_builtin(&self, name: &str) -> bool {
+        self.builtin_registry.is_builtin(name)
+    }
+
+    /// Execute a built-in command with redirection support
+    ///
+    /// Redirection targets are opened before the builtin runs, and its output
+    /// goes straight to them, or to the terminal when it's the last stage of
+    /// a foreground pipeline (`direct`). Output bound for the next stage or
+    /// an enclosing capture is returned as raw bytes instead. An error the
+    /// builtin returns is written to its stderr, so `2>` applies to it, and
+    /// sets the status.
+    fn execute_builtin(
+        &mut self,
+        cmd: &ExpandedCommand,
+        direct: bool,
+    ) -> Result<Vec<u8>, ShellError> {
+        let registry = Rc::clone(&self.builtin_registry);
+        let Some(builtin) = registry.get_command(&cmd.command) else {
+            return Ok(Vec::new());
+        };
+        let opened = open_redirections(&cmd.redirects)?;
+        let _stdin = StdinSwap::for_redirects(&opened)?;
+        let (stdout, stderr) = BuiltinSink::for_stdio(opened);
+
+        // Both streams share one buffer, so `2>&1 |` keeps their order
+        let collected = Rc::new(RefCell::new(Vec::new()));
+        let stream = direct && self.captures.is_empty();
+        let resolve = |sink: Option<BuiltinSink>| match sink {
+            Some(BuiltinSink::Stdout) if !stream => BuiltinSink::Buffer(Rc::clone(&collected)),
+            // A stream pointed at something unwritable is discarded
+            None => BuiltinSink::Buffer(Rc::new(RefCell::new(Vec::new()))),
+            Some(sink) => sink,
+        };
+        let (mut stdout, mut stderr) = (resolve(stdout), resolve(stderr));
+
+        // Commands the builtin runs in the shell (`source`, `bench`) write
+        // where it does
+        let capture = !matches!(stdout, BuiltinSink::Stdout);
+        if capture {
+            self.captures.push(Vec::new());
+        }
+        // `NAME=value` before a builtin assigns NAME only while it runs
+        let saved: Vec<_> = cmd
+            .env
+            .iter()
+            .map(|(name, value)| {
+                let saved = (
+                    name,
+                    self.variables.get(name).cloned(),
+                    std::env::var_os(name),
+                );
+                self.assign_variable(name, value);
+                saved
+            })
+            .collect();
+        self.array_args = cmd.arrays.clone();
+        let result = builtin.execute(&cmd.args, self, &mut stdout, &mut stderr);
+        self.array_args.clear();
+        for (name, local, exported) in saved.into_iter().rev() {
+            self.restore_variable(name, local, exported);
+        }
+        if capture {
+            stdout.write_all(&self.captures.pop().unwrap_or_default())?;
+        }
+        self.last_status = match result {
+            Ok(status) => status,
+            Err(e) => {
+                let e = self.locate(e);
+                let _ = writeln!(stderr, "{}", e);
+                e.exit_status()
+            }
+        };
+
+        stdout.flush()?;
+        stderr.flush()?;
+        Ok(collected.take())
+    }
+
+    /// Build the process for an external command (not a built-in)
+    ///
+    /// Redirection targets are opened here and applied in the child after the
+    /// caller has wired up its stdio. `NAME=value` prefixes are added to the
+    /// child's environment only. In an interactive shell a `foreground` child
+    /// takes the terminal for its process group.
+    fn external_process(
+        &self,
+        cmd: &ExpandedCommand,
+        foreground: bool,
+    ) -> Result<Command, ShellError> {
+        // The command hash finds a PATH command, unless the command sets its own PATH
+        let own_path = cmd.env.iter().any(|(name, _)| name == "PATH");
+        let program = if cmd.command.contains('/') || own_path {
+            None
+        } else {
+            self.hashed.write().unwrap().lookup(&cmd.command)
+        };
+        let mut process = Command::new(program.as_deref().unwrap_or(Path::new(&cmd.command)));
+        process
+            .arg0(&cmd.command)
+            .args(&cmd.args)
+            .envs(cmd.env.iter().map(|(name, value)| (name, value)))
+            .current_dir(&self.current_dir);
+        if self.is_interactive() {
+            signals::restore_in_child(&mut process, foreground);
+        }
+        redirect::apply_in_child(&mut process, open_redirections(&cmd.redirects)?);
+        Ok(process)
+    }
+
+    /// Reject commands a restricted shell (`-r`) doesn't allow
+    fn check_restrictions(&self, cmd: &ExpandedCommand) -> Result<(), ShellError> {
+        if cmd.command == "cd" {
+            return Err(ShellError::Restricted("cd: restricted".to_string()));
+        }
+        if cmd.command.contains('/') {
+            return Err(ShellError::Restricted(format!(
+                "{}: restricted: cannot specify `/' in command names",
+                cmd.command
+            )));
+        }
+        if cmd
+            .redirects
+            .iter()
+            .any(|redirect| matches!(redirect, Redirection::Output { .. }))
+        {
+            return Err(ShellError::Restricted(
+                "restricted: cannot redirect output".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Execute a single command, dispatching to either built-in or external execution
+    pub(crate) fn execute_command(&mut self, cmd: CommandParts) -> Result<Vec<u8>, ShellError> {
+        self.execute_pipeline(&Pipeline::single(cmd))
+    }
+
+    /// Execute a pipeline, then clean up the process substitutions its stages started
+    pub(crate) fn execute_pipeline(&mut self, pipeline: &Pipeline) -> Result<Vec<u8>, ShellError> {
+        let first_substitution = self.substitutions.len();
+        let result = self.execute_stages(pipeline);
+        for substitution in self.substitutions.split_off(first_substitution) {
+            substitution.finish(pipeline.background);
+        }
+        result
+    }
+
+    /// Execute a pipeline, connecting each stage's stdout to the next stage's stdin
+    ///
+    /// Every stage but the last runs concurrently in a process of its own
+    /// (forked, for builtins, functions and compound commands) connected
+    /// with OS pipes. A last stage that runs in the shell does so once the
+    /// others have started, reading the previous stage's pipe. The status is
+    /// that of the last stage. Errors in earlier stages are reported right
+    /// away and the following stage sees empty input, as in other shells.
+    /// With `set -o pipefail`, the status is instead that of the last stage
+    /// that failed, or 0 if none did.
+    ///
+    /// Background pipelines read stdin from /dev/null so they can never
+    /// compete with the prompt for terminal input. Their stdout/stderr that
+    /// isn't redirected goes to the terminal, or into the job's output
+    /// buffer if [`buffers_job_output`](Self::buffers_job_output) says so.
+    fn execute_stages(&mut self, pipeline: &Pipeline) -> Result<Vec<u8>, ShellError> {
+        let background = pipeline.background;
+        let job_output = JobOutput::new();
+        let mut input: Option<StageInput> = None;
+        // Process IDs of the stages that run as processes of their own
+        let mut children: Vec<u32> = Vec::new();
+        let mut status = 0;
+        let mut last_error = None;
+        // Status of each stage run in the shell; external stages are filled
+        // in once they've been waited for
+        let mut stage_statuses: Vec<Option<i32>> = Vec::new();
+
+        // Jobs get their own process group, led by the first process; without
+        // job control, foreground jobs stay in the shell's group
+        let job_control = self.is_interactive();
+        let own_group = background || job_control;
+
+        for (index, cmd) in pipeline.commands.iter().enumerate() {
+            let last = index + 1 == pipeline.commands.len();
+            let group = own_group.then(|| children.first().map_or(0, |&pid| pid as i32));
+            let stage = self.run_stage(cmd, input.take(), last, background, group, &job_output);
+            match stage {
+                Ok((child, next)) => {
+                    // Stages run inside the shell have already set their status
+                    status = if child.is_some() { 0 } else { self.last_status };
+                    stage_statuses.push(child.is_none().then_some(status));
+                    // The group leader also takes the terminal itself; doing it
+                    // here too means it's done before the shell starts waiting
+                    if let Some(leader) = child.filter(|_| children.is_empty()) {
+                        if job_control && !background {
+                            signals::give_terminal_to(leader);
+                        }
+                    }
+                    children.extend(child);
+                    input = next;
+                }
+                Err(e) if last => {
+                    status = e.exit_status();
+                    stage_statuses.push(Some(status));
+                    last_error = Some(e);
+                    input = None;
+                }
+                Err(e) => {
+                    stage_statuses.push(Some(self.report_error(e)));
+                    input = Some(StageInput::Bytes(Vec::new()));
+                }
+            }
+        }
+
+        if background && !children.is_empty() {
+            if let Some(StageInput::Bytes(output)) = &input {
+                if self.buffers_job_output() {
+                    job_output.push(output);
+                } else {
+                    self.write_output(output);
+                }
+            }
+            let pid = children[children.len() - 1];
+            let id = self
+                .jobs
+                .write()
+                .unwrap()
+                .add(children, pipeline.command_line(), job_output);
+            self.last_background_pid = Some(pid);
+            self.last_status = 0;
+            // The notice is for the user at the terminal, never part of the output
+            if self.is_interactive() {
+                let _ = writeln!(std::io::stderr(), "[{}] {}", id, pid);
+            }
+            return Ok(Vec::new());
+        }
+
+        // Wait for every external stage; the last stage decides the status
+        let last_is_external = input.is_none() && last_error.is_none();
+        let mut process_statuses = Vec::new();
+        if job_control && !children.is_empty() {
+            // Waiting through the job table notices a job stopped with Ctrl+Z,
+            // which then stays in the table for `fg`/`bg`
+            let command = pipeline.command_line();
+            let mut jobs = self.jobs.write().unwrap();
+            let id = jobs.add(children, command.clone(), job_output.clone());
+            let state = jobs.wait(id);
+            signals::reclaim_terminal();
+            if state == Some(JobState::Stopped) {
+                self.last_status = 128 + libc::SIGTSTP;
+                return Ok(format!("\n[{}]+  Stopped  {}\n", id, command).into_bytes());
+            }
+            process_statuses = jobs
+                .remove(id)
+                .map(|job| job.statuses())
+                .unwrap_or_default();
+            if let (true, Some(JobState::Done(code))) = (last_is_external, state) {
+                status = code;
+            }
+            // A job killed by Ctrl+C interrupts the rest of the command line too
+            if status == 128 + libc::SIGINT {
+                signals::interrupt();
+            }
+        } else {
+            let child_count = children.len();
+            for (index, pid) in children.into_iter().enumerate() {
+                let exit = wait_exit(pid);
+                process_statuses.push(exit);
+                if last_is_external && index + 1 == child_count {
+                    status = exit;
+                }
+            }
+        }
+        if self.flags.pipefail {
+            let mut process_statuses = process_statuses.into_iter();
+            let failed = stage_statuses
+                .into_iter()
+                .map(|stage| stage.or_else(|| process_statuses.next()).unwrap_or(0))
+                .filter(|&stage| stage != 0)
+                .last();
+            status = failed.unwrap_or(status);
+        }
+        self.last_status = status;
+
+        match (last_error, input) {
+            (Some(e), _) => Err(e),
+            (None, Some(StageInput::Bytes(output))) => Ok(output),
+            // Output of the last stage, if it was being collected
+            _ => {
+                job_output.drain();
+                Ok(job_output.take())
+            }
+        }
+    }
+
+    /// Run one stage of a pipeline, returning the process ID of its child
+    /// (for stages not run in the shell itself) and the input for the next
+    /// stage
+    ///
+    /// Stages that would run in the shell are [forked](Self::fork_stage)
+    /// unless they're the last stage of a foreground pipeline, so they
+    /// stream into the next stage as they run and don't hold up the shell.
+    fn run_stage(
+        &mut self,
+        cmd: &ShellCommand,
+        mut input: Option<StageInput>,
+        last: bool,
+        background: bool,
+        group: Option<i32>,
+        job_output: &JobOutput,
+    ) -> Result<(Option<u32>, Option<StageInput>), ShellError> {
+        let fork = background || !last;
+        if fork
+            && !matches!(
+                cmd,
+                ShellCommand::Simple(_) | ShellCommand::FunctionDef { .. }
+            )
+        {
+            let (pid, next) = self.fork_stage(
+                input,
+                last,
+                background,
+                group,
+                job_output,
+                |shell| match shell.run_stage(cmd, None, true, false, None, &JobOutput::new())? {
+                    (_, Some(StageInput::Bytes(output))) => Ok(output),
+                    _ => Ok(Vec::new()),
+                },
+            )?;
+            return Ok((Some(pid), next));
+        }
+        // Output of commands run in the shell is collected unless it can go straight to stdout
+        let direct = last && !background;
+        // Commands run in the shell read the previous stage's output as its stdin
+        let _stdin = match cmd {
+            ShellCommand::Simple(_) => None,
+            _ => stage_stdin(input.take())?,
+        };
+        let cmd = match cmd {
+            ShellCommand::Simple(cmd) => cmd,
+            ShellCommand::FunctionDef { name, body } => {
+                self.define_function(name, Rc::clone(body));
+                self.last_status = 0;
+                return Ok((None, Some(StageInput::Bytes(Vec::new()))));
+            }
+            ShellCommand::Group { body, redirects } => {
+                let redirects = self.expand_redirections(redirects)?;
+                let output =
+                    self.run_in_shell(&redirects, direct, |shell| shell.execute_list(body))?;
+                return Ok((None, Some(StageInput::Bytes(output))));
+            }
+            ShellCommand::Subshell { body, redirects } => {
+                let redirects = self.expand_redirections(redirects)?;
+                let opened = open_redirections(&redirects)?;
+                let output = self.execute_subshell(body, &opened, direct)?;
+                return Ok((None, Some(StageInput::Bytes(output))));
+            }
+            ShellCommand::If {
+                branches,
+                else_body,
+                redirects,
+            } => {
+                let redirects = self.expand_redirections(redirects)?;
+                let output = self.run_in_shell(&redirects, direct, |shell| {
+                    shell.execute_if(branches, else_body.as_deref())
+                })?;
+                return Ok((None, Some(StageInput::Bytes(output))));
+            }
+            ShellCommand::For {
+                variable,
+                words,
+                body,
+                redirects,
+            } => {
+                if !is_name(variable) {
+                    return Err(ShellError::BuiltinError {
+                        name: "for".to_string(),
+                        status: 1,
+                        message: format!("`{}': not a valid identifier", variable),
+                    });
+                }
+                let values = match words {
+                    Some(words) => self.expand_words(words)?,
+                    None => self.positional.clone(),
+                };
+                let redirects = self.expand_redirections(redirects)?;
+                let output = self.run_in_shell(&redirects, direct, |shell| {
+                    shell.execute_for(variable, values, body)
+                })?;
+                return Ok((None, Some(StageInput::Bytes(output))));
+            }
+            ShellCommand::Case {
+                word,
+                items,
+                redirects,
+            } => {
+                let subject = expand::expand_assignment(self, word)?;
+                let mut patterns = Vec::with_capacity(items.len());
+                for item in items {
+                    let expanded = item
+                        .patterns
+                        .iter()
+                        .map(|pattern| expand::expand_pattern(self, pattern))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    patterns.push(expanded);
+                }
+                let redirects = self.expand_redirections(redirects)?;
+                let output = self.run_in_shell(&redirects, direct, |shell| {
+                    shell.execute_case(&subject, items, &patterns)
+                })?;
+                return Ok((None, Some(StageInput::Bytes(output))));
+            }
+            ShellCommand::Conditional {
+                condition,
+                redirects,
+            } => {
+                let redirects = self.expand_redirections(redirects)?;
+                let mut result = Ok(());
+                let output = self.run_in_shell(&redirects, direct, |shell| {
+                    result = shell.execute_conditional(condition)
+                })?;
+                result?;
+                return Ok((None, Some(StageInput::Bytes(output))));
+            }
+            ShellCommand::While {
+                condition,
+                body,
+                redirects,
+            } => {
+                let redirects = self.expand_redirections(redirects)?;
+                let output = self.run_in_shell(&redirects, direct, |shell| {
+                    shell.execute_while(condition, body)
+                })?;
+                return Ok((None, Some(StageInput::Bytes(output))));
+            }
+        };
+
+        let cmd = &*self.substitute_processes(cmd)?;
+        if cmd.words.is_empty() {
+            // A command made only of assignments sets shell variables, left to right
+            for assignment in &cmd.env {
+                self.assign(assignment)?;
+            }
+            self.last_status = 0;
+            return Ok((None, Some(StageInput::Bytes(Vec::new()))));
+        }
+        let cmd = expand::expand_command(self, cmd)?;
+        // Not even a single command can see another value for a readonly variable
+        if let Some((name, _)) = cmd
+            .env
+            .iter()
+            .find(|(name, _)| self.readonly.contains(name))
+        {
+            return Err(ShellError::ReadonlyVariable(name.clone()));
+        }
+        if cmd.command.is_empty() {
+            self.last_status = 0;
+            return Ok((None, Some(StageInput::Bytes(Vec::new()))));
+        }
+        if self.options.restricted {
+            self.check_restrictions(&cmd)?;
+        }
+        let cmd = self.correct_command(cmd);
+        if self.flags.xtrace {
+            let assignments = cmd
+                .env
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, traced_word(value)));
+            let words = std::iter::once(&cmd.command)
+                .chain(&cmd.args)
+                .map(|word| traced_word(word));
+            self.trace(&assignments.chain(words).collect::<Vec<_>>());
+        }
+
+        // Functions are looked up before builtins and PATH
+        let function = self.functions.get(&cmd.command).cloned();
+        if function.is_some() || self.builtin_registry.is_builtin(&cmd.command) {
+            let run = |shell: &mut Self, direct| match &function {
+                Some(body) => shell.run_in_shell(&cmd.redirects, direct, |shell| {
+                    shell.call_function(body, cmd.args.clone())
+                }),
+                None => shell.execute_builtin(&cmd, direct),
+            };
+            if fork {
+                let (pid, next) =
+                    self.fork_stage(input, last, background, group, job_output, |shell| {
+                        run(shell, true)
+                    })?;
+                return Ok((Some(pid), next));
+            }
+            let _stdin = stage_stdin(input)?;
+            let output = run(self, direct)?;
+            return Ok((None, Some(StageInput::Bytes(output))));
+        }
+        match self.spawn_stage(&cmd, input, last, background, group, job_output) {
+            Ok((child, next)) => Ok((Some(child.id()), next)),
+            Err(e @ (ShellError::CommandNotFound(_) | ShellError::SpawnError { .. })) => {
+                let output = self.report_spawn_error(&cmd, e);
+                Ok((None, Some(StageInput::Bytes(output))))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Run a stage that would run in the shell in a forked copy of it
+    /// instead, as a process of the pipeline's job
+    ///
+    /// The copy reads the stage's `input`, writes to a pipe for the next
+    /// stage (or, as the last stage, where a background job's output goes),
+    /// joins the job's process `group`, [detaches](Self::detach) and calls
+    /// `run` the way the last foreground stage runs, writing out whatever
+    /// output `run` returns (what the stage's own redirections collected)
+    /// and exiting with the status it leaves. Returns the copy's process ID
+    /// and the input for the next stage.
+    fn fork_stage(
+        &mut self,
+        input: Option<StageInput>,
+        last: bool,
+        background: bool,
+        group: Option<i32>,
+        job_output: &JobOutput,
+        run: impl FnOnce(&mut Self) -> Result<Vec<u8>, ShellError>,
+    ) -> Result<(u32, Option<StageInput>), ShellError> {
+        let buffered = background && self.buffers_job_output();
+        let stdin: Option<OwnedFd> = match input {
+            Some(StageInput::Pipe(fd)) => Some(fd),
+            Some(StageInput::Bytes(bytes)) => Some(redirect::pipe_from(bytes)?.into()),
+            None if background => Some(File::open("/dev/null")?.into()),
+            None => None,
+        };
+        let stdout = if !last || buffered {
+            Some(std::io::pipe()?)
+        } else {
+            None
+        };
+        let stderr = if buffered {
+            Some(std::io::pipe()?)
+        } else {
+            None
+        };
+        let job_control = self.is_interactive();
+
+        let _ = std::io::stdout().flush();
+        // SAFETY: the child only uses its own copy of the shell and leaves
+        // with `_exit`, never returning into the caller
+        let pid = unsafe { libc::fork() };
+        if pid == -1 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        if pid == 0 {
+            // SAFETY: these calls only change this process's group, signal
+            // dispositions and standard descriptors, from fds it owns
+            unsafe {
+                if let Some(group) = group {
+                    libc::setpgid(0, group);
+                }
+                if job_control {
+                    signals::restore_in_fork(!background);
+                }
+                // Like any command writing to a closed pipe, end quietly once
+                // the next stage stops reading
+                libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+                if let Some(fd) = &stdin {
+                    libc::dup2(fd.as_raw_fd(), 0);
+                }
+                if let Some((_, writer)) = &stdout {
+                    libc::dup2(writer.as_raw_fd(), 1);
+                }
+                if let Some((_, writer)) = &stderr {
+                    libc::dup2(writer.as_raw_fd(), 2);
+                }
+            }
+            drop((stdin, stdout, stderr));
+            // The stage's own process substitutions are still to be opened
+            let substitutions = std::mem::take(&mut self.substitutions);
+            self.detach();
+            self.substitutions = substitutions;
+            let status = match run(self) {
+                Ok(output) => {
+                    self.write_output(&output);
+                    self.final_status()
+                }
+                Err(e) => self.report_error(e),
+            };
+            let _ = std::io::stdout().flush();
+            // SAFETY: ends the forked child without running the parent's cleanup
+            unsafe { libc::_exit(status) };
+        }
+        if let Some(group) = group {
+            // Also done here so the next stage can join the group right away
+            // SAFETY: setpgid only changes the process group of our child
+            unsafe { libc::setpgid(pid, if group == 0 { pid } else { group }) };
+        }
+        drop(stdin);
+        if let Some((reader, _)) = stderr {
+            job_output.capture(reader);
+        }
+        let next = match stdout {
+            Some((reader, _)) if !last => Some(StageInput::Pipe(reader.into())),
+            Some((reader, _)) => {
+                job_output.capture(reader);
+                None
+            }
+            None => None,
+        };
+        Ok((pid as u32, next))
+    }
+
+    /// Carry out an assignment standing on its own, without a command
+    fn assign(&mut self, assignment: &Assignment) -> Result<(), ShellError> {
+        let name = &assignment.name;
+        let index = match &assignment.index {
+            Some(index) => Some(expand::expand_index(self, index)?),
+            None => None,
+        };
+        let target = format!(
+            "{}{}{}=",
+            name,
+            index
+                .map(|index| format!("[{}]", index))
+                .unwrap_or_default(),
+            if assignment.append { "+" } else { "" }
+        );
+        match &assignment.value {
+            AssignedValue::Array(_) if index.is_some() => Err(ShellError::ExpansionError(format!(
+                "{}: cannot assign list to array member",
+                &target[..target.len() - 1]
+            ))),
+            AssignedValue::Array(words) => {
+                let mut values = Vec::new();
+                for word in words {
+                    values.extend(expand::expand_word(self, word)?);
+                }
+                let traced: Vec<_> = values.iter().map(|value| traced_word(value)).collect();
+                self.trace(&[format!("{}({})", target, traced.join(" "))]);
+                self.set_array(name, values, assignment.append)
+            }
+            AssignedValue::Scalar(word) => {
+                let mut value = expand::expand_assignment(self, word)?;
+                self.trace(&[format!("{}{}", target, traced_word(&value))]);
+                if assignment.append {
+                    let current = match index {
+                        Some(index) => self.element(name, index),
+                        None => self.variable(name),
+                    };
+                    value.insert_str(0, &current.unwrap_or_default());
+                }
+                match index {
+                    Some(index) => self.set_element(name, index, &value),
+                    None => self.set_variable(name, &value),
+                }
+            }
+        }
+    }
+
+    /// Under `set -x`, print a command's expanded `words` to stderr after
+    /// `$PS4`, `+ ` unless set
+    fn trace(&self, words: &[String]) {
+        if self.flags.xtrace {
+            let ps4 = self.variable("PS4").unwrap_or_else(|| "+ ".to_string());
+            let _ = writeln!(std::io::stderr(), "{}{}", ps4, words.join(" "));
+        }
+    }
+
+    /// Start the commands of any `<(...)` or `>(...)` in `cmd`, replacing each
+    /// with the `/dev/fd` path connected to it
+    fn substitute_processes<'a>(
+        &mut self,
+        cmd: &'a CommandParts,
+    ) -> Result<Cow<'a, CommandParts>, ShellError> {
+        if !cmd.has_process_substitution() {
+            return Ok(Cow::Borrowed(cmd));
+        }
+        let mut cmd = cmd.clone();
+        self.substitute_parts(cmd.parts_mut())?;
+        Ok(Cow::Owned(cmd))
+    }
+
+    /// Start the command of each `<(...)` or `>(...)` among `parts`,
+    /// replacing it with the `/dev/fd` path connected to it
+    fn substitute_parts<'p>(
+        &mut self,
+        parts: impl Iterator<Item = &'p mut WordPart>,
+    ) -> Result<(), ShellError> {
+        for part in parts {
+            if let WordPart::ProcessSubstitution { command, output } = part {
+                let substitution = Substitution::spawn(self, command, *output)?;
+                *part = WordPart::Quoted(substitution.path());
+                self.substitutions.push(substitution);
+            }
+        }
+        Ok(())
+    }
+
+    /// Expand the redirections of a compound command, starting the process
+    /// substitutions among their targets, as in `done < <(cmd)`
+    fn expand_redirections(
+        &mut self,
+        redirects: &[Redirection],
+    ) -> Result<Vec<Redirection<PathBuf, String>>, ShellError> {
+        let mut redirects = redirects.to_vec();
+        self.substitute_parts(target_parts_mut(&mut redirects))?;
+        expand::expand_redirections(self, &redirects)
+    }
+
+    /// Expand the word list of a `for` loop, starting the process
+    /// substitutions in it
+    fn expand_words(&mut self, words: &[Word]) -> Result<Vec<String>, ShellError> {
+        let mut words = words.to_vec();
+        self.substitute_parts(words.iter_mut().flat_map(|word| &mut word.parts))?;
+        let mut values = Vec::new();
+        for word in &words {
+            values.extend(expand::expand_word(self, word)?);
+        }
+        Ok(values)
+    }
+
+    /// Run the command of a process substitution in a forked copy of the
+    /// shell, returning the status to exit with
+    ///
+    /// The copy first [detaches](Self::detach) from the parent.
+    pub(crate) fn run_substituted(&mut self, command: &str) -> i32 {
+        self.detach();
+        self.execute_line(command);
+        self.final_status()
+    }
+
+    /// Drop everything tying a forked copy of the shell to the parent's
+    /// terminal and output: the line editor (so it runs without job
+    /// control), pending captures and the parent's process substitutions
+    pub(crate) fn detach(&mut self) {
+        std::mem::forget(self.editor.take());
+        self.captures.clear();
+        for substitution in self.substitutions.drain(..) {
+            substitution.forget();
+        }
+    }
+
+    /// Run the completion function `request` names, in a [`Generator`]'s
+    /// copy of the shell
+    ///
+    /// The function gets the command, the word being completed and the word
+    /// before it as `$1`, `$2` and `$3`, with the line and cursor position
+    /// in `COMP_LINE` and `COMP_POINT`.
+    pub(crate) fn run_completion_function(&mut self, request: &Request) {
+        let Some(body) = self.functions.get(&request.function).cloned() else {
+            return;
+        };
+        self.assign_variable("COMP_LINE", &request.line);
+        self.assign_variable("COMP_POINT", &request.point.to_string());
+        let args = vec![
+            request.command.clone(),
+            request.word.clone(),
+            request.previous.clone(),
+        ];
+        self.call_function(&body, args);
+    }
+
+    /// Spawn one external stage of a pipeline
+    ///
+    /// `input` is what the previous stage produced. Stages of a job with its
+    /// own process group are given the `group` to join (0 starts a new one).
+    /// Returns the child and, for every stage but the last, the input for the
+    /// next stage.
+    fn spawn_stage(
+        &self,
+        cmd: &ExpandedCommand,
+        input: Option<StageInput>,
+        last: bool,
+        background: bool,
+        group: Option<i32>,
+        job_output: &JobOutput,
+    ) -> Result<(Child, Option<StageInput>), ShellError> {
+        let mut process = self.external_process(cmd, !background)?;
+        if let Some(group) = group {
+            process.process_group(group);
+        }
+        let buffered = background && self.buffers_job_output();
+
+        let pending = match input {
+            Some(StageInput::Pipe(stdout)) => {
+                process.stdin(Stdio::from(stdout));
+                None
+            }
+            Some(StageInput::Bytes(bytes)) => {
+                process.stdin(Stdio::piped());
+                Some(bytes)
+            }
+            None if background => {
+                process.stdin(Stdio::null());
+                None
+            }
+            None => None,
+        };
+        // Redirections are applied on top of these pipes in the child
+        if !last || buffered || !self.captures.is_empty() {
+            process.stdout(Stdio::piped());
+        }
+        if buffered {
+            process.stderr(Stdio::piped());
+        }
+
+        let mut child = process.spawn().map_err(|e| {
+            // The child may have taken the terminal before its exec failed
+            if self.is_interactive() && !background {
+                signals::reclaim_terminal();
+            }
+            spawn_error(&cmd.command, e)
+        })?;
+
+        // Feed a builtin's buffered output from a thread so a full pipe can't block the shell
+        if let (Some(bytes), Some(mut stdin)) = (pending, child.stdin.take()) {
+            std::thread::spawn(move || {
+                let _ = stdin.write_all(&bytes);
+            });
+        }
+        if let Some(stderr) = child.stderr.take() {
+            job_output.capture(stderr);
+        }
+
+        let next = if last {
+            if let Some(stdout) = child.stdout.take() {
+                job_output.capture(stdout);
+            }
+            None
+        } else {
+            child
+                .stdout
+                .take()
+                .map(|stdout| StageInput::Pipe(stdout.into()))
+        };
+        Ok((child, next))
+    }
+}
+
+/// Input waiting to be fed to the next stage of a pipeline
+enum StageInput {
+    /// Read end of the pipe the previous stage's process writes to
+    Pipe(OwnedFd),
+    /// Output a builtin stage produced
+    Bytes(Vec<u8>),
+}
+
+/// Point the shell's stdin at a stage's `input` while the stage runs in the
+/// shell; the first stage keeps the shell's own
+fn stage_stdin(input: Option<StageInput>) -> Result<Option<StdinSwap>, ShellError> {
+    let swap = match input {
+        None => None,
+        Some(StageInput::Pipe(stdout)) => Some(StdinSwap::to(&stdout)?),
+        Some(StageInput::Bytes(bytes)) => Some(StdinSwap::to(&redirect::pipe_from(bytes)?)?),
+    };
+    Ok(swap)
+}
+
+/// `word` as `set -x` shows it: quoted only if it would otherwise be read
+/// back differently
+fn traced_word(word: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+@%^".contains(c);
+    if !word.is_empty() && word.chars().all(plain) {
+        word.to_string()
+    } else {
+        Word::quoted(word).to_string()
+    }
+}
+
+/// `dir` taken from `base`, with `.` dropped and each `..` removing the
+/// component before it rather than following it back through a link
+fn logical_path(base: &Path, dir: &Path) -> PathBuf {
+    let mut path = if dir.is_absolute() {
+        PathBuf::new()
+    } else {
+        base.to_path_buf()
+    };
+    for component in dir.components() {
+        match component {
+            std::path::Component::CurDir => {}
+            std::path::Component::ParentDir => {
+                path.pop();
+            }
+            other => path.push(other),
+        }
+    }
+    path
+}
+
+/// Turn a failure to start `command` into the matching shell error
+fn spawn_error(command: &str, source: std::io::Error) -> ShellError {
+    // A missing path is reported as such; only PATH lookups are "not found"
+    if source.kind() == std::io::ErrorKind::NotFound && !command.contains('/') {
+        ShellError::CommandNotFound(command.to_string())
+    } else {
+        ShellError::SpawnError {
+            command: command.to_string(),
+            source,
+        }
+    }
+}
+
+/// Group lines into complete commands, each with the number of the line it starts on
+///
+/// Lines are gathered until they parse as complete commands, so a function
+/// body or here-document can span several lines. Whatever is left when the
+/// lines run out is returned as the last command.
+fn complete_commands(
+    lines: impl Iterator<Item = std::io::Result<String>>,
+) -> impl Iterator<Item = std::io::Result<(usize, String)>> {
+    let mut lines = lines.enumerate();
+    std::iter::from_fn(move || {
+        let mut pending = String::new();
+        let mut first_line = 0;
+        for (number, line) in lines.by_ref() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e)),
+            };
+            if pending.is_empty() {
+                first_line = number + 1;
+            }
+            pending.push_str(&line);
+            pending.push('\n');
+            if !CommandParser::is_incomplete(&pending) {
+                return Some(Ok((first_line, pending)));
+            }
+        }
+        (!pending.is_empty()).then_some(Ok((first_line, pending)))
+    })
+}
+
+/// Lines of stdin, read one byte at a time
+///
+/// Reading unbuffered leaves everything after the current line in stdin, so
+/// in `printf 'cat\nhello\n' | shelly` the `cat` command gets to read
+/// `hello`. Invalid UTF-8 is replaced rather than ending the input.
+fn stdin_lines() -> impl Iterator<Item = std::io::Result<String>> {
+    // SAFETY: fd 0 stays open for the life of the process, and `ManuallyDrop`
+    // keeps this handle from closing it
+    let mut stdin = ManuallyDrop::new(unsafe { File::from_raw_fd(0) });
+    let mut done = false;
+    std::iter::from_fn(move || {
+        let mut line = Vec::new();
+        let mut byte = [0u8];
+        while !done {
+            match stdin.read(&mut byte) {
+                Ok(0) => done = true,
+                Ok(_) if byte[0] == b'\n' => break,
+                Ok(_) => line.push(byte[0]),
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        if done && line.is_empty() {
+            return None;
+        }
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        Some(Ok(String::from_utf8_lossy(&line).into_owned()))
+    })
 }
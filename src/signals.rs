@@ -0,0 +1,220 @@
+//! Signal handling and terminal control for interactive shells
+//!
+//! An interactive shell runs each job in its own process group and hands
+//! the terminal to the job in the foreground, so Ctrl+C, Ctrl+\ and Ctrl+Z
+//! reach only that job. The shell ignores those signals itself (and the
+//! SIGTTOU/SIGTTIN it would get while taking the terminal back), and every
+//! child gets the default handling restored before it execs. A Ctrl+C that
+//! reaches the shell itself, while only builtins are running, is recorded
+//! so loops can stop.
+
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set when Ctrl+C interrupted the command line being run
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Signals an interactive shell ignores but the commands it runs should not
+const JOB_CONTROL_SIGNALS: [libc::c_int; 5] = [
+    libc::SIGINT,
+    libc::SIGQUIT,
+    libc::SIGTSTP,
+    libc::SIGTTIN,
+    libc::SIGTTOU,
+];
+
+/// Set up job control: ignore keyboard signals (only recording Ctrl+C) and
+/// take over the terminal
+///
+/// The shell moves into its own process group (unless it already leads one)
+/// and makes that group the terminal's foreground group.
+pub fn init_job_control() {
+    for signal in JOB_CONTROL_SIGNALS {
+        // SAFETY: installing SIG_IGN has no memory-safety preconditions
+        unsafe { libc::signal(signal, libc::SIG_IGN) };
+    }
+    let handler: extern "C" fn(libc::c_int) = note_interrupt;
+    // SAFETY: the handler only stores to an atomic, which is async-signal-safe
+    unsafe { libc::signal(libc::SIGINT, handler as *const () as libc::sighandler_t) };
+    // SAFETY: these calls only change the process group and the terminal's
+    // foreground group; failures (e.g. for a session leader) are harmless
+    unsafe {
+        libc::setpgid(0, 0);
+        libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpgrp());
+    }
+}
+
+extern "C" fn note_interrupt(_signal: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::Relaxed);
+}
+
+/// Record that Ctrl+C interrupted the command line, as when it killed the
+/// foreground job rather than reaching the shell
+pub fn interrupt() {
+    INTERRUPTED.store(true, Ordering::Relaxed);
+}
+
+/// Whether Ctrl+C has interrupted the command line being run
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+/// Clear the interrupt before the next command line, returning whether it was set
+pub fn take_interrupt() -> bool {
+    INTERRUPTED.swap(false, Ordering::Relaxed)
+}
+
+/// Make process group `pgid` the terminal's foreground group
+pub fn give_terminal_to(pgid: u32) {
+    // SAFETY: tcsetpgrp has no memory-safety preconditions; it fails
+    // harmlessly if stdin isn't a terminal or the group is gone
+    unsafe { libc::tcsetpgrp(libc::STDIN_FILENO, pgid as libc::pid_t) };
+}
+
+/// Make the shell's own process group the terminal's foreground group again
+pub fn reclaim_terminal() {
+    // SAFETY: as for `give_terminal_to`; SIGTTOU is ignored, so a shell in
+    // the background of its terminal isn't stopped by this
+    unsafe { libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpgrp()) };
+}
+
+/// How the terminal on stdin took input before [`TerminalMode::set`]
+/// changed it; dropping this puts it back
+pub struct TerminalMode {
+    saved: libc::termios,
+}
+
+impl TerminalMode {
+    /// Have the terminal on stdin echo what's typed only if `echo`, and
+    /// hand it over a line at a time only if `lines`, rather than as each
+    /// key is pressed
+    ///
+    /// Returns `None` if stdin isn't a terminal.
+    pub fn set(echo: bool, lines: bool) -> Option<Self> {
+        // SAFETY: tcgetattr fills in the zeroed termios it's given, and
+        // tcsetattr only reads the copy changed from it
+        unsafe {
+            let mut saved: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut saved) != 0 {
+                return None;
+            }
+            let mut changed = saved;
+            if !echo {
+                changed.c_lflag &= !libc::ECHO;
+            }
+            if !lines {
+                changed.c_lflag &= !libc::ICANON;
+                changed.c_cc[libc::VMIN] = 1;
+                changed.c_cc[libc::VTIME] = 0;
+            }
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &changed);
+            Some(TerminalMode { saved })
+        }
+    }
+}
+
+impl Drop for TerminalMode {
+    fn drop(&mut self) {
+        // SAFETY: restores the settings read in `set`
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.saved) };
+    }
+}
+
+/// Prepare a child of an interactive shell for running in its job
+///
+/// A `foreground` child takes the terminal for its process group itself, so
+/// it can't read from the terminal before the shell has handed it over.
+/// Ignored signals stay ignored across `exec`, so the default handling of
+/// every signal the shell ignores is restored.
+pub fn restore_in_child(process: &mut Command, foreground: bool) {
+    // SAFETY: the hook only calls tcsetpgrp, getpgrp and signal, which are
+    // async-signal-safe
+    unsafe {
+        process.pre_exec(move || {
+            restore_in_fork(foreground);
+            Ok(())
+        });
+    }
+}
+
+/// Prepare a forked copy of an interactive shell for running as part of a
+/// job, as [`restore_in_child`] does for a command about to exec
+pub fn restore_in_fork(foreground: bool) {
+    // SAFETY: tcsetpgrp, getpgrp and signal have no memory-safety
+    // preconditions, and are async-signal-safe
+    unsafe {
+        if foreground {
+            libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpgrp());
+        }
+        for signal in JOB_CONTROL_SIGNALS {
+            libc::signal(signal, libc::SIG_DFL);
+        }
+    }
+}
+
+/// Signals by name, without the `SIG` prefix, in number order
+pub const SIGNAL_NAMES: [(&str, libc::c_int); 29] = [
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("ILL", libc::SIGILL),
+    ("TRAP", libc::SIGTRAP),
+    ("ABRT", libc::SIGABRT),
+    ("BUS", libc::SIGBUS),
+    ("FPE", libc::SIGFPE),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("SEGV", libc::SIGSEGV),
+    ("USR2", libc::SIGUSR2),
+    ("PIPE", libc::SIGPIPE),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    ("CHLD", libc::SIGCHLD),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+    ("TSTP", libc::SIGTSTP),
+    ("TTIN", libc::SIGTTIN),
+    ("TTOU", libc::SIGTTOU),
+    ("URG", libc::SIGURG),
+    ("XCPU", libc::SIGXCPU),
+    ("XFSZ", libc::SIGXFSZ),
+    ("VTALRM", libc::SIGVTALRM),
+    ("PROF", libc::SIGPROF),
+    ("WINCH", libc::SIGWINCH),
+    ("IO", libc::SIGIO),
+    ("SYS", libc::SIGSYS),
+];
+
+/// The signal `spec` names: a number, or a name in any case with or
+/// without the `SIG` prefix
+///
+/// # Examples
+/// ```
+/// use codecrafters_shell::signals::signal_number;
+///
+/// assert_eq!(signal_number("TERM"), Some(libc::SIGTERM));
+/// assert_eq!(signal_number("sighup"), Some(libc::SIGHUP));
+/// assert_eq!(signal_number("9"), Some(libc::SIGKILL));
+/// assert_eq!(signal_number("0"), Some(0));
+/// assert_eq!(signal_number("NOPE"), None);
+/// ```
+pub fn signal_number(spec: &str) -> Option<libc::c_int> {
+    if let Ok(number) = spec.parse::<libc::c_int>() {
+        return (0..=libc::SIGRTMAX()).contains(&number).then_some(number);
+    }
+    let name = spec.to_ascii_uppercase();
+    let name = name.strip_prefix("SIG").unwrap_or(&name);
+    SIGNAL_NAMES
+        .iter()
+        .find(|&&(known, _)| known == name)
+        .map(|&(_, number)| number)
+}
+
+/// The name of `signal`, without the `SIG` prefix
+pub fn signal_name(signal: libc::c_int) -> Option<&'static str> {
+    SIGNAL_NAMES
+        .iter()
+        .find(|&&(_, number)| number == signal)
+        .map(|&(name, _)| name)
+}
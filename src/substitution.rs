@@ -0,0 +1,97 @@
+//! Process substitution: `<(cmd)` and `>(cmd)`
+//!
+//! The command inside runs in a forked copy of the shell, connected to a
+//! pipe. The shell keeps the other end open on an inheritable descriptor
+//! and the word is replaced by its `/dev/fd/N` path, so the outer command
+//! can open it like a file. Once that command finishes the shell closes its
+//! end and reaps the child.
+
+use crate::error::ShellError;
+use crate::redirect::FIRST_SHELL_FD;
+use crate::shell::Shell;
+use std::io::{self, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+/// A running process substitution
+#[derive(Debug)]
+pub struct Substitution {
+    /// The forked shell running the command
+    pid: libc::pid_t,
+    /// The shell's end of the pipe, which the outer command opens by path
+    fd: OwnedFd,
+}
+
+impl Substitution {
+    /// Start `command` in a forked copy of `shell`
+    ///
+    /// With `output` set (`>(cmd)`) the command reads what is written to the
+    /// path; otherwise (`<(cmd)`) the path reads the command's output.
+    pub fn spawn(shell: &mut Shell, command: &str, output: bool) -> Result<Self, ShellError> {
+        let (reader, writer) = io::pipe()?;
+        let (kept, child_end): (OwnedFd, OwnedFd) = if output {
+            (writer.into(), reader.into())
+        } else {
+            (reader.into(), writer.into())
+        };
+        // Commands the shell runs must inherit the shell's end to open its path
+        // SAFETY: F_DUPFD returns a new descriptor we take ownership of
+        let fd = unsafe { libc::fcntl(kept.as_raw_fd(), libc::F_DUPFD, FIRST_SHELL_FD) };
+        if fd == -1 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        drop(kept);
+
+        // SAFETY: the child only uses its own copy of the shell and leaves
+        // with `_exit`, never returning into the caller
+        let pid = unsafe { libc::fork() };
+        if pid == -1 {
+            return Err(io::Error::last_os_error().into());
+        }
+        if pid == 0 {
+            let target = if output { 0 } else { 1 };
+            // SAFETY: dup2 onto a standard descriptor of this process
+            unsafe { libc::dup2(child_end.as_raw_fd(), target) };
+            drop((child_end, fd));
+            // Like any command writing to a closed pipe, end quietly once the
+            // outer command stops reading, instead of reporting EPIPE
+            // SAFETY: restoring a default disposition has no preconditions
+            unsafe { libc::signal(libc::SIGPIPE, libc::SIG_DFL) };
+            let status = shell.run_substituted(command);
+            let _ = io::stdout().flush();
+            // SAFETY: ends the forked child without running the parent's cleanup
+            unsafe { libc::_exit(status) };
+        }
+        Ok(Substitution { pid, fd })
+    }
+
+    /// The path standing in for the substitution in the outer command
+    pub fn path(&self) -> String {
+        format!("/dev/fd/{}", self.fd.as_raw_fd())
+    }
+
+    /// Close the shell's end of the pipe, then reap the child
+    ///
+    /// A substitution used by a background job is reaped on another thread
+    /// so the shell doesn't wait for the job.
+    pub fn finish(self, background: bool) {
+        let Substitution { pid, fd } = self;
+        drop(fd);
+        let reap = move || {
+            let mut raw_status = 0;
+            // SAFETY: waiting on the child we forked
+            unsafe { libc::waitpid(pid, &mut raw_status, 0) };
+        };
+        if background {
+            std::thread::spawn(reap);
+        } else {
+            reap();
+        }
+    }
+
+    /// Close the shell's end of the pipe without waiting, as a forked child
+    /// does with substitutions belonging to its parent
+    pub fn forget(self) {
+        drop(self.fd);
+    }
+}
@@ -0,0 +1,236 @@
+//! Test harness for running shell commands in isolation
+//!
+//! Enabled with the `testing` feature. Each [`ShellHarness::run`] starts the
+//! `shelly` binary with `-c` and the command string
+//! ([`ShellHarness::run_stdin`] pipes it in instead), so working-directory
+//! and environment changes never leak into the test process or between runs,
+//! and no shell code runs in a fork of the multithreaded test runner. Only
+//! available on Unix.
+
+use std::io::{self, Write};
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Counter used to give every harness its own temporary directory
+static HARNESS_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Result of running a command string through the harness
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HarnessOutput {
+    /// Everything written to stdout
+    pub stdout: Vec<u8>,
+    /// Everything written to stderr
+    pub stderr: Vec<u8>,
+    /// Exit status of the shell (`128 + signal` if it was killed by a signal)
+    pub status: i32,
+}
+
+impl HarnessOutput {
+    /// Stdout decoded as UTF-8 (lossily)
+    pub fn stdout_str(&self) -> String {
+        String::from_utf8_lossy(&self.stdout).into_owned()
+    }
+
+    /// Stderr decoded as UTF-8 (lossily)
+    pub fn stderr_str(&self) -> String {
+        String::from_utf8_lossy(&self.stderr).into_owned()
+    }
+}
+
+/// Runs command strings against an isolated working directory and environment
+///
+/// Every harness owns a temporary directory that is used as the shell's
+/// working directory and `HOME`, and is removed when the harness is dropped.
+/// The child's environment contains only `HOME`, the parent's `PATH`, and
+/// any variables added with [`ShellHarness::env`].
+///
+/// Shell code under test runs in the `shelly` binary that Cargo builds for
+/// the package's integration tests. To test plugin builtins, build a binary
+/// that registers them (see `examples/plugin_shell.rs`) and run that one
+/// with [`ShellHarness::binary`].
+///
+/// # Examples
+/// ```no_run
+/// use codecrafters_shell::testing::ShellHarness;
+///
+/// let mut harness = ShellHarness::new().unwrap();
+/// let output = harness.run("echo hello");
+/// assert_eq!(output.stdout_str(), "hello\n");
+/// ```
+pub struct ShellHarness {
+    dir: PathBuf,
+    binary: PathBuf,
+    env: Vec<(String, String)>,
+    args: Vec<String>,
+    login: bool,
+    interactive: bool,
+    restricted: bool,
+}
+
+impl ShellHarness {
+    /// Create a harness with a fresh, empty temporary directory
+    pub fn new() -> io::Result<Self> {
+        let dir = std::env::temp_dir().join(format!(
+            "shelly-harness-{}-{}",
+            std::process::id(),
+            HARNESS_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&dir)?;
+        // Resolve symlinks (e.g. /tmp on macOS) so `pwd` output is predictable
+        let dir = dir.canonicalize()?;
+        Ok(Self {
+            dir,
+            binary: shelly_binary(),
+            env: Vec::new(),
+            args: Vec::new(),
+            login: false,
+            interactive: false,
+            restricted: false,
+        })
+    }
+
+    /// Set an environment variable for commands run by this harness
+    pub fn env(mut self, key: &str, value: &str) -> Self {
+        self.env.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Run commands with another shell binary instead of `shelly`
+    ///
+    /// It is started with the same arguments as `shelly` would be, so it
+    /// should parse them with [`Cli`](crate::cli::Cli). A crate's
+    /// integration tests can name its own binaries with
+    /// `env!("CARGO_BIN_EXE_<name>")`.
+    pub fn binary(mut self, path: impl Into<PathBuf>) -> Self {
+        self.binary = path.into();
+        self
+    }
+
+    /// Pass arguments after the command string, as in `shelly -c CMD NAME ARGS...`
+    pub fn args(mut self, args: &[&str]) -> Self {
+        self.args = args.iter().map(|arg| arg.to_string()).collect();
+        self
+    }
+
+    /// Run as a login shell, as with `shelly -l`
+    pub fn login(mut self) -> Self {
+        self.login = true;
+        self
+    }
+
+    /// Force an interactive shell, as with `shelly -i`
+    ///
+    /// Stdin still isn't a terminal, so this gets the interactive-only
+    /// behaviour of running commands, not the prompt.
+    pub fn interactive(mut self) -> Self {
+        self.interactive = true;
+        self
+    }
+
+    /// Run as a restricted shell, as with `shelly -r`
+    pub fn restricted(mut self) -> Self {
+        self.restricted = true;
+        self
+    }
+
+    /// The temporary directory commands run in
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Run a command string and capture its output and exit status
+    ///
+    /// The shell's stdin is empty.
+    ///
+    /// # Panics
+    /// Panics if the shell binary can't be started.
+    pub fn run(&mut self, input: &str) -> HarnessOutput {
+        self.run_with(Some(input), b"")
+    }
+
+    /// Feed `input` to the shell on stdin, as in `printf ... | shelly`
+    ///
+    /// The shell reads its commands from the pipe, and commands it runs can
+    /// read the lines the shell hasn't reached yet.
+    ///
+    /// # Panics
+    /// Panics if the shell binary can't be started.
+    pub fn run_stdin(&mut self, input: &str) -> HarnessOutput {
+        self.run_with(None, input.as_bytes())
+    }
+
+    /// Run `command` (or, without one, whatever arrives on stdin) with `stdin` as its input
+    fn run_with(&mut self, command: Option<&str>, stdin: &[u8]) -> HarnessOutput {
+        let mut process = Command::new(&self.binary);
+        if self.login {
+            process.arg("--login");
+        }
+        if self.interactive {
+            process.arg("-i");
+        }
+        if self.restricted {
+            process.arg("-r");
+        }
+        if let Some(command) = command {
+            process.arg("-c").arg(command).args(&self.args);
+        }
+        process
+            .current_dir(&self.dir)
+            .env_clear()
+            .env("HOME", &self.dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        if let Some(path) = std::env::var_os("PATH") {
+            process.env("PATH", path);
+        }
+        process.envs(self.env.iter().map(|(key, value)| (key, value)));
+
+        let mut child = process.spawn().expect("failed to start the shell");
+        let mut stdin_writer = child.stdin.take().expect("child stdin is piped");
+        let stdin = stdin.to_vec();
+        let stdin_thread = std::thread::spawn(move || {
+            // The shell may exit before reading everything; that's not an error here
+            let _ = stdin_writer.write_all(&stdin);
+        });
+        let output = child
+            .wait_with_output()
+            .expect("failed to wait for the shell");
+        let _ = stdin_thread.join();
+
+        let status = output
+            .status
+            .code()
+            .or_else(|| output.status.signal().map(|signal| 128 + signal))
+            .unwrap_or(1);
+        HarnessOutput {
+            stdout: output.stdout,
+            stderr: output.stderr,
+            status,
+        }
+    }
+}
+
+/// Path of the `shelly` binary built alongside the running test
+///
+/// Cargo builds a package's binaries before its integration tests, next to
+/// the `deps` directory the test executable runs from.
+fn shelly_binary() -> PathBuf {
+    let mut dir = std::env::current_exe().expect("test executable has a path");
+    dir.pop();
+    if dir.ends_with("deps") {
+        dir.pop();
+    }
+    dir.join(format!(
+        "codecrafters-shell{}",
+        std::env::consts::EXE_SUFFIX
+    ))
+}
+
+impl Drop for ShellHarness {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
@@ -0,0 +1,124 @@
+//! Measuring how long commands take, for `time` and `REPORTTIME`
+
+use std::time::{Duration, Instant};
+
+/// How `time` prints its report
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimeFormat {
+    /// bash's layout: `real\t0m1.234s`, one line per figure
+    #[default]
+    Default,
+    /// `time -p`: `real 1.23`, in seconds
+    Posix,
+}
+
+/// Elapsed wall-clock and CPU time
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Times {
+    /// Wall-clock time
+    pub real: Duration,
+    /// CPU time spent in user mode, by the shell and the commands it waited for
+    pub user: Duration,
+    /// CPU time spent in the kernel, by the shell and the commands it waited for
+    pub sys: Duration,
+}
+
+impl Times {
+    /// The report `time` prints, ending in a newline
+    ///
+    /// # Examples
+    /// ```
+    /// use codecrafters_shell::timing::{TimeFormat, Times};
+    /// use std::time::Duration;
+    ///
+    /// let times = Times {
+    ///     real: Duration::from_millis(61_500),
+    ///     user: Duration::from_millis(1_250),
+    ///     sys: Duration::from_millis(40),
+    /// };
+    /// assert_eq!(
+    ///     times.report(TimeFormat::Default),
+    ///     "\nreal\t1m1.500s\nuser\t0m1.250s\nsys\t0m0.040s\n"
+    /// );
+    /// assert_eq!(
+    ///     times.report(TimeFormat::Posix),
+    ///     "real 61.50\nuser 1.25\nsys 0.04\n"
+    /// );
+    /// ```
+    pub fn report(&self, format: TimeFormat) -> String {
+        let figures = [("real", self.real), ("user", self.user), ("sys", self.sys)];
+        match format {
+            TimeFormat::Default => {
+                let mut report = String::from("\n");
+                for (name, duration) in figures {
+                    let millis = duration.as_millis();
+                    report.push_str(&format!(
+                        "{}\t{}m{}.{:03}s\n",
+                        name,
+                        millis / 60_000,
+                        millis / 1000 % 60,
+                        millis % 1000
+                    ));
+                }
+                report
+            }
+            TimeFormat::Posix => figures
+                .iter()
+                .map(|(name, duration)| format!("{} {:.2}\n", name, duration.as_secs_f64()))
+                .collect(),
+        }
+    }
+}
+
+/// Measures the time taken from when it's started
+#[derive(Debug, Clone, Copy)]
+pub struct Stopwatch {
+    started: Instant,
+    user: Duration,
+    sys: Duration,
+}
+
+impl Stopwatch {
+    /// Start measuring now
+    pub fn start() -> Self {
+        let (user, sys) = cpu_times();
+        Stopwatch {
+            started: Instant::now(),
+            user,
+            sys,
+        }
+    }
+
+    /// The time taken so far
+    ///
+    /// CPU time only includes commands that have been waited for, so a
+    /// background job's isn't counted.
+    pub fn elapsed(&self) -> Times {
+        let (user, sys) = cpu_times();
+        Times {
+            real: self.started.elapsed(),
+            user: user.saturating_sub(self.user),
+            sys: sys.saturating_sub(self.sys),
+        }
+    }
+}
+
+/// User and system CPU time of the shell plus its waited-for children
+fn cpu_times() -> (Duration, Duration) {
+    let (mut user, mut sys) = (Duration::ZERO, Duration::ZERO);
+    for who in [libc::RUSAGE_SELF, libc::RUSAGE_CHILDREN] {
+        // SAFETY: getrusage fills in the zeroed struct it's given
+        let usage = unsafe {
+            let mut usage: libc::rusage = std::mem::zeroed();
+            libc::getrusage(who, &mut usage);
+            usage
+        };
+        user += timeval_duration(usage.ru_utime);
+        sys += timeval_duration(usage.ru_stime);
+    }
+    (user, sys)
+}
+
+fn timeval_duration(time: libc::timeval) -> Duration {
+    Duration::new(time.tv_sec as u64, time.tv_usec as u32 * 1000)
+}
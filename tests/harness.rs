@@ -0,0 +1,1474 @@
+use codecrafters_shell::testing::ShellHarness;
+use std::path::PathBuf;
+
+#[test]
+fn captures_builtin_stdout() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run("echo hello world");
+    assert_eq!(output.stdout_str(), "hello world\n");
+    assert_eq!(output.status, 0);
+}
+
+#[test]
+fn runs_in_isolated_directory() {
+    let mut harness = ShellHarness::new().unwrap();
+    let expected = format!("{}\n", harness.dir().display());
+    assert_eq!(harness.run("pwd").stdout_str(), expected);
+
+    // A cd in one run doesn't affect the next
+    harness.run("cd /");
+    assert_eq!(harness.run("pwd").stdout_str(), expected);
+}
+
+#[test]
+fn passes_configured_environment_to_children() {
+    let mut harness = ShellHarness::new().unwrap().env("GREETING", "hi there");
+    assert_eq!(harness.run("printenv GREETING").stdout_str(), "hi there\n");
+}
+
+#[test]
+fn login_shells_source_the_profile_and_logout_files() {
+    let harness = ShellHarness::new().unwrap();
+    std::fs::write(
+        harness.dir().join(".shelly_profile"),
+        "export GREETING=hi\nlater() { echo \"bye $GREETING\"; }\n",
+    )
+    .unwrap();
+    std::fs::write(harness.dir().join(".shelly_logout"), "later\n").unwrap();
+
+    let mut login = harness.login();
+    let output = login.run("echo \"$GREETING\"; [ -n \"$SHELL\" ] && echo shell; exit 3");
+    assert_eq!(output.stdout_str(), "hi\nshell\nbye hi\n");
+    assert_eq!(output.status, 3);
+
+    let mut harness = ShellHarness::new().unwrap();
+    std::fs::write(harness.dir().join(".shelly_profile"), "echo profile\n").unwrap();
+    assert_eq!(harness.run("echo plain").stdout_str(), "plain\n");
+}
+
+#[test]
+fn restricted_shells_keep_path_shell_and_env() {
+    let mut harness = ShellHarness::new().unwrap().restricted();
+    let output = harness.run(
+        "PATH=/tmp; echo $?; export PATH=/tmp; echo $?\n\
+         SHELL=/bin/sh; ENV=rc; unset PATH; PATH=/tmp printenv PATH\n\
+         cd /; echo $?",
+    );
+    assert_eq!(output.stdout_str(), "1\n1\n1\n");
+    assert_eq!(
+        output.stderr_str(),
+        "shelly: line 1: PATH: readonly variable\n\
+         shelly: line 1: export: PATH: readonly variable\n\
+         shelly: line 2: SHELL: readonly variable\n\
+         shelly: line 2: ENV: readonly variable\n\
+         shelly: line 2: unset: PATH: cannot unset: readonly variable\n\
+         shelly: line 2: PATH: readonly variable\n\
+         shelly: line 3: cd: restricted\n"
+    );
+}
+
+#[test]
+fn interactive_shells_source_the_rc_file() {
+    let harness = ShellHarness::new().unwrap();
+    std::fs::write(
+        harness.dir().join(".shellyrc"),
+        "alias hi='echo rc says hi'\nRCVAR=set\n",
+    )
+    .unwrap();
+
+    let mut interactive = harness.interactive();
+    let output = interactive.run_stdin("hi\necho \"[$RCVAR]\"\n");
+    assert_eq!(output.stdout_str(), "rc says hi\n[set]\n");
+
+    let mut harness = ShellHarness::new().unwrap();
+    std::fs::write(harness.dir().join(".shellyrc"), "RCVAR=set\n").unwrap();
+    assert_eq!(harness.run("echo \"[$RCVAR]\"").stdout_str(), "[]\n");
+}
+
+#[test]
+fn redirection_writes_into_harness_directory() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run("echo saved > out.txt");
+    assert!(output.stdout.is_empty());
+    let saved = std::fs::read_to_string(harness.dir().join("out.txt")).unwrap();
+    assert_eq!(saved, "saved\n");
+}
+
+/// Path of `examples/plugin_shell.rs`, which `cargo test` builds with the tests
+fn plugin_shell() -> PathBuf {
+    let mut dir = std::env::current_exe().unwrap();
+    dir.pop();
+    if dir.ends_with("deps") {
+        dir.pop();
+    }
+    dir.join("examples")
+        .join(format!("plugin_shell{}", std::env::consts::EXE_SUFFIX))
+}
+
+#[test]
+fn runs_plugin_builtins() {
+    let mut harness = ShellHarness::new().unwrap().binary(plugin_shell());
+    let output = harness.run("greet shelly > greeting.txt; type -t greet");
+    assert_eq!(output.stdout_str(), "builtin\n");
+    assert_eq!(output.status, 0);
+    let saved = std::fs::read_to_string(harness.dir().join("greeting.txt")).unwrap();
+    assert_eq!(saved, "hello, shelly\n");
+}
+
+#[test]
+fn exits_with_last_command_status() {
+    let mut harness = ShellHarness::new().unwrap();
+    assert_eq!(harness.run("false").status, 1);
+    assert_eq!(harness.run("true").status, 0);
+    assert_eq!(harness.run("exit 3").status, 3);
+    assert_eq!(harness.run("no-such-command").status, 127);
+}
+
+#[test]
+fn reports_errors_with_their_position() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run("no-such-command");
+    assert_eq!(output.stdout_str(), "");
+    assert_eq!(
+        output.stderr_str(),
+        "shelly: line 1: no-such-command: command not found\n"
+    );
+}
+
+#[test]
+fn spawn_errors_follow_the_commands_stderr_redirect() {
+    let mut harness = ShellHarness::new().unwrap();
+    std::fs::write(harness.dir().join("notexec"), "").unwrap();
+    let output = harness.run(
+        "nosuchcmd 2>/dev/null; echo $?; ./notexec 2>/dev/null; echo $?\n\
+         nosuchcmd 2>&1 | tr a-z A-Z; nosuchcmd 2>err.txt; cat err.txt",
+    );
+    assert_eq!(
+        output.stdout_str(),
+        "127\n126\nSHELLY: LINE 2: NOSUCHCMD: COMMAND NOT FOUND\n\
+         shelly: line 2: nosuchcmd: command not found\n"
+    );
+    assert_eq!(output.stderr_str(), "");
+}
+
+#[test]
+fn syntax_errors_name_the_unexpected_token_and_stop_the_shell() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run("echo before\nif true; then\n  echo x\ndone\necho after");
+    assert_eq!(output.stdout_str(), "before\n");
+    assert_eq!(
+        output.stderr_str(),
+        "shelly: line 4: syntax error near unexpected token `done'\n"
+    );
+    assert_eq!(output.status, 2);
+
+    let output = harness.run("echo >");
+    assert_eq!(
+        output.stderr_str(),
+        "shelly: line 1: syntax error near unexpected token `newline'\n"
+    );
+    let output = harness.run("echo 'unclosed");
+    assert_eq!(
+        output.stderr_str(),
+        "shelly: line 1: syntax error: unexpected end of file\n"
+    );
+}
+
+#[test]
+fn suggests_a_close_name_for_a_missing_command() {
+    let script = "deploy() { :; }; depoly; alias ll='ls -l'; lll; x";
+    let mut harness = ShellHarness::new().unwrap();
+    assert_eq!(
+        harness.run(script).stderr_str(),
+        "shelly: line 1: depoly: command not found\n\
+         shelly: line 1: lll: command not found\n\
+         shelly: line 1: x: command not found\n"
+    );
+
+    let mut harness = ShellHarness::new().unwrap().interactive();
+    let output = harness.run(script);
+    assert_eq!(
+        output.stderr_str(),
+        "shelly: line 1: depoly: command not found\n\
+         shelly: did you mean `deploy`?\n\
+         shelly: line 1: lll: command not found\n\
+         shelly: did you mean `ll`?\n\
+         shelly: line 1: x: command not found\n"
+    );
+}
+
+#[test]
+fn negation_inverts_exit_status() {
+    let mut harness = ShellHarness::new().unwrap();
+    assert_eq!(harness.run("! false").status, 0);
+    assert_eq!(harness.run("! true").status, 1);
+    assert_eq!(harness.run("! no-such-command").status, 0);
+
+    std::fs::write(harness.dir().join("words.txt"), "foo\n").unwrap();
+    let output = harness.run(
+        "if ! grep -q bar words.txt; then echo missing; fi\n\
+         ! grep -q foo words.txt || echo found\n\
+         ! { false; } && ! (true); echo $?\n\
+         set -o pipefail; (exit 4) | (exit 5) | true; echo $?; ! false | true; echo $?",
+    );
+    assert_eq!(output.stdout_str(), "missing\nfound\n1\n5\n0\n");
+}
+
+#[test]
+fn pipes_output_between_stages() {
+    let mut harness = ShellHarness::new().unwrap();
+    assert_eq!(
+        harness.run("echo hello | tr a-z A-Z").stdout_str(),
+        "HELLO\n"
+    );
+    assert_eq!(harness.run("echo hello | false").status, 1);
+    assert_eq!(harness.run("false | echo done").stdout_str(), "done\n");
+}
+
+#[test]
+fn background_jobs_set_last_pid_without_a_notice_outside_the_prompt() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run("sleep 0 & echo \"pid $!\"; { sleep 0 & } > notice.txt");
+    assert!(output.stdout_str().starts_with("pid "));
+    assert!(output.stdout_str()[4..].trim().parse::<u32>().is_ok());
+    assert_eq!(output.stderr_str(), "");
+    assert_eq!(output.status, 0);
+    let notice = std::fs::read_to_string(harness.dir().join("notice.txt")).unwrap();
+    assert_eq!(notice, "");
+}
+
+#[test]
+fn background_output_goes_straight_to_stdout() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run("sh -c 'echo early; sleep 2' & sleep 0.3; echo later; kill %1");
+    assert_eq!(output.stdout_str(), "early\nlater\n");
+}
+
+#[test]
+fn wait_returns_the_status_of_background_jobs() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run(
+        "sh -c 'sleep 0.2; exit 4' & sh -c 'sleep 0.1; echo out; exit 5' &\n\
+         wait %1; echo $?; wait $!; echo $?\n\
+         sh -c 'exit 3' & sleep 0.1\n\
+         wait $!; echo $?; wait 1; echo $?; sleep 0.1 & wait; echo $?",
+    );
+    assert_eq!(output.stdout_str(), "out\n4\n5\n3\n127\n0\n");
+    assert_eq!(
+        output.stderr_str(),
+        "wait: pid 1 is not a child of this shell\n"
+    );
+}
+
+#[test]
+fn disown_takes_jobs_out_of_the_job_table() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run(
+        "sleep 0.3 & sleep 0.3 & sleep 0.3 &\n\
+         disown -h %1; disown %2; jobs; wait $!; disown -a; jobs; disown %1",
+    );
+    let stdout = output.stdout_str();
+    let listed: Vec<&str> = stdout
+        .lines()
+        .filter(|line| line.contains("Running"))
+        .collect();
+    assert_eq!(listed.len(), 2, "{}", stdout);
+    assert!(listed[0].starts_with("[1]  ") && listed[1].starts_with("[3]  "));
+    assert_eq!(
+        output.stderr_str(),
+        "shelly: line 2: disown: %1: no such job\n"
+    );
+}
+
+#[test]
+fn kill_signals_jobs_by_spec_without_an_external_kill() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run(
+        "sleep 5 & PATH=/nonexistent; kill -s HUP %1; echo $?; /bin/sleep 0.2; jobs\n\
+         kill %4; echo $?; kill -l 143 KILL; kill -FOO 1; echo $?",
+    );
+    let stdout = output.stdout_str();
+    assert!(stdout.starts_with("0\n[1]  "), "{}", stdout);
+    assert!(
+        stdout.contains("Exit 129  sleep 5\n1\nTERM\n9\n1\n"),
+        "{}",
+        stdout
+    );
+    assert_eq!(
+        output.stderr_str(),
+        "kill: %4: no such job\nshelly: line 2: kill: FOO: invalid signal specification\n"
+    );
+}
+
+#[test]
+fn fg_and_bg_move_jobs_between_foreground_and_background() {
+    let mut harness = ShellHarness::new().unwrap().interactive();
+    let output = harness.run_stdin(
+        "sleep 0.3 &\njobs\nfg %1\necho \"fg $?\"\nbg\necho \"bg $?\"\n\
+         sh -c 'kill -STOP $$; echo resumed'\necho \"stopped $?\"\njobs\n\
+         bg %1\nwait %1; echo \"wait $?\"; jobs\n",
+    );
+    let stdout = output.stdout_str();
+    let lines: Vec<&str> = stdout.lines().filter(|line| !line.is_empty()).collect();
+    assert_eq!(lines.len(), 10, "{}", stdout);
+    assert!(lines[0].starts_with("[1]  ") && lines[0].ends_with("  Running  sleep 0.3"));
+    assert_eq!(lines[1..4], ["sleep 0.3", "fg 0", "bg 1"]);
+    assert_eq!(
+        lines[4],
+        "[1]+  Stopped  sh -c 'kill -STOP $$; echo resumed'"
+    );
+    assert_eq!(lines[5], "stopped 148");
+    assert!(lines[6].ends_with("  Stopped  sh -c 'kill -STOP $$; echo resumed'"));
+    assert_eq!(
+        lines[7..],
+        [
+            "[1]  sh -c 'kill -STOP $$; echo resumed' &",
+            "resumed",
+            "wait 0"
+        ]
+    );
+    assert!(output.stderr_str().contains("bg: current: no such job"));
+}
+
+#[test]
+fn interactive_shells_survive_interrupts_that_stop_their_commands() {
+    let script = "kill -INT $$\necho alive\nsh -c 'kill -INT $$'\necho \"child $?\"\n";
+    let mut harness = ShellHarness::new().unwrap().interactive();
+    let output = harness.run_stdin(script);
+    assert_eq!(output.stdout_str(), "alive\nchild 130\n");
+    assert_eq!(output.status, 0);
+
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run_stdin(script);
+    assert_eq!(output.stdout_str(), "");
+    assert_eq!(output.status, 130);
+}
+
+#[test]
+fn interactive_jobs_run_in_their_own_process_groups() {
+    // Each line is a command's pid and process group
+    let script = "sh -c 'echo $$ `ps -o pgid= -p $$`'\n\
+                  true | sh -c 'echo $$ `ps -o pgid= -p $$`'\n\
+                  ps -o pgid= -p $$\n";
+    let groups = |output: String| -> Vec<Vec<u32>> {
+        let parse = |line: &str| {
+            line.split_whitespace()
+                .map(|n| n.parse().unwrap())
+                .collect()
+        };
+        output.lines().map(parse).collect()
+    };
+
+    let mut harness = ShellHarness::new().unwrap().interactive();
+    let lines = groups(harness.run_stdin(script).stdout_str());
+    let shell_group = lines[2][0];
+    // A command leads its own group, and a later pipeline stage joins the first's
+    assert_eq!(lines[0][0], lines[0][1]);
+    assert_ne!(lines[1][0], lines[1][1]);
+    assert!(lines[..2].iter().all(|line| line[1] != shell_group));
+
+    let mut harness = ShellHarness::new().unwrap();
+    let lines = groups(harness.run_stdin(script).stdout_str());
+    let shell_group = lines[2][0];
+    assert!(lines[..2].iter().all(|line| line[1] == shell_group));
+}
+
+#[test]
+fn stopped_foreground_jobs_stay_in_the_job_table() {
+    let mut harness = ShellHarness::new().unwrap().interactive();
+    let output = harness.run_stdin(
+        "sh -c 'kill -STOP $$; echo resumed; exit 3'\necho \"stopped $?\"\n\
+         jobs\nfg\necho \"fg $?\"\njobs\n",
+    );
+    let stdout = output.stdout_str();
+    let lines: Vec<&str> = stdout.lines().filter(|line| !line.is_empty()).collect();
+    assert_eq!(lines.len(), 6, "{}", stdout);
+    assert_eq!(
+        lines[0],
+        "[1]+  Stopped  sh -c 'kill -STOP $$; echo resumed; exit 3'"
+    );
+    assert_eq!(lines[1], "stopped 148");
+    assert!(lines[2].starts_with("[1]  ") && lines[2].contains("  Stopped  "));
+    assert_eq!(
+        lines[3..],
+        [
+            "sh -c 'kill -STOP $$; echo resumed; exit 3'",
+            "resumed",
+            "fg 3"
+        ]
+    );
+    assert_eq!(output.status, 0);
+}
+
+#[test]
+fn read_splits_lines_into_variables() {
+    let mut harness = ShellHarness::new().unwrap();
+    std::fs::write(
+        harness.dir().join("in.txt"),
+        "a b\\ c  d  \nnext\\\nline\nlast",
+    )
+    .unwrap();
+    let output = harness.run(
+        "read x y < in.txt; echo \"[$x][$y]\"\n\
+         while read -r line; do echo \"<$line>\"; done < in.txt; echo $?\n\
+         printf '1 2\\n3 4\\n' | while read a b; do echo $b$a; done\n\
+         IFS=: read u v w <<< 'a:b::c'; echo \"[$u][$v][$w]\"\n\
+         read -n 3 < in.txt; echo \"$REPLY\"; read -x; echo $?",
+    );
+    assert_eq!(
+        output.stdout_str(),
+        "[a][b c  d]\n<a b\\ c  d>\n<next\\>\n<line>\n0\n21\n43\n[a][b][:c]\na b\n2\n"
+    );
+}
+
+#[test]
+fn pushd_and_popd_keep_a_directory_stack() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run(&format!(
+        "mkdir a b; HOME={}\n\
+         pushd a; pushd ../b; dirs -v; pushd +2; pwd\n\
+         popd; popd +1; popd; echo $?",
+        harness.dir().display()
+    ));
+    assert_eq!(
+        output.stdout_str(),
+        format!(
+            "~/a ~\n~/b ~/a ~\n 0  ~/b\n 1  ~/a\n 2  ~\n~ ~/b ~/a\n{}\n~/b ~/a\n~/b\n1\n",
+            harness.dir().display()
+        )
+    );
+    assert!(output
+        .stderr_str()
+        .ends_with("popd: directory stack empty\n"));
+}
+
+#[test]
+fn cd_dash_returns_to_oldpwd_and_cdpath_finds_directories() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run(
+        "mkdir -p a/b c; cd a; cd -; echo $OLDPWD\n\
+         CDPATH=:a; cd b; sh -c 'echo $PWD'; cd c",
+    );
+    let dir = harness.dir().display().to_string();
+    assert_eq!(
+        output.stdout_str(),
+        format!("{dir}\n{dir}/a\n{dir}/a/b\n{dir}/a/b\n")
+    );
+    assert!(output
+        .stderr_str()
+        .contains("cd: c: No such file or directory"));
+}
+
+#[test]
+fn cd_keeps_symbolic_links_in_the_logical_path() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run(
+        "mkdir -p real/sub; ln -s real/sub link\n\
+         cd link; pwd; pwd -P; cd ..; pwd\n\
+         cd -P link; pwd; cd ..; pwd -L",
+    );
+    let dir = std::fs::canonicalize(harness.dir()).unwrap();
+    let dir = dir.display();
+    assert_eq!(
+        output.stdout_str(),
+        format!("{dir}/link\n{dir}/real/sub\n{dir}\n{dir}/real/sub\n{dir}/real\n")
+    );
+}
+
+#[test]
+fn unset_removes_variables_and_functions() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run(
+        "X=1; export Y=2; f() { echo f; }\n\
+         unset X Y; echo \"[$X][$Y]\"; sh -c 'echo [$Y]'\n\
+         unset -v f; f; unset f; f; unset 1x; echo $?",
+    );
+    assert_eq!(output.stdout_str(), "[][]\n[]\nf\n1\n");
+    assert!(output.stderr_str().contains("f: command not found"));
+    assert!(output
+        .stderr_str()
+        .contains("unset: `1x': not a valid identifier"));
+}
+
+#[test]
+fn local_variables_are_restored_when_the_function_returns() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run(
+        "x=g; export e=ge; g() { echo \"g $x\"; }\n\
+         f() { local x=l e; echo \"[$x][$e]\"; e=le; sh -c 'echo [$e]'; g; local; }\n\
+         f; echo \"$x $e\"; sh -c 'echo [$e]'\n\
+         h() { local -r r=1; r=2; local r=3; echo $?; }; h; r=4; echo $r\n\
+         local y; echo $?",
+    );
+    assert_eq!(
+        output.stdout_str(),
+        "[l][]\n[le]\ng l\nx='l'\ne='le'\ng ge\n[ge]\n1\n4\n1\n"
+    );
+    assert!(output.stderr_str().contains("r: readonly variable"));
+    assert!(output
+        .stderr_str()
+        .contains("local: can only be used in a function"));
+}
+
+#[test]
+fn local_takes_array_assignments() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run(
+        "a=(global); f() { local -a a=(1 \"2 3\"); local b=(\"$@\"\n x) c=; \
+         echo \"${#a[@]} ${a[1]} ${#b[@]} ${b[2]} [$c]\"; }\n\
+         f p q; echo \"${a[@]} [${b[@]}]\"\n\
+         echo local x=(1)",
+    );
+    assert_eq!(output.stdout_str(), "2 2 3 3 x []\nglobal []\n");
+    assert_eq!(
+        output.stderr_str(),
+        "shelly: line 4: syntax error near unexpected token `('\n"
+    );
+}
+
+#[test]
+fn readonly_variables_cannot_be_assigned_or_unset() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run(
+        "readonly x=1 y; x=2; echo $?\n\
+         unset x; echo $?; export x=3; echo $?; y=1; echo \"$x[$y]\"\n\
+         readonly",
+    );
+    assert_eq!(
+        output.stdout_str(),
+        "1\n1\n1\n1[]\nreadonly x='1'\nreadonly y\n"
+    );
+    assert!(output.stderr_str().contains("line 1: x: readonly variable"));
+    assert!(output
+        .stderr_str()
+        .contains("unset: x: cannot unset: readonly variable"));
+}
+
+#[test]
+fn arrays_expand_to_one_field_per_element() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run(
+        "arr=(a \"b c\" d); echo ${arr[1]} ${#arr[@]} $arr ${arr[-1]}\n\
+         for x in \"${arr[@]}\"; do echo \"[$x]\"; done; for x in \"${arr[*]}\"; do echo \"<$x>\"; done\n\
+         arr+=(e); i=2; arr[$i]=D; arr[9]=z; echo \"${arr[@]}\" ${#arr[@]}\n\
+         empty=(); for x in \"${empty[@]}\"; do echo never; done; set | grep '^arr='",
+    );
+    assert_eq!(
+        output.stdout_str(),
+        "b c 3 a d\n[a]\n[b c]\n[d]\n<a b c d>\na b c D e z 5\n\
+         arr=([0]='a' [1]='b c' [2]='D' [3]='e' [9]='z')\n"
+    );
+}
+
+#[test]
+fn unset_removes_single_array_elements() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run(
+        "a=(x y z w); unset 'a[1]'; echo \"${#a[@]} ${a[@]}\"\n\
+         i=2; unset \"a[$i]\" 'a[-1]' 'a[7]'; set | grep '^a='\n\
+         s=v; unset 's[0]'; echo \"[${s-unset}]\"\n\
+         r=(1); readonly r; unset 'r[0]'; echo $? ${r[0]}",
+    );
+    assert_eq!(output.stdout_str(), "3 x z w\na=([0]='x')\n[unset]\n1 1\n");
+    assert!(output
+        .stderr_str()
+        .contains("unset: r: cannot unset: readonly variable"));
+}
+
+#[test]
+fn parameter_operators_change_the_expanded_value() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run(
+        "x=abcdef; p=a.tar.gz; echo ${x:1:3} ${x: -2} ${#x} ${x/c*/Z} ${x//[ace]/-}\n\
+         echo ${p%.*} ${p%%.*} ${p#*.} ${p##*.}\n\
+         e=; echo \"[${e-x}][${e:-y}]\" ${u:-\"a b\"} ${v:=set} $v ${x:+alt} \"[${u:+alt}]\"\n\
+         echo ${u:?is required}; echo never",
+    );
+    assert_eq!(
+        output.stdout_str(),
+        "bcd ef 6 abZ -b-d-f\na.tar a tar.gz gz\n[][y] a b set set alt []\n"
+    );
+    assert_eq!(output.status, 127);
+    assert!(output.stderr_str().contains("u: is required"));
+}
+
+#[test]
+fn quoted_at_keeps_each_positional_parameter_a_separate_word() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run(
+        "f() { for a in \"$@\"; do echo \"[$a]\"; done; echo \"$*\"; shift; echo $# $1; shift 5; echo $?; }\n\
+         f 'x y' z; set -- a b c; echo ${@:2}; shift 2; echo \"$@\"; set --; for a in \"$@\"; do echo never; done",
+    );
+    assert_eq!(output.stdout_str(), "[x y]\n[z]\nx y z\n1 z\n1\nb c\nc\n");
+}
+
+#[test]
+fn set_turns_on_errexit_nounset_xtrace_and_pipefail() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run(
+        "set -o pipefail; false | true; echo $?\n\
+         set -x; echo \"a b\" c; set +x\n\
+         set -- x y; echo $# $2 \"[$-]\"\n\
+         set -eu; if false; then :; fi; false || true; ! true\n\
+         f() { false; echo skipped; }; f; echo never",
+    );
+    assert_eq!(output.stdout_str(), "1\na b c\n2 y []\n");
+    assert_eq!(output.status, 1);
+    assert!(output.stderr_str().contains("+ echo 'a b' c\n+ set +x\n"));
+
+    let output = harness.run("set -u; echo $nope; echo never");
+    assert_eq!(output.status, 127);
+    assert!(output.stderr_str().contains("nope: unbound variable"));
+}
+
+#[test]
+fn ulimit_limits_the_commands_the_shell_runs() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run(
+        "ulimit -n 64; sh -c 'ulimit -n'; ulimit -Sn 32; ulimit -Sn; ulimit -Hn\n\
+         ulimit -c 0; ulimit -c -n; ulimit -n lots",
+    );
+    assert_eq!(
+        output.stdout_str(),
+        "64\n32\n64\ncore file size              (blocks, -c) 0\nopen files                          (-n) 32\n"
+    );
+    assert!(output
+        .stderr_str()
+        .ends_with("ulimit: lots: invalid number\n"));
+}
+
+#[test]
+fn echo_takes_n_e_and_capital_e() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run(
+        "echo -n a; echo b\n\
+         echo -e 'x\\ty\\x41\\0102'; echo -E 'x\\ty'\n\
+         echo -e 'stop\\chere'; echo -nx -",
+    );
+    assert_eq!(output.stdout_str(), "ab\nx\tyAB\nx\\ty\nstop-nx -\n");
+}
+
+#[test]
+fn printf_formats_and_reuses_its_format() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run(
+        "printf '%-4s|%03d|%x\\n' a 7 255 bb 12 16\n\
+         printf '%.2f %5.1e %*s %b\\t%%\\n' 3.14159 1234 3 x 'y\\101'\n\
+         printf '%d\\n' 4x; echo $?",
+    );
+    assert_eq!(
+        output.stdout_str(),
+        "a   |007|ff\nbb  |012|10\n3.14 1.2e+03   x yA\t%\n4\n1\n"
+    );
+    assert_eq!(output.stderr_str(), "printf: 4x: invalid number\n");
+}
+
+#[test]
+fn input_redirection_reads_from_file() {
+    let mut harness = ShellHarness::new().unwrap();
+    std::fs::write(harness.dir().join("data.txt"), "b\na\n").unwrap();
+    assert_eq!(harness.run("sort < data.txt").stdout_str(), "a\nb\n");
+    assert_eq!(harness.run("cat < missing.txt").status, 1);
+}
+
+#[test]
+fn duplicates_descriptors_left_to_right() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run("ls missing-file 2>&1 | tr a-z A-Z");
+    assert!(output.stdout_str().contains("MISSING-FILE"));
+    assert_eq!(output.stderr_str(), "");
+
+    harness.run("ls missing-file > both.txt 2>&1");
+    let both = std::fs::read_to_string(harness.dir().join("both.txt")).unwrap();
+    assert!(both.contains("missing-file"));
+
+    let output = harness.run("echo hi >&2");
+    assert_eq!(output.stdout_str(), "");
+    assert_eq!(output.stderr_str(), "hi\n");
+
+    // stderr goes where stdout pointed before `> out.txt` moved it
+    let output = harness
+        .run("ls missing-file 2>&1 > out.txt | tr a-z A-Z; cd missing 2>&1 >> out.txt | wc -l");
+    assert!(output.stdout_str().contains("MISSING-FILE"));
+    assert!(output.stdout_str().ends_with("\n1\n"));
+    assert_eq!(output.stderr_str(), "");
+    let out = std::fs::read_to_string(harness.dir().join("out.txt")).unwrap();
+    assert_eq!(out, "");
+}
+
+#[test]
+fn dash_closes_descriptors_and_bare_dup_names_a_file() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run("ls missing-file 2>&-; echo $?; cat <&-; echo $?");
+    assert_eq!(output.stdout_str(), "2\n1\n");
+    assert!(!harness.dir().join("-").exists());
+
+    let output = harness.run("echo hi >&-");
+    assert_eq!(output.status, 1);
+    assert!(output.stderr_str().contains("Bad file descriptor"));
+
+    // `>&file` sends stdout and stderr to the file, like `&>file`
+    let output = harness.run("echo out >&both.txt; ls missing-file >&both.txt; cat both.txt");
+    assert!(output.stdout_str().contains("missing-file"));
+    assert_eq!(output.stderr_str(), "");
+
+    let output = harness.run("echo hi 2>&file; echo never");
+    assert_eq!(output.status, 2);
+    assert_eq!(output.stdout_str(), "");
+    assert!(output.stderr_str().contains("unexpected token `file'"));
+}
+
+#[test]
+fn leading_digits_are_an_fd_only_before_a_redirection() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run("echo 2fast2furious 1password 3 >out.txt; cat out.txt");
+    assert_eq!(output.stdout_str(), "2fast2furious 1password 3\n");
+
+    harness.run("echo hi 10>fd.txt 1>&10");
+    let saved = std::fs::read_to_string(harness.dir().join("fd.txt")).unwrap();
+    assert_eq!(saved, "hi\n");
+}
+
+#[test]
+fn and_or_lists_short_circuit() {
+    let mut harness = ShellHarness::new().unwrap();
+    assert_eq!(
+        harness.run("true && echo yes || echo no").stdout_str(),
+        "yes\n"
+    );
+    assert_eq!(
+        harness.run("false && echo yes || echo no").stdout_str(),
+        "no\n"
+    );
+    let output = harness.run("false && echo skipped");
+    assert_eq!(output.stdout_str(), "");
+    assert_eq!(output.status, 1);
+}
+
+#[test]
+fn expands_special_parameters() {
+    let mut harness = ShellHarness::new().unwrap();
+    assert_eq!(harness.run("false || echo $?").stdout_str(), "1\n");
+    assert_eq!(
+        harness
+            .run("ls missing-file 2> /dev/null || echo \"status $?\" '$?'")
+            .stdout_str(),
+        "status 2 $?\n"
+    );
+    let output = harness.run("echo $$");
+    assert!(output.stdout_str().trim().parse::<u32>().is_ok());
+    assert_eq!(
+        harness.run("echo ${HOME}").stdout_str().trim(),
+        harness.dir().to_str().unwrap()
+    );
+}
+
+#[test]
+fn expands_glob_patterns() {
+    let mut harness = ShellHarness::new().unwrap();
+    for name in ["b.rs", "a.rs", "notes.txt", ".hidden.rs"] {
+        std::fs::write(harness.dir().join(name), "").unwrap();
+    }
+    assert_eq!(harness.run("echo *.rs").stdout_str(), "a.rs b.rs\n");
+    assert_eq!(harness.run("echo [!a]*").stdout_str(), "b.rs notes.txt\n");
+    assert_eq!(
+        harness.run("echo '*.rs' \\*.rs").stdout_str(),
+        "*.rs *.rs\n"
+    );
+    assert_eq!(harness.run("echo *.md").stdout_str(), "*.md\n");
+}
+
+#[test]
+fn runs_semicolon_separated_commands_in_order() {
+    let mut harness = ShellHarness::new().unwrap();
+    std::fs::create_dir(harness.dir().join("sub")).unwrap();
+    let output = harness.run("cd sub; pwd > where.txt; false; echo $?");
+    assert_eq!(output.stdout_str(), "1\n");
+    let recorded = std::fs::read_to_string(harness.dir().join("sub/where.txt")).unwrap();
+    assert!(recorded.trim_end().ends_with("/sub"));
+
+    let output = harness.run("echo 'a;b'; exit 4; echo unreachable");
+    assert_eq!(output.stdout_str(), "a;b\n");
+    assert_eq!(output.status, 4);
+}
+
+#[test]
+fn exports_shell_variables_to_children() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run(
+        "GREETING='hello world'; echo $GREETING; printenv GREETING || echo unexported; \
+         export GREETING; printenv GREETING",
+    );
+    assert_eq!(
+        output.stdout_str(),
+        "hello world\nunexported\nhello world\n"
+    );
+
+    let output = harness.run("export 9lives");
+    assert_eq!(output.status, 1);
+    assert!(output.stderr_str().contains("not a valid identifier"));
+}
+
+#[test]
+fn assignment_prefixes_apply_to_one_command() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run("LEVEL=debug MODE=\"fast mode\" printenv LEVEL MODE; echo [$LEVEL]");
+    assert_eq!(output.stdout_str(), "debug\nfast mode\n[]\n");
+}
+
+#[test]
+fn expands_aliases_in_command_position() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run(
+        "alias shout='tr a-z A-Z' echo='echo said:'\n\
+         echo hi | shout; echo echo\n\
+         unalias shout; alias",
+    );
+    assert_eq!(
+        output.stdout_str(),
+        "SAID: HI\nsaid: echo\nalias echo='echo said:'\n"
+    );
+}
+
+#[test]
+fn defines_and_calls_functions() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run(
+        "greet() { echo \"hi $1 ($#)\"; return 3; echo unreachable; }\n\
+         greet bob; echo $?\n\
+         greet alice | tr a-z A-Z\n\
+         greet eve > greeting.txt",
+    );
+    assert_eq!(output.stdout_str(), "hi bob (1)\n3\nHI ALICE (1)\n");
+    let saved = std::fs::read_to_string(harness.dir().join("greeting.txt")).unwrap();
+    assert_eq!(saved, "hi eve (1)\n");
+}
+
+#[test]
+fn subshells_keep_their_changes_to_themselves() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run(
+        "x=1; (x=2; cd /; echo \"in $x $PWD\"; exit 3); echo \"out $x $?\"; pwd\n\
+         (echo b; echo a) | sort | tr a-z A-Z\n\
+         (echo out; ls missing-file) > both.txt 2>&1\n\
+         { echo grouped; y=5; } > group.txt; echo \"y=$y\"",
+    );
+    assert_eq!(
+        output.stdout_str(),
+        format!("in 2 /\nout 1 3\n{}\nA\nB\ny=5\n", harness.dir().display())
+    );
+    let both = std::fs::read_to_string(harness.dir().join("both.txt")).unwrap();
+    assert!(both.starts_with("out\nls: "), "{both}");
+}
+
+#[test]
+fn subshells_and_pipeline_stages_share_the_shells_pid() {
+    let mut harness = ShellHarness::new().unwrap();
+    let stdout = harness
+        .run("echo $$; (echo $$); { echo $$; } | cat; cat <(echo $$)")
+        .stdout_str();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 4, "{lines:?}");
+    assert!(lines.iter().all(|line| line == &lines[0]), "{lines:?}");
+}
+
+#[test]
+fn compound_commands_run_in_the_background_as_jobs() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run(
+        "(exit 7) & wait $!; echo \"subshell $?\"\n\
+         (sleep 0.3; echo a) & echo b; wait\n\
+         f() { x=2; return 3; }; x=1; f & wait $!; echo \"function $? $x\"\n\
+         for i in 1 2; do sleep 0.1; done & { exit 4; } & wait %1; echo $?; wait %2; echo $?",
+    );
+    assert_eq!(
+        output.stdout_str(),
+        "subshell 7\nb\na\nfunction 3 1\n0\n4\n"
+    );
+}
+
+#[test]
+fn runs_multi_line_input_with_comments() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run(
+        "#!/usr/bin/env shelly\n\
+         # greet someone\n\
+         greet() {\n\
+           echo \"hi $1\" # inline comment\n\
+           return 5\n\
+         }\n\
+         greet there\n\
+         exit",
+    );
+    assert_eq!(output.stdout_str(), "hi there\n");
+    assert_eq!(output.status, 5);
+}
+
+#[test]
+fn binds_arguments_after_command_string() {
+    let mut harness = ShellHarness::new()
+        .unwrap()
+        .args(&["script-name", "one", "two"]);
+    let output = harness.run("echo \"$0: $1 $2 ($#)\" | tr a-z A-Z; exit 4");
+    assert_eq!(output.stdout_str(), "SCRIPT-NAME: ONE TWO (2)\n");
+    assert_eq!(output.status, 4);
+}
+
+#[test]
+fn reads_commands_from_piped_stdin() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run_stdin("echo first\ncat\nleft for cat\n");
+    assert_eq!(output.stdout_str(), "first\nleft for cat\n");
+
+    let output = harness.run_stdin("echo one\nexit 3\necho never\n");
+    assert_eq!(output.stdout_str(), "one\n");
+    assert_eq!(output.status, 3);
+}
+
+#[test]
+fn feeds_here_documents_and_here_strings() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run(
+        "NAME=world\n\
+         cat <<EOF\nhello $NAME\n\\$NAME\nEOF\n\
+         cat <<'EOF' | tr a-z A-Z\nkeep $NAME\nEOF\n\
+         cat <<-END\n\tindented\n\tEND\n\
+         tr a-z A-Z <<< \"$NAME\"",
+    );
+    assert_eq!(
+        output.stdout_str(),
+        "hello world\n$NAME\nKEEP $NAME\nindented\nWORLD\n"
+    );
+    assert_eq!(output.status, 0);
+}
+
+#[test]
+fn continues_unfinished_commands_on_the_next_line() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run_stdin(
+        "echo one \\\n  two\n\
+         echo 'a\nb'\n\
+         echo x |\n  tr x y\n\
+         false ||\n\n  echo or\n",
+    );
+    assert_eq!(output.stdout_str(), "one two\na\nb\ny\nor\n");
+    assert_eq!(output.stderr_str(), "");
+}
+
+#[test]
+fn interactive_shells_keep_unfinished_commands_as_one_history_entry() {
+    let mut harness = ShellHarness::new().unwrap().interactive();
+    let output = harness.run_stdin(
+        "greet() {\n  echo hi\n}\ngreet\n\
+         echo \"a\nb\" |\ntr a-z A-Z\n\
+         history\n",
+    );
+    let history =
+        "    1  greet() {\n  echo hi\n}\n    2  greet\n    3  echo \"a\nb\" |\ntr a-z A-Z\n";
+    assert_eq!(
+        output.stdout_str(),
+        format!("hi\nA\nB\n{history}    4  history\n")
+    );
+}
+
+#[test]
+fn expands_tilde_in_every_command() {
+    let mut harness = ShellHarness::new().unwrap();
+    std::fs::create_dir(harness.dir().join("projects")).unwrap();
+    let output = harness.run(
+        "echo ~ ~/projects \"~\" \\~ a~ ~no-such-user/x\n\
+         DIR=~/projects; echo $DIR\n\
+         cd /; cd ~/projects; pwd",
+    );
+    let home = harness.dir().display().to_string();
+    assert_eq!(
+        output.stdout_str(),
+        format!(
+            "{home} {home}/projects ~ ~ a~ ~no-such-user/x\n{home}/projects\n{home}/projects\n"
+        )
+    );
+}
+
+#[test]
+fn connects_process_substitutions() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run(
+        "printf 'a\\nb\\n' > one; printf 'a\\nc\\n' > two\n\
+         diff <(cat one) <(cat two) > /dev/null; echo diff=$?\n\
+         cat < <(echo redirected)\n\
+         echo hello | tee >(tr a-z A-Z) > /dev/null",
+    );
+    assert_eq!(output.stdout_str(), "diff=1\nredirected\nHELLO\n");
+    assert_eq!(output.stderr_str(), "");
+}
+
+#[test]
+fn process_substitutions_feed_compound_commands() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run(
+        "n=0; while read l; do n=$n$l; done < <(printf '1\\n2\\n'); echo $n\n\
+         for f in <(echo in-list); do cat $f; done\n\
+         { cat; } < <(echo group) | tr a-z A-Z",
+    );
+    assert_eq!(output.stdout_str(), "012\nin-list\nGROUP\n");
+    assert_eq!(output.stderr_str(), "");
+}
+
+#[test]
+fn unquoted_expansions_split_on_ifs() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run(
+        "x='a  b'; for i in $x; do echo \"[$i]\"; done; set -- \"$x\"; echo $#\n\
+         w='  l m '; set -- pre${w}post; echo \"$# $1 $3\"\n\
+         IFS=:; y=c::d:; set -- $y; echo \"$# [$2] $3\"\n\
+         IFS=; set -- $x; echo $#; unset IFS; e=; set -- $e \"$e\"; echo $#",
+    );
+    assert_eq!(output.stdout_str(), "[a]\n[b]\n1\n4 pre m\n3 [] d\n1\n1\n");
+}
+
+#[test]
+fn runs_if_statements() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run(
+        "x=2\n\
+         if [ $x = 1 ]; then echo one; elif [ $x = 2 ]; then echo two; else echo other; fi\n\
+         if false; then echo no; fi; echo status=$?\n\
+         if false\nthen\n  echo no\nelse\n  echo else | tr a-z A-Z\nfi\n\
+         echo if then fi",
+    );
+    assert_eq!(output.stdout_str(), "two\nstatus=0\nELSE\nif then fi\n");
+}
+
+#[test]
+fn runs_for_and_while_loops() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run(
+        "for x in a b c; do echo $x; done | tr -d '\\n'; echo\n\
+         args() { for arg; do echo \"<$arg>\"; done; }; args 1 '2 3'\n\
+         n=x\n\
+         while [ $n != xxxxx ]\ndo\n  n=${n}x\n  if [ $n = xxx ]; then continue; fi\n  echo $n\ndone\n\
+         for i in 1 2; do for j in a b c; do\n  if [ $j = b ]; then continue 2; fi; echo $i$j\ndone; done\n\
+         for i in 1 2; do while true; do break 2; done; echo never; done; echo status=$?\n\
+         quit() { break; }; for i in 1 2; do quit; done 2>/dev/null; echo $i",
+    );
+    assert_eq!(
+        output.stdout_str(),
+        "abc\n<1>\n<2 3>\nxx\nxxxx\nxxxxx\n1a\n2a\nstatus=0\n2\n"
+    );
+}
+
+#[test]
+fn matches_case_patterns() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run(
+        "for f in main.rs notes.txt Makefile '*' other; do\n\
+           case $f in\n\
+             *.rs|*.toml) echo \"$f: rust\" ;;\n\
+             (*.txt) echo \"$f: text\";;\n\
+             Makefile) echo make ;;\n\
+             \"*\") echo star ;;\n\
+             *) echo \"$f: other\"\n\
+           esac\n\
+         done\n\
+         case x in y) echo no;; esac; echo status=$?",
+    );
+    assert_eq!(
+        output.stdout_str(),
+        "main.rs: rust\nnotes.txt: text\nmake\nstar\nother: other\nstatus=0\n"
+    );
+}
+
+#[test]
+fn evaluates_test_expressions() {
+    let mut harness = ShellHarness::new().unwrap();
+    std::fs::write(harness.dir().join("file.txt"), "data").unwrap();
+    let output = harness.run(
+        "test -f file.txt; echo $?\n\
+         test -d file.txt; echo $?\n\
+         [ -s file.txt -a ! -e missing ]; echo $?\n\
+         [ 3 -lt 10 ] && [ a != b ] && [ -z \"\" ]; echo $?\n\
+         [ \\( 10 -le 3 \\) -o -n \"\" ]; echo $?\n\
+         [ one -eq 1 ] 2>/dev/null; echo $?\n\
+         [ x 2>/dev/null; echo $?\n\
+         test; echo $?",
+    );
+    assert_eq!(output.stdout_str(), "0\n1\n0\n0\n1\n2\n2\n1\n");
+}
+
+#[test]
+fn evaluates_extended_conditionals() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run(
+        "file='my notes.txt'\n\
+         [[ $file == *.txt && $file != \"*.txt\" ]]; echo glob=$?\n\
+         [[ -z $unset || ( 3 -gt 10 ) ]]; echo or=$?\n\
+         if [[ v1.22 =~ ^v([0-9]+)\\.([0-9]+)$ ]]; then\n\
+           echo \"$BASH_REMATCH ${BASH_REMATCH[1]} ${BASH_REMATCH[2]} ${#BASH_REMATCH[@]}\"\n\
+         fi\n\
+         [[ abc =~ \"a.c\" ]]; echo quoted=$? \"[${BASH_REMATCH[@]}]\"\n\
+         [[ a =~ ( ]] 2>/dev/null; echo bad=$?",
+    );
+    assert_eq!(
+        output.stdout_str(),
+        "glob=0\nor=0\nv1.22 1 22 3\nquoted=1 []\nbad=2\n"
+    );
+}
+
+#[test]
+fn sources_files_into_the_current_shell() {
+    let mut harness = ShellHarness::new().unwrap();
+    std::fs::create_dir(harness.dir().join("sub")).unwrap();
+    std::fs::write(
+        harness.dir().join("env.sh"),
+        "GREETING=hello\n\
+         alias hi='echo hi'\n\
+         greet() { echo \"$GREETING $1\"; }\n\
+         cd sub\n\
+         echo \"args: $#\"\n\
+         return 3\n\
+         echo unreachable\n",
+    )
+    .unwrap();
+    let output = harness.run(
+        "source env.sh a b; echo status=$?\n\
+         hi; greet world; test -f ../env.sh && echo in-sub\n\
+         cd ..; . ./env.sh > out.txt; cat ../out.txt\n\
+         source missing.sh 2>/dev/null; echo missing=$?",
+    );
+    assert_eq!(
+        output.stdout_str(),
+        "args: 2\nstatus=3\nhi\nhello world\nin-sub\nargs: 0\nmissing=1\n"
+    );
+}
+
+#[test]
+fn times_pipelines() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run("time -p sleep 0.1 | cat; echo status=$?\ntime ! true");
+    assert_eq!(output.stdout_str(), "status=0\n");
+    let reports: Vec<_> = output.stderr_str().lines().map(str::to_string).collect();
+    assert_eq!(reports.len(), 7, "{:?}", reports);
+    let real: f64 = reports[0].strip_prefix("real ").unwrap().parse().unwrap();
+    assert!(real >= 0.1, "{:?}", reports);
+    assert!(reports[1].starts_with("user ") && reports[2].starts_with("sys "));
+    assert!(reports[4].starts_with("real\t0m"), "{:?}", reports);
+    assert_eq!(output.status, 1);
+}
+
+#[test]
+fn history_is_empty_without_a_prompt() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run("echo hi; history; history 5; history -c; history many; echo $?");
+    assert_eq!(output.stdout_str(), "hi\n2\n");
+    assert!(output
+        .stderr_str()
+        .contains("history: many: numeric argument required"));
+}
+
+#[test]
+fn complete_sets_and_prints_completion_specs() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run(
+        "complete -p cd type; complete -dv go; complete -p go; complete -r go; complete -p go; echo $?; complete -x go",
+    );
+    assert_eq!(
+        output.stdout_str(),
+        "complete -d cd\ncomplete -c type\ncomplete -d -v go\n1\n"
+    );
+    assert!(output
+        .stderr_str()
+        .contains("complete: go: no completion specification"));
+    assert!(output.stderr_str().contains("complete: -x: invalid option"));
+}
+
+#[test]
+fn compgen_lists_what_a_spec_offers() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run(
+        "mkdir sub1 sub2; touch subfile\n\
+         compgen -W 'start stop restart' st; compgen -d sub; compgen -W 'a b' x; echo $?\n\
+         complete -W 'start stop' -F _svc svc; complete -p svc\n\
+         XYZ_ONE=1; compgen -v XYZ_; compgen -F f",
+    );
+    assert_eq!(
+        output.stdout_str(),
+        "start\nstop\nsub1\nsub2\n1\ncomplete -W 'start stop' -F _svc svc\nXYZ_ONE\n"
+    );
+    assert!(output.stderr_str().contains("compgen: -F: not supported"));
+}
+
+#[test]
+fn command_completion_follows_path_changes() {
+    let harness = ShellHarness::new().unwrap();
+    let dir = harness.dir().to_path_buf();
+    std::fs::create_dir_all(dir.join("bin")).unwrap();
+    std::fs::create_dir_all(dir.join("other")).unwrap();
+    std::fs::write(dir.join("other/qqother"), "").unwrap();
+    let path = format!("{}/bin:{}", dir.display(), std::env::var("PATH").unwrap());
+
+    // Interactive, so `compgen -c` asks the line editor's command index
+    let mut harness = harness.interactive().env("PATH", &path);
+    let output = harness.run_stdin(
+        "chmod +x other/qqother; compgen -c qq; echo $?\n\
+         touch bin/qqtool; chmod +x bin/qqtool\ncompgen -c qq\n\
+         PATH=\"$PWD/other:$PATH\"\ncompgen -c qq\n\
+         rm bin/qqtool\ncompgen -c qq\n",
+    );
+    assert_eq!(output.stdout_str(), "1\nqqtool\nqqother\nqqtool\nqqother\n");
+}
+
+#[test]
+fn command_completion_lists_only_executable_files() {
+    let harness = ShellHarness::new().unwrap();
+    let bin = harness.dir().join("bin");
+    std::fs::create_dir_all(bin.join("qqdir")).unwrap();
+    std::fs::write(bin.join("qqdata"), "").unwrap();
+    std::fs::write(bin.join("qqtool"), "").unwrap();
+    let path = format!("{}:{}", bin.display(), std::env::var("PATH").unwrap());
+    let script = "chmod +x bin/qqtool bin/qqdir; compgen -c qq\n";
+
+    let mut harness = harness.env("PATH", &path);
+    assert_eq!(harness.run_stdin(script).stdout_str(), "qqtool\n");
+    let mut harness = harness.interactive();
+    assert_eq!(harness.run_stdin(script).stdout_str(), "qqtool\n");
+}
+
+#[test]
+fn command_completion_keeps_up_with_aliases_and_functions() {
+    let mut harness = ShellHarness::new().unwrap().interactive();
+    let output = harness.run_stdin(
+        "alias qqll='ls -l'\nqqfn() { true; }\ncompgen -c qq\n\
+         unalias qqll\nunset -f qqfn\ncompgen -c qq; echo $?\n\
+         alias echo=printf\nunalias echo\ncompgen -c echo\n",
+    );
+    // A builtin stays completable after an alias of the same name goes
+    assert_eq!(output.stdout_str(), "qqfn\nqqll\n1\necho\n");
+}
+
+#[test]
+fn type_and_which_report_every_resolution_in_lookup_order() {
+    let mut harness = ShellHarness::new().unwrap();
+    let bin = harness.dir().join("bin/tool");
+    let output = harness.run(&format!(
+        "mkdir bin; printf '#!/bin/sh\\n' > bin/tool; chmod +x bin/tool; PATH={}:$PATH\n\
+         alias tool='tool -v'; tool() {{ :; }}\n\
+         type tool; type -a tool; which pwd; which -a tool; which nope; echo $?",
+        harness.dir().join("bin").display()
+    ));
+    assert_eq!(
+        output.stdout_str(),
+        format!(
+            "tool is aliased to `tool -v'\n\
+             tool is aliased to `tool -v'\ntool is a function\ntool is {bin}\n\
+             pwd: shell builtin\n\
+             tool: aliased to tool -v\ntool: shell function\n{bin}\n\
+             1\n",
+            bin = bin.display()
+        )
+    );
+    assert_eq!(output.stderr_str(), "nope not found\n");
+}
+
+#[test]
+fn type_skips_files_that_cannot_run_and_takes_bash_options() {
+    let mut harness = ShellHarness::new().unwrap();
+    let bin = harness.dir().join("bin");
+    let output = harness.run(&format!(
+        "mkdir -p bin/subdir; touch bin/data; printf '#!/bin/sh\\n' > bin/tool; chmod +x bin/tool\n\
+         PATH={}:$PATH; type data subdir; echo $?\n\
+         type -t tool pwd nope; echo $?; type -p tool pwd",
+        bin.display()
+    ));
+    assert_eq!(
+        output.stdout_str(),
+        format!("1\nfile\nbuiltin\n1\n{}\n", bin.join("tool").display())
+    );
+    assert_eq!(output.stderr_str(), "data: not found\nsubdir: not found\n");
+}
+
+#[test]
+fn type_recognises_reserved_words() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run(
+        "type -t if; type if; type -t '[[' done; alias if=x; type -a if; which while; echo $?",
+    );
+    assert_eq!(
+        output.stdout_str(),
+        "keyword\nif is a shell keyword\nkeyword\nkeyword\n\
+         if is a shell keyword\nif is aliased to `x'\nwhile: shell reserved word\n0\n"
+    );
+    assert_eq!(output.status, 0);
+}
+
+#[test]
+fn hash_remembers_where_path_commands_ran_from() {
+    let mut harness = ShellHarness::new().unwrap();
+    let bin = harness.dir().join("bin");
+    let tool = bin.join("tool");
+    let output = harness.run(&format!(
+        "mkdir bin; printf '#!/bin/sh\\necho ran\\n' > bin/tool; chmod +x bin/tool\n\
+         PATH={}:$PATH; hash; tool; tool; hash; type tool; hash -t tool\n\
+         hash -d tool; hash -d tool; echo $?; hash tool; PATH=$PATH; hash; hash -r",
+        bin.display()
+    ));
+    assert_eq!(
+        output.stdout_str(),
+        format!(
+            "hash: hash table empty\nran\nran\nhits\tcommand\n   2\t{0}\n\
+             tool is hashed ({0})\n{0}\n1\nhash: hash table empty\n",
+            tool.display()
+        )
+    );
+    assert_eq!(output.stderr_str(), "hash: tool: not found\n");
+}
+
+#[test]
+fn history_checks_its_options() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run(
+        "history -t -s echo 3; echo $?; history -d 1; echo $?; history -s; echo $?; history -x; echo $?",
+    );
+    assert_eq!(output.stdout_str(), "0\n1\n2\n2\n");
+    let stderr = output.stderr_str();
+    assert!(stderr.contains("history: 1: history position out of range"));
+    assert!(stderr.contains("history: -s: option requires an argument"));
+    assert!(stderr.contains("history: -x: invalid option"));
+}
+
+#[test]
+fn bench_stops_at_the_first_failing_run() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run("bench -n 2 nosuchcmd");
+    assert_eq!(output.stdout_str(), "");
+    assert_eq!(output.status, 127);
+    assert!(
+        output
+            .stderr_str()
+            .ends_with("bench: `nosuchcmd` failed with status 127 on run 1 of 2\n"),
+        "{}",
+        output.stderr_str()
+    );
+
+    let output = harness.run("bench -n 3 true");
+    assert!(output.stdout_str().starts_with("bench: 3 runs of `true`\n"));
+    assert_eq!(output.status, 0);
+}
+
+#[test]
+fn history_deletes_searches_and_timestamps_entries() {
+    let mut harness = ShellHarness::new().unwrap().interactive();
+    let output = harness.run_stdin(
+        "echo a\necho b\nhistory -d 1\nhistory -s echo\nHISTTIMEFORMAT='[%Y] '\nhistory 1\n",
+    );
+    let stdout = output.stdout_str();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(
+        lines[..4],
+        ["a", "b", "    1  echo b", "    3  history -s echo"]
+    );
+    assert!(lines[4].starts_with("    5  [") && lines[4].ends_with("] history 1"));
+
+    // Every entry in the file follows the time it was entered
+    let file = std::fs::read_to_string(harness.dir().join(".shelly_history")).unwrap();
+    let lines: Vec<&str> = file.lines().collect();
+    assert!(
+        lines.len() >= 2 && lines.len().is_multiple_of(2),
+        "{}",
+        file
+    );
+    for pair in lines.chunks(2) {
+        assert!(pair[0][1..].parse::<u64>().is_ok(), "{}", file);
+        assert!(!pair[1].starts_with('#'));
+    }
+}
+
+#[test]
+fn builtin_errors_go_to_their_stderr_and_set_status() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run(
+        "cd /nonexistent 2>/dev/null; echo cd=$?\n\
+         type echo missing-command 2>err.txt; echo type=$?\n\
+         cd /nonexistent 2>&1 | tr a-z A-Z",
+    );
+    assert_eq!(
+        output.stdout_str(),
+        "cd=1\necho is a shell builtin\ntype=1\nSHELLY: LINE 3: CD: /NONEXISTENT: NO SUCH FILE OR DIRECTORY\n"
+    );
+    assert_eq!(output.stderr_str(), "");
+    let errors = std::fs::read_to_string(harness.dir().join("err.txt")).unwrap();
+    assert_eq!(errors, "missing-command: not found\n");
+}
+
+#[test]
+fn streams_builtin_output_in_order() {
+    let mut harness = ShellHarness::new().unwrap();
+    let output = harness.run(
+        "type echo missing-command 2>&1 | cat\n\
+         echo one; printf 'two\\n'; echo three > out.txt; echo four",
+    );
+    assert_eq!(
+        output.stdout_str(),
+        "echo is a shell builtin\nmissing-command: not found\none\ntwo\nfour\n"
+    );
+    let saved = std::fs::read_to_string(harness.dir().join("out.txt")).unwrap();
+    assert_eq!(saved, "three\n");
+}
+
+#[test]
+fn loops_stream_into_the_next_stage_as_they_run() {
+    let mut harness = ShellHarness::new().unwrap();
+    // The loop only ends when `head` has gone and its next write fails
+    let output = harness.run("while true; do echo y; done | head -1; echo $?");
+    assert_eq!(output.stdout_str(), "y\n0\n");
+
+    let output = harness.run("x=1; for i in a b; do x=$i; echo $i; done | tr a-z A-Z; echo $x");
+    assert_eq!(output.stdout_str(), "A\nB\n1\n");
+}
+
+#[test]
+fn forked_stages_keep_what_their_redirections_read_and_write() {
+    let mut harness = ShellHarness::new().unwrap();
+    std::fs::write(harness.dir().join("in.txt"), "a\nb\n").unwrap();
+    let output = harness.run(
+        "while read l; do echo \"<$l>\"; done < in.txt | tr a-z A-Z\n\
+         { cat; } < in.txt | wc -l\n\
+         f() { echo f; }; f > f.txt | cat; cat f.txt\n\
+         { echo bg; } > bg.txt & wait; cat bg.txt",
+    );
+    assert_eq!(output.stdout_str(), "<A>\n<B>\n2\nf\nbg\n");
+}
+
+#[test]
+fn inspection_output_stops_quietly_when_the_reader_goes() {
+    let mut harness = ShellHarness::new()
+        .unwrap()
+        .env("SHELLY_BIN", env!("CARGO_BIN_EXE_codecrafters-shell"));
+    std::fs::write(
+        harness.dir().join("in.txt"),
+        "echo hi | cat > out\n".repeat(2000),
+    )
+    .unwrap();
+    let output = harness.run(
+        "\"$SHELLY_BIN\" --dump-ast --tokenize < in.txt 2> err.txt | head -1; cat err.txt; echo done",
+    );
+    assert_eq!(
+        output.stdout_str(),
+        "0..4       Word(Word { parts: [Literal(\"echo\")] })\ndone\n"
+    );
+}
+
+#[test]
+fn history_expansion_echoes_the_command_unless_stdout_is_gone() {
+    let mut harness = ShellHarness::new().unwrap().interactive();
+    let output = harness.run_stdin("echo a\n!!\n");
+    assert_eq!(output.stdout_str(), "a\necho a\na\n");
+
+    let mut harness = ShellHarness::new()
+        .unwrap()
+        .env("SHELLY_BIN", env!("CARGO_BIN_EXE_codecrafters-shell"));
+    // The reader is gone by the time `!!` is echoed
+    let output = harness.run(
+        "printf 'sleep 0.2\\ntrue\\n!!\\n' | \"$SHELLY_BIN\" -i --norc 2> err.txt | true; cat err.txt",
+    );
+    assert_eq!(output.stdout_str(), "");
+}